@@ -0,0 +1,72 @@
+# NeoTerm shell integration for bash.
+#
+# Loaded through `bash --rcfile`; sources the user's ~/.bashrc first and then reports
+# prompt and command boundaries with OSC 133 and the working directory with OSC 7.
+# Commands run by NeoTerm can have their stderr redirected to the terminal named in
+# $NEOTERM_STDERR_CONTROL.
+
+if [[ -f ~/.bashrc ]]; then
+    source ~/.bashrc
+fi
+
+if [[ -n "${__neoterm_integration_loaded-}" ]]; then
+    return
+fi
+__neoterm_integration_loaded=1
+
+__neoterm_command_running=""
+__neoterm_at_prompt=""
+__neoterm_status=0
+
+__neoterm_preexec() {
+    # The DEBUG trap also fires for PROMPT_COMMAND and completion functions.
+    [[ -n "${COMP_LINE-}" || -z "$__neoterm_at_prompt" ]] && return
+    [[ "$BASH_COMMAND" == __neoterm_* ]] && return
+    __neoterm_at_prompt=""
+    __neoterm_command_running=1
+    local cmd
+    cmd=$(HISTTIMEFORMAT= builtin history 1 | command sed 's/^ *[0-9]* *//')
+    builtin printf '\e]133;C;cmdline=%s\a' "${cmd:-$BASH_COMMAND}"
+    __neoterm_redirect_stderr
+}
+
+# Points stderr at the terminal NeoTerm wrote to the control file for this command. The
+# file is emptied right away, so commands typed into the shell keep their stderr.
+__neoterm_redirect_stderr() {
+    [[ -n "${NEOTERM_STDERR_CONTROL-}" && -s "$NEOTERM_STDERR_CONTROL" ]] || return
+    local target
+    builtin read -r target < "$NEOTERM_STDERR_CONTROL"
+    : >| "$NEOTERM_STDERR_CONTROL"
+    [[ -n "$target" && -w "$target" ]] || return
+    exec {__neoterm_stderr_fd}>&2 2>"$target"
+}
+
+__neoterm_restore_stderr() {
+    [[ -n "${__neoterm_stderr_fd-}" ]] || return
+    exec 2>&"$__neoterm_stderr_fd" {__neoterm_stderr_fd}>&-
+    unset __neoterm_stderr_fd
+}
+
+# Runs first in PROMPT_COMMAND: saves the exit status before other prompt hooks clobber it.
+__neoterm_prompt_begin() {
+    __neoterm_status=$?
+    __neoterm_at_prompt=""
+    __neoterm_restore_stderr
+}
+
+# Runs last in PROMPT_COMMAND.
+__neoterm_precmd() {
+    builtin printf '\e]7;file://%s%s\a' "$HOSTNAME" "$PWD"
+    if [[ -n "$__neoterm_command_running" ]]; then
+        builtin printf '\e]133;D;%s\a' "$__neoterm_status"
+        __neoterm_command_running=""
+    fi
+    builtin printf '\e]133;A\a'
+    if [[ "$PS1" != *'133;B'* ]]; then
+        PS1="$PS1"'\[\e]133;B\a\]'
+    fi
+    __neoterm_at_prompt=1
+}
+
+PROMPT_COMMAND="__neoterm_prompt_begin; ${PROMPT_COMMAND:+$PROMPT_COMMAND; }__neoterm_precmd"
+trap '__neoterm_preexec' DEBUG
@@ -0,0 +1,30 @@
+# NeoTerm shell integration for fish.
+#
+# Loaded through `fish --init-command`. Prompt and command boundaries are reported
+# with OSC 133 and the working directory with OSC 7.
+
+if set -q __neoterm_integration_loaded
+    exit
+end
+set -g __neoterm_integration_loaded 1
+
+function __neoterm_preexec --on-event fish_preexec
+    printf '\e]133;C;cmdline=%s\a' "$argv"
+end
+
+function __neoterm_postexec --on-event fish_postexec
+    set -l ret $status
+    printf '\e]7;file://%s%s\a' "$hostname" "$PWD"
+    printf '\e]133;D;%s\a' $ret
+end
+
+function __neoterm_prompt_start --on-event fish_prompt
+    printf '\e]7;file://%s%s\a' "$hostname" "$PWD"
+    printf '\e]133;A\a'
+end
+
+functions -c fish_prompt __neoterm_original_fish_prompt
+function fish_prompt
+    __neoterm_original_fish_prompt
+    printf '\e]133;B\a'
+end
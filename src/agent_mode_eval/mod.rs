@@ -7,15 +7,18 @@ pub mod tools;
 
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
-use std::sync::{Arc, Mutex};
-use tokio::sync::{mpsc, RwLock};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex, RwLock};
 use log::{info, warn, error};
 use serde::{Serialize, Deserialize};
 use crate::ai::assistant::{Assistant, AgentMessage as ProviderAgentMessage, Tool as AiTool, ToolManager};
 use crate::ai::context::AIContext;
 use crate::block::Block;
+use crate::lint::{Finding, LintContext, Linter};
 use crate::workflows::Workflow;
 use std::collections::HashMap;
+use tools::CommandContext;
 use uuid::Uuid;
 
 /// Represents messages exchanged within the agent mode, including UI interactions.
@@ -31,8 +34,19 @@ pub enum AgentMessage {
    WorkflowSuggested(Workflow),
    AgentPromptRequest { prompt_id: String, message: String },
    AgentPromptResponse { prompt_id: String, response: String },
+   /// The agent wants to run a command the linter found risky. The user's answer goes to
+   /// `respond_to_prompt`: `CONFIRM_COMMAND` runs the command.
+   CommandConfirmationRequest { prompt_id: String, command: String, findings: Vec<Finding> },
 }
 
+/// Response to a `CommandConfirmationRequest` that runs the command; any other aborts it.
+pub const CONFIRM_COMMAND: &str = "run";
+
+/// Senders of the responses to prompts waiting for the user, by prompt ID. The application
+/// keeps a clone to answer prompts without locking the `AgentMode`, which a running
+/// conversation holds.
+pub type PendingPrompts = Arc<Mutex<HashMap<String, mpsc::Sender<String>>>>;
+
 /// Configuration for the AI Agent Mode.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentConfig {
@@ -72,7 +86,7 @@ pub struct AgentMode {
     assistant: Arc<RwLock<Assistant>>,
     ai_context: Arc<RwLock<AIContext>>,
     is_enabled: bool,
-    pending_agent_prompts: Mutex<HashMap<String, mpsc::Sender<String>>>, // Use tokio::sync::Mutex
+    pending_agent_prompts: PendingPrompts, // Use tokio::sync::Mutex
 }
 
 impl AgentMode {
@@ -87,7 +101,7 @@ impl AgentMode {
             assistant,
             ai_context,
             is_enabled: false,
-            pending_agent_prompts: Mutex::new(HashMap::new()), // Initialize with tokio::sync::Mutex
+            pending_agent_prompts: Arc::new(Mutex::new(HashMap::new())), // Initialize with tokio::sync::Mutex
         })
     }
 
@@ -119,16 +133,22 @@ impl AgentMode {
     ///
     /// * `prompt` - The user's message.
     /// * `context_blocks` - UI blocks providing additional context to the AI.
+    /// * `working_directory` - Directory of the shell the agent's commands run in, which
+    ///   relative paths in them are checked against.
+    /// * `profile_env` - Variables of the environment profile of that shell's pane, which
+    ///   the agent's commands run with.
     ///
     /// Returns a receiver for streaming `AgentMessage`s to the UI.
-    pub async fn send_message(&self, prompt: String, context_blocks: Vec<Block>) -> Result<mpsc::Receiver<AgentMessage>> {
+    pub async fn send_message(&self, prompt: String, context_blocks: Vec<Block>, working_directory: Option<PathBuf>, profile_env: HashMap<String, String>) -> Result<mpsc::Receiver<AgentMessage>> {
         let (tx, rx) = mpsc::channel(100);
         let sender_clone = tx.clone();
         let ai_assistant_clone = self.assistant.clone();
         let ai_context_clone = self.ai_context.clone(); // Clone AIContext for the spawned task
+        let pending_prompts = self.pending_agent_prompts.clone();
 
         tokio::spawn(async move {
             let mut ai_assistant = ai_assistant_clone.write().await;
+            ai_assistant.set_command_context(CommandContext { working_dir: working_directory.clone(), env: profile_env });
 
             let system_prompt = crate::ai::prompts::PromptBuilder::new().build_general_chat_prompt();
             let context = ai_context_clone.read().await.get_full_context().await;
@@ -139,8 +159,9 @@ impl AgentMode {
             // Add context blocks to the messages
             for block in context_blocks {
                 let block_content = match block.content {
-                    crate::block::BlockContent::Command { input, output, status, error, .. } => {
-                        format!("Command: `{}`\nOutput:\n\`\`\`\n{}\n\`\`\`\nStatus: {}\nError: {}", input, output.iter().map(|(s, _)| s.clone()).collect::<Vec<String>>().join("\n"), status, error)
+                    crate::block::BlockContent::Command { input, output, status, error, stats, .. } => {
+                        let stats_text = stats.map(|s| format!("\n{}", s.report())).unwrap_or_default();
+                        format!("Command: `{}`\nOutput:\n\`\`\`\n{}\n\`\`\`\nStatus: {}\nError: {}{}", input, output.text(), status, error, stats_text)
                     },
                     crate::block::BlockContent::AgentMessage { content, is_user, .. } => {
                         format!("{}: {}", if is_user { "User" } else { "Agent" }, content)
@@ -160,6 +181,9 @@ impl AgentMode {
                     crate::block::BlockContent::StreamingToolCall { id, name, arguments } => {
                         format!("Streaming Tool Call (ID: {}): {}\nArguments: {}", id, name, arguments)
                     }
+                    crate::block::BlockContent::Playback { path, .. } => {
+                        format!("Terminal recording: {}", path)
+                    }
                 };
                 current_messages.push(ProviderChatMessage { role: "system".to_string(), content: Some(block_content), tool_calls: None, tool_call_id: None });
             }
@@ -244,12 +268,16 @@ impl AgentMode {
                         } else {
                             // Execute tool calls and add results to history for the next AI turn
                             for tool_call in tool_calls_to_execute {
-                                let tool_result = match ai_assistant.execute_tool_call(tool_call.clone()).await {
-                                    Ok(res) => res,
-                                    Err(e) => {
-                                        error!("Failed to execute tool {}: {}", tool_call.function.name, e);
-                                        format!("Error executing tool {}: {}", tool_call.function.name, e)
-                                    }
+                                let declined = Self::confirm_risky_command(&tool_call, working_directory.clone(), &pending_prompts, &sender_clone).await;
+                                let tool_result = match declined {
+                                    Some(reason) => reason,
+                                    None => match ai_assistant.execute_tool_call(tool_call.clone()).await {
+                                        Ok(res) => res,
+                                        Err(e) => {
+                                            error!("Failed to execute tool {}: {}", tool_call.function.name, e);
+                                            format!("Error executing tool {}: {}", tool_call.function.name, e)
+                                        }
+                                    },
                                 };
                                 // Send tool result to UI
                                 if sender_clone.send(AgentMessage::ToolResult(tool_result.clone())).await.is_err() {
@@ -312,17 +340,57 @@ impl AgentMode {
         assistant_lock.get_history()
     }
 
+    /// Asks the user to confirm an `execute_command` tool call whose command the linter
+    /// finds risky.
+    ///
+    /// # Arguments
+    ///
+    /// * `tool_call` - The tool call about to be executed.
+    /// * `working_directory` - Directory the command runs in.
+    /// * `pending_prompts` - Where the user's response is expected.
+    /// * `sender` - The channel to the UI, which shows the confirmation.
+    ///
+    /// # Returns
+    ///
+    /// The tool result to give the AI instead of running the command, if the user aborted
+    /// it; `None` if the command may run.
+    async fn confirm_risky_command(
+        tool_call: &AiToolCall,
+        working_directory: Option<PathBuf>,
+        pending_prompts: &PendingPrompts,
+        sender: &mpsc::Sender<AgentMessage>,
+    ) -> Option<String> {
+        if tool_call.function.name != "execute_command" {
+            return None;
+        }
+        let command = tool_call.function.arguments["command"].as_str()?.to_string();
+        let findings = Linter::default().check(&command, &LintContext::new(working_directory));
+        if findings.is_empty() {
+            return None;
+        }
+        let reasons = findings.iter().map(|finding| finding.message.as_str()).collect::<Vec<_>>().join("; ");
+
+        let prompt_id = Uuid::new_v4().to_string();
+        let (tx, mut rx) = mpsc::channel(1);
+        pending_prompts.lock().await.insert(prompt_id.clone(), tx);
+        info!("Asking the user to confirm command '{}': {}", command, reasons);
+        if sender.send(AgentMessage::CommandConfirmationRequest { prompt_id, command, findings }).await.is_err() {
+            return Some(format!("The command was not run, as nobody could confirm it: {}", reasons));
+        }
+        match rx.recv().await.as_deref() {
+            Some(CONFIRM_COMMAND) => None,
+            _ => Some(format!("The user declined to run the command: {}", reasons)),
+        }
+    }
+
     /// Handles a user's response to an agent prompt.
     pub async fn handle_agent_prompt_response(&self, prompt_id: String, response: String) -> Result<()> {
-        let mut pending_prompts = self.pending_agent_prompts.lock().await;
-        if let Some(tx) = pending_prompts.remove(&prompt_id) {
-            info!("Received response for agent prompt {}: {}", prompt_id, response);
-            tx.send(response).await.map_err(|e| anyhow!("Failed to send response to agent prompt channel: {}", e))?;
-            Ok(())
-        } else {
-            error!("No pending prompt found for ID: {}", prompt_id);
-            Err(anyhow!("No pending prompt found for ID: {}", prompt_id))
-        }
+        respond_to_prompt(&self.pending_agent_prompts, prompt_id, response).await
+    }
+
+    /// Returns the prompts waiting for the user, to answer them with `respond_to_prompt`.
+    pub fn pending_prompts(&self) -> PendingPrompts {
+        self.pending_agent_prompts.clone()
     }
 
     /// Sends an interactive prompt to the user and waits for a response.
@@ -348,6 +416,25 @@ impl AgentMode {
     }
 }
 
+/// Delivers a user's response to a prompt waiting for it.
+///
+/// # Arguments
+///
+/// * `pending_prompts` - The prompts waiting for the user, from `AgentMode::pending_prompts`.
+/// * `prompt_id` - The prompt answered.
+/// * `response` - The user's answer.
+pub async fn respond_to_prompt(pending_prompts: &PendingPrompts, prompt_id: String, response: String) -> Result<()> {
+    let tx = pending_prompts.lock().await.remove(&prompt_id);
+    if let Some(tx) = tx {
+        info!("Received response for agent prompt {}: {}", prompt_id, response);
+        tx.send(response).await.map_err(|e| anyhow!("Failed to send response to agent prompt channel: {}", e))?;
+        Ok(())
+    } else {
+        error!("No pending prompt found for ID: {}", prompt_id);
+        Err(anyhow!("No pending prompt found for ID: {}", prompt_id))
+    }
+}
+
 /// Initializes the `agent_mode_eval` module.
 pub fn init() {
     info!("agent_mode_eval module loaded");
@@ -355,3 +442,51 @@ pub fn init() {
 
 // Alias ChatMessage and ToolCall from crate::ai to avoid conflicts
 use crate::ai::{ChatMessage as ProviderChatMessage, ToolCall as AiToolCall};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::ToolFunction;
+
+    fn execute_command(command: &str) -> AiToolCall {
+        AiToolCall {
+            id: "call-1".to_string(),
+            type_: "function".to_string(),
+            function: ToolFunction { name: "execute_command".to_string(), arguments: serde_json::json!({ "command": command }) },
+        }
+    }
+
+    /// Runs the confirmation of `command` as the agent does, answering it with `response`
+    /// the way the application does.
+    ///
+    /// # Returns
+    ///
+    /// The tool result instead of running the command, and whether the user was asked.
+    async fn confirm(command: &str, response: Option<&str>) -> (Option<String>, bool) {
+        let pending_prompts = PendingPrompts::default();
+        let (sender, mut receiver) = mpsc::channel(10);
+        let confirmation = tokio::spawn({
+            let pending_prompts = pending_prompts.clone();
+            let tool_call = execute_command(command);
+            async move { AgentMode::confirm_risky_command(&tool_call, None, &pending_prompts, &sender).await }
+        });
+        let mut asked = false;
+        while let Some(message) = receiver.recv().await {
+            if let AgentMessage::CommandConfirmationRequest { prompt_id, .. } = message {
+                asked = true;
+                respond_to_prompt(&pending_prompts, prompt_id, response.unwrap().to_string()).await.unwrap();
+            }
+        }
+        (confirmation.await.unwrap(), asked)
+    }
+
+    #[tokio::test]
+    async fn test_risky_command_confirmation() {
+        assert_eq!(confirm("ls -la", None).await, (None, false));
+        assert_eq!(confirm("rm -rf /", Some(CONFIRM_COMMAND)).await, (None, true));
+        let (declined, asked) = confirm("rm -rf /", Some("abort")).await;
+        assert!(asked);
+        assert_eq!(declined.as_deref(), Some("The user declined to run the command: Recursively deletes the root directory"));
+        assert!(respond_to_prompt(&PendingPrompts::default(), "gone".to_string(), CONFIRM_COMMAND.to_string()).await.is_err());
+    }
+}
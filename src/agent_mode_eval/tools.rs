@@ -1,6 +1,8 @@
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 use crate::ai::assistant::Tool; // Import the Tool trait
 use crate::virtual_fs::VirtualFileSystem;
 use crate::command::CommandManager;
@@ -102,14 +104,25 @@ impl Tool for WriteFileTool {
     }
 }
 
+/// Where the `execute_command` tool runs commands: the shell of the pane the agent works in.
+#[derive(Debug, Clone, Default)]
+pub struct CommandContext {
+    /// The shell's working directory, which the linter also resolved the command's paths against.
+    pub working_dir: Option<PathBuf>,
+    /// Variables of the pane's environment profile.
+    pub env: HashMap<String, String>,
+}
+
 /// Tool for executing a shell command.
 pub struct ExecuteCommandTool {
     command_manager: Arc<CommandManager>,
+    /// Shared with the `Assistant`, which updates it for every conversation turn.
+    context: Arc<RwLock<CommandContext>>,
 }
 
 impl ExecuteCommandTool {
-    pub fn new(command_manager: Arc<CommandManager>) -> Self {
-        Self { command_manager }
+    pub fn new(command_manager: Arc<CommandManager>, context: Arc<RwLock<CommandContext>>) -> Self {
+        Self { command_manager, context }
     }
 }
 
@@ -128,24 +141,27 @@ impl Tool for ExecuteCommandTool {
         let command_str = args["command"].as_str().ok_or(anyhow!("Missing 'command' argument for execute_command"))?;
         
         info!("Executing command: {}", command_str);
-        let parts: Vec<&str> = command_str.split_whitespace().collect();
-        if parts.is_empty() {
+        if command_str.trim().is_empty() {
             return Err(anyhow!("No command provided to execute_command"));
         }
-        let cmd_executable = parts[0].to_string();
-        let cmd_args = parts[1..].iter().map(|s| s.to_string()).collect();
+        // The command is shell syntax, as the linter checked it: quotes, pipes and all.
+        let (cmd_executable, shell_flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+        let cmd_args = vec![shell_flag.to_string(), command_str.to_string()];
+        let context = self.context.read().unwrap().clone();
 
         let (tx, mut rx) = mpsc::channel(100); // Channel to capture command output
 
         let cmd_obj = crate::command::Command {
             id: uuid::Uuid::new_v4().to_string(),
-            name: cmd_executable.clone(),
+            name: command_str.to_string(),
             description: format!("Tool executed: {}", command_str),
-            executable: cmd_executable,
+            executable: cmd_executable.to_string(),
             args: cmd_args,
-            env: std::collections::HashMap::new(),
-            working_dir: None,
+            env: context.env,
+            working_dir: context.working_dir,
             output_format: crate::command::CommandOutputFormat::PlainText,
+            // The tool reports stdout and stderr separately, which needs pipes rather than a PTY.
+            execution_mode: crate::command::ExecutionMode::Piped,
         };
 
         self.command_manager.execute_command_with_output_channel(cmd_obj, tx).await?;
@@ -174,6 +190,11 @@ impl Tool for ExecuteCommandTool {
                 crate::command::pty::CommandStatus::Killed => {
                     return Err(anyhow!("Command was killed."));
                 }
+                crate::command::pty::CommandStatus::Signaled(signal) => {
+                    return Err(anyhow!("Command was {}.", crate::command::CommandStatus::Signaled(signal)));
+                }
+                // A stopped command may still be continued from its block.
+                crate::command::pty::CommandStatus::Stopped(_) => {}
             }
         }
 
@@ -18,6 +18,7 @@ use crate::plugins::plugin_manager::PluginManager;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use crate::block::{Block, BlockContent};
+use crate::agent_mode_eval::tools::CommandContext;
 use log::{info, error}; // Import error macro
 
 /// Trait defining the interface for an AI tool.
@@ -77,6 +78,8 @@ pub struct Assistant {
    local_only_ai_mode: bool,
    pub tool_manager: Arc<Mutex<ToolManager>>, // Corrected to tokio::sync::Mutex
    ai_context: Arc<tokio::sync::RwLock<AIContext>>,
+   /// Where the `execute_command` tool runs commands.
+   command_context: Arc<std::sync::RwLock<CommandContext>>,
 }
 
 impl Assistant {
@@ -130,12 +133,13 @@ impl Assistant {
            None => None,
        };
 
+       let command_context = Arc::new(std::sync::RwLock::new(CommandContext::default()));
        let mut tool_manager = ToolManager::new();
        // Register concrete tools
        tool_manager.register_tool(Box::new(crate::agent_mode_eval::tools::ListFilesTool::new(virtual_file_system.clone())));
        tool_manager.register_tool(Box::new(crate::agent_mode_eval::tools::ReadFileTool::new(virtual_file_system.clone())));
        tool_manager.register_tool(Box::new(crate::agent_mode_eval::tools::WriteFileTool::new(virtual_file_system.clone())));
-       tool_manager.register_tool(Box::new(crate::agent_mode_eval::tools::ExecuteCommandTool::new(command_manager.clone())));
+       tool_manager.register_tool(Box::new(crate::agent_mode_eval::tools::ExecuteCommandTool::new(command_manager.clone(), command_context.clone())));
        tool_manager.register_tool(Box::new(crate::agent_mode_eval::tools::ChangeDirectoryTool::new(virtual_file_system.clone())));
 
        Ok(Self {
@@ -149,9 +153,16 @@ impl Assistant {
            local_only_ai_mode,
            tool_manager: Arc::new(Mutex::new(tool_manager)), // Wrap in tokio::sync::Mutex
            ai_context,
+           command_context,
        })
    }
 
+   /// Sets the directory and the environment profile variables that commands of the
+   /// `execute_command` tool run with.
+   pub fn set_command_context(&self, context: CommandContext) {
+       *self.command_context.write().unwrap() = context;
+   }
+
    /// Streams a chat conversation with the AI. This is for general chat, not the agent loop.
    ///
    /// # Arguments
@@ -0,0 +1,606 @@
+//! Recording and playback of terminal sessions in the asciicast v2 format.
+//!
+//! A cast file starts with a JSON header line, followed by one JSON array per event:
+//! `[seconds, "o", "output"]`, `[seconds, "i", "input"]` or `[seconds, "r", "80x24"]`.
+//! Files written here can be played with `asciinema play` and vice versa.
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use vte::Parser;
+
+use crate::terminal::TerminalScreen;
+
+/// The first line of a cast file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    pub width: u16,
+    pub height: u16,
+    /// Start of the recording as a Unix timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Captured environment, usually `SHELL` and `TERM`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+}
+
+/// Type of a recorded event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// Data written to the terminal.
+    Output,
+    /// Data typed by the user.
+    Input,
+    /// The terminal was resized; the data is `COLSxROWS`.
+    Resize,
+    /// A marker (e.g. a chapter) set during recording.
+    Marker,
+}
+
+impl EventKind {
+    /// Returns the code used in cast files.
+    pub fn code(self) -> &'static str {
+        match self {
+            EventKind::Output => "o",
+            EventKind::Input => "i",
+            EventKind::Resize => "r",
+            EventKind::Marker => "m",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "o" => Some(EventKind::Output),
+            "i" => Some(EventKind::Input),
+            "r" => Some(EventKind::Resize),
+            "m" => Some(EventKind::Marker),
+            _ => None,
+        }
+    }
+}
+
+/// A single event: its time in seconds since the start of the recording, type and data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub time: f64,
+    pub kind: EventKind,
+    pub data: String,
+}
+
+impl Event {
+    /// Serializes the event as a cast file line, without the newline.
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(&(self.time, self.kind.code(), &self.data)).expect("events serialize")
+    }
+
+    /// Parses a cast file line. Returns `Ok(None)` for event types this version doesn't know.
+    pub fn from_line(line: &str) -> Result<Option<Self>> {
+        let (time, code, data): (f64, String, String) = serde_json::from_str(line)?;
+        Ok(EventKind::from_code(&code).map(|kind| Event { time, kind, data }))
+    }
+
+    /// Returns the new size of a resize event as `(cols, rows)`.
+    pub fn size(&self) -> Option<(u16, u16)> {
+        let (cols, rows) = self.data.split_once('x')?;
+        Some((cols.trim().parse().ok()?, rows.trim().parse().ok()?))
+    }
+}
+
+/// A loaded recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cast {
+    pub header: Header,
+    pub events: Vec<Event>,
+}
+
+impl Cast {
+    /// Parses the contents of a cast file.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+        let header_line = lines.next().ok_or_else(|| anyhow!("The recording is empty."))?;
+        let header: Header = serde_json::from_str(header_line).context("Invalid asciicast header")?;
+        if header.version != 2 {
+            return Err(anyhow!("Unsupported asciicast version {} (only version 2 is supported).", header.version));
+        }
+        let mut events = Vec::new();
+        for (index, line) in lines.enumerate() {
+            match Event::from_line(line) {
+                Ok(Some(event)) => events.push(event),
+                Ok(None) => {}
+                Err(e) => return Err(anyhow!("Invalid event on line {}: {}", index + 2, e)),
+            }
+        }
+        Ok(Self { header, events })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).with_context(|| format!("Failed to read recording {:?}", path))?;
+        Self::parse(&contents)
+    }
+
+    /// Returns the time of the last event.
+    pub fn duration(&self) -> f64 {
+        self.events.last().map(|event| event.time).unwrap_or(0.0)
+    }
+}
+
+/// Writes the output of a PTY, with timing and resizes, to a cast file.
+///
+/// The recorder is shared between the tasks reading and writing a PTY. Write errors are
+/// logged rather than returned, so a full disk never interrupts the session itself.
+pub struct Recorder {
+    path: PathBuf,
+    started: Instant,
+    state: Mutex<RecorderState>,
+}
+
+struct RecorderState {
+    writer: LineWriter<File>,
+    /// Trailing bytes of a UTF-8 character split across two output chunks.
+    pending_output: Vec<u8>,
+    failed: bool,
+}
+
+impl Recorder {
+    /// Creates the cast file at `path` and writes its header.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Where to write the recording. Parent directories are created.
+    /// * `cols`, `rows` - The terminal size at the start of the recording.
+    /// * `title` - An optional title stored in the header.
+    pub fn create(path: &Path, cols: u16, rows: u16, title: Option<String>) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+        }
+        let file = File::create(path).with_context(|| format!("Failed to create recording {:?}", path))?;
+        let mut writer = LineWriter::new(file);
+        let env = ["SHELL", "TERM"]
+            .iter()
+            .filter_map(|key| std::env::var(key).ok().map(|value| (key.to_string(), value)))
+            .collect();
+        let header = Header {
+            version: 2,
+            width: cols,
+            height: rows,
+            timestamp: Some(chrono::Utc::now().timestamp()),
+            title,
+            env,
+        };
+        writeln!(writer, "{}", serde_json::to_string(&header)?)?;
+        info!("Recording terminal session to {:?}", path);
+        Ok(Self {
+            path: path.to_path_buf(),
+            started: Instant::now(),
+            state: Mutex::new(RecorderState { writer, pending_output: Vec::new(), failed: false }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records output written to the terminal.
+    pub fn output(&self, bytes: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.pending_output.extend_from_slice(bytes);
+        let data = take_complete_utf8(&mut state.pending_output);
+        if !data.is_empty() {
+            self.write_event(&mut state, EventKind::Output, data);
+        }
+    }
+
+    /// Records input typed by the user. Callers must leave out input typed while the
+    /// terminal is reading a secret; see `is_reading_secret`.
+    pub fn input(&self, bytes: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        self.write_event(&mut state, EventKind::Input, String::from_utf8_lossy(bytes).into_owned());
+    }
+
+    /// Records a terminal resize.
+    pub fn resize(&self, cols: u16, rows: u16) {
+        let mut state = self.state.lock().unwrap();
+        self.write_event(&mut state, EventKind::Resize, format!("{}x{}", cols, rows));
+    }
+
+    /// Records a marker, e.g. the start of a command.
+    pub fn marker(&self, label: &str) {
+        let mut state = self.state.lock().unwrap();
+        self.write_event(&mut state, EventKind::Marker, label.to_string());
+    }
+
+    /// Writes any buffered output and flushes the file.
+    pub fn finish(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.pending_output.is_empty() {
+            let data = String::from_utf8_lossy(&std::mem::take(&mut state.pending_output)).into_owned();
+            self.write_event(&mut state, EventKind::Output, data);
+        }
+        state.writer.flush()?;
+        info!("Finished recording {:?}", self.path);
+        Ok(())
+    }
+
+    fn write_event(&self, state: &mut RecorderState, kind: EventKind, data: String) {
+        if state.failed {
+            return;
+        }
+        let event = Event { time: self.started.elapsed().as_secs_f64(), kind, data };
+        if let Err(e) = writeln!(state.writer, "{}", event.to_line()) {
+            warn!("Stopped writing recording {:?}: {}", self.path, e);
+            state.failed = true;
+        }
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder").field("path", &self.path).finish_non_exhaustive()
+    }
+}
+
+/// Removes and returns the complete UTF-8 text at the start of `bytes`, keeping a character
+/// cut off at the end for the next chunk. Invalid bytes are replaced.
+fn take_complete_utf8(bytes: &mut Vec<u8>) -> String {
+    let mut text = String::new();
+    let mut rest = &bytes[..];
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                rest = &[];
+                break;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                match e.error_len() {
+                    Some(len) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        rest = &after[len..];
+                    }
+                    // The input ends in the middle of a character.
+                    None => {
+                        rest = after;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    *bytes = rest.to_vec();
+    text
+}
+
+/// Returns true if the terminal behind `fd` is reading a secret such as a password:
+/// echo is off while input is still line-buffered, which is what `getpass` and `sudo` set.
+/// Line editors like readline also turn echo off, but they switch to raw mode and echo
+/// keys themselves.
+#[cfg(unix)]
+pub fn is_reading_secret(fd: std::os::unix::io::RawFd) -> bool {
+    // SAFETY: `termios` is plain data, and `tcgetattr` only writes to it.
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        // If the mode can't be read, assume the worst.
+        return true;
+    }
+    termios.c_lflag & libc::ECHO == 0 && termios.c_lflag & libc::ICANON != 0
+}
+
+/// Replays a recording into a terminal screen, with play/pause, seeking and speed control.
+pub struct Player {
+    cast: Cast,
+    /// Position in the recording, in seconds.
+    position: f64,
+    speed: f64,
+    /// When playback last advanced, while playing.
+    last_tick: Option<Instant>,
+    screen: TerminalScreen,
+    parser: Parser,
+    /// Index of the first event not applied to the screen yet.
+    next_event: usize,
+}
+
+impl Player {
+    /// Speeds offered in the playback controls.
+    pub const SPEEDS: [f64; 5] = [0.5, 1.0, 2.0, 4.0, 8.0];
+
+    pub fn new(cast: Cast) -> Self {
+        let screen = TerminalScreen::new(cast.header.height.max(1) as usize, cast.header.width.max(1) as usize);
+        Self {
+            cast,
+            position: 0.0,
+            speed: 1.0,
+            last_tick: None,
+            screen,
+            parser: Parser::new(),
+            next_event: 0,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::new(Cast::load(path)?))
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.cast.header
+    }
+
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.cast.duration()
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.last_tick.is_some()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.duration()
+    }
+
+    /// Starts playing, from the beginning if the end was reached.
+    pub fn play(&mut self, now: Instant) {
+        if self.is_finished() {
+            self.seek(0.0);
+        }
+        self.last_tick = Some(now);
+    }
+
+    pub fn pause(&mut self) {
+        self.last_tick = None;
+    }
+
+    pub fn toggle(&mut self, now: Instant) {
+        if self.is_playing() {
+            self.pause();
+        } else {
+            self.play(now);
+        }
+    }
+
+    /// Sets the playback speed, limited to 0.1x–16x.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(0.1, 16.0);
+    }
+
+    /// Advances playback to `now`. Playback pauses at the end of the recording.
+    pub fn tick(&mut self, now: Instant) {
+        let Some(last) = self.last_tick else {
+            return;
+        };
+        let elapsed = now.saturating_duration_since(last).as_secs_f64() * self.speed;
+        self.last_tick = Some(now);
+        self.advance_to(self.position + elapsed);
+        if self.is_finished() {
+            self.pause();
+        }
+    }
+
+    /// Advances playback by `elapsed` of recording time, whether playing or not.
+    pub fn step(&mut self, elapsed: Duration) {
+        self.advance_to(self.position + elapsed.as_secs_f64());
+    }
+
+    /// Jumps to `position` seconds. Seeking backwards replays the recording from the start.
+    pub fn seek(&mut self, position: f64) {
+        let position = position.clamp(0.0, self.duration());
+        if position < self.position {
+            let header = &self.cast.header;
+            self.screen = TerminalScreen::new(header.height.max(1) as usize, header.width.max(1) as usize);
+            self.parser = Parser::new();
+            self.next_event = 0;
+        }
+        self.advance_to(position);
+    }
+
+    /// Returns the visible lines of the replayed screen.
+    pub fn visible_lines(&self) -> Vec<String> {
+        self.screen.visible_lines()
+    }
+
+    fn advance_to(&mut self, position: f64) {
+        self.position = position.min(self.duration());
+        while let Some(event) = self.cast.events.get(self.next_event) {
+            if event.time > self.position {
+                break;
+            }
+            match event.kind {
+                EventKind::Output => self.screen.advance(&mut self.parser, event.data.as_bytes()),
+                EventKind::Resize => {
+                    if let Some((cols, rows)) = event.size() {
+                        self.screen.resize(rows.max(1) as usize, cols.max(1) as usize);
+                    }
+                }
+                EventKind::Input | EventKind::Marker => {}
+            }
+            self.next_event += 1;
+        }
+    }
+}
+
+impl fmt::Debug for Player {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Player")
+            .field("position", &self.position)
+            .field("duration", &self.duration())
+            .field("speed", &self.speed)
+            .field("playing", &self.is_playing())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Formats a position in a recording as `m:ss`.
+pub fn format_time(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Returns a path for a new recording in `dir`, e.g. `2024-05-01T10-15-00-build.cast`.
+/// A number is appended if a recording with that name already exists.
+pub fn recording_path(dir: &Path, label: &str) -> PathBuf {
+    let label: String = label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    let stem = format!("{}-{}", chrono::Local::now().format("%Y-%m-%dT%H-%M-%S"), label);
+    let mut path = dir.join(format!("{}.cast", stem));
+    let mut counter = 2;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.cast", stem, counter));
+        counter += 1;
+    }
+    path
+}
+
+/// Initializes the asciicast module.
+pub fn init() {
+    info!("asciicast module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_lines() {
+        let event = Event { time: 1.5, kind: EventKind::Output, data: "hi\r\n\u{1b}[1m".to_string() };
+        let line = event.to_line();
+        assert_eq!(line, r#"[1.5,"o","hi\r\n\u001b[1m"]"#);
+        assert_eq!(Event::from_line(&line).unwrap(), Some(event));
+        assert_eq!(Event::from_line(r#"[2.0,"x","future event type"]"#).unwrap(), None);
+        let resize = Event::from_line(r#"[3.0,"r","100x30"]"#).unwrap().unwrap();
+        assert_eq!(resize.size(), Some((100, 30)));
+    }
+
+    #[test]
+    fn test_split_utf8_output() {
+        let mut pending = "é".as_bytes()[..1].to_vec();
+        assert_eq!(take_complete_utf8(&mut pending), "");
+        pending.extend_from_slice(&"é".as_bytes()[1..]);
+        pending.extend_from_slice(b"!");
+        assert_eq!(take_complete_utf8(&mut pending), "é!");
+        assert!(pending.is_empty());
+
+        let mut invalid = vec![b'a', 0xff, b'b', 0xc3];
+        assert_eq!(take_complete_utf8(&mut invalid), "a\u{fffd}b");
+        assert_eq!(invalid, vec![0xc3]);
+    }
+
+    #[test]
+    fn test_record_and_play() {
+        let path = std::env::temp_dir().join(format!("neoterm-{}.cast", uuid::Uuid::new_v4()));
+        let recorder = Recorder::create(&path, 20, 4, Some("test".to_string())).unwrap();
+        recorder.output(b"hello\r\n");
+        recorder.input(b"ls\r");
+        recorder.resize(30, 5);
+        recorder.output(b"w\xc3");
+        recorder.output(&[0xa9]); // second half of "é"
+        recorder.output(b"rld");
+        recorder.finish().unwrap();
+
+        let cast = Cast::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cast.header.width, 20);
+        assert_eq!(cast.header.title.as_deref(), Some("test"));
+        let kinds: Vec<EventKind> = cast.events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![EventKind::Output, EventKind::Input, EventKind::Resize, EventKind::Output, EventKind::Output, EventKind::Output]);
+        assert_eq!(cast.events[4].data, "é");
+
+        let mut player = Player::new(cast);
+        player.seek(player.duration());
+        assert!(player.is_finished());
+        assert_eq!(player.visible_lines()[0].trim_end(), "hello");
+        assert_eq!(player.visible_lines()[1].trim_end(), "wérld");
+        assert_eq!(player.visible_lines().len(), 5);
+    }
+
+    #[test]
+    fn test_player_seek_and_speed() {
+        let cast = Cast::parse(concat!(
+            r#"{"version":2,"width":10,"height":2}"#, "\n",
+            r#"[0.5,"o","one"]"#, "\n",
+            r#"[2.0,"o","\r\ntwo"]"#, "\n",
+        )).unwrap();
+        let mut player = Player::new(cast);
+        player.step(Duration::from_secs(1));
+        assert_eq!(player.visible_lines(), vec!["one".to_string(), String::new()]);
+        player.seek(2.0);
+        assert_eq!(player.visible_lines()[1].trim_end(), "two");
+        // Seeking back rebuilds the screen.
+        player.seek(0.0);
+        assert!(player.visible_lines().iter().all(|line| line.trim().is_empty()));
+
+        let start = Instant::now();
+        player.set_speed(4.0);
+        player.play(start);
+        player.tick(start + Duration::from_millis(250));
+        assert_eq!(player.position(), 1.0);
+        player.tick(start + Duration::from_secs(2));
+        assert!(player.is_finished());
+        assert!(!player.is_playing());
+        assert_eq!(format_time(75.2), "1:15");
+    }
+
+    #[test]
+    fn test_recording_path() {
+        let dir = std::env::temp_dir().join(format!("neoterm-recordings-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = recording_path(&dir, "my app");
+        assert!(first.to_str().unwrap().ends_with("-my_app.cast"));
+        std::fs::write(&first, "").unwrap();
+        let second = recording_path(&dir, "my app");
+        assert_ne!(first, second);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        assert!(Cast::parse(r#"{"version":1,"width":80,"height":24}"#).is_err());
+        assert!(Cast::parse("").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_secret_input_detection() {
+        let (mut master, mut slave) = (0, 0);
+        // SAFETY: openpty writes the two descriptors, which are closed below.
+        let result = unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), std::ptr::null()) };
+        assert_eq!(result, 0);
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        unsafe { libc::tcgetattr(slave, &mut termios) };
+        assert!(!is_reading_secret(master));
+
+        // What getpass() does: echo off, still line-buffered.
+        termios.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(slave, libc::TCSANOW, &termios) };
+        assert!(is_reading_secret(master));
+
+        // What readline does: raw mode without echo.
+        termios.c_lflag &= !libc::ICANON;
+        unsafe { libc::tcsetattr(slave, libc::TCSANOW, &termios) };
+        assert!(!is_reading_secret(master));
+
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+}
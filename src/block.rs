@@ -1,24 +1,41 @@
 use iced::{
-    widget::{column, container, row, text, button, scrollable, text_input},
+    widget::{column, container, row, text, button, checkbox, mouse_area, pick_list, scrollable, slider, text_input},
     Element, Length, Color, alignment,
 };
 use uuid::Uuid;
 use chrono::{DateTime, Local, Duration};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use crate::asciicast::{self, Player};
+use crate::clipboard::CopyScope;
+use crate::command::{jobs, CommandStats, JobSignal};
+use crate::links::{self, LinkSpan};
+use crate::output_filter::{self, BlockFilter, FilterAction, FilteredLine, OutputFilter};
+use crate::scrollback::{OutputLine, Scrollback};
+use crate::terminal::images::{CELL_HEIGHT, CELL_WIDTH};
+use crate::terminal::{InlineImage, ScreenView, TermColor};
+use crate::search::{self, Highlight, MatchField, SearchMode, SearchResults};
 use crate::workflows::Workflow;
-use log::info;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
 
 /// Represents the content type of a UI block in the Iced GUI.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BlockContent {
     /// Represents a command execution block with input, output, status, and error state.
     Command {
         input: String,
-        output: Vec<(String, bool)>, // (content, is_stdout)
+        output: Scrollback, // (content, is_stdout) lines, older ones spilled to disk
         status: String,
         error: bool,
         start_time: DateTime<Local>,
         end_time: Option<DateTime<Local>>,
         working_directory: Option<String>, // New field for working directory
+        /// Wall time, terminating signal and resource usage, once the command has finished.
+        stats: Option<CommandStats>,
+        /// Environment profile the command ran under.
+        #[serde(default)]
+        profile: Option<String>,
     },
     /// Represents a message from the AI agent or the user.
     AgentMessage {
@@ -53,96 +70,231 @@ pub enum BlockContent {
         name: String,
         arguments: String, // Accumulate arguments as a string
     },
+    /// Read-only replay of an asciicast recording.
+    Playback {
+        path: String,
+        /// Loaded from `path`; reloaded when a saved session is restored.
+        #[serde(skip)]
+        player: Option<Arc<Mutex<Player>>>,
+    },
     // Add other block types as needed (e.g., Code, Image, Workflow)
 }
 
 /// Represents a UI block in the Iced GUI.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub id: String,
     pub content: BlockContent,
     pub collapsed: bool,
     pub status: Option<String>, // For streaming updates
+    #[serde(default, with = "color_serde")]
     pub background_color: Option<Color>, // New field for custom background color
+    /// Page of earlier output being viewed instead of the latest lines, read back from the scrollback.
+    #[serde(skip)]
+    pub output_page: Option<OutputPage>,
+    /// Rendered process tree of a running command, shown while the user has it open.
+    #[serde(skip)]
+    pub process_tree: Option<String>,
+    /// Search matches to highlight in the block.
+    #[serde(skip)]
+    pub highlights: Vec<Highlight>,
+    /// Filter bar of a command block, while it is open.
+    #[serde(skip)]
+    pub filter: Option<BlockFilter>,
+    /// OSC 8 hyperlinks in a command block's output, by output line. Only lines still in
+    /// memory keep theirs; spilled lines show the URLs detected in their text.
+    #[serde(default)]
+    pub hyperlinks: BTreeMap<usize, Vec<LinkSpan>>,
+    /// Inline images in a command block's output.
+    #[serde(skip)]
+    pub images: OutputImages,
+    /// Selected range of output lines, as the first and last line clicked.
+    #[serde(skip)]
+    pub selection: Option<(usize, usize)>,
+    /// Clipboard selection the command asked to read through OSC 52, waiting for the user's answer.
+    #[serde(skip)]
+    pub clipboard_query: Option<String>,
+    /// Screen of a running command that draws with cursor addressing or on the alternate screen.
+    #[serde(skip)]
+    pub screen: Option<LiveScreen>,
+}
+
+/// Stores an optional `iced::Color` as `[r, g, b, a]`.
+mod color_serde {
+    use iced::Color;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(color: &Option<Color>, serializer: S) -> Result<S::Ok, S::Error> {
+        color.map(|c| [c.r, c.g, c.b, c.a]).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Color>, D::Error> {
+        Ok(Option::<[f32; 4]>::deserialize(deserializer)?.map(|[r, g, b, a]| Color { r, g, b, a }))
+    }
+}
+
+/// A range of a command block's output loaded for scrolling back.
+#[derive(Debug, Clone)]
+pub struct OutputPage {
+    /// Index of the first line of the page within the block's output.
+    pub start: usize,
+    pub lines: Vec<OutputLine>,
+}
+
+/// What a running command drew on the screen, shown in its block until it ends.
+#[derive(Debug, Clone)]
+pub struct LiveScreen {
+    pub view: ScreenView,
+    /// Number of the block's last output lines the command drew over; they are hidden meanwhile.
+    pub replaces: usize,
+}
+
+/// Default memory budget for the images of one block.
+const DEFAULT_IMAGE_MEMORY: usize = 64 * 1024 * 1024;
+
+/// An inline image in a command block's output.
+#[derive(Debug, Clone)]
+pub struct OutputImage {
+    /// Number of output lines before the image.
+    pub line: usize,
+    pub image: InlineImage,
+    /// Created once, so the renderer can cache the uploaded texture.
+    pub handle: iced::widget::image::Handle,
+}
+
+/// The inline images of a command block, kept within a memory budget by dropping the oldest.
+#[derive(Debug, Clone)]
+pub struct OutputImages {
+    images: VecDeque<OutputImage>,
+    bytes: usize,
+    limit: usize,
+    /// Number of images dropped to stay within `limit`.
+    dropped: usize,
+}
+
+impl Default for OutputImages {
+    fn default() -> Self {
+        Self { images: VecDeque::new(), bytes: 0, limit: DEFAULT_IMAGE_MEMORY, dropped: 0 }
+    }
+}
+
+impl OutputImages {
+    /// Sets the memory budget in bytes; 0 disables inline images.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.shrink_to(limit);
+    }
+
+    pub fn push(&mut self, line: usize, image: InlineImage) {
+        if image.bytes() > self.limit {
+            self.dropped += 1;
+            return;
+        }
+        self.shrink_to(self.limit - image.bytes());
+        self.bytes += image.bytes();
+        let handle = iced::widget::image::Handle::from_pixels(image.width, image.height, image.rgba.to_vec());
+        self.images.push_back(OutputImage { line, image, handle });
+    }
+
+    fn shrink_to(&mut self, bytes: usize) {
+        while self.bytes > bytes {
+            let Some(oldest) = self.images.pop_front() else {
+                break;
+            };
+            self.bytes -= oldest.image.bytes();
+            self.dropped += 1;
+        }
+    }
+
+    /// Returns the images shown before output line `line`.
+    pub fn at(&self, line: usize) -> impl Iterator<Item = &OutputImage> {
+        self.images.iter().filter(move |image| image.line == line)
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+/// Removes the hyperlinks of output lines before `spilled`, which are no longer kept in memory.
+fn forget_spilled_links(hyperlinks: &mut BTreeMap<usize, Vec<LinkSpan>>, spilled: usize) {
+    while let Some(entry) = hyperlinks.first_entry() {
+        if *entry.key() >= spilled {
+            break;
+        }
+        entry.remove();
+    }
 }
 
 impl Block {
     /// Creates a new command block.
     pub fn new_command(input: String, working_directory: Option<String>) -> Self {
-        Self {
-            id: Uuid::new_v4().to_string(),
-            content: BlockContent::Command {
+        Self::with_content(
+            BlockContent::Command {
                 input,
-                output: Vec::new(),
+                output: Scrollback::default(),
                 status: "Running...".to_string(),
                 error: false,
                 start_time: Local::now(),
                 end_time: None,
                 working_directory,
+                stats: None,
+                profile: None,
             },
-            collapsed: false,
-            status: Some("Running...".to_string()),
-            background_color: None, // Default to no custom background
-        }
+            Some("Running...".to_string()),
+            None,
+        )
     }
 
     /// Creates a new agent message block (from the AI).
     pub fn new_agent_message(content: String) -> Self {
-        Self {
-            id: Uuid::new_v4().to_string(),
-            content: BlockContent::AgentMessage {
+        Self::with_content(
+            BlockContent::AgentMessage {
                 content,
                 is_user: false,
                 timestamp: Local::now(),
             },
-            collapsed: false,
-            status: None, // Status will be set during streaming
-            background_color: None,
-        }
+            None,
+            None,
+        )
     }
 
     /// Creates a new user message block (sent by the user to the AI).
     pub fn new_user_message(content: String) -> Self {
-        Self {
-            id: Uuid::new_v4().to_string(),
-            content: BlockContent::AgentMessage {
+        Self::with_content(
+            BlockContent::AgentMessage {
                 content,
                 is_user: true,
                 timestamp: Local::now(),
             },
-            collapsed: false,
-            status: None,
-            background_color: None,
-        }
+            None,
+            None,
+        )
     }
 
     /// Creates a new informational block.
     pub fn new_info(title: String, message: String) -> Self {
-        Self {
-            id: Uuid::new_v4().to_string(),
-            content: BlockContent::Info {
+        Self::with_content(
+            BlockContent::Info {
                 title,
                 message,
                 timestamp: Local::now(),
             },
-            collapsed: false,
-            status: None,
-            background_color: None,
-        }
+            None,
+            None,
+        )
     }
 
     /// Creates a new error block.
     pub fn new_error(message: String) -> Self {
-        Self {
-            id: Uuid::new_v4().to_string(),
-            content: BlockContent::Error {
+        Self::with_content(
+            BlockContent::Error {
                 message,
                 timestamp: Local::now(),
             },
-            collapsed: false,
-            status: Some("Error".to_string()),
-            background_color: None,
-        }
+            Some("Error".to_string()),
+            None,
+        )
     }
 
     /// Creates a new output block (typically for initial output display).
@@ -150,68 +302,516 @@ impl Block {
     pub fn new_output(initial_output: String) -> Self {
         let mut block = Self::new_command("".to_string(), None); // Use command block for output
         if let BlockContent::Command { output, .. } = &mut block.content {
-            output.push((initial_output, true));
+            output.push(initial_output, true);
         }
         block
     }
 
     /// Creates a new workflow suggestion block.
     pub fn new_workflow_suggestion(workflow: Workflow) -> Self {
-        Self {
-            id: Uuid::new_v4().to_string(),
-            content: BlockContent::WorkflowSuggestion { workflow },
-            collapsed: false,
-            status: Some("Suggested Workflow".to_string()),
-            background_color: None,
-        }
+        Self::with_content(
+            BlockContent::WorkflowSuggestion { workflow },
+            Some("Suggested Workflow".to_string()),
+            None,
+        )
     }
 
     /// Creates a new agent prompt block, requiring user input.
     pub fn new_agent_prompt(prompt_id: String, message: String) -> Self {
-        Self {
-            id: Uuid::new_v4().to_string(),
-            content: BlockContent::AgentPrompt {
+        Self::with_content(
+            BlockContent::AgentPrompt {
                 prompt_id,
                 message,
                 input_value: String::new(),
             },
-            collapsed: false,
-            status: Some("Agent Input Required".to_string()),
-            background_color: None,
-        }
+            Some("Agent Input Required".to_string()),
+            None,
+        )
     }
 
     /// Creates a new streaming tool call block.
     /// The `block_id` is a new UUID for the UI element, while `tool_call_id` is the AI's ID for the tool call.
     pub fn new_streaming_tool_call(tool_call_id: String, name: String, initial_arguments: String) -> Self {
-        Self {
-            id: Uuid::new_v4().to_string(), // Use a new block ID for the UI block
-            content: BlockContent::StreamingToolCall {
+        Self::with_content(
+            BlockContent::StreamingToolCall {
                 id: tool_call_id, // This is the tool_call_id from the AI
                 name,
                 arguments: initial_arguments,
             },
-            collapsed: false,
-            status: Some("Streaming Tool Call...".to_string()),
-            background_color: None,
+            Some("Streaming Tool Call...".to_string()),
+            None,
+        )
+    }
+
+    /// Creates a block that replays the asciicast recording at `path`.
+    pub fn new_playback(path: String) -> anyhow::Result<Self> {
+        let player = Player::load(std::path::Path::new(&path))?;
+        Ok(Self::with_content(
+            BlockContent::Playback { path, player: Some(Arc::new(Mutex::new(player))) },
+            Some("Paused".to_string()),
+            None,
+        ))
+    }
+
+    /// Returns the player of a playback block.
+    pub fn player(&self) -> Option<Arc<Mutex<Player>>> {
+        match &self.content {
+            BlockContent::Playback { player, .. } => player.clone(),
+            _ => None,
+        }
+    }
+
+    /// Loads the recording of a playback block restored from a saved session.
+    pub fn reload_player(&mut self) {
+        if let BlockContent::Playback { path, player } = &mut self.content {
+            match Player::load(std::path::Path::new(path)) {
+                Ok(loaded) => *player = Some(Arc::new(Mutex::new(loaded))),
+                Err(e) => error!("Failed to load recording {}: {}", path, e),
+            }
         }
     }
 
     /// Creates a new block with a specified background color.
     pub fn new_with_background(content: BlockContent, background_color: Color) -> Self {
+        // Status will be set by content type or later
+        Self::with_content(content, None, Some(background_color))
+    }
+
+    /// Creates an expanded block with a new id and no view state.
+    fn with_content(content: BlockContent, status: Option<String>, background_color: Option<Color>) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
             content,
             collapsed: false,
-            status: None, // Status will be set by content type or later
-            background_color: Some(background_color),
+            status,
+            background_color,
+            output_page: None,
+            process_tree: None,
+            highlights: Vec::new(),
+            filter: None,
+            hyperlinks: BTreeMap::new(),
+            images: OutputImages::default(),
+            selection: None,
+            clipboard_query: None,
+            screen: None,
         }
     }
 
-    /// Adds a line of output to a command block.
+    /// Adds a line of output to a command block, updating its filtered view.
     pub fn add_output_line(&mut self, line: String, is_stdout: bool) {
+        self.add_linked_output_line(line, is_stdout, Vec::new());
+    }
+
+    /// Adds a line of output to a command block along with the OSC 8 hyperlinks in it.
+    pub fn add_linked_output_line(&mut self, line: String, is_stdout: bool, hyperlinks: Vec<LinkSpan>) {
         if let BlockContent::Command { output, .. } = &mut self.content {
-            output.push((line, is_stdout));
+            if let Some(filter) = &mut self.filter {
+                filter.push(&line, is_stdout);
+            }
+            if !hyperlinks.is_empty() {
+                self.hyperlinks.insert(output.len(), hyperlinks);
+            }
+            output.push(line, is_stdout);
+            forget_spilled_links(&mut self.hyperlinks, output.spilled_len());
+        }
+    }
+
+    /// Shows what a running command drew on the screen in place of its last `replaces` output lines.
+    pub fn show_screen(&mut self, view: ScreenView, replaces: usize) {
+        if let BlockContent::Command { .. } = self.content {
+            self.screen = Some(LiveScreen { view, replaces });
+        }
+    }
+
+    /// Replaces the last `replaces` output lines of a command block with the `lines` a command
+    /// left on the screen, and stops showing the screen.
+    pub fn replace_output_lines(&mut self, lines: Vec<String>, replaces: usize) {
+        self.screen = None;
+        if let BlockContent::Command { output, .. } = &mut self.content {
+            output.truncate(output.len().saturating_sub(replaces));
+            let len = output.len();
+            self.hyperlinks.retain(|line, _| *line < len);
+            if let Some(filter) = &mut self.filter {
+                filter.apply(filter.filter.clone(), output);
+            }
+        }
+        for line in lines {
+            self.add_output_line(line, true);
+        }
+    }
+
+    /// Adds an inline image to a command block, after the output received so far.
+    pub fn add_output_image(&mut self, image: InlineImage) {
+        if let BlockContent::Command { output, .. } = &self.content {
+            self.images.push(output.len(), image);
+        }
+    }
+
+    /// Sets the memory budget for a command block's inline images, in bytes.
+    pub fn set_image_memory_limit(&mut self, limit: usize) {
+        self.images.set_limit(limit);
+    }
+
+    /// Selects output line `line` of a command block. Clicking a line starts a selection,
+    /// clicking another extends it to that line, and clicking the only selected line clears it.
+    pub fn select_output_line(&mut self, line: usize) {
+        self.selection = match self.selection {
+            Some((anchor, head)) if anchor == head && anchor == line => None,
+            Some((anchor, head)) if anchor == head => Some((anchor, line)),
+            _ => Some((line, line)),
+        };
+    }
+
+    /// Returns the selected output lines as a range of line indices.
+    pub fn selected_lines(&self) -> Option<std::ops::RangeInclusive<usize>> {
+        self.selection.map(|(anchor, head)| anchor.min(head)..=anchor.max(head))
+    }
+
+    /// Returns the text of the block to put on the clipboard, exactly as stored.
+    ///
+    /// # Arguments
+    ///
+    /// * `scope` - Which part of a command block to copy. Other blocks always copy their whole text.
+    ///
+    /// # Returns
+    ///
+    /// The text, or `None` when copying the selection of a block without one.
+    pub fn copy_text(&self, scope: CopyScope) -> Option<String> {
+        let text = match &self.content {
+            BlockContent::Command { input, output, .. } => match scope {
+                CopyScope::Command => input.clone(),
+                CopyScope::Output => output.text(),
+                CopyScope::Both => format!("$ {}\n{}", input, output.text()),
+                CopyScope::Selection => {
+                    let lines = self.selected_lines()?;
+                    match output.lines(*lines.start(), lines.count()) {
+                        Ok(selected) => selected.into_iter().map(|(line, _)| line).collect::<Vec<_>>().join("\n"),
+                        Err(e) => {
+                            error!("Failed to read the selected output lines of block {}: {}", self.id, e);
+                            return None;
+                        }
+                    }
+                }
+            },
+            BlockContent::AgentMessage { content, .. } => content.clone(),
+            BlockContent::Info { title, message, .. } => format!("{}\n{}", title, message),
+            BlockContent::Error { message, .. } | BlockContent::AgentPrompt { message, .. } => message.clone(),
+            BlockContent::WorkflowSuggestion { workflow } => format!("{:#?}", workflow),
+            BlockContent::StreamingToolCall { name, arguments, .. } => format!("Tool Call: {}\nArguments: {}", name, arguments),
+            BlockContent::Playback { path, .. } => path.clone(),
+        };
+        Some(text)
+    }
+
+    /// Returns the working directory a command block ran in, which relative file links resolve against.
+    pub fn working_directory(&self) -> Option<&str> {
+        match &self.content {
+            BlockContent::Command { working_directory, .. } => working_directory.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Opens or closes the filter bar of a command block. Closing it shows all output again.
+    ///
+    /// # Arguments
+    ///
+    /// * `saved_names` - Names of the saved filters to offer in the bar.
+    pub fn toggle_filter(&mut self, saved_names: Vec<String>) {
+        if !matches!(self.content, BlockContent::Command { .. }) {
+            return;
+        }
+        self.filter = match self.filter {
+            Some(_) => None,
+            None => Some(BlockFilter::new(saved_names)),
+        };
+    }
+
+    /// Filters a command block's output with `filter`, opening the filter bar if needed.
+    /// The view is rebuilt from the complete output, including spilled lines.
+    pub fn set_filter(&mut self, filter: OutputFilter) {
+        let BlockContent::Command { output, .. } = &self.content else {
+            return;
+        };
+        self.filter.get_or_insert_with(BlockFilter::default).apply(filter, output);
+    }
+
+    /// Returns the filter settings of a command block with an open filter bar.
+    pub fn output_filter(&self) -> Option<&OutputFilter> {
+        self.filter.as_ref().map(|filter| &filter.filter)
+    }
+
+    /// Sets how many output lines a command block keeps in memory before spilling to disk.
+    pub fn set_scrollback_limit(&mut self, limit: usize) {
+        if let BlockContent::Command { output, .. } = &mut self.content {
+            output.set_limit(limit);
+            forget_spilled_links(&mut self.hyperlinks, output.spilled_len());
+        }
+    }
+
+    /// Pages back through a command block's output by one scrollback-sized page,
+    /// reading spilled lines back from disk.
+    pub fn show_earlier_output(&mut self) {
+        let BlockContent::Command { output, .. } = &self.content else {
+            return;
+        };
+        let page_size = output.recent().count().max(1);
+        let end = self.output_page.as_ref().map(|page| page.start).unwrap_or_else(|| output.spilled_len());
+        if end == 0 {
+            return;
+        }
+        let start = end.saturating_sub(page_size);
+        match output.lines(start, end - start) {
+            Ok(lines) => self.output_page = Some(OutputPage { start, lines }),
+            Err(e) => error!("Failed to load earlier output for block {}: {}", self.id, e),
+        }
+    }
+
+    /// Shows the page of a command block's output that contains output line `line`,
+    /// reading it back from disk if it was spilled.
+    pub fn show_output_line(&mut self, line: usize) {
+        let BlockContent::Command { output, .. } = &self.content else {
+            return;
+        };
+        if line >= output.spilled_len() {
+            self.output_page = None;
+            return;
+        }
+        if let Some(page) = &self.output_page {
+            if (page.start..page.start + page.lines.len()).contains(&line) {
+                return;
+            }
+        }
+        let page_size = output.recent().count().max(1);
+        let start = line.saturating_sub(page_size / 2);
+        match output.lines(start, page_size) {
+            Ok(lines) => self.output_page = Some(OutputPage { start, lines }),
+            Err(e) => error!("Failed to load output line {} of block {}: {}", line, self.id, e),
+        }
+    }
+
+    /// Adds the matches of `regex` in the block's searchable text to `results`: a command's
+    /// input and all of its output, including spilled lines, and the text of messages.
+    ///
+    /// # Returns
+    ///
+    /// `false` once the results are full and the search should stop.
+    pub fn search(&self, regex: &regex::Regex, results: &mut SearchResults) -> bool {
+        let id = self.id.as_str();
+        match &self.content {
+            BlockContent::Command { input, output, .. } => {
+                results.find_in_line(regex, id, MatchField::Input, input)
+                    && results.find_in_lines(regex, id, MatchField::Output, output.iter().map(|(line, _)| line))
+            }
+            BlockContent::Info { title, message, .. } => {
+                results.find_in_line(regex, id, MatchField::Input, title)
+                    && results.find_in_lines(regex, id, MatchField::Text, message.lines())
+            }
+            BlockContent::AgentMessage { content: text, .. }
+            | BlockContent::Error { message: text, .. }
+            | BlockContent::AgentPrompt { message: text, .. }
+            | BlockContent::StreamingToolCall { arguments: text, .. } => {
+                results.find_in_lines(regex, id, MatchField::Text, text.lines())
+            }
+            BlockContent::WorkflowSuggestion { .. } | BlockContent::Playback { .. } => true,
+        }
+    }
+
+    /// Renders a line of the block, with search matches in `field` highlighted.
+    fn highlighted_text<'a>(&'a self, line: &'a str, field: MatchField, size: u16, color: Color) -> Element<'a, crate::Message> {
+        if !self.highlights.iter().any(|highlight| highlight.field == field) {
+            return text(line).size(size).color(color).into();
+        }
+        search::segments(line, &self.highlights, field)
+            .into_iter()
+            .fold(row![], |row, (segment, highlight)| match highlight {
+                None => row.push(text(segment).size(size).color(color)),
+                Some(current) => row.push(
+                    container(text(segment).size(size).color(Color::BLACK)).style(iced::widget::container::Appearance {
+                        background: Some(iced::Background::Color(if current {
+                            Color::from_rgb(1.0, 0.6, 0.0) // Orange for the current match
+                        } else {
+                            Color::from_rgb(0.9, 0.9, 0.3)
+                        })),
+                        ..Default::default()
+                    }),
+                ),
+            })
+            .into()
+    }
+
+    /// Renders output line `index` of a command block with its hyperlinks and detected
+    /// URLs and file locations clickable. Lines with search matches are shown highlighted instead.
+    fn output_line<'a>(&'a self, line: &'a str, index: usize, color: Color) -> Element<'a, crate::Message> {
+        let field = MatchField::Output(index);
+        let hyperlinks = self.hyperlinks.get(&index).map(Vec::as_slice).unwrap_or_default();
+        let found = links::links_in(line, hyperlinks);
+        if found.is_empty() || self.highlights.iter().any(|highlight| highlight.field == field) {
+            return self.highlighted_text(line, field, 14, color);
+        }
+        links::segments(line, &found)
+            .into_iter()
+            .fold(row![], |row, (segment, target)| match target {
+                None => row.push(text(segment).size(14).color(color)),
+                Some(target) => row.push(
+                    button(text(segment).size(14).color(Color::from_rgb(0.4, 0.7, 1.0))) // Blue for links
+                        .padding(0)
+                        .on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::OpenLink(target.clone())))
+                        .style(iced::widget::button::text::Style::Text),
+                ),
+            })
+            .into()
+    }
+
+    /// Makes a rendered output line clickable to select it, with selected lines highlighted.
+    fn selectable_line<'a>(&'a self, line: Element<'a, crate::Message>, index: usize) -> Element<'a, crate::Message> {
+        let selected = self.selected_lines().is_some_and(|lines| lines.contains(&index));
+        let line = container(line).width(Length::Fill).style(iced::widget::container::Appearance {
+            background: selected.then_some(iced::Background::Color(Color::from_rgb(0.2, 0.3, 0.5))),
+            ..Default::default()
+        });
+        mouse_area(line)
+            .on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::SelectOutputLine(index)))
+            .into()
+    }
+
+    /// Renders the inline images shown before output line `line`, at the size the program asked for.
+    fn output_images(&self, line: usize) -> impl Iterator<Item = Element<'_, crate::Message>> {
+        self.images.at(line).map(|output_image| {
+            let (width, height) = output_image.image.display_size(CELL_WIDTH, CELL_HEIGHT);
+            iced::widget::image(output_image.handle.clone())
+                .width(Length::Fixed(width))
+                .height(Length::Fixed(height))
+                .content_fit(iced::ContentFit::Fill)
+                .into()
+        })
+    }
+
+    /// Renders multi-line message text, with search matches highlighted.
+    fn highlighted_lines<'a>(&'a self, content: &'a str, size: u16, color: Color) -> Element<'a, crate::Message> {
+        if !self.highlights.iter().any(|highlight| matches!(highlight.field, MatchField::Text(_))) {
+            return text(content).size(size).color(color).into();
+        }
+        content
+            .lines()
+            .enumerate()
+            .fold(column![], |col, (index, line)| col.push(self.highlighted_text(line, MatchField::Text(index), size, color)))
+            .into()
+    }
+
+    /// Renders the filter bar of a command block.
+    fn filter_bar<'a>(&'a self, block_filter: &'a BlockFilter) -> Element<'a, crate::Message> {
+        let filter_message = move |message| crate::Message::BlockAction(self.id.clone(), message);
+        let filter = &block_filter.filter;
+        let changed = move |change: &dyn Fn(&mut OutputFilter)| {
+            let mut filter = filter.clone();
+            change(&mut filter);
+            filter_message(crate::main::BlockMessage::FilterChanged(filter))
+        };
+
+        let status = match (block_filter.error(), block_filter.view()) {
+            (Some(error), _) => text(error).size(12).color(Color::from_rgb(1.0, 0.4, 0.4)),
+            (None, Some(view)) => text(format!("{} of {} lines", view.matched(), view.total())).size(12).color(Color::from_rgb(0.7, 0.7, 0.7)),
+            (None, None) => text(""),
+        };
+        let settings = row![
+            text_input("Filter output…", &filter.pattern)
+                .on_input(move |pattern| changed(&|filter: &mut OutputFilter| filter.pattern = pattern.clone()))
+                .padding(4)
+                .size(14)
+                .width(Length::Fill),
+            pick_list(&FilterAction::ALL[..], Some(filter.action), move |action| changed(&|filter: &mut OutputFilter| filter.action = action)).text_size(12),
+            pick_list(&SearchMode::ALL[..], Some(filter.mode), move |mode| changed(&|filter: &mut OutputFilter| filter.mode = mode)).text_size(12),
+            pick_list(&output_filter::CONTEXT_CHOICES[..], Some(filter.context), move |context| changed(&|filter: &mut OutputFilter| filter.context = context))
+                .placeholder("Context")
+                .text_size(12),
+        ].spacing(5).align_items(alignment::Horizontal::Center);
+        // Interactive programs share one terminal for stdout and stderr, so their blocks
+        // have no stderr lines to keep.
+        let has_stderr = matches!(&self.content, BlockContent::Command { output, .. } if output.stderr_len() > 0);
+        let settings = if has_stderr || filter.only_stderr {
+            settings.push(checkbox("Only stderr", filter.only_stderr, move |only_stderr| changed(&|filter: &mut OutputFilter| filter.only_stderr = only_stderr)).size(14).text_size(12))
+        } else {
+            settings
+        };
+        let settings = settings.push(status);
+
+        let saved = row![
+            pick_list(block_filter.saved_names.clone(), None::<String>, move |name| filter_message(crate::main::BlockMessage::ApplySavedFilter(name)))
+                .placeholder("Saved filters…")
+                .text_size(12),
+            text_input("Name", &block_filter.save_name)
+                .on_input(move |name| filter_message(crate::main::BlockMessage::FilterSaveNameChanged(name)))
+                .on_submit(filter_message(crate::main::BlockMessage::SaveFilter))
+                .padding(4)
+                .size(12)
+                .width(Length::Fixed(160.0)),
+            button(text("💾 Save").size(12)).on_press(filter_message(crate::main::BlockMessage::SaveFilter)).style(iced::widget::button::text::Style::Text),
+        ].spacing(5).align_items(alignment::Horizontal::Center);
+
+        column![settings, saved].spacing(5).into()
+    }
+
+    /// Renders the lines of a command block's output that pass its filter.
+    /// Context lines are dimmed and skipped stretches are marked with `--`.
+    fn filtered_output<'a>(&'a self, view: &'a output_filter::FilterView) -> iced::widget::Column<'a, crate::Message> {
+        let mut lines = column![];
+        if view.trimmed() > 0 {
+            lines = lines.push(text(format!("⋯ {} earlier filtered lines not shown", view.trimmed())).size(12).color(Color::from_rgb(0.6, 0.6, 0.6)));
+        }
+        view.lines().fold(lines, |col, line| match line {
+            FilteredLine::Gap => col.push(text("--").size(14).color(Color::from_rgb(0.4, 0.4, 0.4))),
+            FilteredLine::Line { index, line: (line, is_stdout), matched } => {
+                let color = match (*is_stdout, *matched) {
+                    (true, true) => Color::WHITE,
+                    (false, true) => Color::from_rgb(1.0, 0.5, 0.5), // Red for stderr
+                    (true, false) => Color::from_rgb(0.6, 0.6, 0.6),
+                    (false, false) => Color::from_rgb(0.6, 0.4, 0.4),
+                };
+                col.push(self.selectable_line(self.output_line(line, *index, color), *index))
+            }
+        })
+    }
+
+    /// Returns to following the latest output of a command block.
+    pub fn show_latest_output(&mut self) {
+        self.output_page = None;
+    }
+
+    /// Returns true for a command block that hasn't finished yet.
+    pub fn is_running(&self) -> bool {
+        matches!(self.content, BlockContent::Command { end_time: None, .. })
+    }
+
+    /// Returns true for a command block whose job was stopped (e.g. with Ctrl+Z) and can be resumed.
+    pub fn is_stopped(&self) -> bool {
+        matches!(&self.content, BlockContent::Command { stats: Some(stats), .. } if stats.stopped)
+    }
+
+    /// Returns the shell's job number for a stopped command block, read from the notice
+    /// the shell printed when the job stopped.
+    pub fn stopped_job(&self) -> Option<u32> {
+        match &self.content {
+            BlockContent::Command { output, .. } if self.is_stopped() => output
+                .tail(5)
+                .unwrap_or_default()
+                .iter()
+                .rev()
+                .find_map(|(line, _)| jobs::stopped_job_number(line)),
+            _ => None,
+        }
+    }
+
+    /// Marks a stopped command block as resumed. A job resumed in the foreground runs in
+    /// the block again, with its output and final status added to it; one resumed in the
+    /// background is no longer followed.
+    pub fn resume(&mut self, in_foreground: bool) {
+        if let BlockContent::Command { status, end_time, stats, .. } = &mut self.content {
+            *stats = None;
+            *status = if in_foreground { "Running...".to_string() } else { "Resumed in the background".to_string() };
+            if in_foreground {
+                *end_time = None;
+            }
+            self.status = Some(status.clone());
         }
     }
 
@@ -222,6 +822,20 @@ impl Block {
         }
     }
 
+    /// Records the environment profile a command block runs under.
+    pub fn set_profile(&mut self, new_profile: Option<String>) {
+        if let BlockContent::Command { profile, .. } = &mut self.content {
+            *profile = new_profile;
+        }
+    }
+
+    /// Records the stats of a finished command block.
+    pub fn set_stats(&mut self, new_stats: CommandStats) {
+        if let BlockContent::Command { stats, .. } = &mut self.content {
+            *stats = Some(new_stats);
+        }
+    }
+
     /// Sets the status of the block.
     pub fn set_status(&mut self, status: String) {
         match &mut self.content {
@@ -231,7 +845,7 @@ impl Block {
             },
             BlockContent::AgentMessage { .. } | BlockContent::Info { .. } | BlockContent::Error { .. } |
             BlockContent::WorkflowSuggestion { .. } | BlockContent::AgentPrompt { .. } |
-            BlockContent::StreamingToolCall { .. } => {
+            BlockContent::StreamingToolCall { .. } | BlockContent::Playback { .. } => {
                 // For other block types, update the general status field
             }
         }
@@ -269,13 +883,25 @@ impl Block {
         let mut actions_row = row![
             toggle_button,
             id_text,
-            button(text("📋")).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::Copy)).style(iced::widget::button::text::Style::Text),
             button(text("🔄")).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::Rerun)).style(iced::widget::button::text::Style::Text),
             button(text("🗑️")).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::Delete)).style(iced::widget::button::text::Style::Text),
             button(text("📤")).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::Export)).style(iced::widget::button::text::Style::Text),
             button(text("🤖")).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::SendToAI)).style(iced::widget::button::text::Style::Text),
         ];
 
+        // Command blocks can copy their command, output or selected lines; other blocks copy their text
+        actions_row = if let BlockContent::Command { .. } = self.content {
+            actions_row.push(
+                pick_list(&CopyScope::ALL[..], None::<CopyScope>, |scope| crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::Copy(scope)))
+                    .placeholder("📋 Copy…")
+                    .text_size(12)
+            )
+        } else {
+            actions_row.push(
+                button(text("📋")).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::Copy(CopyScope::Both))).style(iced::widget::button::text::Style::Text)
+            )
+        };
+
         // Conditionally show "Fix" button for failed command blocks
         if let BlockContent::Command { error: true, .. } = self.content {
             actions_row = actions_row.push(
@@ -283,6 +909,33 @@ impl Block {
             );
         }
 
+        // Filter a command block's output in place
+        if let BlockContent::Command { .. } = self.content {
+            let active = self.output_filter().is_some_and(|filter| filter.is_active());
+            actions_row = actions_row.push(
+                button(text(if active { "🔍 Filtered" } else { "🔍 Filter" })).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::ToggleFilter)).style(iced::widget::button::text::Style::Text)
+            );
+        }
+
+        // Job control for running and stopped command blocks
+        if self.is_running() {
+            actions_row = actions_row.push(
+                pick_list(&JobSignal::ALL[..], None::<JobSignal>, |signal| crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::SendSignal(signal)))
+                    .placeholder("Signal…")
+                    .text_size(12)
+            );
+            actions_row = actions_row.push(
+                button(text(if self.process_tree.is_some() { "🌳 Hide processes" } else { "🌳 Processes" })).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::ShowProcesses)).style(iced::widget::button::text::Style::Text)
+            );
+        } else if self.is_stopped() {
+            actions_row = actions_row.push(
+                button(text("▶ Resume")).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::Foreground)).style(iced::widget::button::text::Style::Text)
+            );
+            actions_row = actions_row.push(
+                button(text("⏩ Background")).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::Background)).style(iced::widget::button::text::Style::Text)
+            );
+        }
+
         // Conditionally show "Explain Output" button for command and error blocks
         match self.content {
             BlockContent::Command { .. } | BlockContent::Error { .. } => {
@@ -349,58 +1002,140 @@ impl Block {
                         text(arguments.lines().next().unwrap_or("...")).size(14).color(Color::WHITE),
                     ].spacing(10).into()
                 }
+                BlockContent::Playback { path, .. } => {
+                    row![
+                        text(format!("Recording: {}", path)).size(16).color(Color::from_rgb(0.8, 0.5, 1.0)),
+                    ].spacing(10).into()
+                }
             }
         } else {
             // Expanded view: show full content
             match &self.content {
-                BlockContent::Command { input, output, status, error, start_time, end_time, working_directory } => {
-                    // Header for command block: path and duration
-                    let duration_text = if let (Some(start), Some(end)) = (start_time.checked_add_signed(Duration::zero()), end_time) {
+                BlockContent::Command { input, output, status, error, start_time, end_time, working_directory, stats, profile } => {
+                    // Header for command block: path and duration, or the full stats once known
+                    let duration_text = if let Some(stats) = stats {
+                        format!(" ({})", stats.summary())
+                    } else if let (Some(start), Some(end)) = (start_time.checked_add_signed(Duration::zero()), end_time) {
                         let duration = end.signed_duration_since(*start);
                         format!(" ({:.3}s)", duration.num_milliseconds() as f64 / 1000.0)
                     } else {
                         "".to_string()
                     };
                     let path_text = working_directory.as_deref().unwrap_or("~");
-                    let command_header = text(format!("{} {}", path_text, duration_text))
+                    let profile_text = profile.as_deref().map(|name| format!(" · env: {}", name)).unwrap_or_default();
+                    let command_header = text(format!("{} {}{}", path_text, duration_text, profile_text))
                         .size(14)
                         .color(Color::from_rgb(0.7, 0.7, 0.7)); // Light gray for path/duration
 
                     // Render command input
-                    let input_view = text(input).size(16).color(Color::WHITE);
+                    let input_view = self.highlighted_text(input, MatchField::Input, 16, Color::WHITE);
                     
-                    // Render command output, distinguishing stdout/stderr
-                    let output_text = output.iter().map(|(line, is_stdout)| {
-                        text(line).size(14).color(if *is_stdout { Color::WHITE } else { Color::from_rgb(1.0, 0.5, 0.5) }) // Red for stderr
-                    }).fold(column![], |col, txt| col.push(txt));
+                    // Render the latest output (or the page scrolled back to), distinguishing stdout/stderr
+                    let (first_line, mut visible_lines): (usize, Vec<&OutputLine>) = match &self.output_page {
+                        Some(page) => (page.start, page.lines.iter().collect()),
+                        None => (output.spilled_len(), output.recent().collect()),
+                    };
+                    // Lines a running command drew over are shown by its screen instead
+                    if let (Some(screen), None) = (&self.screen, &self.output_page) {
+                        visible_lines.truncate(visible_lines.len().saturating_sub(screen.replaces));
+                    }
+                    let mut output_text = column![];
+                    if first_line > 0 {
+                        output_text = output_text.push(
+                            button(text(format!("⋯ {} earlier lines", first_line)).size(12).color(Color::from_rgb(0.6, 0.6, 0.6)))
+                                .on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::ShowEarlierOutput))
+                                .style(iced::widget::button::text::Style::Text)
+                        );
+                    }
+                    if self.images.dropped() > 0 {
+                        output_text = output_text.push(
+                            text(format!("⋯ {} images removed to stay within the memory limit", self.images.dropped())).size(12).color(Color::from_rgb(0.6, 0.6, 0.6))
+                        );
+                    }
+                    let following = self.output_page.is_none().then(|| first_line + visible_lines.len());
+                    let output_text = visible_lines.into_iter().enumerate().fold(output_text, |col, (index, (line, is_stdout))| {
+                        let color = if *is_stdout { Color::WHITE } else { Color::from_rgb(1.0, 0.5, 0.5) }; // Red for stderr
+                        self.output_images(first_line + index)
+                            .fold(col, |col, image| col.push(image))
+                            .push(self.selectable_line(self.output_line(line, first_line + index, color), first_line + index))
+                    });
+                    // Images after the last line, e.g. from a command that prints nothing else
+                    let output_text = following.into_iter().flat_map(|line| self.output_images(line)).fold(output_text, |col, image| col.push(image));
+                    let output_text = if self.output_page.is_some() {
+                        output_text.push(
+                            button(text("⋯ Jump to latest output").size(12).color(Color::from_rgb(0.6, 0.6, 0.6)))
+                                .on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::ShowLatestOutput))
+                                .style(iced::widget::button::text::Style::Text)
+                        )
+                    } else {
+                        output_text
+                    };
 
-                    column![
+                    let output_text = match self.filter.as_ref().and_then(|filter| filter.view()) {
+                        Some(view) => self.filtered_output(view),
+                        None => output_text,
+                    };
+
+                    let mut command_view = column![
                         command_header,
                         input_view,
-                        scrollable(output_text).height(Length::Shrink).width(Length::Fill),
+                    ].spacing(5);
+                    if let Some(filter) = &self.filter {
+                        command_view = command_view.push(self.filter_bar(filter));
+                    }
+                    // A full-screen program takes the place of the output until it ends
+                    let mut command_view = match &self.screen {
+                        Some(screen) if screen.view.alternate => command_view.push(screen_view(&screen.view)),
+                        Some(screen) => command_view
+                            .push(scrollable(output_text).height(Length::Shrink).width(Length::Fill))
+                            .push(screen_view(&screen.view)),
+                        None => command_view.push(scrollable(output_text).height(Length::Shrink).width(Length::Fill)),
+                    };
+                    if self.clipboard_query.is_some() {
+                        command_view = command_view.push(
+                            row![
+                                text("This command wants to read the clipboard.").size(14).color(Color::from_rgb(1.0, 0.7, 0.0)),
+                                button(text("Allow").size(12)).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::AnswerClipboardQuery(true))),
+                                button(text("Deny").size(12)).on_press(crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::AnswerClipboardQuery(false))),
+                            ].spacing(10).align_items(alignment::Horizontal::Center)
+                        );
+                    }
+                    if let Some(tree) = &self.process_tree {
+                        command_view = command_view.push(
+                            container(text(tree).size(12).font(iced::Font::MONOSPACE).color(Color::from_rgb(0.7, 0.7, 0.7))).padding(5)
+                        );
+                    }
+                    let status_color = if *error {
+                        Color::from_rgb(1.0, 0.0, 0.0)
+                    } else if self.is_stopped() {
+                        Color::from_rgb(1.0, 0.7, 0.0) // Amber for stopped jobs
+                    } else {
+                        Color::from_rgb(0.0, 0.8, 0.0)
+                    };
+                    command_view.push(
                         row![
-                            text(format!("Status: {}", status)).size(14).color(if *error { Color::from_rgb(1.0, 0.0, 0.0) } else { Color::from_rgb(0.0, 0.8, 0.0) }),
+                            text(format!("Status: {}", status)).size(14).color(status_color),
                         ].spacing(10)
-                    ].spacing(5).into()
+                    ).into()
                 }
                 BlockContent::AgentMessage { content, is_user, timestamp } => {
                     column![
                         text(if *is_user { "You:" } else { "Agent:" }).size(14).color(Color::from_rgb(0.5, 0.5, 1.0)),
-                        text(content).size(16).color(Color::WHITE),
+                        self.highlighted_lines(content, 16, Color::WHITE),
                         text(timestamp.format("%H:%M:%S").to_string()).size(12).color(Color::from_rgb(0.7, 0.7, 0.7)),
                     ].spacing(5).into()
                 }
                 BlockContent::Info { title, message, timestamp } => {
                     column![
-                        text(title).size(18).color(Color::from_rgb(0.0, 0.7, 1.0)),
-                        text(message).size(16).color(Color::WHITE),
+                        self.highlighted_text(title, MatchField::Input, 18, Color::from_rgb(0.0, 0.7, 1.0)),
+                        self.highlighted_lines(message, 16, Color::WHITE),
                         text(timestamp.format("%H:%M:%S").to_string()).size(12).color(Color::from_rgb(0.7, 0.7, 0.7)),
                     ].spacing(5).into()
                 }
                 BlockContent::Error { message, timestamp } => {
                     column![
                         text("Error!").size(18).color(Color::from_rgb(1.0, 0.0, 0.0)),
-                        text(message).size(16).color(Color::WHITE),
+                        self.highlighted_lines(message, 16, Color::WHITE),
                         text(timestamp.format("%H:%M:%S").to_string()).size(12).color(Color::from_rgb(0.7, 0.7, 0.7)),
                     ].spacing(5).into()
                 }
@@ -423,7 +1158,7 @@ impl Block {
                 BlockContent::AgentPrompt { prompt_id: _, message, input_value } => {
                     column![
                         text("Agent Prompt:").size(16).color(Color::from_rgb(1.0, 0.7, 0.0)),
-                        text(message).size(16).color(Color::WHITE),
+                        self.highlighted_lines(message, 16, Color::WHITE),
                         // Text input field for user response
                         text_input("Enter your response...", input_value)
                             .on_input(move |s| crate::Message::BlockAction(self.id.clone(), crate::main::BlockMessage::AgentPromptInputChanged(s)))
@@ -447,9 +1182,55 @@ impl Block {
                         text(format!("Streaming Tool Call (ID: {})", id)).size(18).color(Color::from_rgb(1.0, 0.7, 0.0)),
                         text(format!("Function: {}", name)).size(16).color(Color::WHITE),
                         text("Arguments:").size(14).color(Color::from_rgb(0.7, 0.7, 0.7)),
-                        scrollable(text(arguments.clone()).size(14).color(Color::WHITE)).height(Length::Shrink).width(Length::Fill),
+                        scrollable(self.highlighted_lines(arguments, 14, Color::WHITE)).height(Length::Shrink).width(Length::Fill),
                     ].spacing(5).into()
                 }
+                BlockContent::Playback { path, player } => {
+                    let title = text(format!("Recording: {}", path)).size(16).color(Color::from_rgb(0.8, 0.5, 1.0));
+                    match player.as_ref().map(|player| player.lock().unwrap()) {
+                        Some(player) => {
+                            let playback_message = |message| crate::Message::BlockAction(self.id.clone(), message);
+                            let controls = Player::SPEEDS.iter().fold(
+                                row![
+                                    button(text(if player.is_playing() { "⏸" } else { "▶" }))
+                                        .on_press(playback_message(crate::main::BlockMessage::PlaybackToggle))
+                                        .style(iced::widget::button::text::Style::Text),
+                                    slider(0.0..=player.duration().max(0.001), player.position(), move |position| {
+                                        playback_message(crate::main::BlockMessage::PlaybackSeek(position))
+                                    })
+                                    .step(0.1)
+                                    .width(Length::Fill),
+                                    text(format!("{} / {}", asciicast::format_time(player.position()), asciicast::format_time(player.duration())))
+                                        .size(12)
+                                        .color(Color::from_rgb(0.7, 0.7, 0.7)),
+                                ].spacing(5),
+                                |controls, speed| {
+                                    let label = text(format!("{}×", speed)).size(12).color(if *speed == player.speed() { Color::WHITE } else { Color::from_rgb(0.5, 0.5, 0.5) });
+                                    controls.push(
+                                        button(label)
+                                            .on_press(playback_message(crate::main::BlockMessage::PlaybackSpeed(*speed)))
+                                            .style(iced::widget::button::text::Style::Text),
+                                    )
+                                },
+                            );
+                            let screen = player.visible_lines().into_iter().fold(column![], |col, line| {
+                                col.push(text(line).size(14).font(iced::Font::MONOSPACE).color(Color::WHITE))
+                            });
+                            column![
+                                title,
+                                controls.align_items(alignment::Horizontal::Center),
+                                container(screen).padding(5).width(Length::Fill).style(iced::widget::container::Appearance {
+                                    background: Some(iced::Background::Color(Color::BLACK)),
+                                    ..Default::default()
+                                }),
+                            ].spacing(5).into()
+                        }
+                        None => column![
+                            title,
+                            text("The recording could not be loaded.").size(14).color(Color::from_rgb(1.0, 0.0, 0.0)),
+                        ].spacing(5).into(),
+                    }
+                }
             }
         };
 
@@ -474,6 +1255,36 @@ impl Block {
 }
 
 /// Initializes the block module.
+/// Renders the styled rows of a command's screen in a monospace font, like a terminal.
+fn screen_view(view: &ScreenView) -> Element<'_, crate::Message> {
+    let rgb = |color: TermColor| color.to_rgb().map(|(r, g, b)| Color::from_rgb8(r, g, b));
+    let screen = view.rows.iter().fold(column![], |col, runs| {
+        // Keep the height of empty rows
+        let line = row![text("").size(14).font(iced::Font::MONOSPACE)];
+        col.push(runs.iter().fold(line, |line, (run, attrs)| {
+            let (fg, bg) = if attrs.inverse {
+                (rgb(attrs.bg).unwrap_or(Color::BLACK), Some(rgb(attrs.fg).unwrap_or(Color::WHITE)))
+            } else {
+                (rgb(attrs.fg).unwrap_or(Color::WHITE), rgb(attrs.bg))
+            };
+            let font = if attrs.bold {
+                iced::Font { weight: iced::font::Weight::Bold, ..iced::Font::MONOSPACE }
+            } else {
+                iced::Font::MONOSPACE
+            };
+            let run = if attrs.hidden { " ".repeat(run.chars().count()) } else { run.clone() };
+            line.push(container(text(run).size(14).font(font).color(fg)).style(iced::widget::container::Appearance {
+                background: bg.map(iced::Background::Color),
+                ..Default::default()
+            }))
+        }))
+    });
+    container(screen).padding(5).width(Length::Fill).style(iced::widget::container::Appearance {
+        background: Some(iced::Background::Color(Color::BLACK)),
+        ..Default::default()
+    }).into()
+}
+
 pub fn init() {
     info!("Block module initialized.");
 }
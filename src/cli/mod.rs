@@ -4,6 +4,7 @@
 
 use clap::{Parser, Subcommand};
 use log;
+use std::path::PathBuf;
 
 /// NeoTerm: A modern terminal emulator with AI integration and advanced features.
 #[derive(Parser, Debug)]
@@ -24,6 +25,17 @@ pub enum Commands {
         /// Initial directory to open
         #[arg(short, long)]
         path: Option<String>,
+        /// Record the first tab to asciicast files in DIR (default: the recordings directory)
+        #[arg(long, value_name = "DIR")]
+        record: Option<Option<PathBuf>>,
+    },
+    /// Replay an asciicast recording in this terminal
+    Play {
+        /// The .cast file to play
+        file: PathBuf,
+        /// Playback speed multiplier
+        #[arg(short, long, default_value_t = 1.0)]
+        speed: f64,
     },
     /// Run a command in headless mode
     Run {
@@ -0,0 +1,190 @@
+//! System clipboard access.
+//!
+//! Copying from blocks and pasting into the input bar go through `arboard`. Programs
+//! running in a PTY, such as tmux or vim, can reach the clipboard too with OSC 52
+//! (`ESC ] 52 ; <selection> ; <base64 text | ?> ST`); the terminal hands those requests
+//! to the application, which checks them against the clipboard preferences.
+
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{debug, info};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use vte::{Parser, Perform};
+
+/// Largest text a program may put on the clipboard through OSC 52, in bytes.
+pub const MAX_OSC52_TEXT: usize = 1024 * 1024;
+
+/// The clipboard stays open for the lifetime of the application: on X11 and Wayland
+/// the copied text is served by its owner and would vanish when it is dropped.
+static CLIPBOARD: Lazy<Mutex<Option<arboard::Clipboard>>> = Lazy::new(|| Mutex::new(None));
+
+/// Runs `f` with the system clipboard, opening it on first use.
+fn with_clipboard<T>(f: impl FnOnce(&mut arboard::Clipboard) -> Result<T, arboard::Error>) -> Result<T> {
+    let mut guard = CLIPBOARD.lock().unwrap();
+    if guard.is_none() {
+        *guard = Some(arboard::Clipboard::new().map_err(|e| anyhow!("Failed to open the clipboard: {}", e))?);
+    }
+    f(guard.as_mut().unwrap()).map_err(|e| anyhow!("Clipboard error: {}", e))
+}
+
+/// Puts `text` on the system clipboard.
+pub fn set_text(text: &str) -> Result<()> {
+    with_clipboard(|clipboard| clipboard.set_text(text.to_string()))
+}
+
+/// Returns the text on the system clipboard.
+pub fn get_text() -> Result<String> {
+    with_clipboard(|clipboard| clipboard.get_text())
+}
+
+/// Which part of a command block to copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyScope {
+    Command,
+    Output,
+    Both,
+    /// The output lines selected in the block.
+    Selection,
+}
+
+impl CopyScope {
+    pub const ALL: [CopyScope; 4] = [CopyScope::Command, CopyScope::Output, CopyScope::Both, CopyScope::Selection];
+}
+
+impl std::fmt::Display for CopyScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            CopyScope::Command => "Copy command",
+            CopyScope::Output => "Copy output",
+            CopyScope::Both => "Copy command and output",
+            CopyScope::Selection => "Copy selected lines",
+        })
+    }
+}
+
+/// Whether OSC 52 clipboard reads are answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Osc52Read {
+    Allow,
+    Deny,
+    /// Ask in the block of the command that wants to read the clipboard.
+    Ask,
+}
+
+impl Default for Osc52Read {
+    fn default() -> Self {
+        Osc52Read::Ask
+    }
+}
+
+/// A program's request to access the clipboard through OSC 52.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Osc52 {
+    /// Put `text` on the clipboard.
+    Set { selection: String, text: String },
+    /// Report the clipboard's contents back to the program.
+    Query { selection: String },
+}
+
+impl Osc52 {
+    /// Parses the parameters of an OSC 52 sequence, `52 ; <selection> ; <data>`.
+    ///
+    /// # Returns
+    ///
+    /// The request, or `None` if the data is neither `?` nor valid base64-encoded text.
+    pub fn parse(params: &[&[u8]]) -> Option<Self> {
+        if params.first() != Some(&&b"52"[..]) {
+            return None;
+        }
+        let selection = match params.get(1).map(|selection| String::from_utf8_lossy(selection)) {
+            Some(selection) if !selection.is_empty() => selection.to_string(),
+            _ => "c".to_string(),
+        };
+        let data = params.get(2).copied().unwrap_or_default();
+        if data == b"?" {
+            return Some(Osc52::Query { selection });
+        }
+        if data.len() > MAX_OSC52_TEXT.div_ceil(3) * 4 {
+            debug!("Ignoring OSC 52 request of {} bytes", data.len());
+            return None;
+        }
+        let text = STANDARD.decode(data).ok().and_then(|bytes| String::from_utf8(bytes).ok())?;
+        Some(Osc52::Set { selection, text })
+    }
+
+    /// Encodes the answer to a query for `selection`, as sent back to the program.
+    pub fn reply(selection: &str, text: &str) -> Vec<u8> {
+        format!("\x1b]52;{};{}\x1b\\", selection, STANDARD.encode(text)).into_bytes()
+    }
+}
+
+/// Collects the text of a stream with the escape sequences removed.
+#[derive(Default)]
+struct PlainText(String);
+
+impl Perform for PlainText {
+    fn print(&mut self, c: char) {
+        self.0.push(c);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        if matches!(byte, b'\n' | b'\t') {
+            self.0.push(byte as char);
+        }
+    }
+}
+
+/// Removes ANSI escape sequences (colors, cursor movement, OSC strings) from `text`.
+pub fn strip_ansi(text: &str) -> String {
+    if !text.contains('\x1b') && !text.contains('\u{9b}') {
+        return text.to_string();
+    }
+    let mut parser = Parser::new();
+    let mut plain = PlainText::default();
+    for byte in text.bytes() {
+        parser.advance(&mut plain, byte);
+    }
+    plain.0
+}
+
+/// Initializes the clipboard module.
+pub fn init() {
+    info!("clipboard module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(params: &[&str]) -> Option<Osc52> {
+        let params: Vec<&[u8]> = params.iter().map(|param| param.as_bytes()).collect();
+        Osc52::parse(&params)
+    }
+
+    #[test]
+    fn test_parse_osc52() {
+        assert_eq!(
+            parse(&["52", "c", "aGVsbG8="]),
+            Some(Osc52::Set { selection: "c".to_string(), text: "hello".to_string() })
+        );
+        assert_eq!(parse(&["52", "", "?"]), Some(Osc52::Query { selection: "c".to_string() }));
+        assert_eq!(parse(&["52", "p", ""]), Some(Osc52::Set { selection: "p".to_string(), text: String::new() }));
+        assert_eq!(parse(&["52", "c", "not base64!"]), None);
+        assert_eq!(parse(&["8", "", "aGVsbG8="]), None);
+    }
+
+    #[test]
+    fn test_osc52_reply() {
+        assert_eq!(Osc52::reply("c", "hello"), b"\x1b]52;c;aGVsbG8=\x1b\\".to_vec());
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi("plain\ttext"), "plain\ttext");
+        assert_eq!(strip_ansi("\x1b[1;31merror\x1b[0m: \x1b]8;;https://x.io\x1b\\link\x1b]8;;\x1b\\"), "error: link");
+        assert_eq!(strip_ansi("a\x1b[2Kb\r\nc"), "ab\nc");
+    }
+}
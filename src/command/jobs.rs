@@ -0,0 +1,459 @@
+//! Job control for running commands: signal delivery, stop/continue notifications and
+//! process trees.
+//!
+//! Directly spawned commands lead their own process group, and commands run inside the
+//! persistent shell are put in one by the shell's job control, so signals are sent to
+//! the whole group. That way `Ctrl+C` on a pipeline reaches every process in it.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::process::ExitStatus;
+
+use super::usage::{self, ResourceUsage};
+
+/// Signals that can be sent to a running block from its action menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum JobSignal {
+    /// SIGINT, as sent by Ctrl+C.
+    Interrupt,
+    /// SIGTERM: ask the process to exit.
+    Terminate,
+    /// SIGTSTP, as sent by Ctrl+Z.
+    Stop,
+    /// SIGCONT: resume a stopped process.
+    Continue,
+    /// SIGHUP: the terminal went away.
+    Hangup,
+    /// SIGKILL: cannot be caught or ignored.
+    Kill,
+}
+
+impl JobSignal {
+    /// All signals, in the order they are offered in the block menu.
+    pub const ALL: [JobSignal; 6] = [
+        JobSignal::Interrupt,
+        JobSignal::Terminate,
+        JobSignal::Stop,
+        JobSignal::Continue,
+        JobSignal::Hangup,
+        JobSignal::Kill,
+    ];
+
+    /// Returns the platform's number for the signal.
+    #[cfg(unix)]
+    pub fn number(self) -> i32 {
+        match self {
+            JobSignal::Interrupt => libc::SIGINT,
+            JobSignal::Terminate => libc::SIGTERM,
+            JobSignal::Stop => libc::SIGTSTP,
+            JobSignal::Continue => libc::SIGCONT,
+            JobSignal::Hangup => libc::SIGHUP,
+            JobSignal::Kill => libc::SIGKILL,
+        }
+    }
+
+    /// Returns the conventional name, e.g. `SIGINT`.
+    pub fn name(self) -> &'static str {
+        match self {
+            JobSignal::Interrupt => "SIGINT",
+            JobSignal::Terminate => "SIGTERM",
+            JobSignal::Stop => "SIGTSTP",
+            JobSignal::Continue => "SIGCONT",
+            JobSignal::Hangup => "SIGHUP",
+            JobSignal::Kill => "SIGKILL",
+        }
+    }
+}
+
+impl fmt::Display for JobSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            JobSignal::Interrupt => "Interrupt",
+            JobSignal::Terminate => "Terminate",
+            JobSignal::Stop => "Stop",
+            JobSignal::Continue => "Continue",
+            JobSignal::Hangup => "Hang up",
+            JobSignal::Kill => "Kill",
+        };
+        write!(f, "{} ({})", action, self.name())
+    }
+}
+
+/// Sends `signal` to the process group led by `pid`, or to `pid` alone if it doesn't
+/// lead a group.
+#[cfg(unix)]
+pub fn send_signal(pid: u32, signal: JobSignal) -> Result<()> {
+    let pid = pid as libc::pid_t;
+    // SAFETY: kill has no memory-safety preconditions.
+    if unsafe { libc::kill(-pid, signal.number()) } == 0 {
+        return Ok(());
+    }
+    let group_error = std::io::Error::last_os_error();
+    if group_error.raw_os_error() != Some(libc::ESRCH) {
+        return Err(anyhow!("Failed to send {} to process group {}: {}", signal.name(), pid, group_error));
+    }
+    // SAFETY: as above.
+    if unsafe { libc::kill(pid, signal.number()) } == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!("Failed to send {} to process {}: {}", signal.name(), pid, std::io::Error::last_os_error()))
+    }
+}
+
+#[cfg(not(unix))]
+pub fn send_signal(_pid: u32, signal: JobSignal) -> Result<()> {
+    Err(anyhow!("Sending {} is not supported on this platform.", signal.name()))
+}
+
+/// A change in the state of a child process, as reported by `wait_for_change`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildChange {
+    /// The child was stopped by the given signal.
+    Stopped(i32),
+    /// A stopped child was resumed.
+    Continued,
+    /// The child exited and was reaped.
+    Exited(ExitStatus, ResourceUsage),
+}
+
+/// Blocks until the child `pid` stops, continues or exits. Only an exit reaps it.
+#[cfg(unix)]
+pub fn wait_for_change(pid: u32) -> Result<ChildChange> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let (status, usage) = usage::wait4_raw(pid, libc::WUNTRACED | libc::WCONTINUED)?;
+    Ok(if libc::WIFSTOPPED(status) {
+        ChildChange::Stopped(libc::WSTOPSIG(status))
+    } else if libc::WIFCONTINUED(status) {
+        ChildChange::Continued
+    } else {
+        ChildChange::Exited(ExitStatus::from_raw(status), usage)
+    })
+}
+
+/// Scheduling state of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    Sleeping,
+    Stopped,
+    Zombie,
+    Other(char),
+}
+
+impl ProcessState {
+    /// Parses the state letter used by `/proc/<pid>/stat` and `ps -o stat`.
+    pub fn from_code(code: char) -> Self {
+        match code {
+            'R' => ProcessState::Running,
+            'S' | 'D' | 'I' => ProcessState::Sleeping,
+            'T' | 't' => ProcessState::Stopped,
+            'Z' => ProcessState::Zombie,
+            other => ProcessState::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for ProcessState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessState::Running => write!(f, "running"),
+            ProcessState::Sleeping => write!(f, "sleeping"),
+            ProcessState::Stopped => write!(f, "stopped"),
+            ProcessState::Zombie => write!(f, "zombie"),
+            ProcessState::Other(code) => write!(f, "{}", code),
+        }
+    }
+}
+
+/// One row of the system process table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessEntry {
+    pub pid: u32,
+    pub ppid: u32,
+    pub pgid: u32,
+    pub state: ProcessState,
+    pub name: String,
+}
+
+/// A process and its descendants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub pgid: u32,
+    pub state: ProcessState,
+    pub name: String,
+    pub children: Vec<ProcessInfo>,
+}
+
+impl ProcessInfo {
+    /// Builds the tree rooted at `pid` from a process table.
+    pub fn from_entries(pid: u32, entries: &[ProcessEntry]) -> Option<ProcessInfo> {
+        let entry = entries.iter().find(|entry| entry.pid == pid)?;
+        let mut children: Vec<ProcessInfo> = entries
+            .iter()
+            .filter(|child| child.ppid == pid && child.pid != pid)
+            .filter_map(|child| ProcessInfo::from_entries(child.pid, entries))
+            .collect();
+        children.sort_by_key(|child| child.pid);
+        Some(ProcessInfo {
+            pid: entry.pid,
+            pgid: entry.pgid,
+            state: entry.state,
+            name: entry.name.clone(),
+            children,
+        })
+    }
+
+    /// Returns the pids of the process and all its descendants.
+    pub fn pids(&self) -> Vec<u32> {
+        let mut pids = vec![self.pid];
+        for child in &self.children {
+            pids.extend(child.pids());
+        }
+        pids
+    }
+
+    /// Renders the tree one process per line, e.g. `4242 sleep (sleeping)`, with
+    /// children indented below their parent.
+    pub fn render(&self) -> String {
+        let mut lines = Vec::new();
+        self.render_into(0, &mut lines);
+        lines.join("\n")
+    }
+
+    fn render_into(&self, depth: usize, lines: &mut Vec<String>) {
+        lines.push(format!("{}{} {} ({})", "  ".repeat(depth), self.pid, self.name, self.state));
+        for child in &self.children {
+            child.render_into(depth + 1, lines);
+        }
+    }
+}
+
+/// Returns the tree of processes rooted at `pid`.
+pub fn process_tree(pid: u32) -> Result<ProcessInfo> {
+    let entries = list_processes()?;
+    ProcessInfo::from_entries(pid, &entries).ok_or_else(|| anyhow!("Process {} is not running.", pid))
+}
+
+/// Returns the foreground process group of the terminal controlling `pid`, e.g. the job
+/// a shell is currently running. Returns `None` if `pid` has no controlling terminal.
+#[cfg(target_os = "linux")]
+pub fn foreground_process_group(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // Field 8 (tpgid) is the 6th after the parenthesised command name.
+    let tpgid: i64 = stat.rsplit_once(')')?.1.split_whitespace().nth(5)?.parse().ok()?;
+    (tpgid > 0).then_some(tpgid as u32)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn foreground_process_group(pid: u32) -> Option<u32> {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "tpgid=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    let tpgid: i64 = String::from_utf8_lossy(&output.stdout).trim().parse().ok()?;
+    (tpgid > 0).then_some(tpgid as u32)
+}
+
+#[cfg(not(unix))]
+pub fn foreground_process_group(_pid: u32) -> Option<u32> {
+    None
+}
+
+/// Reads the system process table.
+#[cfg(target_os = "linux")]
+pub fn list_processes() -> Result<Vec<ProcessEntry>> {
+    let mut entries = Vec::new();
+    for dir in std::fs::read_dir("/proc").map_err(|e| anyhow!("Failed to read /proc: {}", e))? {
+        let Ok(dir) = dir else { continue };
+        if dir.file_name().to_str().and_then(|name| name.parse::<u32>().ok()).is_none() {
+            continue;
+        }
+        // Processes may exit while the table is being read.
+        if let Some(entry) = std::fs::read_to_string(dir.path().join("stat")).ok().and_then(|stat| parse_proc_stat(&stat)) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn list_processes() -> Result<Vec<ProcessEntry>> {
+    let output = std::process::Command::new("ps")
+        .args(["-A", "-o", "pid=,ppid=,pgid=,stat=,comm="])
+        .output()
+        .map_err(|e| anyhow!("Failed to run ps: {}", e))?;
+    Ok(String::from_utf8_lossy(&output.stdout).lines().filter_map(parse_ps_line).collect())
+}
+
+#[cfg(not(unix))]
+pub fn list_processes() -> Result<Vec<ProcessEntry>> {
+    Err(anyhow!("Listing processes is not supported on this platform."))
+}
+
+/// Parses `/proc/<pid>/stat`. The command name may itself contain spaces and parentheses.
+fn parse_proc_stat(stat: &str) -> Option<ProcessEntry> {
+    let (head, rest) = stat.split_once(" (")?;
+    let (name, fields) = rest.rsplit_once(") ")?;
+    let mut fields = fields.split_whitespace();
+    let state = ProcessState::from_code(fields.next()?.chars().next()?);
+    Some(ProcessEntry {
+        pid: head.trim().parse().ok()?,
+        ppid: fields.next()?.parse().ok()?,
+        pgid: fields.next()?.parse().ok()?,
+        state,
+        name: name.to_string(),
+    })
+}
+
+/// Parses a line of `ps -o pid=,ppid=,pgid=,stat=,comm=`.
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+fn parse_ps_line(line: &str) -> Option<ProcessEntry> {
+    let mut fields = line.split_whitespace();
+    let pid = fields.next()?.parse().ok()?;
+    let ppid = fields.next()?.parse().ok()?;
+    let pgid = fields.next()?.parse().ok()?;
+    let state = ProcessState::from_code(fields.next()?.chars().next()?);
+    let name = fields.collect::<Vec<_>>().join(" ");
+    let name = std::path::Path::new(&name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(&name)
+        .to_string();
+    Some(ProcessEntry { pid, ppid, pgid, state, name })
+}
+
+/// Reads the job number from the notice a shell prints when a job stops, e.g.
+/// `[1]+  Stopped   sleep 100` (bash, dash) or `fish: Job 1, 'sleep 100' has stopped`.
+/// zsh's `zsh: suspended  sleep 100` carries no job number.
+pub fn stopped_job_number(line: &str) -> Option<u32> {
+    let line = line.trim();
+    let (number, stopped) = match line.strip_prefix('[') {
+        Some(rest) => {
+            let (number, status) = rest.split_once(']')?;
+            (number, status.contains("Stopped"))
+        }
+        None => {
+            let (number, rest) = line[line.find("Job ")? + 4..].split_once(", ")?;
+            (number, rest.ends_with("has stopped"))
+        }
+    };
+    if stopped { number.parse().ok() } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_process_table() {
+        let entry = parse_proc_stat("4242 (tmux: server) T 1 4242 4242 0 -1 4194560 100 0 0 0").unwrap();
+        assert_eq!(entry, ProcessEntry { pid: 4242, ppid: 1, pgid: 4242, state: ProcessState::Stopped, name: "tmux: server".to_string() });
+        let entry = parse_ps_line("  501   500   500 S+   /bin/sleep").unwrap();
+        assert_eq!(entry.name, "sleep");
+        assert_eq!(entry.state, ProcessState::Sleeping);
+
+        let entries = vec![
+            ProcessEntry { pid: 10, ppid: 1, pgid: 10, state: ProcessState::Sleeping, name: "sh".to_string() },
+            ProcessEntry { pid: 12, ppid: 10, pgid: 10, state: ProcessState::Running, name: "cat".to_string() },
+            ProcessEntry { pid: 11, ppid: 10, pgid: 10, state: ProcessState::Sleeping, name: "sleep".to_string() },
+        ];
+        let tree = ProcessInfo::from_entries(10, &entries).unwrap();
+        assert_eq!(tree.pids(), vec![10, 11, 12]);
+        assert_eq!(tree.render(), "10 sh (sleeping)\n  11 sleep (sleeping)\n  12 cat (running)");
+    }
+
+    #[test]
+    fn test_stopped_job_number() {
+        assert_eq!(stopped_job_number("[1]+  Stopped                 sleep 100"), Some(1));
+        assert_eq!(stopped_job_number("[12] + Stopped vim notes.txt"), Some(12));
+        assert_eq!(stopped_job_number("fish: Job 2, 'sleep 100' has stopped"), Some(2));
+        assert_eq!(stopped_job_number("zsh: suspended  sleep 100"), None);
+        assert_eq!(stopped_job_number("[1]+  Done                    sleep 1"), None);
+        assert_eq!(stopped_job_number("[x] Stopped"), None);
+    }
+
+    #[cfg(unix)]
+    fn spawn_in_group(program: &str, args: &[&str]) -> std::process::Child {
+        use std::os::unix::process::CommandExt;
+        std::process::Command::new(program)
+            .args(args)
+            .stdin(std::process::Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[allow(clippy::zombie_processes)] // reaped through `wait_for_change`
+    fn test_stop_continue_and_terminate() {
+        let child = spawn_in_group("sleep", &["5"]);
+        let pid = child.id();
+
+        send_signal(pid, JobSignal::Stop).unwrap();
+        assert_eq!(wait_for_change(pid).unwrap(), ChildChange::Stopped(libc::SIGTSTP));
+        send_signal(pid, JobSignal::Continue).unwrap();
+        assert_eq!(wait_for_change(pid).unwrap(), ChildChange::Continued);
+        send_signal(pid, JobSignal::Terminate).unwrap();
+        match wait_for_change(pid).unwrap() {
+            ChildChange::Exited(status, _) => {
+                let stats = usage::CommandStats::from_exit_status(std::time::Duration::ZERO, &status, None);
+                assert_eq!(stats.signal_name().as_deref(), Some("SIGTERM"));
+            }
+            other => panic!("unexpected change: {:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[allow(clippy::zombie_processes)] // reaped through `wait_for_change`
+    fn test_interrupt_reaches_the_whole_group() {
+        use std::io::Read;
+        use std::os::unix::process::CommandExt;
+
+        // `sleep` and `cat`, which waits on the pipe, run in subshells of the same group.
+        // The subshells catch SIGINT, so they outlive it and report how their command ended.
+        let script = r#"{ trap : INT; sleep 5; echo "sleep $?" >&2; } | { trap : INT; cat; echo "cat $?" >&2; }"#;
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", script])
+            .stderr(std::process::Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let commands = |tree: &ProcessInfo| -> Vec<String> {
+            let mut names: Vec<String> = tree.children.iter().flat_map(|shell| &shell.children).map(|command| command.name.clone()).collect();
+            names.sort();
+            names
+        };
+        let tree = (0..100)
+            .find_map(|_| {
+                let tree = process_tree(pid).ok().filter(|tree| commands(tree) == ["cat", "sleep"]);
+                if tree.is_none() {
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                }
+                tree
+            })
+            .expect("sleep and cat started");
+        let entries = list_processes().unwrap();
+        assert!(tree.pids().iter().all(|pid| entries.iter().any(|entry| entry.pid == *pid && entry.pgid == tree.pid)));
+
+        send_signal(pid, JobSignal::Interrupt).unwrap();
+        match wait_for_change(pid).unwrap() {
+            ChildChange::Exited(status, _) => {
+                let stats = usage::CommandStats::from_exit_status(std::time::Duration::ZERO, &status, None);
+                assert_eq!(stats.signal_name().as_deref(), Some("SIGINT"));
+            }
+            other => panic!("unexpected change: {:?}", other),
+        }
+        // Both commands died from the interrupt rather than being orphaned: 128 + SIGINT.
+        let mut reports = String::new();
+        child.stderr.take().unwrap().read_to_string(&mut reports).unwrap();
+        let mut reports: Vec<&str> = reports.lines().collect();
+        reports.sort();
+        assert_eq!(reports, vec!["cat 130", "sleep 130"]);
+    }
+}
@@ -1,12 +1,21 @@
 use anyhow::{anyhow, Result};
 use log::{error, info, warn};
 use std::collections::HashMap;
+use std::fmt;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio::io::AsyncReadExt;
 
+use crate::asciicast::Recorder;
+
+pub mod jobs;
+pub mod piped;
 pub mod pty;
+pub mod usage;
+
+pub use jobs::{JobSignal, ProcessInfo};
+pub use usage::{CommandStats, ResourceUsage};
 
 /// Represents a command to be executed.
 #[derive(Debug, Clone)]
@@ -19,6 +28,44 @@ pub struct Command {
     pub env: HashMap<String, String>,
     pub working_dir: Option<PathBuf>,
     pub output_format: CommandOutputFormat,
+    pub execution_mode: ExecutionMode,
+}
+
+/// Variables every spawned shell and command gets unless its environment sets them.
+pub const DEFAULT_TERMINAL_ENV: [(&str, &str); 2] = [("TERM", "xterm-256color"), ("COLORTERM", "truecolor")];
+
+/// Returns the environment to spawn a process with: `vars` (usually the active
+/// environment profile) on top of the terminal defaults.
+pub fn spawn_env(vars: &HashMap<String, String>) -> HashMap<String, String> {
+    let mut env: HashMap<String, String> = DEFAULT_TERMINAL_ENV
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    env.extend(vars.iter().map(|(key, value)| (key.clone(), value.clone())));
+    env
+}
+
+/// How a command's process is connected to NeoTerm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExecutionMode {
+    /// Use a PTY for programs known to need a terminal and pipes for everything else.
+    #[default]
+    Auto,
+    /// Run in a PTY. stdout and stderr arrive merged on the terminal.
+    Pty,
+    /// Run with stdout and stderr on separate pipes and no stdin.
+    Piped,
+}
+
+impl ExecutionMode {
+    /// Resolves `Auto` to a concrete mode for the given command.
+    pub fn resolve(self, executable: &str, args: &[String]) -> ExecutionMode {
+        match self {
+            ExecutionMode::Auto if piped::is_interactive(executable, args) => ExecutionMode::Pty,
+            ExecutionMode::Auto => ExecutionMode::Piped,
+            mode => mode,
+        }
+    }
 }
 
 /// Defines the expected output format of a command.
@@ -29,12 +76,43 @@ pub enum CommandOutputFormat {
 }
 
 /// Represents the current status of a running command.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CommandStatus {
     Running,
+    /// Suspended by the given signal; can be resumed with `JobSignal::Continue`.
+    Stopped(i32),
     Completed(i32),
     Failed(String),
+    /// Killed on request through `CommandManager::terminate_command`.
     Killed,
+    /// Terminated by the given signal.
+    Signaled(i32),
+}
+
+impl CommandStatus {
+    /// Returns the final status for a command that exited with `stats`.
+    pub fn from_stats(stats: &CommandStats) -> Self {
+        match (stats.signal, stats.exit_code) {
+            (Some(signal), _) if stats.stopped => CommandStatus::Stopped(signal),
+            (Some(signal), None) => CommandStatus::Signaled(signal),
+            (_, Some(code)) => CommandStatus::Completed(code),
+            (None, None) => CommandStatus::Completed(-1),
+        }
+    }
+}
+
+impl fmt::Display for CommandStatus {
+    /// Formats the status for display, e.g. `stopped` or `killed by signal 9 (SIGKILL)`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandStatus::Running => write!(f, "running"),
+            CommandStatus::Stopped(signal) => write!(f, "stopped ({})", usage::signal_name(*signal)),
+            CommandStatus::Completed(code) => write!(f, "exited with code {}", code),
+            CommandStatus::Failed(error) => write!(f, "failed: {}", error),
+            CommandStatus::Killed => write!(f, "killed"),
+            CommandStatus::Signaled(signal) => write!(f, "killed by signal {} ({})", signal, usage::signal_name(*signal)),
+        }
+    }
 }
 
 /// Represents a chunk of output from a command.
@@ -56,6 +134,8 @@ pub enum CommandEvent {
     Completed {
         id: String,
         exit_code: i32,
+        /// Wall time, terminating signal and (when available) resource usage.
+        stats: CommandStats,
     },
     Error {
         id: String,
@@ -64,12 +144,32 @@ pub enum CommandEvent {
     Killed {
         id: String,
     },
+    /// The command was suspended by `signal`.
+    Stopped {
+        id: String,
+        signal: i32,
+    },
+    /// A stopped command was resumed.
+    Continued {
+        id: String,
+    },
+}
+
+/// A command running with piped output, as tracked by the `CommandManager`.
+struct PipedJob {
+    /// Pid of the command, which leads its own process group.
+    pid: u32,
+    /// Asks the command's task to kill it.
+    kill_tx: oneshot::Sender<()>,
 }
 
-/// Manages the execution and lifecycle of commands via PTY sessions.
+/// Manages the execution and lifecycle of commands via PTY sessions or pipes.
 pub struct CommandManager {
     active_ptys: Arc<Mutex<HashMap<String, pty::PtySession>>>, // command_id -> PtySession
+    active_piped: Arc<Mutex<HashMap<String, PipedJob>>>, // command_id -> pid and kill request
     event_sender: mpsc::Sender<CommandEvent>,
+    /// Recording that streamed command output is added to, if any.
+    recorder: Arc<std::sync::Mutex<Option<Arc<Recorder>>>>,
 }
 
 impl CommandManager {
@@ -81,10 +181,18 @@ impl CommandManager {
     pub fn new(event_sender: mpsc::Sender<CommandEvent>) -> Self {
         Self {
             active_ptys: Arc::new(Mutex::new(HashMap::new())),
+            active_piped: Arc::new(Mutex::new(HashMap::new())),
             event_sender,
+            recorder: Arc::new(std::sync::Mutex::new(None)),
         }
     }
 
+    /// Sets the recording that the output of commands started from now on is written to.
+    /// Pass `None` to stop adding to it.
+    pub fn set_recorder(&self, recorder: Option<Arc<Recorder>>) {
+        *self.recorder.lock().unwrap() = recorder;
+    }
+
     /// Executes a command in a new PTY session.
     /// Returns a unique command ID and the PtySession.
     /// This method is for internal use or when direct PtySession control is needed.
@@ -117,10 +225,10 @@ impl CommandManager {
         tokio::spawn(async move {
             let exit_status = pty_session.wait().await;
             let status_msg = match exit_status {
-                Ok(status) => {
-                    info!("Command {} (ID: {}) finished with status: {:?}", command, command_id_clone, status);
-                    let exit_code = status.code().unwrap_or(-1);
-                    let _ = event_sender_clone.send(CommandEvent::Completed { id: command_id_clone.clone(), exit_code }).await;
+                Ok(stats) => {
+                    info!("Command {} (ID: {}) finished: {}", command, command_id_clone, stats.summary());
+                    let exit_code = stats.exit_code.unwrap_or(-1);
+                    let _ = event_sender_clone.send(CommandEvent::Completed { id: command_id_clone.clone(), exit_code, stats }).await;
                     format!("Completed with exit code: {}", exit_code)
                 },
                 Err(e) => {
@@ -140,6 +248,10 @@ impl CommandManager {
     /// Executes a command and streams its output and status updates via an MPSC channel.
     /// This is the preferred method for executing commands that need to stream output to the UI.
     ///
+    /// The command runs in a PTY or with piped stdout/stderr depending on `cmd.execution_mode`.
+    /// In piped mode each `CommandOutput` carries a single line in either `stdout` or `stderr`,
+    /// sent in the order the lines were produced.
+    ///
     /// # Arguments
     ///
     /// * `cmd` - The `Command` struct containing details about the command to execute.
@@ -154,9 +266,17 @@ impl CommandManager {
         output_tx: mpsc::Sender<CommandOutput>,
     ) -> Result<()> {
         info!("Executing command (with output channel): {} with args: {:?}", cmd.executable, cmd.args);
+        let recorder = self.recorder.lock().unwrap().clone();
+        if let Some(recorder) = &recorder {
+            recorder.marker(&std::iter::once(&cmd.executable).chain(&cmd.args).cloned().collect::<Vec<_>>().join(" "));
+        }
+        if cmd.execution_mode.resolve(&cmd.executable, &cmd.args) == ExecutionMode::Piped {
+            return self.execute_piped(cmd, output_tx, recorder).await;
+        }
         let command_id = cmd.id.clone();
 
-        let mut pty_session = pty::PtySession::new(&cmd.executable, &cmd.args.iter().map(|s| s.as_str()).collect::<Vec<&str>>()).await?;
+        let args = cmd.args.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+        let mut pty_session = pty::PtySession::with_env(&cmd.executable, &args, &cmd.env, cmd.working_dir.as_deref()).await?;
         let pty_session_clone_for_storage = pty_session.clone();
 
         {
@@ -182,6 +302,9 @@ impl CommandManager {
                     read_result = pty_session.read_output(&mut buf) => {
                         match read_result {
                             Ok(n) if n > 0 => {
+                                if let Some(recorder) = &recorder {
+                                    recorder.output(&buf[..n]);
+                                }
                                 let output_chunk = String::from_utf8_lossy(&buf[..n]).to_string();
                                 // Simple heuristic: if output contains common error indicators, treat as stderr
                                 // A more robust solution would involve parsing shell prompts or using specific PTY features
@@ -225,11 +348,10 @@ impl CommandManager {
                     // Wait for the command to complete
                     exit_status_result = pty_session.wait() => {
                         match exit_status_result {
-                            Ok(status) => {
-                                let exit_code = status.code().unwrap_or(-1);
-                                info!("Command {} (ID: {}) finished with status: {:?}", cmd.executable, command_id, status);
+                            Ok(stats) => {
+                                info!("Command {} (ID: {}) finished: {}", cmd.executable, command_id, stats.summary());
                                 if let Err(e) = output_tx.send(CommandOutput {
-                                    status: CommandStatus::Completed(exit_code),
+                                    status: CommandStatus::from_stats(&stats),
                                     stdout: stdout_buffer.clone(),
                                     stderr: stderr_buffer.clone(),
                                 }).await {
@@ -265,6 +387,116 @@ impl CommandManager {
         Ok(())
     }
 
+    /// Executes a command with stdout and stderr on separate pipes.
+    ///
+    /// Besides output, the channel reports `CommandStatus::Stopped` when the command is
+    /// suspended and `CommandStatus::Running` again when it is continued.
+    async fn execute_piped(&self, cmd: Command, output_tx: mpsc::Sender<CommandOutput>, recorder: Option<Arc<Recorder>>) -> Result<()> {
+        let command_id = cmd.id.clone();
+        let mut process = piped::PipedProcess::spawn(&cmd)?;
+        let mut job_events = process.watch();
+
+        let (kill_tx, mut kill_rx) = oneshot::channel();
+        self.active_piped.lock().await.insert(command_id.clone(), PipedJob { pid: process.pid(), kill_tx });
+        info!("Command '{}' started without a PTY with ID: {}", cmd.executable, command_id);
+
+        let active_piped_clone = self.active_piped.clone();
+        let event_sender_clone = self.event_sender.clone();
+
+        tokio::spawn(async move {
+            let mut killed = false;
+            let mut kill_requested = false;
+            let mut output_open = true;
+            let mut watching = true;
+            let mut exit_stats = None;
+
+            // Output can end before the exit is seen and vice versa (a background child may
+            // keep the pipes open), so run until both are done.
+            while output_open || (watching && exit_stats.is_none()) {
+                tokio::select! {
+                    line = process.next_line(), if output_open => {
+                        let Some((line, is_stdout)) = line else {
+                            output_open = false;
+                            continue;
+                        };
+                        let data = format!("{}\n", line);
+                        if let Some(recorder) = &recorder {
+                            recorder.output(format!("{}\r\n", line).as_bytes());
+                        }
+                        let _ = event_sender_clone.send(CommandEvent::Output {
+                            id: command_id.clone(),
+                            data: data.clone().into_bytes(),
+                            is_stdout,
+                        }).await;
+                        let (stdout, stderr) = if is_stdout { (data, String::new()) } else { (String::new(), data) };
+                        if let Err(e) = output_tx.send(CommandOutput { status: CommandStatus::Running, stdout, stderr }).await {
+                            error!("Failed to send output chunk for command ID {}: {}", command_id, e);
+                            break;
+                        }
+                    }
+                    event = job_events.recv(), if watching && exit_stats.is_none() => {
+                        let (status, event) = match event {
+                            Some(piped::JobEvent::Stopped(signal)) => {
+                                (CommandStatus::Stopped(signal), CommandEvent::Stopped { id: command_id.clone(), signal })
+                            }
+                            Some(piped::JobEvent::Continued) => {
+                                (CommandStatus::Running, CommandEvent::Continued { id: command_id.clone() })
+                            }
+                            Some(piped::JobEvent::Exited(stats)) => {
+                                exit_stats = Some(stats);
+                                continue;
+                            }
+                            None => {
+                                watching = false;
+                                continue;
+                            }
+                        };
+                        let _ = event_sender_clone.send(event).await;
+                        let _ = output_tx.send(CommandOutput { status, stdout: String::new(), stderr: String::new() }).await;
+                    }
+                    request = &mut kill_rx, if !kill_requested => {
+                        kill_requested = true;
+                        // The sender is also dropped when the command is unregistered; only an explicit request kills.
+                        if request.is_ok() {
+                            killed = process.terminate().await.is_ok();
+                        }
+                    }
+                }
+            }
+
+            let result = match exit_stats {
+                Some(stats) => Ok(stats),
+                None => process.wait().await,
+            };
+            let status = if killed {
+                let _ = event_sender_clone.send(CommandEvent::Killed { id: command_id.clone() }).await;
+                CommandStatus::Killed
+            } else {
+                match result {
+                    Ok(stats) => {
+                        let exit_code = stats.exit_code.unwrap_or(-1);
+                        info!("Command {} (ID: {}) finished: {}", cmd.executable, command_id, stats.summary());
+                        let status = CommandStatus::from_stats(&stats);
+                        let _ = event_sender_clone.send(CommandEvent::Completed { id: command_id.clone(), exit_code, stats }).await;
+                        status
+                    }
+                    Err(e) => {
+                        error!("Error waiting for command {} (ID: {}): {}", cmd.executable, command_id, e);
+                        let _ = event_sender_clone.send(CommandEvent::Error { id: command_id.clone(), message: e.to_string() }).await;
+                        CommandStatus::Failed(format!("Error waiting for command: {}", e))
+                    }
+                }
+            };
+            if let Err(e) = output_tx.send(CommandOutput { status, stdout: String::new(), stderr: String::new() }).await {
+                error!("Failed to send final status for command ID {}: {}", command_id, e);
+            }
+
+            active_piped_clone.lock().await.remove(&command_id);
+        });
+
+        Ok(())
+    }
+
     /// Sends input to a running command's PTY session.
     ///
     /// # Arguments
@@ -280,6 +512,8 @@ impl CommandManager {
         if let Some(pty_session) = active_ptys.get(command_id) {
             info!("Sending input to command ID {}: {:?}", command_id, input);
             pty_session.write_input(input).await
+        } else if self.active_piped.lock().await.contains_key(command_id) {
+            Err(anyhow!("Command with ID {} runs without a terminal and does not accept input.", command_id))
         } else {
             warn!("Command with ID {} not found or not active.", command_id);
             Err(anyhow!("Command with ID {} not found or not active.", command_id))
@@ -303,12 +537,57 @@ impl CommandManager {
             info!("Command with ID {} terminated successfully.", command_id);
             let _ = self.event_sender.send(CommandEvent::Killed { id: command_id.to_string() }).await;
             Ok(())
+        } else if let Some(job) = self.active_piped.lock().await.remove(command_id) {
+            // The command's task kills the process and reports `CommandEvent::Killed`.
+            info!("Terminating piped command with ID: {}", command_id);
+            job.kill_tx.send(()).map_err(|_| anyhow!("Command with ID {} already finished.", command_id))
         } else {
             warn!("Command with ID {} not found or not active.", command_id);
             Err(anyhow!("Command with ID {} not found or not active.", command_id))
         }
     }
 
+    /// Returns the pid of a running command. It leads the command's process group.
+    async fn command_pid(&self, command_id: &str) -> Result<u32> {
+        if let Some(pty_session) = self.active_ptys.lock().await.get(command_id) {
+            return pty_session.pid().ok_or_else(|| anyhow!("Command with ID {} has no process id.", command_id));
+        }
+        if let Some(job) = self.active_piped.lock().await.get(command_id) {
+            return Ok(job.pid);
+        }
+        warn!("Command with ID {} not found or not active.", command_id);
+        Err(anyhow!("Command with ID {} not found or not active.", command_id))
+    }
+
+    /// Sends a signal to a running command and every process in its process group.
+    ///
+    /// Stopping and continuing a command is reported through `CommandEvent::Stopped` and
+    /// `CommandEvent::Continued` (and in the output channel for piped commands).
+    ///
+    /// # Arguments
+    ///
+    /// * `command_id` - The ID of the command to signal.
+    /// * `signal` - The signal to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or an `anyhow::Error` if the command is not found or the signal can't be delivered.
+    pub async fn send_signal(&self, command_id: &str, signal: JobSignal) -> Result<()> {
+        let pid = self.command_pid(command_id).await?;
+        info!("Sending {} to command ID {} (pid {})", signal.name(), command_id, pid);
+        jobs::send_signal(pid, signal)
+    }
+
+    /// Returns the tree of processes started by a running command, with their pids.
+    ///
+    /// # Arguments
+    ///
+    /// * `command_id` - The ID of the command.
+    pub async fn process_tree(&self, command_id: &str) -> Result<ProcessInfo> {
+        let pid = self.command_pid(command_id).await?;
+        tokio::task::spawn_blocking(move || jobs::process_tree(pid)).await?
+    }
+
     /// Lists all currently active command IDs.
     ///
     /// # Returns
@@ -316,7 +595,8 @@ impl CommandManager {
     /// A `Vec<String>` containing the IDs of all active commands.
     pub async fn list_active_commands(&self) -> Vec<String> {
         let active_ptys = self.active_ptys.lock().await;
-        active_ptys.keys().cloned().collect()
+        let active_piped = self.active_piped.lock().await;
+        active_ptys.keys().chain(active_piped.keys()).cloned().collect()
     }
 }
 
@@ -393,6 +673,64 @@ mod tests {
         assert!(!active_commands.contains(&command_id));
     }
 
+    #[test]
+    fn test_spawn_env_defaults() {
+        let env = spawn_env(&HashMap::new());
+        assert_eq!(env.get("TERM").map(String::as_str), Some("xterm-256color"));
+        assert_eq!(env.get("COLORTERM").map(String::as_str), Some("truecolor"));
+
+        let mut profile = HashMap::new();
+        profile.insert("TERM".to_string(), "screen-256color".to_string());
+        profile.insert("API_URL".to_string(), "http://localhost".to_string());
+        let env = spawn_env(&profile);
+        assert_eq!(env.get("TERM").map(String::as_str), Some("screen-256color"));
+        assert_eq!(env.get("API_URL").map(String::as_str), Some("http://localhost"));
+        assert_eq!(env.get("COLORTERM").map(String::as_str), Some("truecolor"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_piped_command_gets_profile_env() {
+        let (event_tx, _event_rx) = mpsc::channel(100);
+        let manager = CommandManager::new(event_tx);
+        let (output_tx, mut output_rx) = mpsc::channel(100);
+        let mut cmd = piped_command("sh", &["-c", "echo $NEOTERM_PROFILE_VAR $COLORTERM"]);
+        cmd.env.insert("NEOTERM_PROFILE_VAR".to_string(), "staging".to_string());
+        manager.execute_command_with_output_channel(cmd, output_tx).await.unwrap();
+
+        let mut stdout = String::new();
+        while let Some(output) = output_rx.recv().await {
+            stdout.push_str(&output.stdout);
+            if output.status != CommandStatus::Running {
+                break;
+            }
+        }
+        assert_eq!(stdout.trim(), "staging truecolor");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_piped_output_is_recorded() {
+        let (event_tx, _event_rx) = mpsc::channel(100);
+        let manager = CommandManager::new(event_tx);
+        let path = std::env::temp_dir().join(format!("neoterm-{}.cast", uuid::Uuid::new_v4()));
+        let recorder = Arc::new(Recorder::create(&path, 80, 24, None).unwrap());
+        manager.set_recorder(Some(recorder.clone()));
+        let (output_tx, mut output_rx) = mpsc::channel(100);
+        manager.execute_command_with_output_channel(piped_command("sh", &["-c", "echo one; echo two"]), output_tx).await.unwrap();
+        while let Some(output) = output_rx.recv().await {
+            if output.status != CommandStatus::Running {
+                break;
+            }
+        }
+        recorder.finish().unwrap();
+
+        let cast = crate::asciicast::Cast::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let events: Vec<&str> = cast.events.iter().map(|event| event.data.as_str()).collect();
+        assert_eq!(events, vec!["sh -c echo one; echo two", "one\r\n", "two\r\n"]);
+    }
+
     #[tokio::test]
     async fn test_send_input_to_non_existent_command() {
         let (tx, mut rx) = mpsc::channel(100);
@@ -427,6 +765,7 @@ mod tests {
             env: HashMap::new(),
             working_dir: None,
             output_format: CommandOutputFormat::PlainText,
+            execution_mode: ExecutionMode::Auto,
         };
 
         manager.execute_command_with_output_channel(cmd, output_tx).await.unwrap();
@@ -455,6 +794,84 @@ mod tests {
         assert!(!active_commands.contains(&command_id));
     }
 
+    #[cfg(unix)]
+    fn piped_command(executable: &str, args: &[&str]) -> Command {
+        Command {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: executable.to_string(),
+            description: String::new(),
+            executable: executable.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            env: HashMap::new(),
+            working_dir: None,
+            output_format: CommandOutputFormat::PlainText,
+            execution_mode: ExecutionMode::Piped,
+        }
+    }
+
+    /// Waits for the next status other than `Running` with output, skipping output chunks.
+    #[cfg(unix)]
+    async fn next_status(output_rx: &mut mpsc::Receiver<CommandOutput>) -> CommandStatus {
+        loop {
+            let output = tokio::time::timeout(Duration::from_secs(5), output_rx.recv())
+                .await
+                .expect("timed out waiting for a status")
+                .expect("output channel closed");
+            if output.stdout.is_empty() && output.stderr.is_empty() {
+                return output.status;
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stop_continue_and_signal_piped_command() {
+        let (event_tx, _event_rx) = mpsc::channel(100);
+        let manager = CommandManager::new(event_tx);
+        let (output_tx, mut output_rx) = mpsc::channel(100);
+        let cmd = piped_command("sleep", &["5"]);
+        let command_id = cmd.id.clone();
+        manager.execute_command_with_output_channel(cmd, output_tx).await.unwrap();
+
+        manager.send_signal(&command_id, JobSignal::Stop).await.unwrap();
+        let status = next_status(&mut output_rx).await;
+        assert_eq!(status, CommandStatus::Stopped(libc::SIGTSTP));
+        assert_eq!(status.to_string(), "stopped (SIGTSTP)");
+
+        manager.send_signal(&command_id, JobSignal::Continue).await.unwrap();
+        assert_eq!(next_status(&mut output_rx).await, CommandStatus::Running);
+
+        manager.send_signal(&command_id, JobSignal::Kill).await.unwrap();
+        let status = next_status(&mut output_rx).await;
+        assert_eq!(status, CommandStatus::Signaled(libc::SIGKILL));
+        assert_eq!(status.to_string(), "killed by signal 9 (SIGKILL)");
+
+        sleep(Duration::from_millis(100)).await;
+        assert!(manager.send_signal(&command_id, JobSignal::Interrupt).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_interrupt_reaches_the_whole_job() {
+        let (event_tx, _event_rx) = mpsc::channel(100);
+        let manager = CommandManager::new(event_tx);
+        let (output_tx, mut output_rx) = mpsc::channel(100);
+        // `cat` waits on a pipe that stays open, so only a signal to the group ends it.
+        let cmd = piped_command("sh", &["-c", "sleep 5 | cat"]);
+        let command_id = cmd.id.clone();
+        manager.execute_command_with_output_channel(cmd, output_tx).await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+
+        let tree = manager.process_tree(&command_id).await.unwrap();
+        let rendered = tree.render();
+        assert!(rendered.contains("sleep"), "{}", rendered);
+        assert!(rendered.contains("cat"), "{}", rendered);
+        assert!(tree.pids().len() >= 3);
+
+        manager.send_signal(&command_id, JobSignal::Interrupt).await.unwrap();
+        assert_eq!(next_status(&mut output_rx).await, CommandStatus::Signaled(libc::SIGINT));
+    }
+
     #[tokio::test]
     async fn test_pty_session_drop_kills_child() {
         #[cfg(unix)]
@@ -0,0 +1,334 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{ChildStderr, ChildStdout};
+use tokio::sync::mpsc;
+
+use super::usage::CommandStats;
+use super::Command as CommandSpec;
+
+/// Programs that need a terminal to work and are therefore always run in a PTY.
+const INTERACTIVE_PROGRAMS: &[&str] = &[
+    "vi", "vim", "nvim", "nano", "emacs", "micro", "helix", "hx",
+    "less", "more", "most", "man",
+    "top", "htop", "btop", "atop", "watch",
+    "ssh", "mosh", "telnet", "ftp", "sftp",
+    "tmux", "screen", "zellij",
+    "sudo", "su", "passwd", "fzf",
+    "mysql", "psql", "sqlite3", "redis-cli", "mongo", "mongosh",
+];
+
+/// Programs that start an interactive REPL when run without arguments.
+const REPL_PROGRAMS: &[&str] = &[
+    "sh", "bash", "zsh", "fish", "dash",
+    "python", "python3", "ipython", "node", "irb", "ghci", "lua", "R", "julia",
+];
+
+/// Returns true if `executable` with `args` is expected to need a terminal.
+pub fn is_interactive(executable: &str, args: &[String]) -> bool {
+    let name = std::path::Path::new(executable)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(executable);
+    INTERACTIVE_PROGRAMS.contains(&name) || (args.is_empty() && REPL_PROGRAMS.contains(&name))
+}
+
+/// A change in the state of a watched piped command.
+#[derive(Debug, Clone)]
+pub enum JobEvent {
+    /// The command was stopped by the given signal.
+    Stopped(i32),
+    /// The stopped command was resumed.
+    Continued,
+    /// The command exited.
+    Exited(CommandStats),
+}
+
+/// A command running without a PTY, with stdout and stderr connected to separate pipes.
+///
+/// The child is reaped with `wait4` rather than by tokio so that its resource usage
+/// can be reported. On unix it leads its own process group, so job control signals
+/// reach everything it starts.
+pub struct PipedProcess {
+    child: Child,
+    started: Instant,
+    /// Set once the child has been reaped; its pid must not be signalled afterwards.
+    reaped: Arc<AtomicBool>,
+    /// Set once `watch` handed reaping to a background thread.
+    watched: bool,
+    stdout: Option<BufReader<ChildStdout>>,
+    stderr: Option<BufReader<ChildStderr>>,
+    // Partial lines survive a cancelled `read_until` in `next_line`.
+    stdout_buf: Vec<u8>,
+    stderr_buf: Vec<u8>,
+}
+
+impl PipedProcess {
+    /// Spawns the command with piped stdout/stderr and no stdin.
+    pub fn spawn(spec: &CommandSpec) -> Result<Self> {
+        info!("Spawning piped command: {} {:?}", spec.executable, spec.args);
+
+        let mut command = Command::new(&spec.executable);
+        command
+            .args(&spec.args)
+            .envs(super::spawn_env(&spec.env))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(dir) = &spec.working_dir {
+            command.current_dir(dir);
+        }
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| anyhow!("Failed to spawn command '{}': {}", spec.executable, e))?;
+        let started = Instant::now();
+        let stdout = child.stdout.take().map(ChildStdout::from_std).transpose()?.map(BufReader::new);
+        let stderr = child.stderr.take().map(ChildStderr::from_std).transpose()?.map(BufReader::new);
+
+        Ok(Self {
+            child,
+            started,
+            reaped: Arc::new(AtomicBool::new(false)),
+            watched: false,
+            stdout,
+            stderr,
+            stdout_buf: Vec::new(),
+            stderr_buf: Vec::new(),
+        })
+    }
+
+    /// Returns the next line from either stream as `(line, is_stdout)`, in the order the
+    /// lines become available, or `None` once both streams are closed.
+    /// The trailing newline is stripped.
+    pub async fn next_line(&mut self) -> Option<(String, bool)> {
+        loop {
+            let (result, is_stdout) = match (self.stdout.as_mut(), self.stderr.as_mut()) {
+                (None, None) => return None,
+                (Some(out), None) => (out.read_until(b'\n', &mut self.stdout_buf).await, true),
+                (None, Some(err)) => (err.read_until(b'\n', &mut self.stderr_buf).await, false),
+                (Some(out), Some(err)) => tokio::select! {
+                    result = out.read_until(b'\n', &mut self.stdout_buf) => (result, true),
+                    result = err.read_until(b'\n', &mut self.stderr_buf) => (result, false),
+                },
+            };
+
+            let buf = if is_stdout { &mut self.stdout_buf } else { &mut self.stderr_buf };
+            let at_eof = !matches!(result, Ok(n) if n > 0);
+            if at_eof {
+                if is_stdout { self.stdout = None } else { self.stderr = None }
+            }
+            if !buf.is_empty() {
+                let line = String::from_utf8_lossy(buf)
+                    .trim_end_matches('\n')
+                    .trim_end_matches('\r')
+                    .to_string();
+                buf.clear();
+                return Some((line, is_stdout));
+            }
+        }
+    }
+
+    /// Returns the pid of the command, which is also its process group id on unix.
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Starts watching the command for stops, continues and its exit on a background
+    /// thread, which also reaps it. `wait` can't be used afterwards.
+    ///
+    /// # Returns
+    ///
+    /// A receiver of `JobEvent`s that ends with `JobEvent::Exited`. On platforms without
+    /// job control it closes immediately and `wait` must be used instead.
+    pub fn watch(&mut self) -> mpsc::UnboundedReceiver<JobEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        #[cfg(unix)]
+        {
+            use super::jobs::{self, ChildChange};
+
+            self.watched = true;
+            let pid = self.child.id();
+            let started = self.started;
+            let reaped = self.reaped.clone();
+            std::thread::spawn(move || loop {
+                let event = match jobs::wait_for_change(pid) {
+                    Ok(ChildChange::Stopped(signal)) => JobEvent::Stopped(signal),
+                    Ok(ChildChange::Continued) => JobEvent::Continued,
+                    Ok(ChildChange::Exited(status, usage)) => {
+                        reaped.store(true, Ordering::SeqCst);
+                        let _ = tx.send(JobEvent::Exited(CommandStats::from_exit_status(started.elapsed(), &status, Some(usage))));
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("Stopped watching process {}: {}", pid, e);
+                        break;
+                    }
+                };
+                let _ = tx.send(event);
+            });
+        }
+        #[cfg(not(unix))]
+        drop(tx);
+        rx
+    }
+
+    /// Waits for the command to exit and returns its timing, exit status and resource usage.
+    pub async fn wait(&mut self) -> Result<CommandStats> {
+        if self.watched {
+            return Err(anyhow!("Child process is reaped by its watcher"));
+        }
+        if self.reaped.load(Ordering::SeqCst) {
+            return Err(anyhow!("Child process was already waited for"));
+        }
+
+        #[cfg(unix)]
+        let (status, usage) = {
+            let pid = self.child.id();
+            let (status, usage) = tokio::task::spawn_blocking(move || super::usage::wait4(pid))
+                .await
+                .map_err(|e| anyhow!("Failed to join wait task: {}", e))??;
+            (status, Some(usage))
+        };
+        #[cfg(not(unix))]
+        let (status, usage) = loop {
+            match self.child.try_wait() {
+                Ok(Some(status)) => break (status, None),
+                Ok(None) => tokio::time::sleep(std::time::Duration::from_millis(20)).await,
+                Err(e) => return Err(anyhow!("Failed to wait for child process: {}", e)),
+            }
+        };
+
+        self.reaped.store(true, Ordering::SeqCst);
+        Ok(CommandStats::from_exit_status(self.started.elapsed(), &status, usage))
+    }
+
+    /// Kills the command along with everything it started.
+    pub async fn terminate(&mut self) -> Result<()> {
+        if self.reaped.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.kill()
+    }
+
+    /// Sends SIGKILL to the command's process group, so processes it started, such as the
+    /// stages of a pipeline, don't outlive it.
+    #[cfg(unix)]
+    fn kill(&mut self) -> Result<()> {
+        super::jobs::send_signal(self.pid(), super::jobs::JobSignal::Kill)
+    }
+
+    #[cfg(not(unix))]
+    fn kill(&mut self) -> Result<()> {
+        self.child
+            .kill()
+            .map_err(|e| anyhow!("Failed to kill child process: {}", e))
+    }
+}
+
+impl Drop for PipedProcess {
+    fn drop(&mut self) {
+        if !self.reaped.load(Ordering::SeqCst) {
+            // Don't leave a running process or a zombie behind. A watched process is
+            // reaped by its watcher thread.
+            let _ = self.kill();
+            if !self.watched {
+                let _ = self.child.wait();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn spec(executable: &str, args: &[&str]) -> CommandSpec {
+        CommandSpec {
+            id: "test".to_string(),
+            name: executable.to_string(),
+            description: String::new(),
+            executable: executable.to_string(),
+            args: args.iter().map(|s| s.to_string()).collect(),
+            env: HashMap::new(),
+            working_dir: None,
+            output_format: super::super::CommandOutputFormat::PlainText,
+            execution_mode: super::super::ExecutionMode::Piped,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_piped_process_separates_streams() {
+        let mut process = PipedProcess::spawn(&spec("sh", &["-c", "echo out; sleep 0.1; echo err >&2; sleep 0.1; printf tail; exit 3"])).unwrap();
+        let mut lines = Vec::new();
+        while let Some(line) = process.next_line().await {
+            lines.push(line);
+        }
+        assert_eq!(lines, vec![
+            ("out".to_string(), true),
+            ("err".to_string(), false),
+            ("tail".to_string(), true),
+        ]);
+        let stats = process.wait().await.unwrap();
+        assert_eq!(stats.exit_code, Some(3));
+        assert!(stats.wall_time >= std::time::Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_piped_process_terminate() {
+        let mut process = PipedProcess::spawn(&spec("sleep", &["5"])).unwrap();
+        process.terminate().await.unwrap();
+        let stats = process.wait().await.unwrap();
+        assert_eq!(stats.exit_code, None);
+        assert_eq!(stats.signal_name().as_deref(), Some("SIGKILL"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_terminate_kills_process_group() {
+        let mut process = PipedProcess::spawn(&spec("sh", &["-c", "sleep 100 | cat"])).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        process.terminate().await.unwrap();
+        // `sleep` and `cat` hold the output pipes open until they are killed too.
+        let line = tokio::time::timeout(std::time::Duration::from_secs(5), process.next_line()).await;
+        assert_eq!(line.expect("the pipeline outlived its shell"), None);
+        let stats = process.wait().await.unwrap();
+        assert_eq!(stats.signal_name().as_deref(), Some("SIGKILL"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_watch_reports_stop_and_continue() {
+        use super::super::jobs::{send_signal, JobSignal};
+
+        let mut process = PipedProcess::spawn(&spec("sh", &["-c", "sleep 0.3; echo done"])).unwrap();
+        let mut events = process.watch();
+        assert!(process.wait().await.is_err());
+
+        send_signal(process.pid(), JobSignal::Stop).unwrap();
+        assert!(matches!(events.recv().await, Some(JobEvent::Stopped(signal)) if signal == libc::SIGTSTP));
+        send_signal(process.pid(), JobSignal::Continue).unwrap();
+        assert!(matches!(events.recv().await, Some(JobEvent::Continued)));
+
+        assert_eq!(process.next_line().await, Some(("done".to_string(), true)));
+        match events.recv().await {
+            Some(JobEvent::Exited(stats)) => assert_eq!(stats.exit_code, Some(0)),
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_is_interactive() {
+        assert!(is_interactive("vim", &["file.txt".to_string()]));
+        assert!(is_interactive("/usr/bin/python3", &[]));
+        assert!(!is_interactive("python3", &["script.py".to_string()]));
+        assert!(!is_interactive("ls", &["-la".to_string()]));
+    }
+}
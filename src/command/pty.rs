@@ -1,11 +1,17 @@
 use anyhow::{anyhow, Result};
 use log::{error, info};
+use std::collections::HashMap;
 use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::process::{Child, Command};
 use tokio::task;
 
+use super::usage::CommandStats;
+
 #[cfg(unix)]
 use portable_pty::{native_pty, CommandBuilder, PtySize};
 #[cfg(windows)]
@@ -18,11 +24,24 @@ pub struct PtySession {
     reader: Arc<Mutex<Box<dyn Read + Send>>>,
     writer: Arc<Mutex<Box<dyn Write + Send>>>,
     _child: Arc<Mutex<Child>>, // Keep child handle to ensure process is managed
+    pid: Option<u32>,
+    started: Instant,
+    /// The stats of the exited command. Kept so that a `wait` cancelled by `select!`
+    /// doesn't lose the exit status the next call needs.
+    exit: Arc<Mutex<Option<CommandStats>>>,
+    /// Set once the child has been reaped; its pid must not be signalled afterwards.
+    reaped: Arc<AtomicBool>,
 }
 
 impl PtySession {
     /// Creates a new PTY session and spawns a command within it.
     pub async fn new(command: &str, args: &[&str]) -> Result<Self> {
+        Self::with_env(command, args, &HashMap::new(), None).await
+    }
+
+    /// Creates a new PTY session and spawns a command within it, with `env` on top of
+    /// the terminal defaults and, if given, in `working_dir`.
+    pub async fn with_env(command: &str, args: &[&str], env: &HashMap<String, String>, working_dir: Option<&Path>) -> Result<Self> {
         info!("Spawning PTY for command: {} {:?}", command, args);
 
         let pty_system = native_pty().map_err(|e| anyhow!("Failed to create native PTY system: {}", e))?;
@@ -38,11 +57,20 @@ impl PtySession {
 
         let mut cmd_builder = CommandBuilder::new(command);
         cmd_builder.args(args);
+        for (key, value) in super::spawn_env(env) {
+            cmd_builder.env(key, value);
+        }
+        if let Some(dir) = working_dir {
+            cmd_builder.cwd(dir);
+        }
 
         let child = pair
             .slave
             .spawn_command(cmd_builder)
             .map_err(|e| anyhow!("Failed to spawn command in PTY: {}", e))?;
+        let started = Instant::now();
+        // Captured up front: `wait` blocks for as long as the command runs.
+        let pid = child.id();
 
         let master_reader = pair.master.try_clone_reader().map_err(|e| anyhow!("Failed to clone PTY reader: {}", e))?;
         let master_writer = pair.master.try_clone_writer().map_err(|e| anyhow!("Failed to clone PTY writer: {}", e))?;
@@ -52,9 +80,18 @@ impl PtySession {
             reader: Arc::new(Mutex::new(master_reader)),
             writer: Arc::new(Mutex::new(master_writer)),
             _child: Arc::new(Mutex::new(child)),
+            pid,
+            started,
+            exit: Arc::new(Mutex::new(None)),
+            reaped: Arc::new(AtomicBool::new(false)),
         })
     }
 
+    /// Returns the pid of the command. The PTY makes it a session and process group leader.
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
     /// Reads output from the PTY.
     pub async fn read_output(&mut self, buffer: &mut [u8]) -> Result<usize> {
         let reader_clone = self.reader.clone();
@@ -77,18 +114,49 @@ impl PtySession {
         .await?
     }
 
-    /// Waits for the command running in the PTY to exit.
-    pub async fn wait(&self) -> Result<std::process::ExitStatus> {
+    /// Waits for the command running in the PTY to exit and returns its stats.
+    ///
+    /// On unix the child is reaped with `wait4`, so its resource usage is reported as for
+    /// piped commands. The stats are kept, so the call may be cancelled and repeated.
+    pub async fn wait(&self) -> Result<CommandStats> {
+        let exit = self.exit.clone();
+        let reaped = self.reaped.clone();
+        let started = self.started;
+        #[cfg(unix)]
+        let pid = self.pid;
+        #[cfg(not(unix))]
         let child_clone = self._child.clone();
         task::spawn_blocking(move || {
-            let mut child = child_clone.lock().unwrap();
-            child.wait().map_err(|e| anyhow!("Failed to wait for child process: {}", e))
+            let mut exit = exit.lock().unwrap();
+            if let Some(stats) = exit.as_ref() {
+                return Ok(stats.clone());
+            }
+
+            #[cfg(unix)]
+            let stats = {
+                let pid = pid.ok_or_else(|| anyhow!("PTY command has no process id"))?;
+                let (status, usage) = super::usage::wait4(pid)?;
+                CommandStats::from_exit_status(started.elapsed(), &status, Some(usage))
+            };
+            #[cfg(not(unix))]
+            let stats = {
+                let mut child = child_clone.lock().unwrap();
+                let status = child.wait().map_err(|e| anyhow!("Failed to wait for child process: {}", e))?;
+                CommandStats::from_exit_status(started.elapsed(), &status, None)
+            };
+
+            reaped.store(true, Ordering::SeqCst);
+            *exit = Some(stats.clone());
+            Ok(stats)
         })
         .await?
     }
 
     /// Terminates the command running in the PTY.
     pub async fn terminate(&self) -> Result<()> {
+        if self.reaped.load(Ordering::SeqCst) {
+            return Ok(());
+        }
         let child_clone = self._child.clone();
         task::spawn_blocking(move || {
             let mut child = child_clone.lock().unwrap();
@@ -120,6 +188,9 @@ impl Drop for PtySession {
     /// This is a best-effort attempt as `drop` cannot be async.
     fn drop(&mut self) {
         info!("Dropping PtySession for child process.");
+        if self.reaped.load(Ordering::SeqCst) {
+            return;
+        }
         let child_clone = self._child.clone();
         tokio::task::block_in_place(move || {
             let mut child = child_clone.lock().unwrap();
@@ -148,8 +219,8 @@ mod tests {
         let n = pty.read_output(&mut buf).await.unwrap();
         let output = String::from_utf8_lossy(&buf[..n]);
         assert!(output.contains("hello world"));
-        let status = pty.wait().await.unwrap();
-        assert!(status.success());
+        let stats = pty.wait().await.unwrap();
+        assert_eq!(stats.exit_code, Some(0));
     }
 
     #[tokio::test]
@@ -167,8 +238,8 @@ mod tests {
         assert!(output.contains("test input"));
 
         pty.terminate().await.unwrap();
-        let status = pty.wait().await.unwrap();
-        assert!(!status.success()); // Should be killed
+        let stats = pty.wait().await.unwrap();
+        assert_ne!(stats.exit_code, Some(0)); // Should be killed
     }
 
     #[tokio::test]
@@ -179,8 +250,8 @@ mod tests {
         let pty = PtySession::new("ping", &["-n", "5", "127.0.0.1"]).await.unwrap();
 
         pty.terminate().await.unwrap();
-        let status = pty.wait().await.unwrap();
-        assert!(!status.success()); // Should be killed
+        let stats = pty.wait().await.unwrap();
+        assert_ne!(stats.exit_code, Some(0)); // Should be killed
     }
 
     #[tokio::test]
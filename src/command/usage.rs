@@ -0,0 +1,445 @@
+//! Timing, exit status and resource usage of finished commands.
+//!
+//! Directly spawned commands are reaped with `wait4(2)`, which reports their rusage.
+//! Commands run inside the persistent shell are grandchildren of NeoTerm, so their usage
+//! is derived from the shell's accumulated child totals instead, which the kernel only
+//! exposes partially: see `children_usage`.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// Resource usage of a finished command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// CPU time spent in user mode.
+    pub user_time: Duration,
+    /// CPU time spent in the kernel.
+    pub system_time: Duration,
+    /// Peak resident set size in KiB. Not known for commands run inside the shell.
+    pub max_rss_kb: Option<u64>,
+    /// Number of block input operations (`ru_inblock`). Not known for commands run inside the shell.
+    pub block_input_ops: Option<u64>,
+    /// Number of block output operations (`ru_oublock`). Not known for commands run inside the shell.
+    pub block_output_ops: Option<u64>,
+    /// Bytes read from storage. Only known for commands run inside the shell on Linux.
+    #[serde(default)]
+    pub read_bytes: Option<u64>,
+    /// Bytes written to storage. Only known for commands run inside the shell on Linux.
+    #[serde(default)]
+    pub write_bytes: Option<u64>,
+}
+
+impl ResourceUsage {
+    /// Returns the usage accrued since `earlier`, a snapshot of the same counters.
+    pub fn since(&self, earlier: &ResourceUsage) -> ResourceUsage {
+        ResourceUsage {
+            user_time: self.user_time.saturating_sub(earlier.user_time),
+            system_time: self.system_time.saturating_sub(earlier.system_time),
+            max_rss_kb: self.max_rss_kb,
+            block_input_ops: counter_since(self.block_input_ops, earlier.block_input_ops),
+            block_output_ops: counter_since(self.block_output_ops, earlier.block_output_ops),
+            read_bytes: counter_since(self.read_bytes, earlier.read_bytes),
+            write_bytes: counter_since(self.write_bytes, earlier.write_bytes),
+        }
+    }
+}
+
+/// Returns the growth of a counter between two snapshots, if both have it.
+fn counter_since(now: Option<u64>, earlier: Option<u64>) -> Option<u64> {
+    Some(now?.saturating_sub(earlier?))
+}
+
+/// How a finished command ran: how long it took, how it exited and what it used.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct CommandStats {
+    /// Time between the command starting and finishing.
+    pub wall_time: Duration,
+    /// The exit code, if the command exited normally (or the shell reported one).
+    pub exit_code: Option<i32>,
+    /// The signal that terminated the command, or stopped it if `stopped` is set.
+    pub signal: Option<i32>,
+    /// The command didn't finish but was stopped (e.g. by Ctrl+Z) and can be resumed.
+    #[serde(default)]
+    pub stopped: bool,
+    /// Resource usage, when the platform can report it.
+    pub usage: Option<ResourceUsage>,
+}
+
+impl CommandStats {
+    /// Builds stats for a command that was waited on directly.
+    pub fn from_exit_status(wall_time: Duration, status: &ExitStatus, usage: Option<ResourceUsage>) -> Self {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(status);
+        #[cfg(not(unix))]
+        let signal = None;
+
+        Self {
+            wall_time,
+            exit_code: status.code(),
+            signal,
+            stopped: false,
+            usage,
+        }
+    }
+
+    /// Builds stats from a status reported by a shell (`$?`).
+    ///
+    /// Shells report a command killed by signal N as `128 + N`, so such statuses are
+    /// also recorded as a signal. A command that itself exits with e.g. 130 is
+    /// indistinguishable from one interrupted by SIGINT. A stop signal means the job
+    /// was suspended rather than terminated.
+    pub fn from_shell_status(wall_time: Duration, status: i32, usage: Option<ResourceUsage>) -> Self {
+        let signal = (129..=128 + 64).contains(&status).then(|| status - 128);
+        Self {
+            wall_time,
+            exit_code: Some(status),
+            signal,
+            stopped: signal.is_some_and(is_stop_signal),
+            usage,
+        }
+    }
+
+    /// Returns the name of the terminating signal, e.g. `SIGINT`.
+    pub fn signal_name(&self) -> Option<String> {
+        self.signal.map(signal_name)
+    }
+
+    /// Returns a one-line summary for the block header,
+    /// e.g. `1.204s · user 0.84s · sys 0.12s · 35.2 MiB · 96 in / 8 out`, where the block
+    /// counts are replaced by e.g. `4.0 KiB read / 1 KiB written` for commands run in the shell.
+    pub fn summary(&self) -> String {
+        let mut parts = vec![format_duration(self.wall_time)];
+        if let Some(usage) = &self.usage {
+            parts.push(format!("user {:.2}s", usage.user_time.as_secs_f64()));
+            parts.push(format!("sys {:.2}s", usage.system_time.as_secs_f64()));
+            if let Some(max_rss_kb) = usage.max_rss_kb {
+                parts.push(format_kb(max_rss_kb));
+            }
+            match (usage.block_input_ops, usage.block_output_ops) {
+                (Some(input), Some(output)) if input > 0 || output > 0 => parts.push(format!("{} in / {} out", input, output)),
+                _ => {}
+            }
+            match (usage.read_bytes, usage.write_bytes) {
+                (Some(read), Some(written)) if read > 0 || written > 0 => {
+                    parts.push(format!("{} read / {} written", format_bytes(read), format_bytes(written)))
+                }
+                _ => {}
+            }
+        }
+        match self.signal_name() {
+            Some(name) if self.stopped => parts.push(format!("stopped by {}", name)),
+            Some(name) => parts.push(format!("killed by {}", name)),
+            None => {}
+        }
+        parts.join(" · ")
+    }
+
+    /// Returns a multi-line description for AI context and exports.
+    pub fn report(&self) -> String {
+        let mut lines = vec![format!("Wall time: {}", format_duration(self.wall_time))];
+        match (self.exit_code, self.signal_name()) {
+            (_, Some(name)) if self.stopped => lines.push(format!("Stopped by signal: {}", name)),
+            (_, Some(name)) => lines.push(format!("Terminated by signal: {}", name)),
+            (Some(code), None) => lines.push(format!("Exit code: {}", code)),
+            (None, None) => {}
+        }
+        if let Some(usage) = &self.usage {
+            lines.push(format!("User CPU: {:.3}s", usage.user_time.as_secs_f64()));
+            lines.push(format!("System CPU: {:.3}s", usage.system_time.as_secs_f64()));
+            if let Some(max_rss_kb) = usage.max_rss_kb {
+                lines.push(format!("Max RSS: {}", format_kb(max_rss_kb)));
+            }
+            if let (Some(input), Some(output)) = (usage.block_input_ops, usage.block_output_ops) {
+                lines.push(format!("Block I/O: {} in / {} out", input, output));
+            }
+            if let (Some(read), Some(written)) = (usage.read_bytes, usage.write_bytes) {
+                lines.push(format!("Storage I/O: {} read / {} written", format_bytes(read), format_bytes(written)));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// Formats a wall time as `0.042s`, `12.310s` or `3m 05.2s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+    if secs < 60.0 {
+        format!("{:.3}s", secs)
+    } else {
+        let minutes = (secs / 60.0).floor();
+        format!("{}m {:04.1}s", minutes as u64, secs - minutes * 60.0)
+    }
+}
+
+/// Formats a KiB count with a binary unit.
+fn format_kb(kb: u64) -> String {
+    if kb >= 1024 * 1024 {
+        format!("{:.1} GiB", kb as f64 / (1024.0 * 1024.0))
+    } else if kb >= 1024 {
+        format!("{:.1} MiB", kb as f64 / 1024.0)
+    } else {
+        format!("{} KiB", kb)
+    }
+}
+
+/// Formats a byte count with a binary unit.
+fn format_bytes(bytes: u64) -> String {
+    if bytes >= 1024 {
+        format_kb(bytes / 1024)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// Returns true for signals that suspend a process instead of terminating it.
+#[cfg(unix)]
+pub fn is_stop_signal(signal: i32) -> bool {
+    matches!(signal, libc::SIGSTOP | libc::SIGTSTP | libc::SIGTTIN | libc::SIGTTOU)
+}
+
+#[cfg(not(unix))]
+pub fn is_stop_signal(_signal: i32) -> bool {
+    false
+}
+
+/// Returns the name of a signal, e.g. `SIGINT`, or `signal N` for unlisted ones.
+#[cfg(unix)]
+pub fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGCHLD => "SIGCHLD",
+        libc::SIGCONT => "SIGCONT",
+        libc::SIGSTOP => "SIGSTOP",
+        libc::SIGTSTP => "SIGTSTP",
+        libc::SIGTTIN => "SIGTTIN",
+        libc::SIGTTOU => "SIGTTOU",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        _ => return format!("signal {}", signal),
+    };
+    name.to_string()
+}
+
+#[cfg(not(unix))]
+pub fn signal_name(signal: i32) -> String {
+    format!("signal {}", signal)
+}
+
+/// Blocks until the child `pid` exits and reaps it, returning its status and rusage.
+#[cfg(unix)]
+pub fn wait4(pid: u32) -> Result<(ExitStatus, ResourceUsage)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let (status, usage) = wait4_raw(pid, 0)?;
+    Ok((ExitStatus::from_raw(status), usage))
+}
+
+/// Calls `wait4(2)` for `pid` with `options`, retrying on EINTR.
+///
+/// # Returns
+///
+/// The raw wait status and, if the child was reaped, its rusage.
+#[cfg(unix)]
+pub(super) fn wait4_raw(pid: u32, options: libc::c_int) -> Result<(libc::c_int, ResourceUsage)> {
+    let mut status = 0;
+    // SAFETY: an all-zero `rusage` is a valid value, and both pointers outlive the call.
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: see above; `wait4` only writes through the provided pointers.
+        let result = unsafe { libc::wait4(pid as libc::pid_t, &mut status, options, &mut rusage) };
+        if result == pid as libc::pid_t {
+            break;
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(anyhow!("wait4 failed for process {}: {}", pid, error));
+        }
+    }
+
+    let timeval = |tv: libc::timeval| Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64);
+    // macOS reports ru_maxrss in bytes, other unixes in KiB.
+    let max_rss_kb = if cfg!(target_os = "macos") { rusage.ru_maxrss as u64 / 1024 } else { rusage.ru_maxrss as u64 };
+    Ok((status, ResourceUsage {
+        user_time: timeval(rusage.ru_utime),
+        system_time: timeval(rusage.ru_stime),
+        max_rss_kb: Some(max_rss_kb),
+        block_input_ops: Some(rusage.ru_inblock as u64),
+        block_output_ops: Some(rusage.ru_oublock as u64),
+        read_bytes: None,
+        write_bytes: None,
+    }))
+}
+
+/// Returns the accumulated usage of all children the process `pid` has reaped so far.
+///
+/// The shell reaps each command it runs, so the difference between two snapshots taken
+/// around a command is that command's usage. The kernel keeps no per-child peak RSS or
+/// block counts for another process, so those are left unset. On Linux the CPU times come
+/// from `/proc/<pid>/stat` and the bytes read and written from `/proc/<pid>/io`; on macOS
+/// the CPU times come from `proc_pid_rusage`. Other platforms report no usage.
+#[cfg(target_os = "linux")]
+pub fn children_usage(pid: u32) -> Option<ResourceUsage> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let (user_ticks, system_ticks) = parse_child_cpu_ticks(&stat)?;
+    // SAFETY: sysconf has no preconditions.
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_second <= 0 {
+        return None;
+    }
+    let ticks = |t: u64| Duration::from_secs_f64(t as f64 / ticks_per_second as f64);
+
+    // Reaped children's I/O is folded into the parent's counters, together with the
+    // shell's own (small) I/O. Reading it needs the same permissions as ptrace.
+    let io = std::fs::read_to_string(format!("/proc/{}/io", pid)).ok().and_then(|io| parse_io_bytes(&io));
+
+    Some(ResourceUsage {
+        user_time: ticks(user_ticks),
+        system_time: ticks(system_ticks),
+        max_rss_kb: None,
+        block_input_ops: None,
+        block_output_ops: None,
+        read_bytes: io.map(|(read, _)| read),
+        write_bytes: io.map(|(_, written)| written),
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub fn children_usage(pid: u32) -> Option<ResourceUsage> {
+    // SAFETY: an all-zero `rusage_info_v2` is a valid value, and the kernel writes at most
+    // the size of the requested flavor into it.
+    let mut info: libc::rusage_info_v2 = unsafe { std::mem::zeroed() };
+    // SAFETY: see above; the buffer outlives the call.
+    let result = unsafe { libc::proc_pid_rusage(pid as libc::c_int, libc::RUSAGE_INFO_V2, (&mut info as *mut libc::rusage_info_v2).cast()) };
+    if result != 0 {
+        return None;
+    }
+
+    // The times are in mach absolute time units, which are not nanoseconds on Apple silicon.
+    let mut timebase = libc::mach_timebase_info { numer: 0, denom: 0 };
+    // SAFETY: `mach_timebase_info` only writes through the provided pointer.
+    #[allow(deprecated)]
+    let _ = unsafe { libc::mach_timebase_info(&mut timebase) };
+    if timebase.denom == 0 {
+        return None;
+    }
+    let time = |t: u64| Duration::from_nanos((t as u128 * timebase.numer as u128 / timebase.denom as u128) as u64);
+
+    Some(ResourceUsage {
+        user_time: time(info.ri_child_user_time),
+        system_time: time(info.ri_child_system_time),
+        ..ResourceUsage::default()
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn children_usage(_pid: u32) -> Option<ResourceUsage> {
+    None
+}
+
+/// Extracts `cutime` and `cstime` (fields 16 and 17) from `/proc/<pid>/stat`.
+#[cfg(any(target_os = "linux", test))]
+fn parse_child_cpu_ticks(stat: &str) -> Option<(u64, u64)> {
+    // The command name in field 2 may contain spaces, so count fields after its closing ')'.
+    let rest = &stat[stat.rfind(')')? + 1..];
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    // `fields[0]` is field 3 (state).
+    Some((fields.get(13)?.parse().ok()?, fields.get(14)?.parse().ok()?))
+}
+
+/// Extracts `read_bytes` and `write_bytes` from `/proc/<pid>/io`.
+#[cfg(any(target_os = "linux", test))]
+fn parse_io_bytes(io: &str) -> Option<(u64, u64)> {
+    let value = |key: &str| {
+        io.lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+            .and_then(|v| v.trim().parse().ok())
+    };
+    Some((value("read_bytes")?, value("write_bytes")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_status_signal() {
+        let stats = CommandStats::from_shell_status(Duration::from_millis(1500), 130, None);
+        assert_eq!(stats.exit_code, Some(130));
+        assert_eq!(stats.signal, Some(2));
+        assert!(CommandStats::from_shell_status(Duration::ZERO, 1, None).signal.is_none());
+
+        // Ctrl+Z in an interactive shell: 128 + SIGTSTP.
+        let stopped = CommandStats::from_shell_status(Duration::from_secs(2), 128 + libc::SIGTSTP, None);
+        assert!(stopped.stopped);
+        assert_eq!(stopped.summary(), "2.000s · stopped by SIGTSTP");
+    }
+
+    #[test]
+    fn test_summary() {
+        let stats = CommandStats {
+            wall_time: Duration::from_millis(1204),
+            exit_code: Some(0),
+            signal: None,
+            stopped: false,
+            usage: Some(ResourceUsage {
+                user_time: Duration::from_millis(840),
+                system_time: Duration::from_millis(120),
+                max_rss_kb: Some(36045),
+                block_input_ops: Some(96),
+                block_output_ops: Some(8),
+                read_bytes: None,
+                write_bytes: None,
+            }),
+        };
+        assert_eq!(stats.summary(), "1.204s · user 0.84s · sys 0.12s · 35.2 MiB · 96 in / 8 out");
+
+        // Commands run in the shell only have byte counts.
+        let in_shell = ResourceUsage { read_bytes: Some(4096), write_bytes: Some(512), ..ResourceUsage::default() };
+        let earlier = ResourceUsage { read_bytes: Some(0), write_bytes: Some(0), ..ResourceUsage::default() };
+        let stats = CommandStats { usage: Some(in_shell.since(&earlier)), ..stats };
+        assert_eq!(stats.summary(), "1.204s · user 0.00s · sys 0.00s · 4 KiB read / 512 B written");
+        assert!(!stats.report().contains("Block I/O"));
+        assert_eq!(format_duration(Duration::from_millis(185_200)), "3m 05.2s");
+    }
+
+    #[test]
+    fn test_parse_proc_files() {
+        let stat = "1234 (my (odd) sh) S 1 1234 1234 34816 1234 4194304 100 200 0 0 5 3 70 25 20 0 1 0 100 1000 100";
+        assert_eq!(parse_child_cpu_ticks(stat), Some((70, 25)));
+        let io = "rchar: 10\nwchar: 20\nsyscr: 1\nsyscw: 2\nread_bytes: 4096\nwrite_bytes: 1024\ncancelled_write_bytes: 0\n";
+        assert_eq!(parse_io_bytes(io), Some((4096, 1024)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wait4_reports_signal_and_usage() {
+        #[allow(clippy::zombie_processes)] // Reaped by `wait4` below.
+        let child = std::process::Command::new("sh")
+            .args(["-c", "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done; kill -TERM $$"])
+            .spawn()
+            .unwrap();
+        let (status, usage) = wait4(child.id()).unwrap();
+        let stats = CommandStats::from_exit_status(Duration::from_secs(1), &status, Some(usage));
+        assert_eq!(stats.exit_code, None);
+        assert_eq!(stats.signal_name().as_deref(), Some("SIGTERM"));
+        assert!(usage.max_rss_kb.unwrap_or(0) > 0);
+        assert!(usage.user_time + usage.system_time > Duration::ZERO);
+    }
+}
@@ -0,0 +1,394 @@
+//! Syntax highlighting of the command line.
+//!
+//! The input bar's text is parsed with tree-sitter-bash whenever it changes and split into
+//! `Token`s, which the input bar colors from the active theme. Syntax errors, like an
+//! unterminated quote, and commands that can't be found are reported as `Problem`s, so
+//! they show before the command runs.
+
+use crate::completion;
+use iced::{theme::Palette, Color};
+use log::{error, info};
+use std::ops::Range;
+use std::path::Path;
+use tree_sitter::{Node, Parser};
+
+/// Tokens separating commands.
+const OPERATORS: &[&str] = &["|", "|&", "&&", "||", ";", ";;", "&"];
+
+/// Nodes whose tokens are redirection operators.
+const REDIRECTS: &[&str] = &["file_redirect", "heredoc_redirect", "herestring_redirect"];
+
+/// What a piece of the command line is, which decides its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Plain arguments, whitespace and anything else without a color of its own.
+    Plain,
+    Command,
+    /// A command that is neither a builtin nor found on `$PATH`.
+    UnknownCommand,
+    Keyword,
+    Flag,
+    String,
+    Variable,
+    Redirect,
+    /// Pipes, `&&`, `||`, `;` and `&`.
+    Operator,
+    Comment,
+}
+
+impl TokenKind {
+    /// Returns the color of the kind in the theme with `palette`.
+    pub fn color(self, palette: &Palette) -> Color {
+        match self {
+            TokenKind::Plain => palette.text,
+            TokenKind::Command | TokenKind::Keyword => palette.primary,
+            TokenKind::UnknownCommand => palette.danger,
+            TokenKind::Flag => mix(palette.text, palette.primary, 0.5),
+            TokenKind::String => palette.success,
+            TokenKind::Variable => mix(palette.primary, palette.danger, 0.5),
+            TokenKind::Redirect | TokenKind::Operator => mix(palette.danger, palette.success, 0.5),
+            TokenKind::Comment => palette.text.scale_alpha(0.5),
+        }
+    }
+}
+
+/// Blends `b` into `a` by `amount`, from 0 (only `a`) to 1 (only `b`).
+fn mix(a: Color, b: Color, amount: f32) -> Color {
+    let blend = |a: f32, b: f32| a + (b - a) * amount;
+    Color::from_rgba(blend(a.r, b.r), blend(a.g, b.g), blend(a.b, b.b), blend(a.a, b.a))
+}
+
+/// A run of the command line with the same color.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    /// Byte range in the line.
+    pub range: Range<usize>,
+    pub kind: TokenKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// The line doesn't parse, e.g. a quote or an `if` isn't closed.
+    Syntax,
+    /// The command isn't a builtin nor found on `$PATH`.
+    UnknownCommand,
+}
+
+/// Something that would make the command line fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Byte range in the line.
+    pub range: Range<usize>,
+    pub kind: ProblemKind,
+    pub message: String,
+}
+
+/// A highlighted command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineHighlight {
+    pub line: String,
+    /// Tokens covering the whole line, in order.
+    pub tokens: Vec<Token>,
+    /// Problems ordered by where they start.
+    pub problems: Vec<Problem>,
+}
+
+impl LineHighlight {
+    /// Returns the text of a token of the line.
+    pub fn text(&self, token: &Token) -> &str {
+        &self.line[token.range.clone()]
+    }
+
+    /// Marks the syntax errors with `~` under their characters, for a monospace font.
+    ///
+    /// # Returns
+    ///
+    /// One character per character of the line, or `None` without syntax errors. An
+    /// error at the very end, like a missing `fi`, is marked after the last character.
+    pub fn underline(&self) -> Option<String> {
+        let errors: Vec<&Range<usize>> = self.problems
+            .iter()
+            .filter(|problem| problem.kind == ProblemKind::Syntax)
+            .map(|problem| &problem.range)
+            .collect();
+        if errors.is_empty() {
+            return None;
+        }
+        let mut underline: String = self.line
+            .char_indices()
+            .map(|(index, _)| if errors.iter().any(|range| range.contains(&index)) { '~' } else { ' ' })
+            .collect();
+        if errors.iter().any(|range| range.start >= self.line.len()) {
+            underline.push('~');
+        }
+        Some(underline.trim_end().to_string())
+    }
+}
+
+/// Highlights a command line.
+///
+/// # Arguments
+///
+/// * `line` - The command line, in bash syntax.
+/// * `cwd` - The directory relative command paths, like `./run.sh`, are resolved from.
+///
+/// # Returns
+///
+/// The line's tokens and problems. Commands are looked up on `$PATH`, which is only read
+/// again every few seconds, so this is cheap enough to run on every keystroke.
+pub fn highlight(line: &str, cwd: Option<&Path>) -> LineHighlight {
+    highlight_with(line, &|name| completion::is_known_command(name, cwd))
+}
+
+fn highlight_with(line: &str, is_known_command: &dyn Fn(&str) -> bool) -> LineHighlight {
+    let mut highlighter = Highlighter {
+        source: line,
+        kinds: vec![TokenKind::Plain; line.len()],
+        problems: Vec::new(),
+        is_known_command,
+    };
+    let mut parser = Parser::new();
+    match parser.set_language(&tree_sitter_bash::language()) {
+        Ok(()) => {
+            if let Some(tree) = parser.parse(line, None) {
+                highlighter.visit(tree.root_node(), None);
+            }
+        }
+        Err(e) => error!("Failed to load the bash grammar: {}", e),
+    }
+
+    let Highlighter { kinds, mut problems, .. } = highlighter;
+    if let Some(start) = unterminated_quote(line) {
+        // The errors tree-sitter finds after an open quote only follow from it.
+        problems.retain(|problem| problem.range.end <= start);
+        problems.push(Problem {
+            range: start..line.len(),
+            kind: ProblemKind::Syntax,
+            message: "Unterminated quote".to_string(),
+        });
+    }
+    problems.sort_by_key(|problem| problem.range.start);
+    problems.dedup_by(|a, b| a.range == b.range && a.kind == b.kind);
+
+    let mut tokens: Vec<Token> = Vec::new();
+    for (index, kind) in kinds.into_iter().enumerate() {
+        match tokens.last_mut() {
+            Some(token) if token.kind == kind => token.range.end = index + 1,
+            _ => tokens.push(Token { range: index..index + 1, kind }),
+        }
+    }
+    LineHighlight { line: line.to_string(), tokens, problems }
+}
+
+/// Colors the bytes of a line from its syntax tree. Nodes are visited before their
+/// children, so the innermost node decides, e.g. for a variable in a string.
+struct Highlighter<'a> {
+    source: &'a str,
+    /// The kind of each byte of the line.
+    kinds: Vec<TokenKind>,
+    problems: Vec<Problem>,
+    is_known_command: &'a dyn Fn(&str) -> bool,
+}
+
+impl<'a> Highlighter<'a> {
+    fn visit(&mut self, node: Node, parent: Option<Node>) {
+        if node.is_missing() {
+            // Zero-width; blame the construct that misses it.
+            let range = parent.map_or(node.byte_range(), |parent| parent.byte_range());
+            self.problem(range, ProblemKind::Syntax, format!("Missing `{}`", node.kind()));
+            return;
+        }
+        if node.is_error() {
+            let text: String = self.text(node).trim().chars().take(20).collect();
+            let message = if text.is_empty() { "Syntax error".to_string() } else { format!("Syntax error near `{}`", text) };
+            self.problem(node.byte_range(), ProblemKind::Syntax, message);
+        }
+
+        let parent_kind = parent.map(|parent| parent.kind());
+        match node.kind() {
+            "comment" => self.paint(node, TokenKind::Comment),
+            "string" | "raw_string" | "ansi_c_string" | "translated_string" => self.paint(node, TokenKind::String),
+            "simple_expansion" | "expansion" | "variable_name" => self.paint(node, TokenKind::Variable),
+            "file_descriptor" => self.paint(node, TokenKind::Redirect),
+            "command_name" => self.command_name(node),
+            "word" if self.text(node).starts_with('-') && is_argument(node, parent_kind) => self.paint(node, TokenKind::Flag),
+            kind if !node.is_named() => {
+                if parent_kind.is_some_and(|parent| REDIRECTS.contains(&parent)) {
+                    self.paint(node, TokenKind::Redirect);
+                } else if OPERATORS.contains(&kind) {
+                    self.paint(node, TokenKind::Operator);
+                } else if kind.chars().all(|c| c.is_ascii_alphabetic()) {
+                    // `if`, `then`, `for`, `do`, `export`, …
+                    self.paint(node, TokenKind::Keyword);
+                }
+            }
+            _ => {}
+        }
+
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.visit(child, Some(node));
+        }
+    }
+
+    /// Colors the name of a command, and reports it when it can't be found. Names that
+    /// are expanded, like `$EDITOR`, aren't looked up.
+    fn command_name(&mut self, node: Node) {
+        let name = self.text(node);
+        let literal = node.named_child(0).is_some_and(|child| child.kind() == "word") && !name.contains(['$', '`', '*', '?']);
+        if literal && !(self.is_known_command)(name) {
+            let message = format!("Command not found: {}", name);
+            self.paint(node, TokenKind::UnknownCommand);
+            self.problem(node.byte_range(), ProblemKind::UnknownCommand, message);
+        } else {
+            self.paint(node, TokenKind::Command);
+        }
+    }
+
+    fn text(&self, node: Node) -> &'a str {
+        &self.source[node.byte_range()]
+    }
+
+    fn paint(&mut self, node: Node, kind: TokenKind) {
+        let range = node.byte_range();
+        let end = range.end.min(self.kinds.len());
+        if range.start < end {
+            self.kinds[range.start..end].fill(kind);
+        }
+    }
+
+    fn problem(&mut self, range: Range<usize>, kind: ProblemKind, message: String) {
+        self.problems.push(Problem { range, kind, message });
+    }
+}
+
+/// Whether a word is an argument of a command, or starts one, like `--file=` in `--file=$f`.
+fn is_argument(node: Node, parent_kind: Option<&str>) -> bool {
+    match parent_kind {
+        Some("command") => true,
+        Some("concatenation") => node.prev_sibling().is_none(),
+        _ => false,
+    }
+}
+
+/// Finds a quote the line leaves open.
+///
+/// # Returns
+///
+/// The byte offset of the opening quote, or `None` if all quotes are closed. Quotes in
+/// comments and escaped quotes don't count.
+fn unterminated_quote(line: &str) -> Option<usize> {
+    // The opening quote's offset, the quote that closes it and whether `\` escapes in it.
+    let mut open: Option<(usize, char, bool)> = None;
+    let mut word_start = true;
+    let mut chars = line.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match open {
+            Some((_, quote, escapes)) => {
+                if c == '\\' && escapes {
+                    chars.next();
+                } else if c == quote {
+                    open = None;
+                }
+            }
+            None => match c {
+                '\\' => {
+                    chars.next();
+                }
+                '#' if word_start => return None,
+                '$' if chars.peek().is_some_and(|(_, next)| *next == '\'') => {
+                    chars.next();
+                    open = Some((index, '\'', true));
+                }
+                '\'' => open = Some((index, '\'', false)),
+                '"' | '`' => open = Some((index, c, true)),
+                _ => {}
+            },
+        }
+        word_start = open.is_none() && (c.is_whitespace() || matches!(c, ';' | '|' | '&' | '('));
+    }
+    open.map(|(index, _, _)| index)
+}
+
+/// Initializes the command highlighting module.
+pub fn init() {
+    info!("command_highlight module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind_at(highlight: &LineHighlight, text: &str) -> TokenKind {
+        let start = highlight.line.find(text).unwrap();
+        highlight.tokens.iter().find(|token| token.range.contains(&start)).unwrap().kind
+    }
+
+    #[test]
+    fn test_token_kinds() {
+        let line = r#"git commit -m "fix $USER" | grep x > out.txt && echo $HOME # done"#;
+        let highlight = highlight_with(line, &|_| true);
+        assert_eq!(kind_at(&highlight, "git"), TokenKind::Command);
+        assert_eq!(kind_at(&highlight, "commit"), TokenKind::Plain);
+        assert_eq!(kind_at(&highlight, "-m"), TokenKind::Flag);
+        assert_eq!(kind_at(&highlight, "\"fix"), TokenKind::String);
+        assert_eq!(kind_at(&highlight, "$USER"), TokenKind::Variable);
+        assert_eq!(kind_at(&highlight, "|"), TokenKind::Operator);
+        assert_eq!(kind_at(&highlight, ">"), TokenKind::Redirect);
+        assert_eq!(kind_at(&highlight, "out.txt"), TokenKind::Plain);
+        assert_eq!(kind_at(&highlight, "&&"), TokenKind::Operator);
+        assert_eq!(kind_at(&highlight, "$HOME"), TokenKind::Variable);
+        assert_eq!(kind_at(&highlight, "# done"), TokenKind::Comment);
+        assert!(highlight.problems.is_empty());
+        assert_eq!(highlight.tokens.iter().map(|token| highlight.text(token)).collect::<String>(), line);
+
+        let highlight = highlight_with("for f in *.rs; do wc -l $f; done", &|_| true);
+        assert_eq!(kind_at(&highlight, "for"), TokenKind::Keyword);
+        assert_eq!(kind_at(&highlight, "do"), TokenKind::Keyword);
+        assert_eq!(kind_at(&highlight, "wc"), TokenKind::Command);
+        assert_eq!(kind_at(&highlight, "-l"), TokenKind::Flag);
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let highlight = highlight_with("gti status | grep main", &|name| name != "gti");
+        assert_eq!(kind_at(&highlight, "gti"), TokenKind::UnknownCommand);
+        assert_eq!(kind_at(&highlight, "grep"), TokenKind::Command);
+        assert_eq!(highlight.problems, vec![Problem {
+            range: 0..3,
+            kind: ProblemKind::UnknownCommand,
+            message: "Command not found: gti".to_string(),
+        }]);
+        assert_eq!(highlight.underline(), None);
+
+        let highlight = highlight_with("$EDITOR notes.txt", &|_| false);
+        assert_eq!(kind_at(&highlight, "$EDITOR"), TokenKind::Variable);
+        assert!(highlight.problems.is_empty());
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let highlight = highlight_with("echo \"hello", &|_| true);
+        assert_eq!(highlight.problems, vec![Problem {
+            range: 5..11,
+            kind: ProblemKind::Syntax,
+            message: "Unterminated quote".to_string(),
+        }]);
+        assert_eq!(highlight.underline().as_deref(), Some("     ~~~~~~"));
+
+        let highlight = highlight_with("if true; then echo yes", &|_| true);
+        assert!(!highlight.problems.is_empty());
+        assert!(highlight.underline().is_some());
+    }
+
+    #[test]
+    fn test_unterminated_quote() {
+        assert_eq!(unterminated_quote("echo 'it''s'"), None);
+        assert_eq!(unterminated_quote("echo it\\'s"), None);
+        assert_eq!(unterminated_quote("echo ok # it's"), None);
+        assert_eq!(unterminated_quote("echo \"a \\\" b\""), None);
+        assert_eq!(unterminated_quote("echo $'it\\'s'"), None);
+        assert_eq!(unterminated_quote("echo a#'b"), Some(7));
+        assert_eq!(unterminated_quote("echo `date"), Some(5));
+    }
+}
@@ -0,0 +1,351 @@
+//! What the system knows about its commands.
+//!
+//! One-line descriptions of the commands come from the NAME sections of their man pages,
+//! as listed by `apropos`. Options are parsed from a command's `--help` output the first
+//! time its flags are completed, for commands with a man page only: anything else, like a
+//! script in `~/bin`, may do its work instead of printing help. Both are cached in `command_info.json` in the cache
+//! directory. The descriptions are read again when `$PATH` or one of its directories
+//! changed, which also forgets the options, as the commands may have been updated.
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// Name of the cache file in the cache directory.
+const CACHE_FILE: &str = "command_info.json";
+
+/// How long `apropos` gets to list the man pages.
+const APROPOS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a command gets to print its `--help`.
+const HELP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often `$PATH` is checked for changes.
+const STALE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Man page sections of commands: user commands, games and administration.
+const COMMAND_SECTIONS: &[char] = &['1', '6', '8'];
+
+/// Help for one option of a command.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OptionHelp {
+    /// The spellings of the option, e.g. `-a` and `--all`.
+    pub flags: Vec<String>,
+    pub help: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KnowledgeBase {
+    /// Fingerprint of the `$PATH` the descriptions were read for.
+    fingerprint: u64,
+    descriptions: HashMap<String, String>,
+    /// Options by command. Commands whose `--help` showed none have an empty list, so
+    /// they aren't run again; those that failed to start or timed out are tried again.
+    options: HashMap<String, Vec<OptionHelp>>,
+}
+
+impl KnowledgeBase {
+    fn load(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable command cache {:?}: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_vec(self)?)?;
+        std::fs::rename(&temp, path).with_context(|| format!("Failed to write command cache {:?}", path))
+    }
+}
+
+static KNOWLEDGE: Lazy<RwLock<KnowledgeBase>> = Lazy::new(|| RwLock::new(KnowledgeBase::load(&cache_path())));
+
+/// When `$PATH` was last checked for changes.
+static LAST_CHECK: Lazy<Mutex<Option<Instant>>> = Lazy::new(|| Mutex::new(None));
+
+fn cache_path() -> PathBuf {
+    crate::config::CACHE_DIR.join(CACHE_FILE)
+}
+
+fn save() {
+    if let Err(e) = KNOWLEDGE.read().unwrap().save(&cache_path()) {
+        warn!("{:#}", e);
+    }
+}
+
+/// Returns the one-line description of a command from its man page.
+pub fn describe(command: &str) -> Option<String> {
+    KNOWLEDGE.read().unwrap().descriptions.get(command).cloned()
+}
+
+/// Returns the help of a command's option, if its options were read.
+pub fn option_help(command: &str, flag: &str) -> Option<String> {
+    let knowledge = KNOWLEDGE.read().unwrap();
+    knowledge.options.get(command)?
+        .iter()
+        .find(|option| option.flags.iter().any(|known| known == flag))
+        .map(|option| option.help.clone())
+}
+
+/// Fingerprints a `$PATH` value together with the modification times of its directories,
+/// which change when commands are installed or removed.
+pub fn path_fingerprint(path: &OsStr) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    for dir in std::env::split_paths(path) {
+        std::fs::metadata(&dir).and_then(|metadata| metadata.modified()).ok().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Reads the descriptions again if `$PATH` changed since they were read. Runs at most
+/// once every 30 seconds; later calls return right away.
+pub async fn refresh_if_stale() {
+    {
+        let mut last_check = LAST_CHECK.lock().unwrap();
+        if last_check.is_some_and(|checked| checked.elapsed() < STALE_CHECK_INTERVAL) {
+            return;
+        }
+        *last_check = Some(Instant::now());
+    }
+    let fingerprint = path_fingerprint(&std::env::var_os("PATH").unwrap_or_default());
+    if KNOWLEDGE.read().unwrap().fingerprint == fingerprint {
+        return;
+    }
+    let descriptions = match run(Command::new("apropos").arg("."), APROPOS_TIMEOUT).await {
+        Some(output) => parse_apropos(&output),
+        None => HashMap::new(),
+    };
+    info!("Read descriptions of {} commands from the man pages", descriptions.len());
+    {
+        let mut knowledge = KNOWLEDGE.write().unwrap();
+        knowledge.fingerprint = fingerprint;
+        knowledge.descriptions = descriptions;
+        knowledge.options.clear();
+    }
+    save();
+}
+
+/// Returns the options of a command, running `<command> --help` to read them the first time.
+pub async fn options(command: &str) -> Vec<OptionHelp> {
+    if let Some(options) = KNOWLEDGE.read().unwrap().options.get(command) {
+        return options.clone();
+    }
+    if !may_run_help(&KNOWLEDGE.read().unwrap(), command) {
+        return Vec::new();
+    }
+    let Some(output) = run(Command::new(command).arg("--help"), HELP_TIMEOUT).await else {
+        return Vec::new();
+    };
+    let options = parse_help(&output);
+    debug!("Read {} options from `{} --help`", options.len(), command);
+    KNOWLEDGE.write().unwrap().options.insert(command.to_string(), options.clone());
+    save();
+    options
+}
+
+/// Whether `<command> --help` may run to read its options: only for commands on `$PATH`,
+/// never paths or anything the shell would expand, and only for those with a man page.
+fn may_run_help(knowledge: &KnowledgeBase, command: &str) -> bool {
+    !command.is_empty()
+        && command.chars().all(|c| c.is_alphanumeric() || "-_.+".contains(c))
+        && knowledge.descriptions.contains_key(command)
+}
+
+/// Runs a command with its input closed and returns what it printed, or `None` if it
+/// could not be started or didn't finish in time. Help often goes to stderr, which is
+/// used when nothing was printed on stdout.
+async fn run(command: &mut Command, timeout: Duration) -> Option<String> {
+    command.stdin(Stdio::null()).kill_on_drop(true);
+    match tokio::time::timeout(timeout, command.output()).await {
+        Ok(Ok(output)) => {
+            let printed = if output.stdout.is_empty() { output.stderr } else { output.stdout };
+            Some(String::from_utf8_lossy(&printed).into_owned())
+        }
+        Ok(Err(e)) => {
+            debug!("Failed to run {:?}: {}", command, e);
+            None
+        }
+        Err(_) => {
+            debug!("{:?} timed out", command);
+            None
+        }
+    }
+}
+
+/// Reads the output of `apropos`, `name (section) - description` per line. BSD systems
+/// list several names per line, separated by commas.
+///
+/// # Returns
+///
+/// The description of each command in the command sections, the first one listed for a name.
+pub fn parse_apropos(output: &str) -> HashMap<String, String> {
+    let mut descriptions = HashMap::new();
+    for line in output.lines() {
+        let Some((names, description)) = line.split_once(" - ") else {
+            continue;
+        };
+        let description = description.trim();
+        for name in names.split(',') {
+            let Some((name, section)) = name.trim().split_once('(') else {
+                continue;
+            };
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) || !section.starts_with(COMMAND_SECTIONS) {
+                continue;
+            }
+            descriptions.entry(name.to_string()).or_insert_with(|| description.to_string());
+        }
+    }
+    descriptions
+}
+
+/// Parses the options out of a command's `--help` output. An option line starts, indented,
+/// with its flags; the help follows after two or more spaces or on the next, further
+/// indented lines.
+pub fn parse_help(output: &str) -> Vec<OptionHelp> {
+    let mut options: Vec<OptionHelp> = Vec::new();
+    // Indentation of the last option's line, while more lines may continue its help.
+    let mut option_indent: Option<usize> = None;
+    for line in output.lines() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if trimmed.starts_with('-') && indent <= 12 {
+            let (spec, help) = split_at_gap(trimmed);
+            let flags = parse_flags(spec);
+            if !flags.is_empty() {
+                options.push(OptionHelp { flags, help: help.trim().to_string() });
+                option_indent = Some(indent);
+                continue;
+            }
+        }
+        match (option_indent, options.last_mut()) {
+            (Some(option_indent), Some(option)) if indent > option_indent => {
+                if !option.help.is_empty() {
+                    option.help.push(' ');
+                }
+                option.help.push_str(trimmed.trim_end());
+            }
+            _ => option_indent = None,
+        }
+    }
+    options
+}
+
+/// Splits an option line at the first run of two or more spaces or a tab.
+fn split_at_gap(line: &str) -> (&str, &str) {
+    let bytes = line.as_bytes();
+    for (index, &byte) in bytes.iter().enumerate() {
+        if byte == b'\t' || (byte == b' ' && bytes.get(index + 1) == Some(&b' ')) {
+            return (&line[..index], &line[index..]);
+        }
+    }
+    (line, "")
+}
+
+/// Picks the flags out of an option specification such as `-w, --width=COLS` or `-o <FILE>`.
+fn parse_flags(spec: &str) -> Vec<String> {
+    spec.split(|c: char| c == ',' || c == '|' || c.is_whitespace())
+        .filter(|token| token.starts_with('-'))
+        .map(|token| token.split(['=', '[', '<']).next().unwrap_or(token))
+        .filter(|flag| flag.trim_start_matches('-').chars().next().is_some_and(|c| c.is_ascii_alphanumeric()))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Initializes the command info module.
+pub fn init() {
+    info!("command info module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_apropos() {
+        let output = "ls (1)               - list directory contents\n\
+                      ls (1p)              - list directory contents (POSIX)\n\
+                      printf (3)           - formatted output conversion\n\
+                      git-commit(1), git commit(1) - Record changes to the repository\n\
+                      mount (8)            - mount a filesystem\n\
+                      no description line\n";
+        let descriptions = parse_apropos(output);
+        assert_eq!(descriptions.get("ls").map(String::as_str), Some("list directory contents"));
+        assert_eq!(descriptions.get("git-commit").map(String::as_str), Some("Record changes to the repository"));
+        assert_eq!(descriptions.get("mount").map(String::as_str), Some("mount a filesystem"));
+        assert!(!descriptions.contains_key("printf"));
+        assert_eq!(descriptions.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_help() {
+        let gnu = "Usage: ls [OPTION]... [FILE]...\n\
+                   \n\
+                   Mandatory arguments to long options are mandatory for short options too.\n  \
+                   -a, --all                  do not ignore entries starting with .\n      \
+                   --block-size=SIZE      with -l, scale sizes by SIZE when printing them;\n                               \
+                   e.g., '--block-size=M'\n  \
+                   -w, --width=COLS           set output width to COLS.\n\
+                   \n\
+                   Exit status:\n \
+                   0  if OK,\n";
+        assert_eq!(parse_help(gnu), vec![
+            OptionHelp { flags: vec!["-a".into(), "--all".into()], help: "do not ignore entries starting with .".into() },
+            OptionHelp { flags: vec!["--block-size".into()], help: "with -l, scale sizes by SIZE when printing them; e.g., '--block-size=M'".into() },
+            OptionHelp { flags: vec!["-w".into(), "--width".into()], help: "set output width to COLS.".into() },
+        ]);
+
+        let clap = "Options:\n  \
+                    -o, --output <FILE>\n          \
+                    Where to write the result\n\
+                    \n  \
+                    -V, --version\n          \
+                    Print version\n";
+        assert_eq!(parse_help(clap), vec![
+            OptionHelp { flags: vec!["-o".into(), "--output".into()], help: "Where to write the result".into() },
+            OptionHelp { flags: vec!["-V".into(), "--version".into()], help: "Print version".into() },
+        ]);
+    }
+
+    #[test]
+    fn test_may_run_help() {
+        let mut knowledge = KnowledgeBase::default();
+        knowledge.descriptions.insert("ls".to_string(), "list directory contents".to_string());
+        knowledge.descriptions.insert("../ls".to_string(), "list directory contents".to_string());
+        assert!(may_run_help(&knowledge, "ls"));
+        assert!(!may_run_help(&knowledge, "deploy-prod"), "no man page");
+        assert!(!may_run_help(&knowledge, "../ls"));
+        assert!(!may_run_help(&knowledge, ""));
+    }
+
+    #[test]
+    fn test_path_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = std::env::join_paths([dir.path()]).unwrap();
+        let before = path_fingerprint(&path);
+        assert_eq!(path_fingerprint(&path), before);
+        let other = std::env::join_paths([dir.path(), Path::new("/nonexistent")]).unwrap();
+        assert_ne!(path_fingerprint(&other), before);
+    }
+}
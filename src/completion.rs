@@ -0,0 +1,623 @@
+//! Tab completion for the command line.
+//!
+//! Completions come from two places. Executables on `$PATH`, shell builtins, files and
+//! directories relative to the tab's working directory, and environment variables are
+//! found right away by `local_completions`. Subcommands, flags and other arguments come
+//! from the user's shell, and git branches and remotes from git; `shell_completions`
+//! queries them in a side process, so the application runs it in the background.
+//!
+//! Bash is asked through the completion specs its `bash-completion` package registers and
+//! fish through `complete --do-complete`, which also describes its candidates. Zsh's
+//! completion system only runs inside its line editor, from a widget bound to a key, so
+//! answering a query would mean driving an interactive zsh through `zpty`. That is not
+//! done: zsh users get the bash completions, and commands that only ship a zsh completion
+//! get just the local candidates and the flags from their `--help`.
+
+use crate::command_info;
+use log::{debug, info};
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// Most candidates a single source returns.
+const MAX_CANDIDATES: usize = 200;
+
+/// How long the shell gets to answer before its completions are dropped.
+const SHELL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long the executables found on `$PATH` are reused before the directories are read again.
+const PATH_CACHE_TTL: Duration = Duration::from_secs(30);
+
+const BUILTINS: &[&str] = &[
+    "alias", "bg", "break", "builtin", "cd", "command", "continue", "dirs", "disown", "echo",
+    "eval", "exec", "exit", "export", "fg", "getopts", "hash", "help", "history", "jobs", "let",
+    "popd", "pushd", "pwd", "read", "return", "set", "shift", "shopt", "source", "trap", "type",
+    "ulimit", "umask", "unalias", "unset", "wait",
+];
+
+/// Bash script printing the completions of the line in `$1`, one per line. It follows the
+/// completion spec registered for the command, if any: the function (`-F`) and command
+/// (`-C`) are run as bash would run them, and word lists (`-W`), actions (`-A`, `-d`, ...)
+/// and globs (`-G`) are expanded with `compgen`. `-o` options are dropped, as files are
+/// completed locally.
+const BASH_COMPLETE: &str = r#"
+for script in /usr/share/bash-completion/bash_completion /etc/bash_completion /usr/local/etc/profile.d/bash_completion.sh; do
+    [[ -r $script ]] && { source "$script"; break; }
+done 2>/dev/null
+line=$1
+read -ra COMP_WORDS <<< "$line"
+[[ $line == *[[:space:]] || ${#COMP_WORDS[@]} -eq 0 ]] && COMP_WORDS+=("")
+COMP_CWORD=$(( ${#COMP_WORDS[@]} - 1 ))
+export COMP_LINE=$line
+export COMP_POINT=${#line}
+cmd=${COMP_WORDS[0]}
+cur=${COMP_WORDS[COMP_CWORD]}
+prev=${COMP_WORDS[COMP_CWORD-1]}
+if ! complete -p "$cmd" &>/dev/null && declare -F _completion_loader >/dev/null; then
+    _completion_loader "$cmd" 2>/dev/null
+fi
+spec=$(complete -p "$cmd" 2>/dev/null) || exit 0
+eval "words=($spec)" 2>/dev/null || exit 0
+function= command= options=()
+for (( i = 1; i < ${#words[@]} - 1; i++ )); do
+    case ${words[i]} in
+        -F) function=${words[++i]} ;;
+        -C) command=${words[++i]} ;;
+        -o) (( i++ )) ;;
+        -[AGWXPS]) options+=("${words[i]}" "${words[i+1]}"); (( i++ )) ;;
+        *) options+=("${words[i]}") ;;
+    esac
+done
+if [[ -n $function ]]; then
+    "$function" "$cmd" "$cur" "$prev" 2>/dev/null
+    printf '%s\n' "${COMPREPLY[@]}"
+fi
+[[ -n $command ]] && eval "$command"' "$cmd" "$cur" "$prev"' 2>/dev/null
+(( ${#options[@]} )) && compgen "${options[@]}" -- "$cur" 2>/dev/null
+exit 0
+"#;
+
+/// What kind of thing a completion is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Executable,
+    Builtin,
+    File,
+    Directory,
+    Variable,
+    GitBranch,
+    GitRemote,
+    Subcommand,
+    Flag,
+    /// Any other argument the shell offered.
+    Argument,
+}
+
+/// A word that completes the last word of the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    /// The word, quoted for the shell, that replaces the last word.
+    pub text: String,
+    pub description: Option<String>,
+    pub kind: CompletionKind,
+}
+
+impl Completion {
+    fn new(text: impl Into<String>, description: Option<String>, kind: CompletionKind) -> Self {
+        Self { text: text.into(), description, kind }
+    }
+}
+
+/// The command line split at the word being completed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineContext<'a> {
+    /// Words of the command being typed, before the current word. Earlier commands of a
+    /// pipeline or list are left out.
+    pub words: Vec<&'a str>,
+    /// The word at the end of the line, possibly empty.
+    pub current: &'a str,
+    /// Byte offset of `current` in the line.
+    pub start: usize,
+}
+
+impl<'a> LineContext<'a> {
+    /// Splits `line` into words at unescaped whitespace and command separators.
+    /// Quotes are not taken into account.
+    pub fn parse(line: &'a str) -> Self {
+        let mut words = Vec::new();
+        let mut word_start = None;
+        let mut escaped = false;
+        for (index, c) in line.char_indices() {
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            match c {
+                '\\' => {
+                    escaped = true;
+                    word_start.get_or_insert(index);
+                }
+                c if c.is_whitespace() || matches!(c, '|' | '&' | ';' | '(') => {
+                    if let Some(start) = word_start.take() {
+                        words.push(&line[start..index]);
+                    }
+                    if !c.is_whitespace() {
+                        // A new command starts.
+                        words.clear();
+                    }
+                }
+                _ => {
+                    word_start.get_or_insert(index);
+                }
+            }
+        }
+        let start = word_start.unwrap_or(line.len());
+        Self { words, current: &line[start..], start }
+    }
+
+    /// Whether the current word is the command name, i.e. only variable assignments precede it.
+    pub fn is_command_position(&self) -> bool {
+        self.words.iter().all(|word| word.contains('='))
+    }
+
+    /// The command whose arguments are being completed.
+    pub fn command(&self) -> Option<&'a str> {
+        self.words.iter().copied().find(|word| !word.contains('='))
+    }
+
+    /// Returns `line` with the current word replaced by `completion`.
+    pub fn apply(&self, line: &str, completion: &Completion) -> String {
+        format!("{}{}", &line[..self.start], completion.text)
+    }
+}
+
+/// Escapes the characters a shell would split or expand in a file name.
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_whitespace() || "\\'\"`$&|;<>()*?[]#!{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn unescape(word: &str) -> String {
+    let mut unescaped = String::with_capacity(word.len());
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Finds the completions that don't need a side process.
+///
+/// # Arguments
+///
+/// * `line` - The command line, completed at its end.
+/// * `cwd` - The directory relative paths are resolved from.
+///
+/// # Returns
+///
+/// The completions of the current word: commands at the start of a command, variables
+/// after `$`, files and directories otherwise.
+pub fn local_completions(line: &str, cwd: Option<&Path>) -> Vec<Completion> {
+    let context = LineContext::parse(line);
+    let current = context.current;
+    if let Some(rest) = current.strip_prefix('$') {
+        return match rest.strip_prefix('{') {
+            Some(prefix) => variable_completions(prefix, "${"),
+            None => variable_completions(rest, "$"),
+        };
+    }
+    let mut completions = Vec::new();
+    if context.is_command_position() && !current.contains('/') {
+        if current.is_empty() {
+            return completions;
+        }
+        completions.extend(
+            BUILTINS.iter()
+                .filter(|builtin| builtin.starts_with(current))
+                .map(|builtin| Completion::new(*builtin, Some("Shell builtin".to_string()), CompletionKind::Builtin)),
+        );
+        completions.extend(
+            path_executables().into_iter()
+                .filter(|name| name.starts_with(current) && !BUILTINS.contains(&name.as_str()))
+                .take(MAX_CANDIDATES)
+                .map(|name| Completion::new(escape(&name), command_info::describe(&name), CompletionKind::Executable)),
+        );
+        return completions;
+    }
+    if let Some(cwd) = cwd {
+        completions.extend(file_completions(current, cwd));
+    }
+    completions
+}
+
+/// Lists the environment variables whose names start with `prefix`, written after `sigil`
+/// (`$` or `${`), with the start of their value as description.
+fn variable_completions(prefix: &str, sigil: &str) -> Vec<Completion> {
+    let mut names: Vec<(String, String)> = std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.to_string_lossy().into_owned())))
+        .filter(|(name, _)| name.starts_with(prefix))
+        .collect();
+    names.sort();
+    let closing = if sigil.ends_with('{') { "}" } else { "" };
+    names.into_iter()
+        .take(MAX_CANDIDATES)
+        .map(|(name, value)| {
+            let value: String = value.chars().take(40).collect();
+            Completion::new(format!("{}{}{}", sigil, name, closing), Some(value), CompletionKind::Variable)
+        })
+        .collect()
+}
+
+/// Lists the entries of the directory named by `word`, up to its last slash, whose names
+/// start with the rest of it. `~` is the home directory.
+fn file_completions(word: &str, cwd: &Path) -> Vec<Completion> {
+    let word = unescape(word);
+    let (dir_part, name_prefix) = match word.rfind('/') {
+        Some(slash) => word.split_at(slash + 1),
+        None => ("", word.as_str()),
+    };
+    let dir = match dir_part.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => match dirs::home_dir() {
+            Some(home) => home.join(rest.trim_start_matches('/')),
+            None => return Vec::new(),
+        },
+        _ => cwd.join(dir_part),
+    };
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut found: Vec<(String, bool)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let hidden_ok = !name.starts_with('.') || name_prefix.starts_with('.');
+            (hidden_ok && name.starts_with(name_prefix)).then(|| (name, entry.path().is_dir()))
+        })
+        .collect();
+    // Directories first, then by name.
+    found.sort_by(|(a, a_dir), (b, b_dir)| b_dir.cmp(a_dir).then_with(|| a.cmp(b)));
+    found.into_iter()
+        .take(MAX_CANDIDATES)
+        .map(|(name, is_dir)| {
+            let text = format!("{}{}", escape(&format!("{}{}", dir_part, name)), if is_dir { "/" } else { "" });
+            let kind = if is_dir { CompletionKind::Directory } else { CompletionKind::File };
+            Completion::new(text, None, kind)
+        })
+        .collect()
+}
+
+/// Whether `name` runs something: a shell builtin, an executable on `$PATH`, or a path to
+/// an executable file, relative to `cwd` unless it is absolute or starts with `~/`.
+/// Aliases and shell functions aren't known, so they count as missing.
+pub fn is_known_command(name: &str, cwd: Option<&Path>) -> bool {
+    if name == "." || name == ":" || BUILTINS.contains(&name) {
+        return true;
+    }
+    if !name.contains('/') {
+        return path_executables().iter().any(|executable| executable == name);
+    }
+    let name = unescape(name);
+    let path = match name.strip_prefix("~/") {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => home.join(rest),
+            None => return false,
+        },
+        None => match cwd {
+            Some(cwd) => cwd.join(&name),
+            None => PathBuf::from(&name),
+        },
+    };
+    is_executable(&path)
+}
+
+/// Executables on `$PATH`, by the value of `$PATH` they were found with and when.
+static PATH_EXECUTABLES: Lazy<Mutex<Option<(OsString, Instant, Vec<String>)>>> = Lazy::new(|| Mutex::new(None));
+
+/// Returns the names of the executables on `$PATH`, sorted, shortest first.
+fn path_executables() -> Vec<String> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let mut cache = PATH_EXECUTABLES.lock().unwrap();
+    match cache.as_ref() {
+        Some((cached_path, found_at, names)) if *cached_path == path && found_at.elapsed() < PATH_CACHE_TTL => names.clone(),
+        _ => {
+            let names = executables_in(&path);
+            *cache = Some((path, Instant::now(), names.clone()));
+            names
+        }
+    }
+}
+
+/// Lists the executable files in the directories of a `$PATH` value.
+fn executables_in(path: &std::ffi::OsStr) -> Vec<String> {
+    let mut names: Vec<String> = std::env::split_paths(path)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .filter(|entry| is_executable(&entry.path()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    names.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+    names
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+        && path.extension().is_some_and(|extension| {
+            ["exe", "bat", "cmd", "com"].iter().any(|executable| extension.eq_ignore_ascii_case(executable))
+        })
+}
+
+/// Asks the user's shell and git for completions of the command line.
+///
+/// # Arguments
+///
+/// * `line` - The command line, completed at its end.
+/// * `cwd` - The directory the shell and git run in.
+/// * `shell` - Path of the user's shell. Fish is asked itself; other shells, zsh included,
+///   through bash, see the module documentation.
+///
+/// # Returns
+///
+/// Git branches and remotes for git commands, then the shell's completions. Completions
+/// of a shell that fails or doesn't answer in time are left out. Flags of the command
+/// itself are described with the help from its `--help`.
+pub async fn shell_completions(line: String, cwd: Option<PathBuf>, shell: String) -> Vec<Completion> {
+    tokio::spawn(command_info::refresh_if_stale());
+    let context = LineContext::parse(&line);
+    if context.is_command_position() || context.current.starts_with('$') {
+        return Vec::new();
+    }
+    let mut completions = Vec::new();
+    if context.command() == Some("git") && context.words.len() >= 2 && !context.current.starts_with('-') {
+        completions.extend(git_completions(context.current, cwd.as_deref()).await);
+    }
+    let is_fish = Path::new(&shell).file_name().is_some_and(|name| name.to_string_lossy().contains("fish"));
+    let mut command = if is_fish {
+        let mut command = Command::new(&shell);
+        command.args(["--no-config", "-c", "complete --do-complete \"$NEOTERM_COMPLETE_LINE\""]);
+        command.env("NEOTERM_COMPLETE_LINE", &line);
+        command
+    } else {
+        let mut command = Command::new("bash");
+        command.args(["--norc", "--noprofile", "-c", BASH_COMPLETE, "bash", &line]);
+        command
+    };
+    if let Some(output) = run(&mut command, cwd.as_deref()).await {
+        let known: HashSet<String> = completions.iter().map(|completion| completion.text.clone()).collect();
+        completions.extend(
+            parse_shell_output(&output, &context)
+                .into_iter()
+                .filter(|completion| !known.contains(&completion.text)),
+        );
+    }
+    // The help of a command describes its own flags, not those of its subcommands.
+    let on_command = context.words.iter().skip_while(|word| word.contains('=')).skip(1).all(|word| word.starts_with('-'));
+    if context.current.starts_with('-') && on_command {
+        if let Some(name) = context.command() {
+            add_option_help(&mut completions, name, context.current).await;
+        }
+    }
+    completions
+}
+
+/// Describes flag completions with the help of the command's options, and offers the
+/// options matching `current` if the shell offered no flags.
+async fn add_option_help(completions: &mut Vec<Completion>, command: &str, current: &str) {
+    let options = command_info::options(command).await;
+    if completions.iter().any(|completion| completion.kind == CompletionKind::Flag) {
+        for completion in completions.iter_mut().filter(|completion| completion.kind == CompletionKind::Flag && completion.description.is_none()) {
+            completion.description = options.iter()
+                .find(|option| option.flags.contains(&completion.text))
+                .map(|option| option.help.clone())
+                .filter(|help| !help.is_empty());
+        }
+        return;
+    }
+    completions.extend(
+        options.iter()
+            .flat_map(|option| option.flags.iter().map(move |flag| (flag, &option.help)))
+            .filter(|(flag, _)| flag.starts_with(current))
+            .take(MAX_CANDIDATES)
+            .map(|(flag, help)| Completion::new(flag.clone(), Some(help.clone()).filter(|help| !help.is_empty()), CompletionKind::Flag)),
+    );
+}
+
+/// Runs a completion query, returning its standard output.
+async fn run(command: &mut Command, cwd: Option<&Path>) -> Option<String> {
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+    command.stdin(std::process::Stdio::null()).kill_on_drop(true);
+    match tokio::time::timeout(SHELL_TIMEOUT, command.output()).await {
+        Ok(Ok(output)) => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        Ok(Err(e)) => {
+            debug!("Completion query {:?} failed: {}", command, e);
+            None
+        }
+        Err(_) => {
+            debug!("Completion query {:?} timed out", command);
+            None
+        }
+    }
+}
+
+async fn git_completions(prefix: &str, cwd: Option<&Path>) -> Vec<Completion> {
+    let mut completions = Vec::new();
+    let queries = [
+        (vec!["remote"], CompletionKind::GitRemote, "Remote"),
+        (vec!["for-each-ref", "--format=%(refname:short)", "refs/heads", "refs/remotes"], CompletionKind::GitBranch, "Branch"),
+    ];
+    for (args, kind, description) in queries {
+        let mut command = Command::new("git");
+        command.args(&args);
+        if let Some(output) = run(&mut command, cwd).await {
+            completions.extend(
+                output.lines()
+                    .filter(|name| name.starts_with(prefix) && !name.ends_with("/HEAD"))
+                    .take(MAX_CANDIDATES)
+                    .map(|name| Completion::new(escape(name), Some(description.to_string()), kind)),
+            );
+        }
+    }
+    completions
+}
+
+/// Reads the completions a shell printed, one per line with an optional description
+/// after a tab, dropping duplicates and candidates that don't extend the current word.
+fn parse_shell_output(output: &str, context: &LineContext) -> Vec<Completion> {
+    let mut seen = HashSet::new();
+    output.lines()
+        .filter_map(|line| {
+            let (candidate, description) = match line.split_once('\t') {
+                Some((candidate, description)) => (candidate.trim_end(), Some(description.trim().to_string()).filter(|d| !d.is_empty())),
+                None => (line.trim_end(), None),
+            };
+            if candidate.is_empty() || !unescape(candidate).starts_with(&unescape(context.current)) || !seen.insert(candidate.to_string()) {
+                return None;
+            }
+            let kind = if candidate.starts_with('-') {
+                CompletionKind::Flag
+            } else if context.words.len() == 1 {
+                CompletionKind::Subcommand
+            } else {
+                CompletionKind::Argument
+            };
+            Some(Completion::new(candidate, description, kind))
+        })
+        .take(MAX_CANDIDATES)
+        .collect()
+}
+
+/// Initializes the completion module.
+pub fn init() {
+    info!("completion module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let context = LineContext::parse("cd /tmp && FOO=1 git chec");
+        assert_eq!(context.words, vec!["FOO=1", "git"]);
+        assert_eq!(context.current, "chec");
+        assert_eq!(context.command(), Some("git"));
+        assert!(!context.is_command_position());
+
+        let context = LineContext::parse("ls my\\ dir/");
+        assert_eq!(context.words, vec!["ls"]);
+        assert_eq!(context.current, "my\\ dir/");
+
+        let context = LineContext::parse("cat foo | gr");
+        assert!(context.is_command_position());
+        assert_eq!(context.apply("cat foo | gr", &Completion::new("grep", None, CompletionKind::Executable)), "cat foo | grep");
+
+        assert_eq!(LineContext::parse("git ").current, "");
+    }
+
+    #[test]
+    fn test_file_completions() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src").join("main.rs"), "").unwrap();
+        std::fs::write(dir.path().join("some file"), "").unwrap();
+        std::fs::write(dir.path().join(".secret"), "").unwrap();
+
+        let texts = |line: &str| -> Vec<String> {
+            local_completions(line, Some(dir.path())).into_iter().map(|completion| completion.text).collect()
+        };
+        assert_eq!(texts("cat s"), vec!["src/", "some\\ file"]);
+        assert_eq!(texts("cat src/m"), vec!["src/main.rs"]);
+        assert_eq!(texts("cat some\\ f"), vec!["some\\ file"]);
+        assert_eq!(texts("cat ."), vec![".secret"]);
+        assert_eq!(local_completions("cat s", Some(dir.path()))[0].kind, CompletionKind::Directory);
+    }
+
+    #[test]
+    fn test_variable_completions() {
+        std::env::set_var("NEOTERM_COMPLETION_TEST", "value");
+        let completions = local_completions("echo ${NEOTERM_COMPLETION_T", None);
+        assert_eq!(completions, vec![Completion::new("${NEOTERM_COMPLETION_TEST}", Some("value".to_string()), CompletionKind::Variable)]);
+        assert_eq!(local_completions("echo $NEOTERM_COMPLETION_T", None)[0].text, "$NEOTERM_COMPLETION_TEST");
+    }
+
+    #[test]
+    fn test_executables_in() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["tool", "tl", "data"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            for name in ["tool", "tl"] {
+                std::fs::set_permissions(dir.path().join(name), std::fs::Permissions::from_mode(0o755)).unwrap();
+            }
+            let path = std::env::join_paths([dir.path(), dir.path()]).unwrap();
+            assert_eq!(executables_in(&path), vec!["tl", "tool"]);
+        }
+    }
+
+    #[test]
+    fn test_is_known_command() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("run.sh"), "").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(dir.path().join("run.sh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+            assert!(is_known_command("./run.sh", Some(dir.path())));
+        }
+        assert!(is_known_command("cd", None));
+        assert!(!is_known_command("./missing.sh", Some(dir.path())));
+        assert!(!is_known_command("neoterm-no-such-command", None));
+    }
+
+    #[test]
+    fn test_parse_shell_output() {
+        let line = "git ch";
+        let context = LineContext::parse(line);
+        let completions = parse_shell_output("checkout\tSwitch branches\ncherry-pick\ncheckout\nstatus\n", &context);
+        assert_eq!(completions, vec![
+            Completion::new("checkout", Some("Switch branches".to_string()), CompletionKind::Subcommand),
+            Completion::new("cherry-pick", None, CompletionKind::Subcommand),
+        ]);
+        let context = LineContext::parse("ls --co");
+        assert_eq!(parse_shell_output("--color\n", &context)[0].kind, CompletionKind::Flag);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bash_complete_follows_word_list_and_command_specs() {
+        let complete = |specs: &str, line: &str| {
+            let script = format!("{}\n{}", specs, BASH_COMPLETE);
+            let output = std::process::Command::new("bash").args(["--norc", "--noprofile", "-c", &script, "bash", line]).output().unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+        assert_eq!(complete("complete -o default -W 'alpha beta' neoterm-test", "neoterm-test b"), "beta\n");
+        assert_eq!(complete("complete -C 'echo \"$COMP_LINE\" #' neoterm-test", "neoterm-test x"), "neoterm-test x\n");
+        assert_eq!(complete("_neoterm_test() { COMPREPLY=(\"$2-fn\"); }; complete -F _neoterm_test -W 'word' neoterm-test", "neoterm-test w"), "w-fn\nword\n");
+    }
+}
@@ -75,8 +75,8 @@ impl AppConfig {
             .context("Failed to load user preferences")?;
         // Load other config components here if they have separate files
         Ok(Self {
+            env_profiles: preferences.env_profiles.clone(),
             preferences,
-            env_profiles: preferences::EnvironmentProfiles::default(), // For now, use default or load from preferences
         })
     }
 }
@@ -6,6 +6,10 @@ use anyhow::Result;
 use log::{info, error};
 
 use super::CONFIG_DIR;
+use crate::clipboard::Osc52Read;
+use crate::links::{self, LinkRule};
+use crate::paste::{self, PasteRule};
+use crate::output_filter::OutputFilter;
 
 /// Top-level preferences struct
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -36,6 +40,16 @@ pub struct UserPreferences {
     pub workflows: WorkflowPreferences,
     #[serde(default)]
     pub indexing: IndexingPreferences,
+    #[serde(default)]
+    pub env_profiles: EnvironmentProfiles,
+    #[serde(default)]
+    pub output_filters: OutputFilterPreferences,
+    #[serde(default)]
+    pub links: LinkPreferences,
+    #[serde(default)]
+    pub clipboard: ClipboardPreferences,
+    #[serde(default)]
+    pub paste: PastePreferences,
 }
 
 impl Default for UserPreferences {
@@ -54,6 +68,11 @@ impl Default for UserPreferences {
             drive: DrivePreferences::default(),
             workflows: WorkflowPreferences::default(),
             indexing: IndexingPreferences::default(),
+            env_profiles: EnvironmentProfiles::default(),
+            output_filters: OutputFilterPreferences::default(),
+            links: LinkPreferences::default(),
+            clipboard: ClipboardPreferences::default(),
+            paste: PastePreferences::default(),
         }
     }
 }
@@ -97,6 +116,12 @@ pub struct GeneralPreferences {
     pub telemetry_enabled: bool,
     #[serde(default = "default_startup_command")]
     pub startup_command: String,
+    /// Save blocks, history and the agent conversation on exit and offer to restore them on startup.
+    #[serde(default = "default_restore_session")]
+    pub restore_session: bool,
+    /// When restoring a session, start shells in the directories they were last in.
+    #[serde(default = "default_restore_shell_directories")]
+    pub restore_shell_directories: bool,
 }
 
 impl Default for GeneralPreferences {
@@ -106,6 +131,8 @@ impl Default for GeneralPreferences {
             auto_update: default_auto_update(),
             telemetry_enabled: default_telemetry_enabled(),
             startup_command: default_startup_command(),
+            restore_session: default_restore_session(),
+            restore_shell_directories: default_restore_shell_directories(),
         }
     }
 }
@@ -114,6 +141,8 @@ fn default_font_size() -> u16 { 14 }
 fn default_auto_update() -> bool { true }
 fn default_telemetry_enabled() -> bool { true }
 fn default_startup_command() -> String { "".to_string() }
+fn default_restore_session() -> bool { true }
+fn default_restore_shell_directories() -> bool { true }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct UiPreferences {
@@ -133,6 +162,9 @@ pub struct UiPreferences {
     pub input_type: InputType,
     #[serde(default = "default_input_position")]
     pub input_position: InputPosition,
+    /// Key handling of the command input: plain, Emacs or modal vi editing.
+    #[serde(default = "default_input_editing_mode")]
+    pub input_editing_mode: EditingMode,
     #[serde(default = "default_dim_inactive_panes")]
     pub dim_inactive_panes: bool,
     #[serde(default = "default_focus_follows_mouse")]
@@ -150,6 +182,7 @@ impl Default for UiPreferences {
             window_blur_radius: default_window_blur_radius(),
             input_type: default_input_type(),
             input_position: default_input_position(),
+            input_editing_mode: default_input_editing_mode(),
             dim_inactive_panes: default_dim_inactive_panes(),
             focus_follows_mouse: default_focus_follows_mouse(),
         }
@@ -164,6 +197,7 @@ fn default_window_opacity() -> f32 { 1.0 }
 fn default_window_blur_radius() -> f32 { 0.0 }
 fn default_input_type() -> InputType { InputType::Universal }
 fn default_input_position() -> InputPosition { InputPosition::PinToBottom }
+fn default_input_editing_mode() -> EditingMode { EditingMode::Standard }
 fn default_dim_inactive_panes() -> bool { false }
 fn default_focus_follows_mouse() -> bool { false }
 
@@ -179,6 +213,27 @@ pub enum InputPosition {
     // Other positions could be added here
 }
 
+/// How keys edit the command input.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EditingMode {
+    /// The text field's own editing.
+    Standard,
+    /// Emacs bindings with a kill ring, like readline's default.
+    Emacs,
+    /// Modal vi editing, like `set -o vi`.
+    Vi,
+}
+
+impl std::fmt::Display for EditingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            EditingMode::Standard => "Standard",
+            EditingMode::Emacs => "Emacs",
+            EditingMode::Vi => "Vi",
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct TerminalPreferences {
     #[serde(default = "default_shell")]
@@ -187,6 +242,9 @@ pub struct TerminalPreferences {
     pub scrollback_lines: u32,
     #[serde(default = "default_bell_enabled")]
     pub bell_enabled: bool,
+    /// Memory budget for the inline images of each block, in MiB; 0 turns inline images off.
+    #[serde(default = "default_image_memory_limit_mb")]
+    pub image_memory_limit_mb: u32,
 }
 
 impl Default for TerminalPreferences {
@@ -195,6 +253,7 @@ impl Default for TerminalPreferences {
             shell: default_shell(),
             scrollback_lines: default_scrollback_lines(),
             bell_enabled: default_bell_enabled(),
+            image_memory_limit_mb: default_image_memory_limit_mb(),
         }
     }
 }
@@ -211,6 +270,7 @@ fn default_shell() -> String {
 }
 fn default_scrollback_lines() -> u32 { 10000 }
 fn default_bell_enabled() -> bool { false }
+fn default_image_memory_limit_mb() -> u32 { 64 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct EditorPreferences {
@@ -242,12 +302,58 @@ pub struct KeybindingPreferences {
     pub bindings: HashMap<String, String>, // Action -> Key combination
 }
 
+/// Built-in key bindings. `Cmd` is the platform's command key: Ctrl on Linux and Windows.
+const DEFAULT_KEYBINDINGS: &[(&str, &str)] = &[
+    ("copy", "Cmd+C"),
+    ("paste", "Cmd+V"),
+    ("new_tab", "Cmd+T"),
+    ("close_pane", "Cmd+W"),
+    ("next_tab", "Ctrl+Tab"),
+    ("previous_tab", "Ctrl+Shift+Tab"),
+    ("split_right", "Cmd+D"),
+    ("split_down", "Cmd+Shift+D"),
+    ("focus_pane_left", "Cmd+Alt+Left"),
+    ("focus_pane_right", "Cmd+Alt+Right"),
+    ("focus_pane_up", "Cmd+Alt+Up"),
+    ("focus_pane_down", "Cmd+Alt+Down"),
+    ("command_palette", "Cmd+Shift+P"),
+    ("toggle_recording", "Cmd+Shift+R"),
+    ("search", "Cmd+F"),
+    ("search_next", "Cmd+G"),
+    ("search_previous", "Cmd+Shift+G"),
+    ("history_search", "Ctrl+R"),
+    ("edit_command_in_editor", "Cmd+Shift+E"),
+    // Command input editing, used in the Emacs and vi editing modes.
+    ("emacs_beginning_of_line", "Ctrl+A"),
+    ("emacs_end_of_line", "Ctrl+E"),
+    ("emacs_backward_word", "Alt+B"),
+    ("emacs_forward_word", "Alt+F"),
+    ("emacs_kill_line", "Ctrl+K"),
+    ("emacs_kill_word_backward", "Ctrl+W"),
+    ("emacs_yank", "Ctrl+Y"),
+    ("emacs_yank_pop", "Alt+Y"),
+    ("vi_normal_mode", "Esc"),
+];
+
+impl KeybindingPreferences {
+    /// Returns the key combination bound to `action`, falling back to the built-in
+    /// binding for actions missing from older configuration files.
+    pub fn binding(&self, action: &str) -> Option<&str> {
+        self.bindings.get(action).map(String::as_str).or_else(|| {
+            DEFAULT_KEYBINDINGS
+                .iter()
+                .find(|(name, _)| *name == action)
+                .map(|(_, binding)| *binding)
+        })
+    }
+}
+
 impl Default for KeybindingPreferences {
     fn default() -> Self {
-        let mut bindings = HashMap::new();
-        bindings.insert("copy".to_string(), "Cmd+C".to_string());
-        bindings.insert("paste".to_string(), "Cmd+V".to_string());
-        bindings.insert("new_tab".to_string(), "Cmd+T".to_string());
+        let bindings = DEFAULT_KEYBINDINGS
+            .iter()
+            .map(|(action, binding)| (action.to_string(), binding.to_string()))
+            .collect();
         Self { bindings }
     }
 }
@@ -474,6 +580,27 @@ impl Default for EnvironmentProfiles {
 
 fn default_active_profile() -> Option<String> { Some("default".to_string()) }
 
+impl EnvironmentProfiles {
+    /// Returns the profile names, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.profiles.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Returns the variables of the named profile, or none if it doesn't exist.
+    pub fn variables(&self, name: Option<&str>) -> HashMap<String, String> {
+        name.and_then(|name| self.profiles.get(name))
+            .map(|profile| profile.variables.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the variables of the active profile.
+    pub fn active_variables(&self) -> HashMap<String, String> {
+        self.variables(self.active_profile.as_deref())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct EnvironmentProfile {
     #[serde(default)]
@@ -488,6 +615,92 @@ impl Default for EnvironmentProfile {
     }
 }
 
+/// Output filters saved by name for reuse on other blocks.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct OutputFilterPreferences {
+    #[serde(default)]
+    pub saved: HashMap<String, OutputFilter>,
+}
+
+impl OutputFilterPreferences {
+    /// Returns the names of the saved filters, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.saved.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+/// How links in command output are detected and opened.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LinkPreferences {
+    /// Patterns that turn output text into links, tried in order.
+    #[serde(default = "links::default_rules")]
+    pub rules: Vec<LinkRule>,
+    /// Editor command for file links and for editing the input bar's command; `$VISUAL`
+    /// or `$EDITOR` when unset. It must be a GUI editor such as `code`; terminal editors
+    /// can't be used, as blocks don't pass keys on to the programs they run.
+    #[serde(default)]
+    pub editor: Option<String>,
+}
+
+impl Default for LinkPreferences {
+    fn default() -> Self {
+        Self {
+            rules: links::default_rules(),
+            editor: None,
+        }
+    }
+}
+
+/// How text is copied from blocks, and what programs may do with the clipboard.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ClipboardPreferences {
+    /// Keep ANSI escape sequences in copied output instead of stripping them.
+    #[serde(default)]
+    pub copy_ansi: bool,
+    /// Let programs set the clipboard with OSC 52.
+    #[serde(default = "default_osc52_write")]
+    pub osc52_write: bool,
+    /// Whether programs may read the clipboard with OSC 52.
+    #[serde(default)]
+    pub osc52_read: Osc52Read,
+}
+
+impl Default for ClipboardPreferences {
+    fn default() -> Self {
+        Self {
+            copy_ansi: false,
+            osc52_write: default_osc52_write(),
+            osc52_read: Osc52Read::default(),
+        }
+    }
+}
+
+fn default_osc52_write() -> bool { true }
+
+/// When pasted text has to be confirmed before it reaches the input bar or a program.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PastePreferences {
+    /// Ask before pasting more than one line, unless the program uses bracketed paste.
+    #[serde(default = "default_confirm_multiline_paste")]
+    pub confirm_multiline: bool,
+    /// Risky commands to warn about in pasted text.
+    #[serde(default = "paste::default_rules")]
+    pub warning_rules: Vec<PasteRule>,
+}
+
+impl Default for PastePreferences {
+    fn default() -> Self {
+        Self {
+            confirm_multiline: default_confirm_multiline_paste(),
+            warning_rules: paste::default_rules(),
+        }
+    }
+}
+
+fn default_confirm_multiline_paste() -> bool { true }
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct DrivePreferences {
     #[serde(default = "default_enable_drive_integration")]
@@ -601,7 +814,10 @@ mod tests {
     async fn test_default_values() {
         let prefs = UserPreferences::default();
         assert_eq!(prefs.general.font_size, 14);
+        assert_eq!(prefs.general.restore_session, true);
+        assert_eq!(prefs.general.restore_shell_directories, true);
         assert_eq!(prefs.ui.theme_name, "nord");
+        assert_eq!(prefs.ui.input_editing_mode, EditingMode::Standard);
         assert_eq!(prefs.terminal.scrollback_lines, 10000);
         assert_eq!(prefs.editor.tab_size, 4);
         assert_eq!(prefs.ai.ai_provider_type, "openai");
@@ -625,6 +841,16 @@ mod tests {
         assert_eq!(prefs.ai.permission_execute_commands, AgentPermissionLevel::AgentDecides);
     }
 
+    #[tokio::test]
+    async fn test_keybinding_fallback() {
+        let mut keybindings = KeybindingPreferences { bindings: HashMap::new() };
+        keybindings.bindings.insert("new_tab".to_string(), "Ctrl+Shift+T".to_string());
+        assert_eq!(keybindings.binding("new_tab"), Some("Ctrl+Shift+T"));
+        // Actions missing from an older configuration use the built-in binding.
+        assert_eq!(keybindings.binding("split_right"), Some("Cmd+D"));
+        assert_eq!(keybindings.binding("unknown_action"), None);
+    }
+
     #[tokio::test]
     async fn test_environment_profiles_default() {
         let env_profiles = EnvironmentProfiles::default();
@@ -632,4 +858,18 @@ mod tests {
         assert!(env_profiles.profiles.contains_key("default"));
         assert!(env_profiles.profiles.get("default").unwrap().variables.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_environment_profile_variables() {
+        let mut env_profiles = EnvironmentProfiles::default();
+        let mut staging = EnvironmentProfile::default();
+        staging.variables.insert("API_URL".to_string(), "https://staging.example.com".to_string());
+        env_profiles.profiles.insert("staging".to_string(), staging);
+
+        assert_eq!(env_profiles.names(), vec!["default".to_string(), "staging".to_string()]);
+        assert!(env_profiles.active_variables().is_empty());
+        env_profiles.active_profile = Some("staging".to_string());
+        assert_eq!(env_profiles.active_variables().get("API_URL").map(String::as_str), Some("https://staging.example.com"));
+        assert!(env_profiles.variables(Some("missing")).is_empty());
+    }
 }
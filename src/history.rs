@@ -0,0 +1,739 @@
+//! Persistent command history.
+//!
+//! Every command run from the input bar is stored with its working directory, exit code,
+//! duration, time, tab and host in `history.jsonl` in the data directory. The file is an
+//! append-only log of JSON entries: a command is written when it starts and written again,
+//! under the same id, when it finishes, and the last record of an id wins when the file is
+//! read. `HistoryStore::compact` rewrites the file, dropping expired and repeated entries.
+//! Histories of bash, zsh and fish can be imported.
+
+use crate::fuzzy_match::FuzzyMatchManager;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, TimeZone};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Name of the history file in the data directory.
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// Returns the path of the history file.
+pub fn default_path() -> PathBuf {
+    crate::config::DATA_DIR.join(HISTORY_FILE)
+}
+
+/// A command that was run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub command: String,
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// Set once the command finished.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    pub timestamp: DateTime<Local>,
+    /// The application run the command was entered in.
+    #[serde(default)]
+    pub session_id: Option<String>,
+    #[serde(default)]
+    pub tab_id: Option<String>,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// The shell history file the entry was imported from.
+    #[serde(default)]
+    pub imported_from: Option<String>,
+    /// Position of the command in the history file it was imported from, for commands the
+    /// shell stored without a time. Such entries carry the file's modification time instead.
+    #[serde(default)]
+    pub import_position: Option<usize>,
+}
+
+impl HistoryEntry {
+    /// Creates an entry for a command starting now on this host.
+    pub fn new(id: String, command: String, cwd: Option<String>) -> Self {
+        Self {
+            id,
+            command,
+            cwd,
+            exit_code: None,
+            duration_ms: None,
+            timestamp: Local::now(),
+            session_id: None,
+            tab_id: None,
+            hostname: hostname(),
+            imported_from: None,
+            import_position: None,
+        }
+    }
+}
+
+/// Returns the name of this host.
+pub fn hostname() -> Option<String> {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+            return None;
+        }
+        let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        Some(String::from_utf8_lossy(&buf[..end]).into_owned())
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("COMPUTERNAME").ok()
+    }
+}
+
+/// How far back a history search looks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeRange {
+    #[default]
+    AnyTime,
+    LastHour,
+    LastDay,
+    LastWeek,
+    LastMonth,
+}
+
+impl TimeRange {
+    pub const ALL: [TimeRange; 5] = [
+        TimeRange::AnyTime,
+        TimeRange::LastHour,
+        TimeRange::LastDay,
+        TimeRange::LastWeek,
+        TimeRange::LastMonth,
+    ];
+
+    /// Returns the start of the range, counting back from `now`.
+    pub fn since(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let span = match self {
+            TimeRange::AnyTime => return None,
+            TimeRange::LastHour => chrono::Duration::hours(1),
+            TimeRange::LastDay => chrono::Duration::days(1),
+            TimeRange::LastWeek => chrono::Duration::weeks(1),
+            TimeRange::LastMonth => chrono::Duration::days(30),
+        };
+        Some(now - span)
+    }
+}
+
+impl std::fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            TimeRange::AnyTime => "Any time",
+            TimeRange::LastHour => "Last hour",
+            TimeRange::LastDay => "Last day",
+            TimeRange::LastWeek => "Last week",
+            TimeRange::LastMonth => "Last 30 days",
+        })
+    }
+}
+
+/// Restricts the entries a history search looks at. Unset fields don't restrict.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistoryFilter {
+    /// Only commands run in this directory.
+    pub cwd: Option<String>,
+    /// Only commands that exited with 0.
+    pub successful_only: bool,
+    /// Only commands of this application run.
+    pub session_id: Option<String>,
+    /// Only commands run at or after this time.
+    pub since: Option<DateTime<Local>>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.cwd.as_ref().map_or(true, |cwd| entry.cwd.as_ref() == Some(cwd))
+            && (!self.successful_only || entry.exit_code == Some(0))
+            && self.session_id.as_ref().map_or(true, |session| entry.session_id.as_ref() == Some(session))
+            && self.since.map_or(true, |since| entry.timestamp >= since)
+    }
+}
+
+/// A command found by a history search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryMatch {
+    /// The most recent entry of the command.
+    pub entry: HistoryEntry,
+    /// Positions of the matched characters in the command.
+    pub indices: Vec<usize>,
+}
+
+/// A shell whose history file can be imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellHistory {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl ShellHistory {
+    pub const ALL: [ShellHistory; 3] = [ShellHistory::Bash, ShellHistory::Zsh, ShellHistory::Fish];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShellHistory::Bash => "Bash",
+            ShellHistory::Zsh => "Zsh",
+            ShellHistory::Fish => "Fish",
+        }
+    }
+
+    /// Returns where the shell keeps its history by default.
+    pub fn default_path(&self) -> Option<PathBuf> {
+        match self {
+            ShellHistory::Bash => dirs::home_dir().map(|home| home.join(".bash_history")),
+            ShellHistory::Zsh => dirs::home_dir().map(|home| home.join(".zsh_history")),
+            ShellHistory::Fish => dirs::data_dir().map(|data| data.join("fish").join("fish_history")),
+        }
+    }
+
+    /// Reads the commands of a history file, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The contents of the history file.
+    ///
+    /// # Returns
+    ///
+    /// Each command with the time it was run, or `None` for commands stored without one,
+    /// such as those of a bash history without `HISTTIMEFORMAT`.
+    pub fn parse(&self, content: &[u8]) -> Vec<(String, Option<DateTime<Local>>)> {
+        match self {
+            ShellHistory::Bash => parse_bash(&String::from_utf8_lossy(content)),
+            ShellHistory::Zsh => parse_zsh(&String::from_utf8_lossy(&unmetafy(content))),
+            ShellHistory::Fish => parse_fish(&String::from_utf8_lossy(content)),
+        }
+    }
+}
+
+fn timestamp(seconds: &str) -> Option<DateTime<Local>> {
+    Local.timestamp_opt(seconds.trim().parse().ok()?, 0).single()
+}
+
+/// Bash history: one command per line, each optionally preceded by a `#<seconds>` line.
+fn parse_bash(content: &str) -> Vec<(String, Option<DateTime<Local>>)> {
+    let mut commands = Vec::new();
+    let mut time = None;
+    for line in content.lines() {
+        if let Some(seconds) = line.strip_prefix('#').filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())) {
+            time = timestamp(seconds);
+        } else if !line.trim().is_empty() {
+            commands.push((line.to_string(), time.take()));
+        }
+    }
+    commands
+}
+
+/// Undoes zsh's metafication of history files, which stores some bytes as 0x83 followed
+/// by the byte xor 0x20.
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    let mut unmetafied = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            0x83 => unmetafied.extend(bytes.next().map(|next| next ^ 0x20)),
+            byte => unmetafied.push(byte),
+        }
+    }
+    unmetafied
+}
+
+/// Zsh history: `: <seconds>:<duration>;<command>` with `EXTENDED_HISTORY`, otherwise just
+/// the command. Lines of multi-line commands end with a backslash.
+fn parse_zsh(content: &str) -> Vec<(String, Option<DateTime<Local>>)> {
+    let mut commands = Vec::new();
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        let (time, mut command) = match line.strip_prefix(": ").and_then(|rest| rest.split_once(';')) {
+            Some((meta, command)) => (meta.split(':').next().and_then(timestamp), command.to_string()),
+            None => (None, line.to_string()),
+        };
+        while command.ends_with('\\') {
+            command.pop();
+            command.push('\n');
+            match lines.next() {
+                Some(next) => command.push_str(next),
+                None => break,
+            }
+        }
+        if !command.trim().is_empty() {
+            commands.push((command, time));
+        }
+    }
+    commands
+}
+
+/// Fish history: a YAML-like list of `- cmd: <command>` items with a `when: <seconds>` field.
+/// Newlines and backslashes in commands are escaped.
+fn parse_fish(content: &str) -> Vec<(String, Option<DateTime<Local>>)> {
+    let mut commands: Vec<(String, Option<DateTime<Local>>)> = Vec::new();
+    for line in content.lines() {
+        if let Some(command) = line.strip_prefix("- cmd: ") {
+            commands.push((unescape_fish(command), None));
+        } else if let Some(seconds) = line.trim_start().strip_prefix("when: ") {
+            if let (Some(last), Some(time)) = (commands.last_mut(), timestamp(seconds)) {
+                last.1 = Some(time);
+            }
+        }
+    }
+    commands
+}
+
+fn unescape_fish(command: &str) -> String {
+    let mut unescaped = String::with_capacity(command.len());
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                unescaped.push('\n');
+            }
+            ('\\', Some('\\')) => {
+                chars.next();
+                unescaped.push('\\');
+            }
+            (c, _) => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// The command history, kept in memory and mirrored to a file.
+#[derive(Debug, Default)]
+pub struct HistoryStore {
+    /// `None` for a history that is not saved.
+    path: Option<PathBuf>,
+    /// Oldest first.
+    entries: Vec<HistoryEntry>,
+    /// Position of each entry in `entries`, by id.
+    positions: HashMap<String, usize>,
+}
+
+impl HistoryStore {
+    /// Creates a history that is not saved to disk.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Opens the history file at `path`, which is created when the first command is added.
+    /// Lines that can't be read are skipped.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut store = Self { path: Some(path.to_path_buf()), ..Self::default() };
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(store),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read history file {:?}", path)),
+        };
+        for (number, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            match serde_json::from_str::<HistoryEntry>(line) {
+                Ok(entry) => store.upsert(entry),
+                Err(e) => warn!("Skipping line {} of history file {:?}: {}", number + 1, path, e),
+            }
+        }
+        Ok(store)
+    }
+
+    /// All entries, oldest first.
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    fn upsert(&mut self, entry: HistoryEntry) {
+        match self.positions.get(&entry.id) {
+            Some(&position) => self.entries[position] = entry,
+            None => {
+                self.positions.insert(entry.id.clone(), self.entries.len());
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    /// Appends records to the history file.
+    fn append(&self, entries: &[&HistoryEntry]) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("Failed to open history file {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+        for entry in entries {
+            serde_json::to_writer(&mut writer, entry)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Records a command. An entry with the same id is replaced.
+    pub fn add(&mut self, entry: HistoryEntry) -> Result<()> {
+        self.append(&[&entry])?;
+        self.upsert(entry);
+        Ok(())
+    }
+
+    /// Records the exit code and duration of the command with entry `id`, once it finished.
+    pub fn finish(&mut self, id: &str, exit_code: i32, duration: std::time::Duration) -> Result<()> {
+        let Some(&position) = self.positions.get(id) else {
+            return Ok(());
+        };
+        let entry = &mut self.entries[position];
+        entry.exit_code = Some(exit_code);
+        entry.duration_ms = Some(duration.as_millis() as u64);
+        self.append(&[&self.entries[position]])
+    }
+
+    /// Adds commands the history doesn't have, such as those of a session saved before the
+    /// history file existed, as its oldest entries. They are not written to the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `commands` - Commands ordered most recent first.
+    pub fn restore(&mut self, commands: Vec<String>) {
+        let known: HashSet<&str> = self.entries.iter().map(|entry| entry.command.as_str()).collect();
+        let time = self.entries.first().map_or_else(Local::now, |entry| entry.timestamp);
+        let mut restored: Vec<HistoryEntry> = commands.into_iter()
+            .rev()
+            .filter(|command| !command.trim().is_empty() && !known.contains(command.as_str()))
+            .map(|command| HistoryEntry { timestamp: time, ..HistoryEntry::new(Uuid::new_v4().to_string(), command, None) })
+            .collect();
+        if restored.is_empty() {
+            return;
+        }
+        restored.append(&mut self.entries);
+        self.positions = restored.iter().enumerate().map(|(position, entry)| (entry.id.clone(), position)).collect();
+        self.entries = restored;
+    }
+
+    /// Returns the distinct commands, most recently run first.
+    pub fn commands(&self) -> impl Iterator<Item = &str> {
+        let mut seen = HashSet::new();
+        self.entries.iter().rev().map(|entry| entry.command.as_str()).filter(move |command| seen.insert(*command))
+    }
+
+    /// Returns the `n`th distinct command, counting back from the most recent one.
+    pub fn nth_command(&self, n: usize) -> Option<String> {
+        self.commands().nth(n).map(str::to_string)
+    }
+
+    /// Finds the distinct commands that fuzzily match `query` among the entries passing
+    /// `filter`. Each command is returned with its most recent entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `matcher` - The fuzzy matcher to rank the commands with.
+    /// * `query` - The characters to look for. An empty query matches every command.
+    /// * `filter` - Which entries to search.
+    /// * `limit` - The maximum number of matches to return.
+    ///
+    /// # Returns
+    ///
+    /// The best matches first; equally good matches are ordered most recent first.
+    pub fn fuzzy_search(&self, matcher: &FuzzyMatchManager, query: &str, filter: &HistoryFilter, limit: usize) -> Vec<HistoryMatch> {
+        let mut seen = HashSet::new();
+        let candidates: Vec<&HistoryEntry> = self.entries.iter()
+            .rev()
+            .filter(|entry| filter.matches(entry) && seen.insert(entry.command.as_str()))
+            .collect();
+        if query.is_empty() {
+            return candidates.into_iter()
+                .take(limit)
+                .map(|entry| HistoryMatch { entry: entry.clone(), indices: Vec::new() })
+                .collect();
+        }
+        let by_command: HashMap<&str, &HistoryEntry> = candidates.iter().map(|entry| (entry.command.as_str(), *entry)).collect();
+        let commands: Vec<String> = candidates.iter().map(|entry| entry.command.clone()).collect();
+        matcher.fuzzy_match(query, &commands)
+            .into_iter()
+            .take(limit)
+            .map(|result| HistoryMatch { entry: by_command[result.id.as_str()].clone(), indices: result.indices })
+            .collect()
+    }
+
+    /// Drops entries older than `retention_days` (all of them when 0), and repeats of the
+    /// command run just before in the same tab, keeping the latest. The history file is rewritten.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries removed.
+    pub fn compact(&mut self, retention_days: u16, now: DateTime<Local>) -> Result<usize> {
+        let cutoff = now - chrono::Duration::days(retention_days as i64);
+        let before = self.entries.len();
+        let mut kept: Vec<HistoryEntry> = Vec::with_capacity(before);
+        for entry in std::mem::take(&mut self.entries) {
+            if retention_days == 0 || entry.timestamp < cutoff {
+                continue;
+            }
+            let previous = kept.iter().rposition(|earlier| {
+                earlier.tab_id == entry.tab_id && earlier.imported_from == entry.imported_from
+            });
+            if let Some(position) = previous {
+                if kept[position].command == entry.command && kept[position].cwd == entry.cwd {
+                    kept.remove(position);
+                }
+            }
+            kept.push(entry);
+        }
+        self.positions = kept.iter().enumerate().map(|(position, entry)| (entry.id.clone(), position)).collect();
+        self.entries = kept;
+        let removed = before - self.entries.len();
+        if removed > 0 {
+            self.rewrite()?;
+        }
+        Ok(removed)
+    }
+
+    /// Writes all entries to a new history file, replacing the old one.
+    fn rewrite(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let temp = path.with_extension("jsonl.tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&temp)?);
+            for entry in &self.entries {
+                serde_json::to_writer(&mut writer, entry)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+        fs::rename(&temp, path).with_context(|| format!("Failed to replace history file {:?}", path))
+    }
+
+    /// Imports the commands of a shell's history file. Commands already imported are skipped,
+    /// so importing a file again only adds what is new in it.
+    ///
+    /// Commands with a time are recognized by their command and time. Commands stored
+    /// without one get the file's modification time, which changes whenever the shell
+    /// appends to it, so they are recognized by their position in the file instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `shell` - The shell that wrote the file.
+    /// * `path` - The history file.
+    ///
+    /// # Returns
+    ///
+    /// The number of commands imported.
+    pub fn import(&mut self, shell: ShellHistory, path: &Path) -> Result<usize> {
+        let content = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        // Commands stored without a time are at most as old as the file.
+        let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).map(DateTime::<Local>::from).unwrap_or_else(|_| Local::now());
+        let source = path.to_string_lossy().to_string();
+        let commands = shell.parse(&content);
+        let previous: Vec<&HistoryEntry> = self.entries.iter()
+            .filter(|entry| entry.imported_from.as_deref() == Some(source.as_str()))
+            .collect();
+        let existing: HashSet<(&str, DateTime<Local>)> = previous.iter()
+            .filter(|entry| entry.import_position.is_none())
+            .map(|entry| (entry.command.as_str(), entry.timestamp))
+            .collect();
+        // Everything up to the last untimed command imported before that is still in its
+        // place was imported then, even if compaction has since removed some of it.
+        let imported_through = previous.iter()
+            .filter_map(|entry| {
+                let position = entry.import_position?;
+                matches!(commands.get(position), Some((command, None)) if *command == entry.command).then_some(position)
+            })
+            .max();
+        let host = hostname();
+        let imported: Vec<HistoryEntry> = commands.into_iter()
+            .enumerate()
+            .filter(|(position, (command, time))| match time {
+                Some(time) => !existing.contains(&(command.as_str(), *time)),
+                None => imported_through.is_none_or(|through| *position > through),
+            })
+            .map(|(position, (command, time))| HistoryEntry {
+                timestamp: time.unwrap_or(modified),
+                hostname: host.clone(),
+                imported_from: Some(source.clone()),
+                import_position: time.is_none().then_some(position),
+                ..HistoryEntry::new(Uuid::new_v4().to_string(), command, None)
+            })
+            .collect();
+        self.append(&imported.iter().collect::<Vec<_>>())?;
+        let count = imported.len();
+        for entry in imported {
+            self.upsert(entry);
+        }
+        info!("Imported {} commands from {} history {:?}", count, shell.name(), path);
+        Ok(count)
+    }
+}
+
+/// Initializes the history module.
+pub fn init() {
+    info!("history module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> DateTime<Local> {
+        Local.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    fn entry(id: &str, command: &str, seconds: i64) -> HistoryEntry {
+        HistoryEntry { timestamp: at(seconds), ..HistoryEntry::new(id.to_string(), command.to_string(), Some("/tmp".to_string())) }
+    }
+
+    #[test]
+    fn test_log_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let mut store = HistoryStore::open(&path).unwrap();
+        store.add(entry("a", "cargo build", 100)).unwrap();
+        store.add(entry("b", "cargo test", 200)).unwrap();
+        store.finish("a", 101, std::time::Duration::from_millis(1500)).unwrap();
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"not json\n").unwrap();
+
+        let reopened = HistoryStore::open(&path).unwrap();
+        assert_eq!(reopened.entries().len(), 2);
+        assert_eq!(reopened.entries()[0].exit_code, Some(101));
+        assert_eq!(reopened.entries()[0].duration_ms, Some(1500));
+        assert_eq!(reopened.entries()[1].exit_code, None);
+    }
+
+    #[test]
+    fn test_distinct_commands() {
+        let mut store = HistoryStore::in_memory();
+        for (id, command) in [("1", "ls"), ("2", "git status"), ("3", "ls"), ("4", "git diff")] {
+            store.add(entry(id, command, 0)).unwrap();
+        }
+        assert_eq!(store.commands().collect::<Vec<_>>(), vec!["git diff", "ls", "git status"]);
+        assert_eq!(store.nth_command(1).as_deref(), Some("ls"));
+        assert_eq!(store.nth_command(3), None);
+
+        store.restore(vec!["pwd".to_string(), "ls".to_string(), "make".to_string()]);
+        assert_eq!(store.commands().collect::<Vec<_>>(), vec!["git diff", "ls", "git status", "pwd", "make"]);
+    }
+
+    #[test]
+    fn test_fuzzy_search() {
+        let mut store = HistoryStore::in_memory();
+        let day = 24 * 60 * 60;
+        let runs = [
+            ("1", "git status", "/repo", Some(0), "old", 0),
+            ("2", "cargo test", "/repo", Some(101), "old", day),
+            ("3", "git stash", "/other", Some(0), "current", 2 * day),
+            ("4", "cargo test", "/repo", Some(0), "current", 3 * day),
+            ("5", "grep -r todo", "/repo", Some(1), "current", 3 * day),
+        ];
+        for (id, command, cwd, exit_code, session, seconds) in runs {
+            store.add(HistoryEntry {
+                cwd: Some(cwd.to_string()),
+                exit_code,
+                session_id: Some(session.to_string()),
+                ..entry(id, command, seconds)
+            }).unwrap();
+        }
+        let matcher = FuzzyMatchManager::new();
+        let commands = |query: &str, filter: &HistoryFilter| -> Vec<String> {
+            store.fuzzy_search(&matcher, query, filter, 10).into_iter().map(|found| found.entry.command).collect()
+        };
+
+        let all = HistoryFilter::default();
+        assert_eq!(commands("", &all), vec!["grep -r todo", "cargo test", "git stash", "git status"]);
+        let mut git = commands("gits", &all);
+        git.sort();
+        assert_eq!(git, vec!["git stash", "git status"]);
+        assert_eq!(store.fuzzy_search(&matcher, "ct", &all, 10)[0].entry.id, "4");
+
+        let in_repo = HistoryFilter { cwd: Some("/repo".to_string()), successful_only: true, ..HistoryFilter::default() };
+        assert_eq!(commands("", &in_repo), vec!["cargo test", "git status"]);
+        let this_session = HistoryFilter { session_id: Some("current".to_string()), since: Some(at(2 * day + 1)), ..HistoryFilter::default() };
+        assert_eq!(commands("", &this_session), vec!["grep -r todo", "cargo test"]);
+    }
+
+    #[test]
+    fn test_compact() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let mut store = HistoryStore::open(&path).unwrap();
+        let day = 24 * 60 * 60;
+        store.add(entry("old", "make", 0)).unwrap();
+        store.add(entry("1", "make", 10 * day)).unwrap();
+        store.add(entry("2", "make", 10 * day + 5)).unwrap();
+        store.add(entry("3", "ls", 10 * day + 9)).unwrap();
+        store.add(entry("4", "make", 10 * day + 10)).unwrap();
+
+        assert_eq!(store.compact(5, at(12 * day)).unwrap(), 2);
+        let ids: Vec<_> = store.entries().iter().map(|entry| entry.id.as_str()).collect();
+        assert_eq!(ids, vec!["2", "3", "4"]);
+        assert_eq!(HistoryStore::open(&path).unwrap().entries().len(), 3);
+
+        assert_eq!(store.compact(0, at(12 * day)).unwrap(), 3);
+        assert!(HistoryStore::open(&path).unwrap().entries().is_empty());
+    }
+
+    #[test]
+    fn test_parse_shell_histories() {
+        let commands = |shell: ShellHistory, content: &[u8]| -> Vec<(String, Option<i64>)> {
+            shell.parse(content).into_iter().map(|(command, time)| (command, time.map(|time| time.timestamp()))).collect()
+        };
+        assert_eq!(
+            commands(ShellHistory::Bash, b"ls\n#1700000000\ngit status\n#not a time\n"),
+            vec![("ls".to_string(), None), ("git status".to_string(), Some(1700000000)), ("#not a time".to_string(), None)]
+        );
+        assert_eq!(
+            commands(ShellHistory::Zsh, b": 1700000000:0;echo one\\\ntwo\n: 1700000100:3;caf\xc3\x83\xa9\nls\n"),
+            vec![("echo one\ntwo".to_string(), Some(1700000000)), ("caf\u{c9}".to_string(), Some(1700000100)), ("ls".to_string(), None)]
+        );
+        assert_eq!(
+            commands(ShellHistory::Fish, b"- cmd: echo a\\nb \\\\ c\n  when: 1700000000\n  paths:\n    - b\n- cmd: ls\n"),
+            vec![("echo a\nb \\ c".to_string(), Some(1700000000)), ("ls".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn test_import_is_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = dir.path().join(".bash_history");
+        std::fs::write(&history, "#1700000000\nls\n#1700000001\nls\n").unwrap();
+        let mut store = HistoryStore::in_memory();
+        assert_eq!(store.import(ShellHistory::Bash, &history).unwrap(), 2);
+        assert_eq!(store.import(ShellHistory::Bash, &history).unwrap(), 0);
+        std::fs::write(&history, "#1700000000\nls\n#1700000001\nls\n#1700000002\npwd\n").unwrap();
+        assert_eq!(store.import(ShellHistory::Bash, &history).unwrap(), 1);
+        assert_eq!(store.entries()[2].imported_from.as_deref(), Some(history.to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_reimporting_a_plain_bash_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = dir.path().join(".bash_history");
+        let set_modified = |seconds: u64| {
+            let file = File::options().write(true).open(&history).unwrap();
+            file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds)).unwrap();
+        };
+        std::fs::write(&history, "ls\ncd /tmp\nls\n").unwrap();
+        set_modified(1700000000);
+        let mut store = HistoryStore::in_memory();
+        assert_eq!(store.import(ShellHistory::Bash, &history).unwrap(), 3);
+        assert_eq!(store.entries()[0].timestamp, at(1700000000));
+
+        // The shell appended to the file, which moved its modification time.
+        std::fs::write(&history, "ls\ncd /tmp\nls\nls\nmake\n").unwrap();
+        set_modified(1700000500);
+        assert_eq!(store.import(ShellHistory::Bash, &history).unwrap(), 2);
+        assert_eq!(store.entries().len(), 5);
+        assert_eq!(store.entries()[3].timestamp, at(1700000500));
+        assert_eq!(store.import(ShellHistory::Bash, &history).unwrap(), 0);
+
+        // Repeats dropped by compaction are not imported again.
+        store.compact(u16::MAX, Local::now()).unwrap();
+        assert_eq!(store.entries().len(), 4);
+        assert_eq!(store.import(ShellHistory::Bash, &history).unwrap(), 0);
+    }
+}
@@ -2,22 +2,66 @@
 //! and intelligent suggestions for commands and files.
 //! It provides an `EnhancedTextInput` widget for the Iced GUI.
 
-use iced::{Element, widget::{text_input, column, row, container, button, text, pick_list}, Length};
+use iced::{Command, Element, widget::{text_input, column, row, container, button, text, pick_list}, Length};
 use iced::keyboard::{self, KeyCode, Modifiers};
 use iced::{keyboard::Event as KeyEvent, Event as IcedEvent};
-use std::collections::{VecDeque, HashMap};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use anyhow::Result;
 use log::info;
+use crate::command_highlight::{self, LineHighlight, ProblemKind};
+use crate::completion::{self, Completion, CompletionKind, LineContext};
+use crate::config::preferences::{EditingMode, KeybindingPreferences};
+use crate::fuzzy_match::FuzzyMatchManager;
+use crate::history::{HistoryFilter, HistoryStore};
+use crate::line_editor::{EmacsCommand, Key, KeyOutcome, LineEditor, ViMode};
+use crate::multiline::{self, Continuation};
+use crate::workspace;
+
+/// Id of the command input, so it can be focused after text is put in it.
+pub const INPUT_ID: &str = "command-input";
+
+/// Prompt of the lines after the first of a multi-line command, as the shell's `PS2`.
+const CONTINUATION_PROMPT: &str = "> ";
+
+/// Prefixes of input that isn't run by the shell, but asks the AI or plays a recording.
+const NON_SHELL_PREFIXES: &[&str] = &["#", "/ai", "/play "];
+
+/// Whether the shell runs `line` when it is submitted.
+pub fn is_shell_command(line: &str) -> bool {
+    !NON_SHELL_PREFIXES.iter().any(|prefix| line.starts_with(prefix))
+}
 
 /// Represents the state and logic for an enhanced text input field.
 #[derive(Debug, Clone)]
 pub struct EnhancedTextInput {
     value: String,
+    /// Lines of a multi-line command before the one in the text field, which is `value`.
+    lines_above: Vec<String>,
+    /// Lines of a multi-line command after the one in the text field.
+    lines_below: Vec<String>,
+    /// What the command waits for, if the shell would wait for more lines of it.
+    continuation: Option<Continuation>,
+    /// Spaces per indentation level of multi-line commands.
+    indent_width: usize,
+    /// Whether the text field edited the value since the last key press, so that key
+    /// was handled by the field.
+    edited_since_key: bool,
     suggestions: Vec<Suggestion>,
     active_suggestion: Option<usize>,
-    history: VecDeque<String>,
+    /// The command history, shared with the application, which records the commands.
+    history: Arc<Mutex<HistoryStore>>,
     history_index: Option<usize>,
+    /// Directory of the focused pane, which file completions are relative to.
+    cwd: Option<String>,
     live_preview: String,
+    /// Syntax highlighting of the value, if it is a shell command.
+    highlight: Option<LineHighlight>,
+    /// Vi or Emacs editing state, which follows the text field's cursor.
+    editor: LineEditor,
+    /// Modifiers held, as Alt+letter types the letter besides running an Emacs command.
+    modifiers: Modifiers,
     // New fields for AI model selection
     available_ai_models: Vec<String>,
     selected_ai_model: Option<String>,
@@ -26,7 +70,10 @@ pub struct EnhancedTextInput {
 /// Represents a single suggestion for the input field.
 #[derive(Debug, Clone)]
 pub struct Suggestion {
+    /// The input once the suggestion is applied.
     pub text: String,
+    /// What the dropdown shows, e.g. only the completed word.
+    pub label: String,
     pub description: Option<String>,
     pub suggestion_type: SuggestionType,
     pub score: f30,
@@ -41,6 +88,27 @@ pub enum SuggestionType {
     Flag,
     History,
     Alias,
+    Variable,
+    Subcommand,
+    /// A git branch or remote.
+    GitRef,
+    /// Any other argument offered by the shell.
+    Argument,
+}
+
+impl From<CompletionKind> for SuggestionType {
+    fn from(kind: CompletionKind) -> Self {
+        match kind {
+            CompletionKind::Executable | CompletionKind::Builtin => SuggestionType::Command,
+            CompletionKind::File => SuggestionType::File,
+            CompletionKind::Directory => SuggestionType::Directory,
+            CompletionKind::Variable => SuggestionType::Variable,
+            CompletionKind::GitBranch | CompletionKind::GitRemote => SuggestionType::GitRef,
+            CompletionKind::Subcommand => SuggestionType::Subcommand,
+            CompletionKind::Flag => SuggestionType::Flag,
+            CompletionKind::Argument => SuggestionType::Argument,
+        }
+    }
 }
 
 /// Direction for navigating suggestions.
@@ -61,11 +129,21 @@ pub enum HistoryDirection {
 #[derive(Debug, Clone)]
 pub enum Message {
     InputChanged(String),
+    /// Text was pasted into the field. The application reads the clipboard itself to
+    /// check the paste, as the field would drop its line breaks.
+    Pasted,
     Submit,
     SuggestionSelected(usize),
     NavigateSuggestions(Direction),
     ApplySuggestion,
     HistoryNavigated(HistoryDirection),
+    /// Breaks the line at the cursor, indenting the new line as the command's nesting.
+    NewLine,
+    /// Replaces the whole input, which may have several lines, e.g. with a command from
+    /// the history.
+    SetText(String),
+    /// Asks to edit the input in `$VISUAL` or `$EDITOR`.
+    OpenInEditor,
     // New messages for AI model selection and icon clicks
     AiModelSelected(String),
     ToggleAiMode,
@@ -76,15 +154,29 @@ pub enum Message {
 }
 
 impl EnhancedTextInput {
-    /// Creates a new `EnhancedTextInput` instance.
+    /// Creates a new `EnhancedTextInput` instance with a history that isn't saved.
     pub fn new() -> Self {
+        Self::with_history(Arc::new(Mutex::new(HistoryStore::in_memory())))
+    }
+
+    /// Creates a new `EnhancedTextInput` instance that navigates and suggests from `history`.
+    pub fn with_history(history: Arc<Mutex<HistoryStore>>) -> Self {
         Self {
             value: String::new(),
+            lines_above: Vec::new(),
+            lines_below: Vec::new(),
+            continuation: None,
+            indent_width: 4,
+            edited_since_key: false,
             suggestions: Vec::new(),
             active_suggestion: None,
-            history: VecDeque::new(),
+            history,
             history_index: None,
+            cwd: None,
             live_preview: String::new(),
+            highlight: None,
+            editor: LineEditor::new(EditingMode::Standard),
+            modifiers: Modifiers::default(),
             available_ai_models: vec![
                 "claude 4 sonnet".to_string(),
                 "gpt-4o".to_string(),
@@ -100,24 +192,39 @@ impl EnhancedTextInput {
     ///
     /// * `message` - The `Message` to process.
     pub fn update(&mut self, message: Message) {
+        let previous = self.value.clone();
+        let moves_cursor = matches!(message, Message::InputChanged(_) | Message::NewLine);
         match message {
             Message::InputChanged(value) => {
-                self.value = value;
+                self.edited_since_key = true;
+                if value.contains('\n') {
+                    self.set_line(value);
+                    self.editor.text_replaced(&self.value);
+                } else {
+                    self.editor.text_changed(&self.value, &value);
+                    self.value = value;
+                }
                 self.update_suggestions();
                 // Auto-select the first suggestion and update live preview
                 self.active_suggestion = self.suggestions.first().map(|_| 0);
                 self.update_live_preview();
             }
+            Message::Pasted => {
+                // Handled by the application, which inserts the text once it was checked.
+            }
             Message::Submit => {
-                self.add_to_history(self.value.clone());
+                self.editor.line_submitted();
+                self.history_index = None;
                 self.value.clear();
+                self.lines_above.clear();
+                self.lines_below.clear();
                 self.suggestions.clear();
                 self.active_suggestion = None;
                 self.live_preview.clear();
             }
             Message::SuggestionSelected(index) => {
                 if let Some(suggestion) = self.suggestions.get(index) {
-                    self.value = suggestion.text.clone();
+                    self.set_line(suggestion.text.clone());
                     self.suggestions.clear();
                     self.active_suggestion = None;
                     self.live_preview.clear();
@@ -143,7 +250,7 @@ impl EnhancedTextInput {
             Message::ApplySuggestion => {
                 if let Some(index) = self.active_suggestion {
                     if let Some(suggestion) = self.suggestions.get(index) {
-                        self.value = suggestion.text.clone();
+                        self.set_line(suggestion.text.clone());
                         self.suggestions.clear();
                         self.active_suggestion = None;
                         self.live_preview.clear();
@@ -151,13 +258,49 @@ impl EnhancedTextInput {
                 }
             }
             Message::HistoryNavigated(direction) => {
-                if let Some(cmd) = self.navigate_history(direction) {
-                    self.value = cmd;
+                // The arrows move between the lines of a multi-line command before the history.
+                let moved = match direction {
+                    HistoryDirection::Up => self.lines_above.pop().map(|line| {
+                        self.lines_below.insert(0, std::mem::replace(&mut self.value, line));
+                    }),
+                    HistoryDirection::Down => (!self.lines_below.is_empty()).then(|| {
+                        let line = self.lines_below.remove(0);
+                        self.lines_above.push(std::mem::replace(&mut self.value, line));
+                    }),
+                };
+                let moved = moved.is_some() || match self.navigate_history(direction) {
+                    Some(cmd) => {
+                        self.set_text(cmd);
+                        true
+                    }
+                    None => false,
+                };
+                if moved {
                     self.suggestions.clear();
                     self.active_suggestion = None;
                     self.live_preview.clear();
                 }
             }
+            Message::NewLine => {
+                let split = self.value.char_indices().nth(self.editor.cursor()).map_or(self.value.len(), |(i, _)| i);
+                let rest = self.value.split_off(split);
+                self.lines_above.push(multiline::dedent_closer(&self.value, self.indent_width));
+                let indent = multiline::indentation(&self.lines_above.join("\n"), self.indent_width);
+                self.value = format!("{}{}", indent, rest.trim_start());
+                self.editor.text_replaced_at(&self.value, indent.chars().count());
+                self.suggestions.clear();
+                self.active_suggestion = None;
+                self.live_preview.clear();
+            }
+            Message::SetText(text) => {
+                self.set_text(text);
+                self.update_suggestions();
+                self.active_suggestion = self.suggestions.first().map(|_| 0);
+                self.update_live_preview();
+            }
+            Message::OpenInEditor => {
+                // Handled by the application, which opens the command in a GUI editor.
+            }
             Message::AiModelSelected(model) => {
                 self.selected_ai_model = Some(model);
                 info!("AI Model selected: {:?}", self.selected_ai_model);
@@ -183,6 +326,187 @@ impl EnhancedTextInput {
                 // Implement image (insert image) toggle logic here
             }
         }
+        if !moves_cursor && self.value != previous {
+            self.editor.text_replaced(&self.value);
+        }
+        self.refresh_highlight();
+    }
+
+    /// Puts `line` in the text field. Text before its last line break, e.g. of a pasted
+    /// script or a multi-line command from the history, goes to the lines above.
+    fn set_line(&mut self, line: String) {
+        match line.rsplit_once('\n') {
+            Some((lines, last)) => {
+                self.lines_above.extend(lines.split('\n').map(str::to_string));
+                self.value = last.to_string();
+            }
+            None => self.value = line,
+        }
+    }
+
+    /// Replaces the whole input with `text`, editing its last line.
+    fn set_text(&mut self, text: String) {
+        self.lines_above.clear();
+        self.lines_below.clear();
+        self.set_line(text);
+    }
+
+    /// Returns the whole input, with the lines of a multi-line command joined by line breaks.
+    pub fn script(&self) -> String {
+        let mut lines = self.lines_above.clone();
+        lines.push(self.value.clone());
+        lines.extend(self.lines_below.iter().cloned());
+        lines.join("\n")
+    }
+
+    /// Whether the input has more than the line in the text field.
+    pub fn is_multiline(&self) -> bool {
+        !self.lines_above.is_empty() || !self.lines_below.is_empty()
+    }
+
+    /// Whether Enter adds a line instead of submitting the input: Shift is held, or the
+    /// shell would wait for more of the command.
+    pub fn wants_new_line(&self) -> bool {
+        self.modifiers.shift() || self.continuation.is_some()
+    }
+
+    /// Sets the spaces per indentation level of multi-line commands.
+    pub fn set_indent_width(&mut self, width: usize) {
+        self.indent_width = width.max(1);
+    }
+
+    /// Sets how keys edit the input.
+    pub fn set_editing_mode(&mut self, mode: EditingMode) {
+        self.editor.set_mode(mode);
+    }
+
+    /// Whether text typed into the field belongs in it. In vi's normal mode keys are
+    /// commands, and Alt+letter runs an Emacs command instead of typing the letter.
+    pub fn accepts_typing(&self) -> bool {
+        self.editor.accepts_typing() && !(self.editor.mode() == EditingMode::Emacs && self.modifiers.alt())
+    }
+
+    /// Records the modifiers held.
+    pub fn set_modifiers(&mut self, modifiers: Modifiers) {
+        self.modifiers = modifiers;
+    }
+
+    /// Edits the input as the vi or Emacs editing mode does for a key. Emacs commands and
+    /// the switch to vi's normal mode are looked up in the key bindings, so they can be
+    /// remapped. In every mode, Backspace at the start of a line of a multi-line command
+    /// joins it to the line above.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - A keyboard event, which the text field may have handled too.
+    /// * `bindings` - The user's key bindings.
+    ///
+    /// # Returns
+    ///
+    /// Whether the key edited the input, in which case the text field's cursor has to be
+    /// moved with `sync_cursor`.
+    pub fn handle_key(&mut self, event: &KeyEvent, bindings: &KeybindingPreferences) -> bool {
+        let bound = |action: &str, key_code, modifiers| {
+            bindings.binding(action).is_some_and(|binding| workspace::binding_matches(binding, key_code, modifiers))
+        };
+        let previous = self.value.clone();
+        if let KeyEvent::KeyPressed { key_code, .. } = event {
+            // The text field deletes a character before the cursor itself, which it reported first.
+            let edited = std::mem::take(&mut self.edited_since_key);
+            if *key_code == KeyCode::Backspace && !edited && self.editor.cursor() == 0 && self.accepts_typing() {
+                if let Some(line) = self.lines_above.pop() {
+                    let cursor = line.chars().count();
+                    self.value.insert_str(0, &line);
+                    self.editor.text_replaced_at(&self.value, cursor);
+                    self.suggestions.clear();
+                    self.active_suggestion = None;
+                    self.live_preview.clear();
+                    self.refresh_highlight();
+                    return true;
+                }
+            }
+        }
+        let outcome = match (self.editor.mode(), event) {
+            (EditingMode::Standard, KeyEvent::KeyPressed { key_code, .. }) => {
+                if let Some(key) = cursor_key(*key_code) {
+                    self.editor.move_cursor(key, &self.value);
+                }
+                KeyOutcome::Ignored
+            }
+            (EditingMode::Emacs, KeyEvent::KeyPressed { key_code, modifiers, .. }) => {
+                let command = EmacsCommand::BINDINGS
+                    .iter()
+                    .find(|(action, _)| bound(action, *key_code, *modifiers))
+                    .map(|(_, command)| *command);
+                match (command, cursor_key(*key_code)) {
+                    (Some(command), _) => {
+                        self.editor.emacs(command, &mut self.value);
+                        KeyOutcome::Handled
+                    }
+                    (None, Some(key)) => {
+                        self.editor.move_cursor(key, &self.value);
+                        KeyOutcome::Ignored
+                    }
+                    (None, None) => KeyOutcome::Ignored,
+                }
+            }
+            (EditingMode::Vi, KeyEvent::KeyPressed { key_code, modifiers, .. }) => {
+                let key = if self.editor.vi_mode() == ViMode::Insert && bound("vi_normal_mode", *key_code, *modifiers) {
+                    Some(Key::Escape)
+                } else if *key_code == KeyCode::Escape && self.editor.vi_mode() != ViMode::Insert {
+                    Some(Key::Escape)
+                } else {
+                    cursor_key(*key_code)
+                };
+                match key {
+                    Some(key) => self.editor.vi_key(key, &mut self.value),
+                    None => KeyOutcome::Ignored,
+                }
+            }
+            (EditingMode::Vi, KeyEvent::CharacterReceived(c))
+                if self.editor.vi_mode() != ViMode::Insert && !c.is_control() && !self.modifiers.control() && !self.modifiers.logo() =>
+            {
+                self.editor.vi_key(Key::Char(*c), &mut self.value)
+            }
+            _ => KeyOutcome::Ignored,
+        };
+        match outcome {
+            KeyOutcome::Ignored => return false,
+            KeyOutcome::HistoryPrevious => self.update(Message::HistoryNavigated(HistoryDirection::Up)),
+            KeyOutcome::HistoryNext => self.update(Message::HistoryNavigated(HistoryDirection::Down)),
+            KeyOutcome::Handled if self.value != previous => {
+                self.update_suggestions();
+                self.active_suggestion = self.suggestions.first().map(|_| 0);
+                self.update_live_preview();
+                self.refresh_highlight();
+            }
+            KeyOutcome::Handled => {}
+        }
+        true
+    }
+
+    /// Focuses the text field and moves its cursor to where the editing left it, e.g. a vi
+    /// command or a new line.
+    pub fn sync_cursor<T: 'static>(&self) -> Command<T> {
+        let id = text_input::Id::new(INPUT_ID);
+        Command::batch([text_input::focus(id.clone()), text_input::move_cursor_to(id, self.editor.cursor())])
+    }
+
+    /// Highlights the value again if it changed, and finds what the command waits for.
+    /// A multi-line command isn't highlighted, as its lines don't parse on their own.
+    fn refresh_highlight(&mut self) {
+        let script = self.script();
+        self.continuation = if is_shell_command(&script) { multiline::continuation(&script) } else { None };
+        if self.value.trim().is_empty() || !is_shell_command(&self.value) || self.is_multiline() {
+            self.highlight = None;
+        } else if self.highlight.as_ref().map_or(true, |highlight| highlight.line != self.value) {
+            let mut highlight = command_highlight::highlight(&self.value, self.cwd.as_deref().map(Path::new));
+            // A command that continues on the next line isn't wrong yet.
+            if self.continuation.is_some() {
+                highlight.problems.retain(|problem| problem.kind != ProblemKind::Syntax);
+            }
+            self.highlight = Some(highlight);
+        }
     }
 
     /// Returns the current value of the text input.
@@ -190,21 +514,18 @@ impl EnhancedTextInput {
         &self.value
     }
 
-    /// Adds a command to the history.
-    ///
-    /// Commands are added to the front of the history and duplicates are avoided.
-    /// The history is capped at 1000 entries.
+    /// Returns the command history, most recent first, up to 1000 distinct commands.
+    pub fn history(&self) -> Vec<String> {
+        self.history.lock().unwrap().commands().take(1000).map(str::to_string).collect()
+    }
+
+    /// Adds the commands of a restored session to the history, for those it doesn't have.
     ///
     /// # Arguments
     ///
-    /// * `command` - The command string to add to history.
-    pub fn add_to_history(&mut self, command: String) {
-        if !command.trim().is_empty() && self.history.front() != Some(&command) {
-            self.history.push_front(command);
-            if self.history.len() > 1000 {
-                self.history.pop_back();
-            }
-        }
+    /// * `history` - Commands ordered most recent first.
+    pub fn set_history(&mut self, history: Vec<String>) {
+        self.history.lock().unwrap().restore(history.into_iter().take(1000).collect());
         self.history_index = None;
     }
 
@@ -218,19 +539,17 @@ impl EnhancedTextInput {
     ///
     /// An `Option<String>` containing the command from history, or `None` if navigation is not possible.
     fn navigate_history(&mut self, direction: HistoryDirection) -> Option<String> {
+        let history = self.history.lock().unwrap();
         match direction {
             HistoryDirection::Up => {
-                let new_index = match self.history_index {
-                    None => Some(0),
-                    Some(i) if i < self.history.len() - 1 => Some(i + 1),
-                    Some(i) => Some(i),
-                };
-                
-                if let Some(index) = new_index {
-                    self.history_index = new_index;
-                    self.history.get(index).cloned()
-                } else {
-                    None
+                let next = self.history_index.map_or(0, |i| i + 1);
+                match history.nth_command(next) {
+                    Some(command) => {
+                        self.history_index = Some(next);
+                        Some(command)
+                    }
+                    // Stay on the oldest command.
+                    None => self.history_index.and_then(|i| history.nth_command(i)),
                 }
             }
             HistoryDirection::Down => {
@@ -241,7 +560,7 @@ impl EnhancedTextInput {
                     }
                     Some(i) => {
                         self.history_index = Some(i - 1);
-                        self.history.get(i - 1).cloned()
+                        history.nth_command(i - 1)
                     }
                     None => None,
                 }
@@ -251,30 +570,54 @@ impl EnhancedTextInput {
 
     /// Updates the list of suggestions based on the current input value.
     ///
-    /// This method generates command and history suggestions and sorts them by relevance.
+    /// This method generates completion and history suggestions and sorts them by relevance.
     fn update_suggestions(&mut self) {
         let mut suggestions = Vec::new();
-        let current_input = self.value.trim();
-
-        if current_input.is_empty() {
-            suggestions.extend(self.get_command_suggestions(""));
-        } else {
-            let last_word = current_input.split_whitespace().last().unwrap_or("");
-
-            if current_input.split_whitespace().count() <= 1 {
-                suggestions.extend(self.get_command_suggestions(last_word));
-            }
-            
-            suggestions.extend(self.get_history_suggestions(current_input));
+        if !self.value.trim().is_empty() {
+            suggestions.extend(self.get_completion_suggestions(completion::local_completions(&self.value, self.cwd.as_deref().map(Path::new))));
+            suggestions.extend(self.get_history_suggestions(self.value.trim()));
         }
+        self.set_suggestions(suggestions);
+    }
 
-        // Sort suggestions by score in descending order
+    /// Sorts suggestions by score in descending order and keeps the top 10.
+    fn set_suggestions(&mut self, mut suggestions: Vec<Suggestion>) {
         suggestions.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
-        suggestions.truncate(10); // Limit to top 10 suggestions
-
+        suggestions.truncate(10);
         self.suggestions = suggestions;
     }
 
+    /// Sets the directory file completions are relative to.
+    pub fn set_cwd(&mut self, cwd: Option<String>) {
+        self.cwd = cwd;
+    }
+
+    /// Adds completions the shell found for `line`, unless the input changed since.
+    /// The active suggestion stays active.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The input the completions were asked for.
+    /// * `completions` - Completions of the last word of `line`.
+    pub fn add_completions(&mut self, line: &str, completions: Vec<Completion>) {
+        if line != self.value || completions.is_empty() {
+            return;
+        }
+        let active = self.active_suggestion.and_then(|index| self.suggestions.get(index)).map(|s| s.text.clone());
+        let mut suggestions = std::mem::take(&mut self.suggestions);
+        for suggestion in self.get_completion_suggestions(completions) {
+            if !suggestions.iter().any(|known| known.text == suggestion.text) {
+                suggestions.push(suggestion);
+            }
+        }
+        self.set_suggestions(suggestions);
+        self.active_suggestion = match active {
+            Some(text) => self.suggestions.iter().position(|s| s.text == text),
+            None => self.suggestions.first().map(|_| 0),
+        };
+        self.update_live_preview();
+    }
+
     /// Updates the live preview text based on the active suggestion.
     fn update_live_preview(&mut self) {
         self.live_preview = if let Some(index) = self.active_suggestion {
@@ -284,32 +627,25 @@ impl EnhancedTextInput {
         };
     }
 
-    /// Generates command suggestions based on a given prefix.
+    /// Turns completions of the last word into suggestions for the whole input.
     ///
     /// # Arguments
     ///
-    /// * `prefix` - The input prefix to filter commands by.
+    /// * `completions` - Completions of the last word of the input.
     ///
     /// # Returns
     ///
-    /// A `Vec<Suggestion>` containing matching command suggestions.
-    fn get_command_suggestions(&self, prefix: &str) -> Vec<Suggestion> {
-        let common_commands = [
-            "ls", "cd", "pwd", "mkdir", "rmdir", "rm", "cp", "mv", "cat", "less", "more",
-            "grep", "find", "which", "whereis", "man", "info", "help", "history",
-            "ps", "top", "htop", "kill", "killall", "jobs", "bg", "fg", "nohup",
-            "git", "npm", "yarn", "cargo", "docker", "kubectl", "ssh", "scp", "rsync",
-            "echo", "ping", "curl", "wget", "chmod", "chown", "df", "du", "tar", "zip", "unzip",
-        ];
-
-        common_commands
-            .iter()
-            .filter(|cmd| cmd.starts_with(prefix))
-            .map(|cmd| Suggestion {
-                text: cmd.to_string(),
-                description: Some(self.get_command_description(cmd)),
-                suggestion_type: SuggestionType::Command,
-                score: self.calculate_fuzzy_score(cmd, prefix),
+    /// A `Vec<Suggestion>` with the input completed by each completion.
+    fn get_completion_suggestions(&self, completions: Vec<Completion>) -> Vec<Suggestion> {
+        let context = LineContext::parse(&self.value);
+        completions
+            .into_iter()
+            .map(|completion| Suggestion {
+                text: context.apply(&self.value, &completion),
+                score: self.calculate_fuzzy_score(&completion.text, context.current),
+                description: completion.description,
+                suggestion_type: completion.kind.into(),
+                label: completion.text,
             })
             .collect()
     }
@@ -318,50 +654,29 @@ impl EnhancedTextInput {
     ///
     /// # Arguments
     ///
-    /// * `prefix` - The input prefix to filter history entries by.
+    /// * `prefix` - The input to fuzzily match history entries with.
     ///
     /// # Returns
     ///
     /// A `Vec<Suggestion>` containing matching history suggestions.
     fn get_history_suggestions(&self, prefix: &str) -> Vec<Suggestion> {
-        self.history
-            .iter()
-            .filter(|cmd| cmd.contains(prefix) && cmd != &self.value)
+        let matcher = FuzzyMatchManager::new();
+        self.history.lock().unwrap()
+            .fuzzy_search(&matcher, prefix, &HistoryFilter::default(), 6)
+            .into_iter()
+            .map(|found| found.entry.command)
+            .filter(|cmd| cmd != &self.value)
             .take(5) // Limit history suggestions
             .map(|cmd| Suggestion {
-                text: cmd.clone(),
+                score: self.calculate_fuzzy_score(&cmd, prefix) * 0.9, // Slightly lower score for history
+                label: cmd.clone(),
+                text: cmd,
                 description: Some("From history".to_string()),
                 suggestion_type: SuggestionType::History,
-                score: self.calculate_fuzzy_score(cmd, prefix) * 0.9, // Slightly lower score for history
             })
             .collect()
     }
 
-    /// Provides a brief description for common commands.
-    ///
-    /// # Arguments
-    ///
-    /// * `command` - The command string.
-    ///
-    /// # Returns
-    ///
-    /// A `String` containing the description.
-    fn get_command_description(&self, command: &str) -> String {
-        match command {
-            "ls" => "List directory contents".to_string(),
-            "cd" => "Change directory".to_string(),
-            "pwd" => "Print working directory".to_string(),
-            "git" => "Git version control".to_string(),
-            "npm" => "Node package manager".to_string(),
-            "cargo" => "Rust package manager".to_string(),
-            "docker" => "Container management".to_string(),
-            "echo" => "Display a line of text".to_string(),
-            "ping" => "Send ICMP ECHO_REQUEST packets to network hosts".to_string(),
-            "curl" => "Transfer data from or to a server".to_string(),
-            _ => format!("Execute {}", command),
-        }
-    }
-
     /// Calculates a fuzzy match score between text and a query.
     ///
     /// # Arguments
@@ -424,17 +739,53 @@ impl EnhancedTextInput {
 
         // Create the main text input widget
         let input = text_input(current_placeholder, &self.value)
+            .id(text_input::Id::new(INPUT_ID))
             .on_input(Message::InputChanged)
+            .on_paste(|_| Message::Pasted)
             .on_submit(Message::Submit)
             .padding(12)
             .size(16);
 
-        // Combine prompt indicator and input field
-        let input_with_prompt = row![
-            text(prompt_indicator).size(16),
-            input
-        ].spacing(8)
-        .width(Length::Fill);
+        // Combine prompt indicator and input field, after the editing mode if there is one
+        let mut input_with_prompt = row![].spacing(8).width(Length::Fill).align_items(iced::Alignment::Center);
+        if let Some(mode) = self.editor.mode_label() {
+            input_with_prompt = input_with_prompt.push(
+                text(format!("{} {}", self.editor.cursor_shape().symbol(), mode))
+                    .size(12)
+                    .font(iced::Font::MONOSPACE)
+                    .style(|theme: &iced::Theme| iced::widget::text::Appearance { color: Some(theme.palette().primary) }),
+            );
+        }
+        // Lines after the first of a multi-line command get the shell's continuation prompt
+        let line_prompt = |first: bool| if first { prompt_indicator } else { CONTINUATION_PROMPT };
+        let input_with_prompt = input_with_prompt.push(text(line_prompt(self.lines_above.is_empty())).size(16)).push(input);
+        let script_line = |(i, line): (usize, &String)| -> Element<Message> {
+            row![text(line_prompt(i == 0)).size(16), container(text(line).size(16)).padding([0, 12])]
+                .spacing(8)
+                .into()
+        };
+        let lines_above = column(self.lines_above.iter().enumerate().map(script_line).collect::<Vec<_>>());
+        let lines_below = column(self.lines_below.iter().enumerate().map(|(i, line)| script_line((i + 1, line))).collect::<Vec<_>>());
+
+        let script_status = if self.is_multiline() || self.continuation.is_some() {
+            let status = match &self.continuation {
+                Some(continuation) => format!("{} · Enter adds a line", continuation),
+                None => "Enter runs the whole command · Shift+Enter adds a line".to_string(),
+            };
+            container(
+                text(status)
+                    .size(12)
+                    .style(|theme: &iced::Theme| iced::widget::text::Appearance { color: Some(theme.palette().text.scale_alpha(0.7)) }),
+            )
+            .padding([0, 8])
+        } else {
+            container(column![])
+        };
+
+        let highlight_view = match &self.highlight {
+            Some(highlight) => Self::highlight_view(highlight),
+            None => column![].into(),
+        };
 
         // Render suggestions if available
         let suggestions_view = if !self.suggestions.is_empty() {
@@ -446,7 +797,7 @@ impl EnhancedTextInput {
                     
                     container(
                         row![
-                            text(&suggestion.text).size(14),
+                            text(&suggestion.label).size(14),
                             if let Some(desc) = &suggestion.description {
                                 text(desc)
                                     .size(12)
@@ -507,13 +858,18 @@ impl EnhancedTextInput {
             button(text("🎤").size(16)).on_press(Message::ToggleMicrophone).padding(8),
             button(text("@").size(16)).on_press(Message::ToggleAtSymbol).padding(8),
             button(text("🖼️").size(16)).on_press(Message::ToggleImage).padding(8),
+            button(text("✎").size(16)).on_press(Message::OpenInEditor).padding(8),
             ai_model_dropdown,
         ]
         .spacing(8)
         .align_items(iced::Alignment::Center);
 
         column![
+            lines_above,
             input_with_prompt,
+            lines_below,
+            script_status,
+            highlight_view,
             suggestions_view,
             Rule::horizontal(1), // Separator line
             action_buttons
@@ -522,6 +878,50 @@ impl EnhancedTextInput {
         .padding(8)
         .into()
     }
+
+    /// Renders the value colored by its syntax, since the text field itself can't color
+    /// its text, with its syntax errors underlined and its problems described below.
+    ///
+    /// # Arguments
+    ///
+    /// * `highlight` - The highlighted value.
+    ///
+    /// # Returns
+    ///
+    /// An `Element<Message>` to show under the text field.
+    fn highlight_view(highlight: &LineHighlight) -> Element<Message> {
+        let danger = |theme: &iced::Theme| iced::widget::text::Appearance { color: Some(theme.palette().danger) };
+        let line = highlight.tokens.iter().fold(row![], |line, token| {
+            let kind = token.kind;
+            line.push(
+                text(highlight.text(token))
+                    .size(14)
+                    .font(iced::Font::MONOSPACE)
+                    .style(move |theme: &iced::Theme| iced::widget::text::Appearance { color: Some(kind.color(&theme.palette())) }),
+            )
+        });
+        let mut lines = column![line];
+        if let Some(underline) = highlight.underline() {
+            lines = lines.push(text(underline).size(14).font(iced::Font::MONOSPACE).style(danger));
+        }
+        if !highlight.problems.is_empty() {
+            let messages: Vec<&str> = highlight.problems.iter().map(|problem| problem.message.as_str()).collect();
+            lines = lines.push(text(messages.join(" · ")).size(12).style(danger));
+        }
+        container(lines).padding([0, 8]).into()
+    }
+}
+
+/// Returns the line editor key for a cursor key the text field handles too.
+fn cursor_key(key_code: KeyCode) -> Option<Key> {
+    match key_code {
+        KeyCode::Left => Some(Key::Left),
+        KeyCode::Right => Some(Key::Right),
+        KeyCode::Home => Some(Key::Home),
+        KeyCode::End => Some(Key::End),
+        KeyCode::Backspace => Some(Key::Backspace),
+        _ => None,
+    }
 }
 
 /// Initializes the input module.
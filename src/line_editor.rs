@@ -0,0 +1,1311 @@
+//! Vi and Emacs editing of the command input.
+//!
+//! The text field inserts and deletes typed text by itself. A `LineEditor` follows the
+//! field's cursor and performs the edits the field doesn't know: Emacs' word motions and
+//! kill ring, and vi's modal editing with counts, operators, motions, text objects,
+//! registers and `.` repeat. Positions are char indices, as the text field counts them.
+
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use log::info;
+use crate::config::preferences::EditingMode;
+
+/// Number of kills the kill ring keeps.
+const KILL_RING_SIZE: usize = 16;
+/// Number of changes `u` can undo.
+const UNDO_LIMIT: usize = 100;
+/// Largest count a vi command is repeated with.
+const MAX_COUNT: usize = 1000;
+
+/// The mode of vi editing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViMode {
+    /// Typing inserts text.
+    Insert,
+    /// Keys are commands.
+    Normal,
+    /// Keys are commands, and operators act on the text between the cursor and where
+    /// visual mode started.
+    Visual,
+}
+
+/// The cursor shape that tells the editing mode apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    /// Typing inserts text.
+    Bar,
+    /// Keys are commands.
+    Block,
+    /// An operator waits for its motion.
+    Underline,
+}
+
+impl CursorShape {
+    /// Returns the character drawn for the shape.
+    pub fn symbol(self) -> char {
+        match self {
+            CursorShape::Bar => '▏',
+            CursorShape::Block => '█',
+            CursorShape::Underline => '▁',
+        }
+    }
+}
+
+/// A key the line editor handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Escape,
+    Backspace,
+    Left,
+    Right,
+    Home,
+    End,
+}
+
+/// What became of a key given to the line editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOutcome {
+    /// The key edited the text or moved the cursor, or is part of a command being typed.
+    Handled,
+    /// The key asks for the previous command of the history.
+    HistoryPrevious,
+    /// The key asks for the next command of the history.
+    HistoryNext,
+    /// The key is left to the text field.
+    Ignored,
+}
+
+/// The commands of the Emacs editing mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmacsCommand {
+    BeginningOfLine,
+    EndOfLine,
+    BackwardWord,
+    ForwardWord,
+    /// Kills the text from the cursor to the end of the line.
+    KillLine,
+    /// Kills the whitespace-separated word before the cursor.
+    KillWordBackward,
+    /// Inserts the most recent kill.
+    Yank,
+    /// Replaces the text just yanked with the kill before it.
+    YankPop,
+}
+
+impl EmacsCommand {
+    /// The key binding actions that run each command.
+    pub const BINDINGS: &'static [(&'static str, EmacsCommand)] = &[
+        ("emacs_beginning_of_line", EmacsCommand::BeginningOfLine),
+        ("emacs_end_of_line", EmacsCommand::EndOfLine),
+        ("emacs_backward_word", EmacsCommand::BackwardWord),
+        ("emacs_forward_word", EmacsCommand::ForwardWord),
+        ("emacs_kill_line", EmacsCommand::KillLine),
+        ("emacs_kill_word_backward", EmacsCommand::KillWordBackward),
+        ("emacs_yank", EmacsCommand::Yank),
+        ("emacs_yank_pop", EmacsCommand::YankPop),
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+/// The direction and stop of `f`, `t`, `F` and `T`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FindKind {
+    To,
+    Till,
+    BackTo,
+    BackTill,
+}
+
+impl FindKind {
+    fn reversed(self) -> Self {
+        match self {
+            FindKind::To => FindKind::BackTo,
+            FindKind::Till => FindKind::BackTill,
+            FindKind::BackTo => FindKind::To,
+            FindKind::BackTill => FindKind::Till,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    Left,
+    Right,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    Column,
+    WordForward { big: bool },
+    WordBackward { big: bool },
+    WordEnd { big: bool },
+    Find(FindKind, char),
+    RepeatFind { reverse: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextObject {
+    Word { big: bool },
+    Quote(char),
+    Pair(char, char),
+}
+
+/// The text an operator acts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Motion(Motion),
+    Object { inner: bool, object: TextObject },
+    /// The whole line, as with `dd`.
+    Line,
+    /// The visual mode selection.
+    Selection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InsertAt {
+    Cursor,
+    After,
+    LineStart,
+    LineEnd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Move(Motion),
+    Operate(Operator, Target),
+    DeleteChars { before: bool },
+    Substitute,
+    Put { before: bool },
+    Insert(InsertAt),
+    Replace(char),
+    ToggleCase,
+    Undo,
+    Repeat,
+    /// Starts visual mode, or leaves it.
+    Visual,
+    SwapEnds,
+    Select { inner: bool, object: TextObject },
+    History { previous: bool },
+}
+
+impl Action {
+    /// Whether `.` repeats the action.
+    fn is_change(self) -> bool {
+        matches!(
+            self,
+            Action::Operate(Operator::Delete | Operator::Change, Target::Motion(_) | Target::Object { .. } | Target::Line)
+                | Action::DeleteChars { .. }
+                | Action::Substitute
+                | Action::Put { .. }
+                | Action::Insert(_)
+                | Action::Replace(_)
+                | Action::ToggleCase
+        )
+    }
+}
+
+/// A complete vi command, e.g. `"a2dw`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ViCommand {
+    register: Option<char>,
+    count: Option<usize>,
+    action: Action,
+}
+
+enum Parsed {
+    Incomplete,
+    Invalid,
+    Complete(ViCommand),
+}
+
+/// The last change, which `.` repeats.
+#[derive(Debug, Clone)]
+struct Change {
+    command: ViCommand,
+    /// Text typed in the insert mode the command started.
+    inserted: String,
+}
+
+/// Insert mode started by a command.
+#[derive(Debug, Clone)]
+struct InsertSession {
+    command: ViCommand,
+    /// The text when insert mode started.
+    before: String,
+}
+
+/// Editing state of the command input.
+#[derive(Debug, Clone)]
+pub struct LineEditor {
+    mode: EditingMode,
+    vi_mode: ViMode,
+    cursor: usize,
+    /// Keys of the vi command being typed.
+    keys: Vec<char>,
+    registers: HashMap<char, String>,
+    /// Where visual mode started.
+    anchor: usize,
+    last_find: Option<(FindKind, char)>,
+    last_change: Option<Change>,
+    insert: Option<InsertSession>,
+    undo: Vec<(String, usize)>,
+    /// Kills, most recent first.
+    kill_ring: VecDeque<String>,
+    /// The text last yanked and the kill ring entry it came from.
+    yanked: Option<(Range<usize>, usize)>,
+    last_emacs: Option<EmacsCommand>,
+}
+
+impl LineEditor {
+    /// Creates a line editor for an empty line.
+    pub fn new(mode: EditingMode) -> Self {
+        Self {
+            mode,
+            vi_mode: ViMode::Insert,
+            cursor: 0,
+            keys: Vec::new(),
+            registers: HashMap::new(),
+            anchor: 0,
+            last_find: None,
+            last_change: None,
+            insert: None,
+            undo: Vec::new(),
+            kill_ring: VecDeque::new(),
+            yanked: None,
+            last_emacs: None,
+        }
+    }
+
+    /// Switches the editing mode. Vi editing starts in insert mode.
+    pub fn set_mode(&mut self, mode: EditingMode) {
+        if mode != self.mode {
+            self.mode = mode;
+            self.vi_mode = ViMode::Insert;
+            self.keys.clear();
+            self.insert = None;
+        }
+    }
+
+    pub fn mode(&self) -> EditingMode {
+        self.mode
+    }
+
+    pub fn vi_mode(&self) -> ViMode {
+        self.vi_mode
+    }
+
+    /// Returns the cursor position in chars.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Whether the text field may insert typed text, which vi's normal and visual modes
+    /// take as commands instead.
+    pub fn accepts_typing(&self) -> bool {
+        self.mode != EditingMode::Vi || self.vi_mode == ViMode::Insert
+    }
+
+    /// Returns the cursor shape of the current mode.
+    pub fn cursor_shape(&self) -> CursorShape {
+        match (self.mode, self.vi_mode) {
+            (EditingMode::Vi, ViMode::Normal) if !self.keys.is_empty() => CursorShape::Underline,
+            (EditingMode::Vi, ViMode::Normal | ViMode::Visual) => CursorShape::Block,
+            _ => CursorShape::Bar,
+        }
+    }
+
+    /// Returns the name of the current mode followed by the keys of a command being
+    /// typed, or `None` for the text field's own editing.
+    pub fn mode_label(&self) -> Option<String> {
+        let name = match (self.mode, self.vi_mode) {
+            (EditingMode::Standard, _) => return None,
+            (EditingMode::Emacs, _) => "EMACS",
+            (EditingMode::Vi, ViMode::Insert) => "INSERT",
+            (EditingMode::Vi, ViMode::Normal) => "NORMAL",
+            (EditingMode::Vi, ViMode::Visual) => "VISUAL",
+        };
+        let pending: String = self.keys.iter().collect();
+        Some(if pending.is_empty() { name.to_string() } else { format!("{} {}", name, pending) })
+    }
+
+    /// Returns the visual mode selection.
+    pub fn selection(&self, text: &str) -> Option<Range<usize>> {
+        (self.mode == EditingMode::Vi && self.vi_mode == ViMode::Visual)
+            .then(|| self.selection_range(text.chars().count()))
+    }
+
+    /// Follows the text field's cursor through an edit it made, e.g. typing or deleting
+    /// a character. When the edit is ambiguous, as when typing `a` into `aa`, the cursor
+    /// goes where the edit at the known cursor would leave it.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The text before the edit.
+    /// * `new` - The text after the edit.
+    pub fn text_changed(&mut self, old: &str, new: &str) {
+        self.last_emacs = None;
+        let old: Vec<char> = old.chars().collect();
+        let new: Vec<char> = new.chars().collect();
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old.iter().rev().zip(new.iter().rev()).take_while(|(a, b)| a == b).count();
+        if new.len() > old.len() {
+            let lowest = old.len().saturating_sub(suffix);
+            if lowest <= prefix {
+                self.cursor = self.cursor.clamp(lowest, prefix) + new.len() - old.len();
+                return;
+            }
+        } else if new.len() < old.len() {
+            let removed = old.len() - new.len();
+            let lowest = new.len().saturating_sub(suffix);
+            if lowest <= prefix {
+                // Deleting before the cursor, as Backspace does, is the likelier edit.
+                self.cursor = self.cursor.saturating_sub(removed).clamp(lowest, prefix);
+                return;
+            }
+        }
+        // Text replaced, e.g. a selection typed over: the cursor follows the new text.
+        self.cursor = new.len() - suffix.min(new.len().min(old.len()) - prefix);
+    }
+
+    /// Puts the cursor at the end of text that replaced the line, e.g. from the history.
+    pub fn text_replaced(&mut self, text: &str) {
+        self.last_emacs = None;
+        self.yanked = None;
+        self.cursor = text.chars().count();
+        self.clamp_cursor(text.chars().count());
+    }
+
+    /// Puts the cursor at `cursor` in text that replaced the line, e.g. after the
+    /// indentation of a new line.
+    pub fn text_replaced_at(&mut self, text: &str, cursor: usize) {
+        self.text_replaced(text);
+        self.cursor = cursor.min(text.chars().count());
+        self.clamp_cursor(text.chars().count());
+    }
+
+    /// Starts a new line after one was submitted; vi editing starts it in insert mode.
+    pub fn line_submitted(&mut self) {
+        self.vi_mode = ViMode::Insert;
+        self.cursor = 0;
+        self.keys.clear();
+        self.insert = None;
+        self.undo.clear();
+        self.last_emacs = None;
+        self.yanked = None;
+    }
+
+    /// Follows the cursor keys the text field handles itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key pressed.
+    /// * `text` - The line.
+    pub fn move_cursor(&mut self, key: Key, text: &str) {
+        let len = text.chars().count();
+        self.cursor = match key {
+            Key::Left => self.cursor.saturating_sub(1),
+            Key::Right => (self.cursor + 1).min(len),
+            Key::Home => 0,
+            Key::End => len,
+            _ => return,
+        };
+        self.last_emacs = None;
+    }
+
+    /// Runs an Emacs command on the line.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command bound to the key pressed.
+    /// * `text` - The line, edited in place.
+    pub fn emacs(&mut self, command: EmacsCommand, text: &mut String) {
+        let mut chars: Vec<char> = text.chars().collect();
+        let cursor = self.cursor.min(chars.len());
+        let last = self.last_emacs.replace(command);
+        let after_kill = matches!(last, Some(EmacsCommand::KillLine | EmacsCommand::KillWordBackward));
+        match command {
+            EmacsCommand::BeginningOfLine => self.cursor = 0,
+            EmacsCommand::EndOfLine => self.cursor = chars.len(),
+            EmacsCommand::BackwardWord => {
+                let mut i = cursor;
+                while i > 0 && !chars[i - 1].is_alphanumeric() {
+                    i -= 1;
+                }
+                while i > 0 && chars[i - 1].is_alphanumeric() {
+                    i -= 1;
+                }
+                self.cursor = i;
+            }
+            EmacsCommand::ForwardWord => {
+                let mut i = cursor;
+                while i < chars.len() && !chars[i].is_alphanumeric() {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i].is_alphanumeric() {
+                    i += 1;
+                }
+                self.cursor = i;
+            }
+            EmacsCommand::KillLine => {
+                let killed: String = chars.drain(cursor..).collect();
+                self.kill(killed, after_kill, false);
+            }
+            EmacsCommand::KillWordBackward => {
+                let mut start = cursor;
+                while start > 0 && chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                while start > 0 && !chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                let killed: String = chars.drain(start..cursor).collect();
+                self.kill(killed, after_kill, true);
+                self.cursor = start;
+            }
+            EmacsCommand::Yank => {
+                if let Some(kill) = self.kill_ring.front() {
+                    let length = kill.chars().count();
+                    chars.splice(cursor..cursor, kill.chars());
+                    self.yanked = Some((cursor..cursor + length, 0));
+                    self.cursor = cursor + length;
+                }
+            }
+            EmacsCommand::YankPop => {
+                let yanked = self.yanked.take().filter(|_| matches!(last, Some(EmacsCommand::Yank | EmacsCommand::YankPop)));
+                match yanked {
+                    Some((range, index)) if range.end <= chars.len() => {
+                        let index = (index + 1) % self.kill_ring.len();
+                        let kill = &self.kill_ring[index];
+                        let length = kill.chars().count();
+                        chars.splice(range.clone(), kill.chars());
+                        self.yanked = Some((range.start..range.start + length, index));
+                        self.cursor = range.start + length;
+                    }
+                    _ => self.last_emacs = None,
+                }
+            }
+        }
+        *text = chars.into_iter().collect();
+    }
+
+    /// Adds killed text to the kill ring. Consecutive kills make up one entry.
+    fn kill(&mut self, killed: String, append: bool, before: bool) {
+        if killed.is_empty() {
+            return;
+        }
+        match self.kill_ring.front_mut() {
+            Some(last) if append && before => last.insert_str(0, &killed),
+            Some(last) if append => last.push_str(&killed),
+            _ => {
+                self.kill_ring.push_front(killed);
+                self.kill_ring.truncate(KILL_RING_SIZE);
+            }
+        }
+    }
+
+    /// Handles a key in vi editing: Escape leaves insert mode, and in normal and visual
+    /// mode keys make up commands, which run once complete.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key pressed.
+    /// * `text` - The line, edited in place.
+    ///
+    /// # Returns
+    ///
+    /// What became of the key.
+    pub fn vi_key(&mut self, key: Key, text: &mut String) -> KeyOutcome {
+        if self.vi_mode == ViMode::Insert {
+            return match key {
+                Key::Escape => {
+                    self.finish_insert(text);
+                    KeyOutcome::Handled
+                }
+                _ => {
+                    self.move_cursor(key, text);
+                    KeyOutcome::Ignored
+                }
+            };
+        }
+        let key = match key {
+            Key::Char(c) => c,
+            Key::Escape => {
+                if self.keys.is_empty() && self.vi_mode == ViMode::Visual {
+                    self.vi_mode = ViMode::Normal;
+                }
+                self.keys.clear();
+                return KeyOutcome::Handled;
+            }
+            Key::Backspace | Key::Left => 'h',
+            Key::Right => 'l',
+            Key::Home => '0',
+            Key::End => '$',
+        };
+        self.keys.push(key);
+        match parse(&self.keys, self.vi_mode == ViMode::Visual) {
+            Parsed::Incomplete => KeyOutcome::Handled,
+            Parsed::Invalid => {
+                self.keys.clear();
+                KeyOutcome::Handled
+            }
+            Parsed::Complete(command) => {
+                self.keys.clear();
+                self.run(command, text)
+            }
+        }
+    }
+
+    /// Runs a complete vi command, keeping what's needed to undo and repeat it.
+    fn run(&mut self, command: ViCommand, text: &mut String) -> KeyOutcome {
+        let changes = command.action.is_change() || matches!(command.action, Action::Operate(_, Target::Selection) | Action::Repeat);
+        if changes {
+            self.undo.push((text.clone(), self.cursor));
+            if self.undo.len() > UNDO_LIMIT {
+                self.undo.remove(0);
+            }
+        }
+        let before = text.clone();
+        let outcome = self.execute(command, text);
+        if self.vi_mode == ViMode::Insert {
+            if command.action.is_change() {
+                self.insert = Some(InsertSession { command, before: text.clone() });
+            }
+        } else {
+            if command.action.is_change() {
+                self.last_change = Some(Change { command, inserted: String::new() });
+            }
+            if changes && *text == before {
+                self.undo.pop();
+            }
+        }
+        outcome
+    }
+
+    fn execute(&mut self, command: ViCommand, text: &mut String) -> KeyOutcome {
+        let mut chars: Vec<char> = text.chars().collect();
+        let len = chars.len();
+        let count = command.count.unwrap_or(1).min(MAX_COUNT);
+        self.cursor = self.cursor.min(len);
+        match command.action {
+            Action::Move(motion) => {
+                if let Some((position, _)) = self.motion_target(&chars, motion, count, false) {
+                    self.cursor = position;
+                }
+            }
+            Action::Operate(operator, target) => {
+                if let Some(range) = self.target_range(&chars, operator, target, count) {
+                    self.operate(operator, range, command.register, &mut chars);
+                }
+                if target == Target::Selection && self.vi_mode == ViMode::Visual {
+                    self.vi_mode = ViMode::Normal;
+                }
+            }
+            Action::DeleteChars { before } => {
+                let range = if before {
+                    self.cursor.saturating_sub(count)..self.cursor
+                } else {
+                    self.cursor..(self.cursor + count).min(len)
+                };
+                if !range.is_empty() {
+                    self.operate(Operator::Delete, range, command.register, &mut chars);
+                }
+            }
+            Action::Substitute => {
+                let range = self.cursor..(self.cursor + count).min(len);
+                self.operate(Operator::Change, range, command.register, &mut chars);
+            }
+            Action::Put { before } => {
+                if let Some(content) = self.read_register(command.register).filter(|content| !content.is_empty()) {
+                    let content = content.repeat(count);
+                    let at = if before || len == 0 { self.cursor } else { self.cursor + 1 };
+                    let length = content.chars().count();
+                    chars.splice(at..at, content.chars());
+                    self.cursor = at + length - 1;
+                }
+            }
+            Action::Insert(at) => {
+                self.cursor = match at {
+                    InsertAt::Cursor => self.cursor,
+                    InsertAt::After => (self.cursor + 1).min(len),
+                    InsertAt::LineStart => first_non_blank(&chars),
+                    InsertAt::LineEnd => len,
+                };
+                self.vi_mode = ViMode::Insert;
+            }
+            Action::Replace(c) => {
+                if self.cursor + count <= len {
+                    chars[self.cursor..self.cursor + count].fill(c);
+                    self.cursor += count - 1;
+                }
+            }
+            Action::ToggleCase => {
+                let range = if self.vi_mode == ViMode::Visual {
+                    self.vi_mode = ViMode::Normal;
+                    let range = self.selection_range(len);
+                    self.cursor = range.start;
+                    range
+                } else {
+                    let range = self.cursor..(self.cursor + count).min(len);
+                    self.cursor = range.end;
+                    range
+                };
+                for c in &mut chars[range] {
+                    *c = toggle_case(*c);
+                }
+            }
+            Action::Undo => {
+                for _ in 0..count {
+                    if let Some((previous, cursor)) = self.undo.pop() {
+                        chars = previous.chars().collect();
+                        self.cursor = cursor;
+                    }
+                }
+            }
+            Action::Repeat => {
+                if let Some(change) = self.last_change.clone() {
+                    let mut repeated = change.command;
+                    repeated.count = command.count.or(repeated.count);
+                    self.execute(repeated, text);
+                    if self.vi_mode == ViMode::Insert {
+                        let at = byte_index(text, self.cursor);
+                        text.insert_str(at, &change.inserted);
+                        self.cursor += change.inserted.chars().count();
+                        self.vi_mode = ViMode::Normal;
+                        self.cursor = self.cursor.saturating_sub(1);
+                    }
+                    chars = text.chars().collect();
+                }
+            }
+            Action::Visual => {
+                if self.vi_mode == ViMode::Visual {
+                    self.vi_mode = ViMode::Normal;
+                } else {
+                    self.vi_mode = ViMode::Visual;
+                    self.anchor = self.cursor;
+                }
+            }
+            Action::SwapEnds => std::mem::swap(&mut self.anchor, &mut self.cursor),
+            Action::Select { inner, object } => {
+                if let Some(range) = text_object(&chars, self.cursor, inner, object).filter(|range| !range.is_empty()) {
+                    self.anchor = range.start;
+                    self.cursor = range.end - 1;
+                }
+            }
+            Action::History { previous } => {
+                return if previous { KeyOutcome::HistoryPrevious } else { KeyOutcome::HistoryNext };
+            }
+        }
+        *text = chars.into_iter().collect();
+        if self.vi_mode != ViMode::Insert {
+            self.clamp_cursor(text.chars().count());
+        }
+        KeyOutcome::Handled
+    }
+
+    /// Leaves insert mode, keeping the text typed for `.` to repeat.
+    fn finish_insert(&mut self, text: &str) {
+        if let Some(session) = self.insert.take() {
+            self.last_change = Some(Change { command: session.command, inserted: inserted_text(&session.before, text) });
+        }
+        self.vi_mode = ViMode::Normal;
+        self.cursor = self.cursor.saturating_sub(1);
+        self.clamp_cursor(text.chars().count());
+    }
+
+    /// Keeps the cursor on a character, as vi's normal mode does.
+    fn clamp_cursor(&mut self, len: usize) {
+        if self.mode == EditingMode::Vi && self.vi_mode != ViMode::Insert {
+            self.cursor = self.cursor.min(len.saturating_sub(1));
+        }
+    }
+
+    fn selection_range(&self, len: usize) -> Range<usize> {
+        let start = self.anchor.min(self.cursor);
+        let end = self.anchor.max(self.cursor) + 1;
+        start.min(len)..end.min(len)
+    }
+
+    /// Returns where a motion goes and whether it includes the character it ends on.
+    fn motion_target(&mut self, chars: &[char], motion: Motion, count: usize, operator: bool) -> Option<(usize, bool)> {
+        let len = chars.len();
+        let cursor = self.cursor;
+        let repeat = |step: &dyn Fn(usize) -> usize| (0..count).fold(cursor, |position, _| step(position));
+        let target = match motion {
+            Motion::Left => (cursor.saturating_sub(count), false),
+            Motion::Right => ((cursor + count).min(if operator { len } else { len.saturating_sub(1) }), false),
+            Motion::LineStart => (0, false),
+            Motion::FirstNonBlank => (first_non_blank(chars), false),
+            Motion::LineEnd => (len.saturating_sub(1), true),
+            Motion::Column => ((count - 1).min(len.saturating_sub(1)), false),
+            Motion::WordForward { big } => (repeat(&|position| word_forward(chars, position, big)), false),
+            Motion::WordBackward { big } => (repeat(&|position| word_backward(chars, position, big)), false),
+            Motion::WordEnd { big } => (repeat(&|position| word_end(chars, position, big)), true),
+            Motion::Find(kind, c) => {
+                self.last_find = Some((kind, c));
+                return find(chars, cursor, kind, c, count, false);
+            }
+            Motion::RepeatFind { reverse } => {
+                let (kind, c) = self.last_find?;
+                let kind = if reverse { kind.reversed() } else { kind };
+                return find(chars, cursor, kind, c, count, true);
+            }
+        };
+        Some(target)
+    }
+
+    /// Returns the characters an operator acts on.
+    fn target_range(&mut self, chars: &[char], operator: Operator, target: Target, count: usize) -> Option<Range<usize>> {
+        let len = chars.len();
+        match target {
+            Target::Line => Some(0..len),
+            Target::Selection => Some(self.selection_range(len)),
+            Target::Object { inner, object } => text_object(chars, self.cursor, inner, object),
+            Target::Motion(Motion::WordForward { big })
+                if operator == Operator::Change && chars.get(self.cursor).is_some_and(|c| !c.is_whitespace()) =>
+            {
+                // `cw` changes to the end of the word, like `ce`, but keeps the blanks after it.
+                let class = char_class(chars[self.cursor], big);
+                let mut end = self.cursor;
+                while end + 1 < len && char_class(chars[end + 1], big) == class {
+                    end += 1;
+                }
+                let end = (1..count).fold(end, |position, _| word_end(chars, position, big));
+                Some(self.cursor..end + 1)
+            }
+            Target::Motion(motion) => {
+                let (position, inclusive) = self.motion_target(chars, motion, count, true)?;
+                let (start, end) = if position < self.cursor { (position, self.cursor) } else { (self.cursor, position) };
+                Some(start..(end + usize::from(inclusive)).min(len))
+            }
+        }
+    }
+
+    fn operate(&mut self, operator: Operator, range: Range<usize>, register: Option<char>, chars: &mut Vec<char>) {
+        let text: String = chars[range.clone()].iter().collect();
+        match operator {
+            Operator::Yank => self.write_register(register, text, true),
+            Operator::Delete | Operator::Change => {
+                self.write_register(register, text, false);
+                chars.drain(range.clone());
+                if operator == Operator::Change {
+                    self.vi_mode = ViMode::Insert;
+                }
+            }
+        }
+        self.cursor = range.start;
+    }
+
+    /// Stores text in a register, and in the unnamed register `.` and `p` use. Yanks go
+    /// to register `0` too, an uppercase name appends to its register and `_` discards.
+    fn write_register(&mut self, name: Option<char>, text: String, yank: bool) {
+        let text = match name {
+            Some('_') => return,
+            Some(name) if name.is_ascii_uppercase() => {
+                let register = self.registers.entry(name.to_ascii_lowercase()).or_default();
+                register.push_str(&text);
+                register.clone()
+            }
+            Some(name) if name != '"' => {
+                self.registers.insert(name, text.clone());
+                text
+            }
+            _ => {
+                if yank {
+                    self.registers.insert('0', text.clone());
+                }
+                text
+            }
+        };
+        self.registers.insert('"', text);
+    }
+
+    fn read_register(&self, name: Option<char>) -> Option<String> {
+        self.registers.get(&name.unwrap_or('"').to_ascii_lowercase()).cloned()
+    }
+}
+
+/// Parses the keys of a vi command.
+///
+/// # Arguments
+///
+/// * `keys` - The keys typed since the last command.
+/// * `visual` - Whether visual mode is on, where operators act on the selection.
+fn parse(keys: &[char], visual: bool) -> Parsed {
+    let mut rest = keys;
+    let mut register = None;
+    if rest.first() == Some(&'"') {
+        match rest.get(1) {
+            None => return Parsed::Incomplete,
+            Some(&name) if name.is_ascii_alphanumeric() || name == '"' || name == '_' => register = Some(name),
+            Some(_) => return Parsed::Invalid,
+        }
+        rest = &rest[2..];
+    }
+    let (count, rest) = parse_count(rest);
+    let Some((&key, tail)) = rest.split_first() else {
+        return Parsed::Incomplete;
+    };
+    let complete = |action| Parsed::Complete(ViCommand { register, count, action });
+    let operator = match key {
+        'd' => Some(Operator::Delete),
+        'c' => Some(Operator::Change),
+        'y' => Some(Operator::Yank),
+        _ => None,
+    };
+    if let Some(operator) = operator {
+        if visual {
+            return complete(Action::Operate(operator, Target::Selection));
+        }
+        let (motion_count, tail) = parse_count(tail);
+        let count = match (count, motion_count) {
+            (Some(a), Some(b)) => Some(a.saturating_mul(b)),
+            (a, b) => a.or(b),
+        };
+        let target = match tail.first() {
+            None => return Parsed::Incomplete,
+            Some(&next) if next == key => Target::Line,
+            Some(&scope @ ('i' | 'a')) => match parse_object(&tail[1..]) {
+                Ok(object) => Target::Object { inner: scope == 'i', object },
+                Err(parsed) => return parsed,
+            },
+            Some(_) => match parse_motion(tail) {
+                Ok(motion) => Target::Motion(motion),
+                Err(parsed) => return parsed,
+            },
+        };
+        return Parsed::Complete(ViCommand { register, count, action: Action::Operate(operator, target) });
+    }
+    let action = match (key, visual) {
+        ('x', true) => Action::Operate(Operator::Delete, Target::Selection),
+        ('s', true) => Action::Operate(Operator::Change, Target::Selection),
+        ('o', true) => Action::SwapEnds,
+        (scope @ ('i' | 'a'), true) => match parse_object(tail) {
+            Ok(object) => Action::Select { inner: scope == 'i', object },
+            Err(parsed) => return parsed,
+        },
+        ('x', false) => Action::DeleteChars { before: false },
+        ('X', false) => Action::DeleteChars { before: true },
+        ('D', false) => Action::Operate(Operator::Delete, Target::Motion(Motion::LineEnd)),
+        ('C', false) => Action::Operate(Operator::Change, Target::Motion(Motion::LineEnd)),
+        ('Y', false) => Action::Operate(Operator::Yank, Target::Line),
+        ('s', false) => Action::Substitute,
+        ('S', false) => Action::Operate(Operator::Change, Target::Line),
+        ('p', false) => Action::Put { before: false },
+        ('P', false) => Action::Put { before: true },
+        ('i', false) => Action::Insert(InsertAt::Cursor),
+        ('a', false) => Action::Insert(InsertAt::After),
+        ('I', false) => Action::Insert(InsertAt::LineStart),
+        ('A', false) => Action::Insert(InsertAt::LineEnd),
+        ('r', false) => match tail.first() {
+            Some(&c) => Action::Replace(c),
+            None => return Parsed::Incomplete,
+        },
+        ('u', false) => Action::Undo,
+        ('.', false) => Action::Repeat,
+        ('k', false) => Action::History { previous: true },
+        ('j', false) => Action::History { previous: false },
+        ('~', _) => Action::ToggleCase,
+        ('v', _) => Action::Visual,
+        _ => match parse_motion(rest) {
+            Ok(motion) => Action::Move(motion),
+            Err(parsed) => return parsed,
+        },
+    };
+    complete(action)
+}
+
+/// Splits a count off the start of `keys`. A leading `0` is a motion, not a count.
+fn parse_count(keys: &[char]) -> (Option<usize>, &[char]) {
+    let digits = match keys.first() {
+        Some('1'..='9') => keys.iter().take_while(|c| c.is_ascii_digit()).count(),
+        _ => 0,
+    };
+    let count = keys[..digits].iter().collect::<String>().parse().ok();
+    (count, &keys[digits..])
+}
+
+fn parse_motion(keys: &[char]) -> Result<Motion, Parsed> {
+    let motion = match keys.first() {
+        None => return Err(Parsed::Incomplete),
+        Some('h') => Motion::Left,
+        Some('l' | ' ') => Motion::Right,
+        Some('0') => Motion::LineStart,
+        Some('^') => Motion::FirstNonBlank,
+        Some('$') => Motion::LineEnd,
+        Some('|') => Motion::Column,
+        Some('w') => Motion::WordForward { big: false },
+        Some('W') => Motion::WordForward { big: true },
+        Some('b') => Motion::WordBackward { big: false },
+        Some('B') => Motion::WordBackward { big: true },
+        Some('e') => Motion::WordEnd { big: false },
+        Some('E') => Motion::WordEnd { big: true },
+        Some(';') => Motion::RepeatFind { reverse: false },
+        Some(',') => Motion::RepeatFind { reverse: true },
+        Some(&key @ ('f' | 't' | 'F' | 'T')) => {
+            let kind = match key {
+                'f' => FindKind::To,
+                't' => FindKind::Till,
+                'F' => FindKind::BackTo,
+                _ => FindKind::BackTill,
+            };
+            match keys.get(1) {
+                Some(&c) => Motion::Find(kind, c),
+                None => return Err(Parsed::Incomplete),
+            }
+        }
+        Some(_) => return Err(Parsed::Invalid),
+    };
+    Ok(motion)
+}
+
+fn parse_object(keys: &[char]) -> Result<TextObject, Parsed> {
+    let object = match keys.first() {
+        None => return Err(Parsed::Incomplete),
+        Some('w') => TextObject::Word { big: false },
+        Some('W') => TextObject::Word { big: true },
+        Some(&quote @ ('"' | '\'' | '`')) => TextObject::Quote(quote),
+        Some('(' | ')' | 'b') => TextObject::Pair('(', ')'),
+        Some('[' | ']') => TextObject::Pair('[', ']'),
+        Some('{' | '}' | 'B') => TextObject::Pair('{', '}'),
+        Some('<' | '>') => TextObject::Pair('<', '>'),
+        Some(_) => return Err(Parsed::Invalid),
+    };
+    Ok(object)
+}
+
+/// Classes of characters that make up words: blanks, word characters and punctuation.
+/// For WORDs (`big`), all but blanks are one class.
+fn char_class(c: char, big: bool) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if big || c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+fn first_non_blank(chars: &[char]) -> usize {
+    chars.iter().position(|c| !c.is_whitespace()).unwrap_or(chars.len())
+}
+
+/// Start of the next word, or the end of the line.
+fn word_forward(chars: &[char], position: usize, big: bool) -> usize {
+    let len = chars.len();
+    let mut i = position;
+    if i >= len {
+        return len;
+    }
+    let class = char_class(chars[i], big);
+    if class != 0 {
+        while i < len && char_class(chars[i], big) == class {
+            i += 1;
+        }
+    }
+    while i < len && chars[i].is_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+/// Start of the word before the cursor, or of the one it's in.
+fn word_backward(chars: &[char], position: usize, big: bool) -> usize {
+    let mut i = position.min(chars.len());
+    while i > 0 && chars[i - 1].is_whitespace() {
+        i -= 1;
+    }
+    if i == 0 {
+        return 0;
+    }
+    let class = char_class(chars[i - 1], big);
+    while i > 0 && char_class(chars[i - 1], big) == class {
+        i -= 1;
+    }
+    i
+}
+
+/// Last character of the word after the cursor, or of the one it's in.
+fn word_end(chars: &[char], position: usize, big: bool) -> usize {
+    let len = chars.len();
+    let mut i = position + 1;
+    while i < len && chars[i].is_whitespace() {
+        i += 1;
+    }
+    if i >= len {
+        return len.saturating_sub(1);
+    }
+    let class = char_class(chars[i], big);
+    while i + 1 < len && char_class(chars[i + 1], big) == class {
+        i += 1;
+    }
+    i
+}
+
+/// Finds a character for `f`, `t`, `F` and `T`. A repeated `t` or `T` skips the
+/// character it stopped before, so that `;` moves on.
+fn find(chars: &[char], cursor: usize, kind: FindKind, c: char, count: usize, repeat: bool) -> Option<(usize, bool)> {
+    match kind {
+        FindKind::To | FindKind::Till => {
+            let start = if kind == FindKind::Till && repeat { cursor + 1 } else { cursor };
+            let mut at = start;
+            for _ in 0..count {
+                at = (at + 1..chars.len()).find(|&i| chars[i] == c)?;
+            }
+            Some((if kind == FindKind::Till { at - 1 } else { at }, true))
+        }
+        FindKind::BackTo | FindKind::BackTill => {
+            let start = if kind == FindKind::BackTill && repeat { cursor.saturating_sub(1) } else { cursor };
+            let mut at = start;
+            for _ in 0..count {
+                at = (0..at).rev().find(|&i| chars[i] == c)?;
+            }
+            Some((if kind == FindKind::BackTill { at + 1 } else { at }, false))
+        }
+    }
+}
+
+/// Returns the characters of a text object at the cursor: `iw` is the word, `aw` the
+/// word with the blanks after it, `i"` the inside of the quotes and `a"` the quotes too.
+fn text_object(chars: &[char], cursor: usize, inner: bool, object: TextObject) -> Option<Range<usize>> {
+    let len = chars.len();
+    if len == 0 {
+        return None;
+    }
+    let cursor = cursor.min(len - 1);
+    match object {
+        TextObject::Word { big } => {
+            let class = char_class(chars[cursor], big);
+            let mut start = cursor;
+            while start > 0 && char_class(chars[start - 1], big) == class {
+                start -= 1;
+            }
+            let mut end = cursor + 1;
+            while end < len && char_class(chars[end], big) == class {
+                end += 1;
+            }
+            if !inner {
+                if class == 0 {
+                    // Blanks and the word after them.
+                    if let Some(&next) = chars.get(end) {
+                        let next = char_class(next, big);
+                        while end < len && char_class(chars[end], big) == next {
+                            end += 1;
+                        }
+                    }
+                } else if end < len && chars[end].is_whitespace() {
+                    while end < len && chars[end].is_whitespace() {
+                        end += 1;
+                    }
+                } else {
+                    while start > 0 && chars[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                }
+            }
+            Some(start..end)
+        }
+        TextObject::Quote(quote) => {
+            let quotes: Vec<usize> = (0..len)
+                .filter(|&i| chars[i] == quote && (i == 0 || chars[i - 1] != '\\'))
+                .collect();
+            // Quotes pair up from the start of the line; the cursor may be before the pair.
+            let (open, close) = quotes
+                .chunks_exact(2)
+                .map(|pair| (pair[0], pair[1]))
+                .find(|&(_, close)| cursor <= close)?;
+            Some(if inner { open + 1..close } else { open..close + 1 })
+        }
+        TextObject::Pair(open, close) => {
+            let mut depth = 0;
+            let mut start = None;
+            for i in (0..=cursor).rev() {
+                if chars[i] == close && i != cursor {
+                    depth += 1;
+                } else if chars[i] == open {
+                    if depth == 0 {
+                        start = Some(i);
+                        break;
+                    }
+                    depth -= 1;
+                }
+            }
+            let start = start?;
+            let mut depth = 0;
+            let mut end = None;
+            for (i, &c) in chars.iter().enumerate().skip(start + 1) {
+                if c == open {
+                    depth += 1;
+                } else if c == close {
+                    if depth == 0 {
+                        end = Some(i);
+                        break;
+                    }
+                    depth -= 1;
+                }
+            }
+            let end = end?;
+            Some(if inner { start + 1..end } else { start..end + 1 })
+        }
+    }
+}
+
+fn toggle_case(c: char) -> char {
+    let toggled = if c.is_uppercase() { c.to_lowercase().next() } else { c.to_uppercase().next() };
+    toggled.unwrap_or(c)
+}
+
+/// Returns the text typed between `before` and `after`.
+fn inserted_text(before: &str, after: &str) -> String {
+    let before: Vec<char> = before.chars().collect();
+    let after: Vec<char> = after.chars().collect();
+    let prefix = before.iter().zip(&after).take_while(|(a, b)| a == b).count();
+    let suffix = before.iter().rev().zip(after.iter().rev()).take_while(|(a, b)| a == b).count();
+    let suffix = suffix.min(before.len().min(after.len()) - prefix);
+    after[prefix..after.len() - suffix].iter().collect()
+}
+
+fn byte_index(text: &str, chars: usize) -> usize {
+    text.char_indices().nth(chars).map_or(text.len(), |(index, _)| index)
+}
+
+/// Initializes the line editor module.
+pub fn init() {
+    info!("line editor module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Types `keys` in vi editing, starting in normal mode at `cursor`. `\x1b` is Escape,
+    /// and characters typed in insert mode go to the text as the text field would put them.
+    fn vi(editor: &mut LineEditor, text: &mut String, keys: &str) {
+        for c in keys.chars() {
+            if c == '\x1b' {
+                editor.vi_key(Key::Escape, text);
+            } else if editor.vi_mode() == ViMode::Insert {
+                let old = text.clone();
+                text.insert(byte_index(text, editor.cursor()), c);
+                editor.text_changed(&old, text);
+            } else {
+                editor.vi_key(Key::Char(c), text);
+            }
+        }
+    }
+
+    fn normal(text: &str, cursor: usize) -> (LineEditor, String) {
+        let mut editor = LineEditor::new(EditingMode::Vi);
+        let mut text = text.to_string();
+        editor.text_replaced(&text);
+        editor.vi_key(Key::Escape, &mut text);
+        editor.cursor = cursor;
+        (editor, text)
+    }
+
+    #[test]
+    fn test_vi_motions_and_counts() {
+        let (mut editor, mut text) = normal("git commit -m message", 0);
+        vi(&mut editor, &mut text, "2w");
+        assert_eq!(editor.cursor(), 11);
+        vi(&mut editor, &mut text, "$");
+        assert_eq!(editor.cursor(), 20);
+        vi(&mut editor, &mut text, "0fm");
+        assert_eq!(editor.cursor(), 6);
+        vi(&mut editor, &mut text, ";;");
+        assert_eq!(editor.cursor(), 12);
+        vi(&mut editor, &mut text, "2b");
+        assert_eq!(editor.cursor(), 4);
+        vi(&mut editor, &mut text, "d3w");
+        assert_eq!(text, "git message");
+        assert_eq!(editor.cursor_shape(), CursorShape::Block);
+    }
+
+    #[test]
+    fn test_vi_text_objects() {
+        let (mut editor, mut text) = normal("echo \"hello world\" (a (b) c)", 8);
+        vi(&mut editor, &mut text, "ci\"bye\x1b");
+        assert_eq!(text, "echo \"bye\" (a (b) c)");
+        assert_eq!(editor.vi_mode(), ViMode::Normal);
+        editor.cursor = 12;
+        vi(&mut editor, &mut text, "da(");
+        assert_eq!(text, "echo \"bye\" ");
+        let (mut editor, mut text) = normal("ls -la /tmp", 4);
+        vi(&mut editor, &mut text, "daW");
+        assert_eq!(text, "ls /tmp");
+    }
+
+    #[test]
+    fn test_vi_registers_and_put() {
+        let (mut editor, mut text) = normal("cat file", 5);
+        vi(&mut editor, &mut text, "\"ayiw0\"aP");
+        assert_eq!(text, "filecat file");
+        vi(&mut editor, &mut text, "$x\"_x");
+        assert_eq!(text, "filecat fi");
+        vi(&mut editor, &mut text, "0p");
+        assert_eq!(text, "feilecat fi");
+        assert_eq!(editor.read_register(Some('a')).as_deref(), Some("file"));
+    }
+
+    #[test]
+    fn test_vi_dot_repeats_the_last_change() {
+        let (mut editor, mut text) = normal("one two three", 0);
+        vi(&mut editor, &mut text, "cwuno\x1bw.");
+        assert_eq!(text, "uno uno three");
+        vi(&mut editor, &mut text, "w2x.");
+        assert_eq!(text, "uno uno e");
+        vi(&mut editor, &mut text, "uu");
+        assert_eq!(text, "uno uno three");
+    }
+
+    #[test]
+    fn test_vi_visual_mode() {
+        let (mut editor, mut text) = normal("echo hello", 5);
+        vi(&mut editor, &mut text, "vll");
+        assert_eq!(editor.selection(&text), Some(5..8));
+        vi(&mut editor, &mut text, "~");
+        assert_eq!(text, "echo HELlo");
+        vi(&mut editor, &mut text, "viwd");
+        assert_eq!(text, "echo ");
+        assert_eq!(editor.vi_mode(), ViMode::Normal);
+    }
+
+    #[test]
+    fn test_emacs_kill_ring() {
+        let mut editor = LineEditor::new(EditingMode::Emacs);
+        let mut text = "cd /srv/app && make".to_string();
+        editor.text_replaced(&text);
+        editor.emacs(EmacsCommand::KillWordBackward, &mut text);
+        editor.emacs(EmacsCommand::KillWordBackward, &mut text);
+        assert_eq!(text, "cd /srv/app ");
+        editor.emacs(EmacsCommand::BeginningOfLine, &mut text);
+        editor.emacs(EmacsCommand::ForwardWord, &mut text);
+        assert_eq!(editor.cursor(), 2);
+        editor.emacs(EmacsCommand::KillLine, &mut text);
+        assert_eq!(text, "cd");
+        editor.emacs(EmacsCommand::Yank, &mut text);
+        assert_eq!(text, "cd /srv/app ");
+        editor.emacs(EmacsCommand::YankPop, &mut text);
+        assert_eq!(text, "cd&& make");
+        editor.emacs(EmacsCommand::BackwardWord, &mut text);
+        assert_eq!(editor.cursor(), 5);
+    }
+
+    #[test]
+    fn test_text_changed_follows_the_cursor() {
+        let mut editor = LineEditor::new(EditingMode::Emacs);
+        editor.text_replaced("aa");
+        editor.cursor = 1;
+        editor.text_changed("aa", "aaa");
+        assert_eq!(editor.cursor(), 2);
+        editor.text_changed("aaa", "aa");
+        assert_eq!(editor.cursor(), 1);
+        editor.text_changed("ls", "ls -la");
+        assert_eq!(editor.cursor(), 6);
+    }
+}
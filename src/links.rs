@@ -0,0 +1,442 @@
+//! Clickable links in command output.
+//!
+//! Links come from two places: OSC 8 hyperlinks, which the shell capture records with
+//! each output line, and references detected in plain text — URLs and `path:line:col`
+//! locations as printed by rustc, gcc, pytest or `grep -n`. Detection is driven by
+//! `LinkRule`s, regexes with named groups, so more formats can be added in the preferences.
+
+use anyhow::{anyhow, bail, Result};
+use log::{error, info};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// What a link opens.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinkTarget {
+    /// A URL, opened with the system's default handler.
+    Url(String),
+    /// A file location, opened in the editor. Relative paths are resolved against the
+    /// working directory of the block the link is in.
+    File { path: String, line: Option<u32>, column: Option<u32> },
+}
+
+/// A link over a byte range of a line of output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkSpan {
+    pub range: Range<usize>,
+    pub target: LinkTarget,
+}
+
+/// A pattern that turns text into a link.
+///
+/// The pattern must have either a `url` group, or a `path` group with optional `line`
+/// and `col` groups. The whole match becomes the clickable text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkRule {
+    pub name: String,
+    pub pattern: String,
+}
+
+impl LinkRule {
+    fn new(name: &str, pattern: &str) -> Self {
+        Self { name: name.to_string(), pattern: pattern.to_string() }
+    }
+}
+
+/// Rules for URLs and the file locations printed by common compilers, test runners and grep.
+pub fn default_rules() -> Vec<LinkRule> {
+    vec![
+        LinkRule::new("url", r#"(?P<url>\b(?:https?|ftp|file)://[^\s<>"'`]*[^\s<>"'`.,;:!?)\]}])"#),
+        // Python tracebacks: File "app/main.py", line 12, in <module>
+        LinkRule::new("python-traceback", r#"File "(?P<path>[^"]+)", line (?P<line>\d+)"#),
+        // rustc, gcc, clang, pytest, grep -n: src/main.rs:10:5
+        LinkRule::new(
+            "path-line-col",
+            r"(?P<path>(?:[A-Za-z]:)?[\w.~+@-]*(?:[/\\][\w.~+@-]+)*\.[A-Za-z0-9]+):(?P<line>\d+)(?::(?P<col>\d+))?",
+        ),
+    ]
+}
+
+/// Finds links in lines of plain text.
+#[derive(Debug)]
+pub struct LinkDetector {
+    rules: Vec<(String, Regex)>,
+}
+
+impl LinkDetector {
+    /// Compiles `rules`. Rules with an invalid pattern are logged and skipped.
+    pub fn new(rules: &[LinkRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| match Regex::new(&rule.pattern) {
+                Ok(regex) => Some((rule.name.clone(), regex)),
+                Err(e) => {
+                    error!("Ignoring link rule '{}' with an invalid pattern: {}", rule.name, e);
+                    None
+                }
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Returns the links in `line`, in order and without overlaps.
+    /// Where the matches of two rules overlap, the earlier rule wins.
+    pub fn detect(&self, line: &str) -> Vec<LinkSpan> {
+        let mut spans: Vec<LinkSpan> = Vec::new();
+        for (_, regex) in &self.rules {
+            for captures in regex.captures_iter(line) {
+                let whole = captures.get(0).map(|m| m.range()).unwrap_or_default();
+                if whole.is_empty() || spans.iter().any(|span| overlaps(&span.range, &whole)) {
+                    continue;
+                }
+                let number = |name| captures.name(name).and_then(|m| m.as_str().parse().ok());
+                let target = if let Some(url) = captures.name("url") {
+                    LinkTarget::Url(url.as_str().to_string())
+                } else if let Some(path) = captures.name("path") {
+                    LinkTarget::File { path: path.as_str().to_string(), line: number("line"), column: number("col") }
+                } else {
+                    continue;
+                };
+                spans.push(LinkSpan { range: whole, target });
+            }
+        }
+        spans.sort_by_key(|span| span.range.start);
+        spans
+    }
+}
+
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+static DETECTOR: Lazy<RwLock<Arc<LinkDetector>>> = Lazy::new(|| RwLock::new(Arc::new(LinkDetector::new(&default_rules()))));
+
+/// Replaces the rules used by `detect`, e.g. after the preferences were loaded.
+pub fn configure(rules: &[LinkRule]) {
+    *DETECTOR.write().unwrap() = Arc::new(LinkDetector::new(rules));
+}
+
+/// Returns the links of `line`: the recorded OSC 8 hyperlinks, plus references detected
+/// with the configured rules where they don't overlap a hyperlink.
+pub fn links_in(line: &str, hyperlinks: &[LinkSpan]) -> Vec<LinkSpan> {
+    let detector = DETECTOR.read().unwrap().clone();
+    let mut links: Vec<LinkSpan> = hyperlinks
+        .iter()
+        .filter(|link| link.range.end <= line.len() && line.is_char_boundary(link.range.start) && line.is_char_boundary(link.range.end))
+        .cloned()
+        .collect();
+    for detected in detector.detect(line) {
+        if !links.iter().any(|link| overlaps(&link.range, &detected.range)) {
+            links.push(detected);
+        }
+    }
+    links.sort_by_key(|link| link.range.start);
+    links
+}
+
+/// Splits `line` into runs of plain text and linked text.
+///
+/// # Arguments
+///
+/// * `line` - The text of a line.
+/// * `links` - The links of the line, in order and without overlaps, as returned by `links_in`.
+///
+/// # Returns
+///
+/// The runs in order, each with `None` for plain text or the target of the link.
+pub fn segments<'a, 'b>(line: &'a str, links: &'b [LinkSpan]) -> Vec<(&'a str, Option<&'b LinkTarget>)> {
+    let mut segments = Vec::new();
+    let mut position = 0;
+    for link in links {
+        if link.range.start < position || link.range.end > line.len() {
+            continue;
+        }
+        if link.range.start > position {
+            segments.push((&line[position..link.range.start], None));
+        }
+        segments.push((&line[link.range.clone()], Some(&link.target)));
+        position = link.range.end;
+    }
+    if position < line.len() || segments.is_empty() {
+        segments.push((&line[position..], None));
+    }
+    segments
+}
+
+/// Resolves the path of a file link: `~` is expanded and relative paths are taken
+/// relative to `working_directory`.
+pub fn resolve_path(path: &str, working_directory: Option<&str>) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest);
+        }
+    }
+    let path = Path::new(path);
+    match working_directory {
+        Some(dir) if path.is_relative() => Path::new(dir).join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Quotes `value` as a single POSIX shell word.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Builds the shell command that opens `path` in `editor` at the given position.
+///
+/// # Arguments
+///
+/// * `editor` - The editor command, e.g. the value of `$EDITOR`; it may include arguments.
+/// * `path` - The file to open.
+/// * `line` - The line to jump to, if known.
+/// * `column` - The column to jump to, if known.
+///
+/// # Returns
+///
+/// A command line for the shell, using the position syntax the editor understands.
+pub fn editor_command(editor: &str, path: &Path, line: Option<u32>, column: Option<u32>) -> String {
+    let file = quote(&path.to_string_lossy());
+    let Some(line) = line else {
+        return format!("{} {}", editor, file);
+    };
+    let program = program_name(editor);
+    let position = |separator: &str| match column {
+        Some(column) => format!("{}{}{}{}{}", path.to_string_lossy(), separator, line, separator, column),
+        None => format!("{}{}{}", path.to_string_lossy(), separator, line),
+    };
+    match program.as_str() {
+        "code" | "code-insiders" | "codium" | "cursor" => format!("{} --goto {}", editor, quote(&position(":"))),
+        "subl" | "zed" | "hx" | "helix" | "idea" | "mate" => format!("{} {}", editor, quote(&position(":"))),
+        "emacs" | "emacsclient" => match column {
+            Some(column) => format!("{} +{}:{} {}", editor, line, column, file),
+            None => format!("{} +{} {}", editor, line, file),
+        },
+        // vi, vim, nvim, nano, micro, kak and most other terminal editors
+        _ => format!("{} +{} {}", editor, line, file),
+    }
+}
+
+/// Returns the program an editor command runs, e.g. `code` for `/usr/bin/code -w`.
+fn program_name(editor: &str) -> String {
+    editor
+        .split_whitespace()
+        .next()
+        .and_then(|program| Path::new(program).file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Editors that run in a terminal. Blocks don't pass keys on to the programs they run,
+/// so these can't be opened from the application.
+const TERMINAL_EDITORS: &[&str] = &[
+    "vi", "vim", "nvim", "view", "vis", "ex", "ed", "nano", "pico", "micro", "kak", "hx", "helix",
+    "ne", "joe", "jed", "mg",
+];
+
+/// Returns the flags that make a GUI editor wait until the file is closed, the first
+/// being the one to add.
+fn wait_flags(program: &str) -> &'static [&'static str] {
+    match program {
+        "code" | "code-insiders" | "codium" | "cursor" | "subl" | "mate" => &["-w", "--wait"],
+        "zed" | "gedit" | "idea" | "pycharm" | "webstorm" | "clion" | "goland" => &["--wait", "-w"],
+        "gvim" | "mvim" => &["-f", "--nofork"],
+        "kate" => &["--block", "-b"],
+        // emacs, emacsclient and most other editors wait on their own
+        _ => &[],
+    }
+}
+
+/// Returns the editor to open files in (see `editor`), which must be a GUI editor.
+///
+/// # Arguments
+///
+/// * `configured` - The editor set in the preferences, if any.
+/// * `wait` - Add the flag that makes the editor wait until the file is closed, e.g. `-w`
+///   for VS Code, so the caller can read the file back once the editor exits.
+///
+/// # Returns
+///
+/// The editor command, or an error for a terminal editor such as the default `vi`.
+pub fn gui_editor(configured: Option<&str>, wait: bool) -> Result<String> {
+    let editor = editor(configured);
+    let program = program_name(&editor);
+    let args: Vec<&str> = editor.split_whitespace().skip(1).collect();
+    let in_terminal = TERMINAL_EDITORS.contains(&program.as_str())
+        || (program.starts_with("emacs") && args.iter().any(|arg| matches!(*arg, "-nw" | "-t" | "--tty" | "--no-window-system")));
+    if in_terminal {
+        bail!(
+            "{} runs in a terminal, and blocks don't pass keys on to the programs they run. \
+             Set `links.editor` in the preferences, or $VISUAL, to a GUI editor such as `code -w`.",
+            editor
+        );
+    }
+    let flags = wait_flags(&program);
+    match flags.first() {
+        Some(flag) if wait && !args.iter().any(|arg| flags.contains(arg)) => Ok(format!("{} {}", editor, flag)),
+        _ => Ok(editor),
+    }
+}
+
+/// Returns a command that runs `command_line` with the system shell.
+fn shell_command(command_line: &str) -> std::process::Command {
+    if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", command_line]);
+        command
+    } else {
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", command_line]);
+        command
+    }
+}
+
+/// Runs an editor command line, as built by `editor_command`, and waits for the editor to exit.
+///
+/// # Returns
+///
+/// Whether the editor exited successfully.
+pub async fn run_editor(command_line: String) -> Result<bool> {
+    info!("Running editor: {}", command_line);
+    let status = tokio::process::Command::from(shell_command(&command_line))
+        .stdin(std::process::Stdio::null())
+        .status()
+        .await
+        .map_err(|e| anyhow!("Failed to run {}: {}", command_line, e))?;
+    Ok(status.success())
+}
+
+/// Starts an editor command line, as built by `editor_command`, without waiting for the editor.
+pub fn spawn_editor(command_line: &str) -> Result<()> {
+    info!("Starting editor: {}", command_line);
+    shell_command(command_line)
+        .stdin(std::process::Stdio::null())
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| anyhow!("Failed to run {}: {}", command_line, e))
+}
+
+/// Returns the editor to open files in: the configured one, else `$VISUAL`, `$EDITOR` or `vi`.
+pub fn editor(configured: Option<&str>) -> String {
+    configured
+        .filter(|editor| !editor.trim().is_empty())
+        .map(str::to_string)
+        .or_else(|| std::env::var("VISUAL").ok().filter(|editor| !editor.is_empty()))
+        .or_else(|| std::env::var("EDITOR").ok().filter(|editor| !editor.is_empty()))
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// Opens `url` with the system's default handler.
+pub fn open_url(url: &str) -> Result<()> {
+    info!("Opening URL: {}", url);
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    command
+        .arg(url)
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| anyhow!("Failed to open {}: {}", url, e))
+}
+
+/// Initializes the links module.
+pub fn init() {
+    info!("links module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, line: Option<u32>, column: Option<u32>) -> LinkTarget {
+        LinkTarget::File { path: path.to_string(), line, column }
+    }
+
+    fn detect(line: &str) -> Vec<(&str, LinkTarget)> {
+        LinkDetector::new(&default_rules())
+            .detect(line)
+            .into_iter()
+            .map(|span| (&line[span.range.clone()], span.target))
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_compiler_and_tool_output() {
+        assert_eq!(detect("  --> src/main.rs:10:5"), vec![("src/main.rs:10:5", file("src/main.rs", Some(10), Some(5)))]);
+        assert_eq!(
+            detect("foo.c:3:14: error: expected ';'"),
+            vec![("foo.c:3:14", file("foo.c", Some(3), Some(14)))]
+        );
+        assert_eq!(
+            detect("tests/test_app.py:42: AssertionError"),
+            vec![("tests/test_app.py:42", file("tests/test_app.py", Some(42), None))]
+        );
+        assert_eq!(
+            detect(r#"  File "/srv/app/main.py", line 7, in <module>"#),
+            vec![(r#"File "/srv/app/main.py", line 7"#, file("/srv/app/main.py", Some(7), None))]
+        );
+        assert!(detect("finished at 12:30:45").is_empty());
+    }
+
+    #[test]
+    fn test_detect_urls() {
+        let url = |text: &str| LinkTarget::Url(text.to_string());
+        assert_eq!(
+            detect("see https://example.com:8080/docs/a.html#top, or (http://x.org/b)."),
+            vec![
+                ("https://example.com:8080/docs/a.html#top", url("https://example.com:8080/docs/a.html#top")),
+                ("http://x.org/b", url("http://x.org/b")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hyperlinks_take_precedence() {
+        let line = "error in src/lib.rs:4";
+        let hyperlink = LinkSpan { range: 9..15, target: LinkTarget::Url("https://docs".to_string()) };
+        assert_eq!(links_in(line, &[hyperlink.clone()]), vec![hyperlink]);
+        // A stale range that doesn't fit the line is dropped.
+        let stale = LinkSpan { range: 0..100, target: LinkTarget::Url("https://docs".to_string()) };
+        assert_eq!(links_in(line, &[stale]).len(), 1);
+    }
+
+    #[test]
+    fn test_segments() {
+        let line = "see src/a.rs:1 and https://x.io";
+        let links = links_in(line, &[]);
+        let runs: Vec<_> = segments(line, &links).into_iter().map(|(text, target)| (text, target.is_some())).collect();
+        assert_eq!(runs, vec![("see ", false), ("src/a.rs:1", true), (" and ", false), ("https://x.io", true)]);
+        assert_eq!(segments("", &[]), vec![("", None)]);
+    }
+
+    #[test]
+    fn test_editor_command() {
+        let path = Path::new("/src/it's.rs");
+        assert_eq!(editor_command("nvim", path, Some(3), Some(9)), r"nvim +3 '/src/it'\''s.rs'");
+        assert_eq!(editor_command("/usr/bin/code -r", path, Some(3), Some(9)), r"/usr/bin/code -r --goto '/src/it'\''s.rs:3:9'");
+        assert_eq!(editor_command("emacsclient -nw", path, Some(3), None), r"emacsclient -nw +3 '/src/it'\''s.rs'");
+        assert_eq!(editor_command("hx", path, None, None), r"hx '/src/it'\''s.rs'");
+        assert_eq!(resolve_path("src/a.rs", Some("/work")), PathBuf::from("/work/src/a.rs"));
+        assert_eq!(resolve_path("/abs/a.rs", Some("/work")), PathBuf::from("/abs/a.rs"));
+    }
+
+    #[test]
+    fn test_gui_editor() {
+        assert_eq!(gui_editor(Some("/usr/bin/code"), true).unwrap(), "/usr/bin/code -w");
+        assert_eq!(gui_editor(Some("code --wait -n"), true).unwrap(), "code --wait -n");
+        assert_eq!(gui_editor(Some("zed"), false).unwrap(), "zed");
+        assert_eq!(gui_editor(Some("emacsclient -c"), true).unwrap(), "emacsclient -c");
+        assert!(gui_editor(Some("nvim"), true).is_err());
+        assert!(gui_editor(Some("emacs -nw"), true).is_err());
+    }
+}
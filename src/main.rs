@@ -6,6 +6,7 @@
 
 use iced::{executor, Application, Command, Element, Settings, Theme};
 use iced::widget::{column, container, scrollable, text_input, button, row, text};
+use iced::widget::pane_grid::{self, PaneGrid};
 use iced::keyboard::{self, KeyCode, Modifiers};
 use std::path::PathBuf;
 use tokio::sync::{mpsc, RwLock, Mutex};
@@ -24,45 +25,63 @@ use serde_json::Value; // For parsing tool call arguments
 mod ai;
 mod agent_mode_eval;
 mod api;
+mod asciicast;
 mod asset_macro;
 mod block;
 mod cli;
+mod clipboard;
 mod cloud;
 mod collaboration;
 mod command;
+mod command_highlight;
+mod command_info;
+mod completion;
 mod config;
 mod drive;
 mod fuzzy_match;
 mod graphql;
+mod history;
 mod input;
 mod integration;
 mod languages;
+mod line_editor;
+mod links;
+mod lint;
 mod lpc;
 mod main_loop;
 mod markdown_parser;
 mod mcq;
+mod multiline;
 mod natural_language_detection;
+mod output_filter;
+mod paste;
 mod performance;
 mod plugins;
 mod renderer;
+mod scrollback;
+mod search;
 mod serve_wasm;
+mod session;
 mod settings;
 mod shell;
+mod shell_syntax;
 mod string_offset;
 mod sum_tree;
 mod syntax_tree;
+mod terminal;
 mod ui;
 mod virtual_fs;
 mod watcher;
 mod websocket;
 mod workflows;
+mod workspace;
 
 // Use statements for key components
 use ai::assistant::Assistant;
 use ai::context::AIContext; // Import AIContext
-use agent_mode_eval::{AgentConfig, AgentMessage, AgentMode};
+use agent_mode_eval::{AgentConfig, AgentMessage, AgentMode, PendingPrompts};
 use cli::{Cli, CliCommand};
-use command::CommandManager;
+use command::{CommandManager, JobSignal};
 use config::ConfigManager;
 use plugins::plugin_manager::PluginManager;
 use virtual_fs::VirtualFileSystem;
@@ -70,11 +89,19 @@ use watcher::Watcher;
 use workflows::manager::WorkflowManager;
 
 use block::{Block, BlockContent};
-use shell::ShellManager;
+use shell::{ShellCommandEvent, ShellManager};
+use output_filter::OutputFilter;
+use links::{LinkSpan, LinkTarget};
+use clipboard::{CopyScope, Osc52, Osc52Read};
+use lint::{LintContext, Linter, PendingCommand};
+use paste::{PasteChecker, PendingPaste};
+use history::{HistoryEntry, HistoryStore, ShellHistory};
 use input::{EnhancedTextInput, Message as InputMessage, HistoryDirection, Direction};
 use config::{AppConfig, preferences::UserPreferences};
 use crate::{
-    ui::command_palette::{CommandPalette, CommandAction},
+    ui::command_palette::{CommandPalette, CommandAction, CommandPaletteMessage},
+    ui::search_bar::{self, SearchAction, SearchBar, SearchBarMessage},
+    ui::history_search::{self, HistorySearch, HistorySearchAction, HistorySearchMessage},
     ui::ai_sidebar::AISidebar,
     command::pty::{PtyManager, CommandStatus},
     workflows::debugger::WorkflowDebugger,
@@ -95,7 +122,6 @@ use mcq::McqManager;
 use natural_language_detection::NaturalLanguageDetector;
 use resources::ResourceManager;
 use settings::SettingsManager;
-use shell::ShellManager as IcedShellManager;
 use string_offset::StringOffsetManager;
 use sum_tree::SumTreeManager;
 use syntax_tree::SyntaxTreeManager;
@@ -111,14 +137,17 @@ use serve_wasm::WasmServer; // Import WasmServer
 /// The main application state for NeoTerm.
 #[derive(Debug, Clone)]
 pub struct NeoTerm {
-    /// List of UI blocks displayed in the terminal.
-    blocks: Vec<Block>,
+    /// Tabs and split panes, each pane with its own shell session and blocks.
+    workspace: workspace::Workspace,
     /// The enhanced text input bar for commands and AI queries.
     input_bar: EnhancedTextInput,
     
     // Agent mode
     /// The AI agent mode instance.
     agent_mode: Arc<RwLock<AgentMode>>,
+    /// Prompts of the agent waiting for the user. Answered without locking `agent_mode`,
+    /// which a running conversation holds.
+    agent_prompts: PendingPrompts,
     /// Flag indicating if agent mode is currently enabled.
     agent_enabled: bool,
     /// Receiver for streaming messages from the AI agent.
@@ -181,8 +210,6 @@ pub struct NeoTerm {
     resource_manager: Arc<ResourceManager>,
     /// Manager for application settings.
     settings_manager: Arc<SettingsManager>,
-    /// Shell manager.
-    shell_manager: Arc<IcedShellManager>,
     /// String offset manager.
     string_offset_manager: Arc<StringOffsetManager>,
     /// Sum tree manager.
@@ -201,8 +228,34 @@ pub struct NeoTerm {
     preferences: UserPreferences,
     /// Results of performance benchmarks.
     benchmark_results: Option<Vec<BenchmarkResult>>,
+    /// A session saved by a previous run, waiting for the user to restore or discard it.
+    pending_session: Option<session::Session>,
+    /// When the session was last written to disk.
+    last_session_save: std::time::Instant,
+    /// Searchable list of application actions, shown on top of the panes while open.
+    command_palette: CommandPalette,
+    /// Full-text search across the blocks of all panes, shown above the panes while open.
+    search_bar: SearchBar,
+    /// Interactive search of the command history, shown above the panes while open.
+    history_search: HistorySearch,
+    /// Pasted text shown for confirmation, as it has several lines or risky commands.
+    pending_paste: Option<PendingPaste>,
+    /// Command shown for confirmation, as the linter found it risky.
+    pending_command: Option<PendingCommand>,
+    /// The input bar's command right after the user pasted risky text into it and confirmed
+    /// the warnings. Running it unchanged doesn't ask for confirmation a second time.
+    confirmed_paste: Option<String>,
+    /// File holding the input bar's command while it is being edited in a GUI editor.
+    script_in_editor: Option<PathBuf>,
+    /// Commands run in this and previous runs, shared with the input bar.
+    history: Arc<std::sync::Mutex<HistoryStore>>,
+    /// Identifies this run of the application in the command history.
+    session_id: String,
 }
 
+/// How often the session is saved while the application runs, so a crash loses little.
+const SESSION_AUTOSAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
 /// Messages that can be sent to the `NeoTerm` application.
 #[derive(Debug, Clone)]
 pub enum Message {
@@ -214,6 +267,8 @@ pub enum Message {
     PtyOutput(PtyMessage),
     /// Keyboard event.
     KeyboardEvent(keyboard::Event),
+    /// A key press no widget handled, e.g. while the input bar isn't focused.
+    UncapturedKey(KeyCode, Modifiers),
     /// Action performed on a UI block.
     BlockAction(String, BlockMessage),
     /// A periodic tick message for UI updates.
@@ -258,6 +313,58 @@ pub enum Message {
     WorkflowExecutionEvent(WorkflowExecutionEvent),
     /// User's response to an agent prompt.
     UserResponseToAgentPrompt(String, String),
+
+    // Session persistence
+    /// Restore (`true`) or discard (`false`) the session saved by the previous run.
+    RestoreSession(bool),
+    /// The window is about to close; the session is saved first.
+    WindowCloseRequested(iced::window::Id),
+
+    // Tabs and panes
+    /// Tab or pane action from the tab bar, a pane or a key binding.
+    Workspace(workspace::WorkspaceMessage),
+    /// Switch the active tab to the named environment profile.
+    SwitchEnvProfile(String),
+
+    // Command palette
+    /// Open or close the command palette.
+    ToggleCommandPalette,
+    /// Message from the command palette.
+    CommandPalette(CommandPaletteMessage),
+
+    // Recording
+    /// Advances the playback blocks that are playing.
+    PlaybackTick(std::time::Instant),
+
+    // Search
+    /// Open the search bar and focus its input.
+    OpenSearch,
+    /// Message from the search bar.
+    Search(SearchBarMessage),
+    /// Open the command history search and focus its input.
+    OpenHistorySearch,
+    /// Message from the command history search.
+    HistorySearch(HistorySearchMessage),
+
+    // Paste
+    /// Paste (`true`) or discard the pasted text waiting for confirmation.
+    ConfirmPaste(bool),
+
+    // Linting
+    /// Run (`true`) or abort the risky command waiting for confirmation.
+    ConfirmCommand(bool),
+
+    // History
+    /// Import the commands of a shell's history file into the command history.
+    ImportShellHistory(ShellHistory),
+
+    // Completion
+    /// Completions the shell found for the input bar's text at the time.
+    ShellCompletions(String, Vec<completion::Completion>),
+
+    // Editor
+    /// The editor opened on the input bar's command exited, successfully (`true`) or not.
+    ScriptEdited(Result<bool, String>),
 }
 
 /// Messages related to PTY (Pseudo-Terminal) operations.
@@ -268,12 +375,38 @@ pub enum PtyMessage {
         block_id: String,
         content: String,
         is_stdout: bool,
+        /// OSC 8 hyperlinks within the line.
+        hyperlinks: Vec<LinkSpan>,
+    },
+    /// An inline image drawn by the command.
+    Image {
+        block_id: String,
+        image: terminal::InlineImage,
+    },
+    /// The command asked to set or read the clipboard through OSC 52.
+    Clipboard {
+        block_id: String,
+        request: Osc52,
+    },
+    /// The command draws on the screen, shown in place of its last `replaces` output lines.
+    Screen {
+        block_id: String,
+        view: terminal::ScreenView,
+        replaces: usize,
+    },
+    /// The lines a command that drew on the screen left there, replacing its last `replaces` output lines.
+    Redrawn {
+        block_id: String,
+        lines: Vec<String>,
+        replaces: usize,
     },
     /// Command completed with an exit code.
     Completed {
         block_id: String,
         exit_code: i32,
         duration: Duration, // Add duration
+        /// Wall time, signal and resource usage of the command.
+        stats: Option<command::CommandStats>,
     },
     /// Command failed with an error message.
     Failed {
@@ -295,8 +428,12 @@ pub enum BlockMessage {
     Rerun,
     /// Delete the block from the UI.
     Delete,
-    /// Copy the content of the block to clipboard.
-    Copy,
+    /// Copy part of the block to the clipboard.
+    Copy(CopyScope),
+    /// Select an output line of a command block, or extend the selection to it.
+    SelectOutputLine(usize),
+    /// Allow (`true`) or deny the command's pending OSC 52 request to read the clipboard.
+    AnswerClipboardQuery(bool),
     /// Export the content of the block.
     Export,
     /// Toggle the collapsed state of the block.
@@ -317,19 +454,51 @@ pub enum BlockMessage {
     SubmitAgentPrompt,
     /// Fetch AI usage quota.
     FetchUsageQuota,
+    /// Page back through a command block's output, including lines spilled to disk.
+    ShowEarlierOutput,
+    /// Return to the latest output of a command block.
+    ShowLatestOutput,
+    /// Send a signal to the job running in a command block.
+    SendSignal(JobSignal),
+    /// Show or hide the process tree of a running command block.
+    ShowProcesses,
+    /// The process tree of a running command block was read.
+    ProcessesLoaded(String),
+    /// Resume a stopped command block's job in the foreground (`fg`).
+    Foreground,
+    /// Resume a stopped command block's job in the background (`bg`).
+    Background,
+    /// Play or pause a playback block.
+    PlaybackToggle,
+    /// Jump to a position, in seconds, in a playback block's recording.
+    PlaybackSeek(f64),
+    /// Set the speed of a playback block.
+    PlaybackSpeed(f64),
+    /// Open or close the output filter bar of a command block.
+    ToggleFilter,
+    /// The output filter settings of a command block were edited.
+    FilterChanged(OutputFilter),
+    /// The name to save a command block's output filter under was edited.
+    FilterSaveNameChanged(String),
+    /// Save a command block's output filter under the name typed in.
+    SaveFilter,
+    /// Filter a command block's output with a saved filter.
+    ApplySavedFilter(String),
+    /// Open a link in a command block's output: a URL in the browser, a file in the editor.
+    OpenLink(LinkTarget),
 }
 
 impl Application for NeoTerm {
     type Message = Message;
     type Theme = Theme;
     type Executor = executor::Default;
-    type Flags = ();
+    type Flags = LaunchOptions;
 
     /// Initializes the application state and returns the initial command.
     ///
     /// This function sets up all core managers, AI components, and communication channels.
     /// It also adds initial sample blocks to the UI.
-    fn new(_flags: ()) -> (Self, Command<Message>) {
+    fn new(flags: LaunchOptions) -> (Self, Command<Message>) {
         // Initialize channels for inter-module communication
         let (pty_tx, pty_rx) = mpsc::channel(100);
         let (workflow_event_tx, workflow_event_rx) = mpsc::channel(100);
@@ -338,6 +507,7 @@ impl Application for NeoTerm {
         // Initialize core managers
         let config = AppConfig::load().unwrap_or_default();
         let preferences = config.preferences.clone();
+        links::configure(&preferences.links.rules);
         let config_manager = Arc::new(tokio::runtime::Handle::current().block_on(async {
             ConfigManager::new().await.expect("Failed to initialize ConfigManager")
         }));
@@ -347,7 +517,13 @@ impl Application for NeoTerm {
         let watcher = Arc::new(Watcher::new(mpsc::channel(100).0)); // Dummy sender for watcher events
         let resource_manager = Arc::new(ResourceManager::new());
         let plugin_manager = Arc::new(PluginManager::new(mpsc::unbounded_channel().0)); // Dummy sender for plugin events
-        let shell_manager = Arc::new(ShellManager::new());
+        let workspace = workspace::Workspace::new(workspace::PaneState::new(
+            None,
+            config.env_profiles.active_profile.clone(),
+            config.env_profiles.active_variables(),
+        ));
+        // Tools that run commands outside of a block use the first pane's shell.
+        let shell_manager = workspace.focused_pane().shell.clone();
         let drive_manager = Arc::new(DriveManager::new(Default::default(), mpsc::channel(100).0)); // Dummy sender for drive events
         let websocket_server = Arc::new(WebSocketServer::new());
         let lpc_engine = Arc::new(LpcEngine::new(mpsc::channel(100).0)); // Dummy sender for LPC events
@@ -415,7 +591,9 @@ impl Application for NeoTerm {
             }
             cfg
         };
-        let agent_mode = Arc::new(RwLock::new(AgentMode::new(agent_config, ai_assistant.clone(), ai_context.clone()).expect("Failed to initialize AgentMode")));
+        let agent_mode = AgentMode::new(agent_config, ai_assistant.clone(), ai_context.clone()).expect("Failed to initialize AgentMode");
+        let agent_prompts = agent_mode.pending_prompts();
+        let agent_mode = Arc::new(RwLock::new(agent_mode));
 
         // Start the API server (if enabled in preferences)
         if preferences.enable_graphql_api {
@@ -450,6 +628,7 @@ impl Application for NeoTerm {
             sync_manager.clone(),
             wasm_server.clone(),
         ));
+        workflow_executor.set_profile_env(config.env_profiles.active_variables());
         // Set the event sender for the executor
         let workflow_executor_clone = workflow_executor.clone();
         tokio::spawn(async move {
@@ -457,10 +636,21 @@ impl Application for NeoTerm {
             executor_lock.set_event_sender(workflow_event_tx);
         });
 
+        let mut command_palette = CommandPalette::new();
+        command_palette.set_env_profiles(&config.env_profiles.names());
+
+        let history = Arc::new(std::sync::Mutex::new(Self::open_history(
+            preferences.privacy.command_history_retention_days,
+        )));
+        let session_id = Uuid::new_v4().to_string();
+        // Command descriptions for the input bar's suggestions are read in the background.
+        tokio::spawn(command_info::refresh_if_stale());
+
         let mut neo_term = Self {
-            blocks: Vec::new(),
-            input_bar: EnhancedTextInput::new(),
+            workspace,
+            input_bar: EnhancedTextInput::with_history(history.clone()),
             agent_mode,
+            agent_prompts,
             agent_enabled: false,
             agent_streaming_rx: None,
             config,
@@ -487,7 +677,6 @@ impl Application for NeoTerm {
             natural_language_detector,
             resource_manager,
             settings_manager,
-            shell_manager,
             string_offset_manager,
             sum_tree_manager,
             syntax_tree_manager,
@@ -498,10 +687,37 @@ impl Application for NeoTerm {
             preferences,
             benchmark_results: None,
             streaming_tool_call_blocks: HashMap::new(), // Initialize new field
+            pending_session: None,
+            last_session_save: std::time::Instant::now(),
+            command_palette,
+            search_bar: SearchBar::new(),
+            history_search: HistorySearch::new(history.clone(), session_id.clone()),
+            pending_paste: None,
+            pending_command: None,
+            confirmed_paste: None,
+            script_in_editor: None,
+            history,
+            session_id,
         };
 
+        neo_term.input_bar.set_editing_mode(neo_term.preferences.ui.input_editing_mode);
+        neo_term.input_bar.set_indent_width(neo_term.preferences.editor.tab_size as usize);
         neo_term.add_sample_blocks();
 
+        if let Some(dir) = flags.record_dir {
+            if let Err(e) = neo_term.workspace.active_tab_mut().start_recording(&dir) {
+                error!("Failed to start recording to {:?}: {}", dir, e);
+            }
+            neo_term.command_manager.set_recorder(neo_term.workspace.focused_pane().shell.recorder());
+        }
+
+        if neo_term.preferences.general.restore_session {
+            match session::Session::load() {
+                Ok(session) => neo_term.pending_session = session,
+                Err(e) => log::warn!("Failed to load previous session: {}", e),
+            }
+        }
+
         (
             neo_term,
             Command::none(),
@@ -533,19 +749,40 @@ impl Application for NeoTerm {
         match message {
             Message::Input(input_message) => {
                 match input_message {
+                    InputMessage::Pasted => self.paste_from_clipboard(),
+                    InputMessage::InputChanged(value) => {
+                        if !self.input_bar.accepts_typing() {
+                            // The key was a vi or Emacs command; the field shows the input as it was.
+                            return self.input_bar.sync_cursor();
+                        }
+                        let cwd = self.focused_cwd();
+                        self.input_bar.set_cwd(cwd.clone());
+                        self.input_bar.update(InputMessage::InputChanged(value.clone()));
+                        self.complete_from_shell(value, cwd)
+                    }
+                    InputMessage::Submit if self.input_bar.wants_new_line() => {
+                        // The shell would wait for more of the command, or Shift is held.
+                        self.input_bar.update(InputMessage::NewLine);
+                        self.input_bar.sync_cursor()
+                    }
                     InputMessage::Submit => {
-                        let command = self.input_bar.value().to_string();
+                        // A multi-line command runs as one script, in one block.
+                        let command = self.input_bar.script();
                         self.input_bar.update(InputMessage::Submit);
                         if !command.trim().is_empty() {
                             if command.starts_with('#') || command.starts_with("/ai") {
                                 self.handle_ai_command(command, None)
+                            } else if let Some(path) = command.strip_prefix("/play ") {
+                                self.open_recording(path.trim());
+                                Command::none()
                             } else {
-                                self.execute_command(command)
+                                self.lint_and_execute(command)
                             }
                         } else {
                             Command::none()
                         }
                     }
+                    InputMessage::OpenInEditor => self.edit_script_in_editor(),
                     _ => {
                         self.input_bar.update(input_message);
                         Command::none()
@@ -555,17 +792,49 @@ impl Application for NeoTerm {
             Message::ExecuteCommand => {
                 Command::none()
             }
+            Message::PtyOutput(PtyMessage::Clipboard { block_id, request }) => {
+                self.handle_clipboard_request(block_id, request)
+            }
             Message::PtyOutput(pty_msg) => {
-                if let Some(block) = self.blocks.iter_mut().find(|b| b.id == pty_msg.get_block_id()) {
+                if let Some(block) = self.workspace.find_block_mut(pty_msg.get_block_id()) {
                     match pty_msg {
-                        PtyMessage::OutputChunk { content, is_stdout, .. } => {
-                            block.add_output_line(content, is_stdout);
+                        PtyMessage::OutputChunk { content, is_stdout, hyperlinks, .. } => {
+                            block.add_linked_output_line(content, is_stdout, hyperlinks);
                         }
-                        PtyMessage::Completed { exit_code, duration, block_id: _ } => {
-                            block.set_status(format!("Completed with exit code: {}", exit_code));
+                        PtyMessage::Image { image, .. } => {
+                            block.add_output_image(image);
+                        }
+                        PtyMessage::Clipboard { .. } => {}
+                        PtyMessage::Screen { view, replaces, .. } => {
+                            block.show_screen(view, replaces);
+                        }
+                        PtyMessage::Redrawn { lines, replaces, .. } => {
+                            block.replace_output_lines(lines, replaces);
+                        }
+                        PtyMessage::Completed { exit_code, duration, stats, block_id } => {
+                            let elapsed = duration.to_std().unwrap_or_default();
+                            if let Err(e) = self.history.lock().unwrap().finish(&block_id, exit_code, elapsed) {
+                                error!("Failed to update command history: {}", e);
+                            }
+                            block.process_tree = None;
+                            block.screen = None;
+                            block.clipboard_query = None;
+                            if let Some(stats) = stats.clone().filter(|s| s.stopped) {
+                                // The shell took the job back; it stays resumable from the block.
+                                block.set_status(format!("Stopped by {}", stats.signal_name().unwrap_or_default()));
+                                block.set_stats(stats);
+                                return Command::none();
+                            }
+                            match stats.as_ref().and_then(|s| s.signal_name()) {
+                                Some(signal) => block.set_status(format!("Terminated by {} (exit code: {})", signal, exit_code)),
+                                None => block.set_status(format!("Completed with exit code: {}", exit_code)),
+                            }
                             if let BlockContent::Command { end_time, .. } = &mut block.content {
                                 *end_time = Some(Local::now()); // Ensure end_time is set
                             }
+                            if let Some(stats) = stats {
+                                block.set_stats(stats);
+                            }
                             if exit_code != 0 {
                                 block.set_error(true);
                                 // Trigger AI fix suggestion
@@ -573,12 +842,16 @@ impl Application for NeoTerm {
                                     let original_command = input.clone();
                                     let error_output = output.iter()
                                         .filter(|(_, is_stdout)| !is_stdout) // Filter for stderr
-                                        .map(|(s, _)| s.clone())
+                                        .map(|(s, _)| s)
                                         .join("\n");
-                                    let error_msg = if error_output.is_empty() {
-                                        format!("Command exited with non-zero code: {}", exit_code)
-                                    } else {
+                                    let error_msg = if !error_output.is_empty() {
                                         format!("Error output:\n{}", error_output)
+                                    } else if !output.is_empty() {
+                                        // Output from a terminal has stdout and stderr merged; the error is usually at the end.
+                                        let tail = output.tail(20).unwrap_or_default().into_iter().map(|(s, _)| s).join("\n");
+                                        format!("Command exited with non-zero code: {}\nOutput:\n{}", exit_code, tail)
+                                    } else {
+                                        format!("Command exited with non-zero code: {}", exit_code)
                                     };
 
                                     let agent_mode_arc_clone = self.agent_mode.clone();
@@ -596,6 +869,7 @@ impl Application for NeoTerm {
                             }
                         }
                         PtyMessage::Failed { error, duration, block_id: _ } => {
+                            block.screen = None;
                             block.set_status(format!("Failed: {}", error));
                             block.set_error(true);
                             if let BlockContent::Command { end_time, .. } = &mut block.content {
@@ -618,6 +892,7 @@ impl Application for NeoTerm {
                             }
                         }
                         PtyMessage::Killed { duration, block_id: _ } => {
+                            block.screen = None;
                             block.set_status("Killed".to_string());
                             block.set_error(true);
                             if let BlockContent::Command { end_time, .. } = &mut block.content {
@@ -657,23 +932,23 @@ impl Application for NeoTerm {
                 match agent_msg {
                     AgentMessage::UserMessage(content) => {
                         let block = Block::new_user_message(content);
-                        self.blocks.push(block);
+                        self.workspace.focused_pane_mut().blocks.push(block);
                     }
                     AgentMessage::AgentResponse(content) => {
-                        if let Some(last_block) = self.blocks.last_mut() {
+                        if let Some(last_block) = self.workspace.focused_pane_mut().blocks.last_mut() {
                             if let BlockContent::AgentMessage { ref mut content: block_content, .. } = last_block.content {
                                 block_content.push_str(&content);
                             } else {
                                 // If the last block isn't an agent message, create a new one
                                 let mut new_block = Block::new_agent_message(content);
                                 new_block.set_status("Streaming...".to_string());
-                                self.blocks.push(new_block);
+                                self.workspace.focused_pane_mut().blocks.push(new_block);
                             }
                         } else {
                             // No blocks yet, create a new agent message block
                             let mut new_block = Block::new_agent_message(content);
                             new_block.set_status("Streaming...".to_string());
-                            self.blocks.push(new_block);
+                            self.workspace.focused_pane_mut().blocks.push(new_block);
                         }
                     }
                     AgentMessage::ToolCall(tool_call) => {
@@ -682,7 +957,7 @@ impl Application for NeoTerm {
 
                         if let Some(block_id) = self.streaming_tool_call_blocks.get(&tool_call_id) {
                             // Update existing streaming tool call block
-                            if let Some(block) = self.blocks.iter_mut().find(|b| b.id == *block_id) {
+                            if let Some(block) = self.workspace.find_block_mut(block_id) {
                                 if let BlockContent::StreamingToolCall { arguments: ref mut current_args, .. } = block.content {
                                     // Update arguments. A more robust solution might parse JSON and merge.
                                     *current_args = arguments_str;
@@ -697,7 +972,7 @@ impl Application for NeoTerm {
                                 arguments_str.clone(),
                             );
                             let new_block_id = new_block.id.clone();
-                            self.blocks.push(new_block);
+                            self.workspace.focused_pane_mut().blocks.push(new_block);
                             self.streaming_tool_call_blocks.insert(tool_call_id.clone(), new_block_id);
                         }
 
@@ -705,7 +980,7 @@ impl Application for NeoTerm {
                         // This is a heuristic to determine if streaming for this tool call is done.
                         if tool_call.function.arguments.is_object() || tool_call.function.arguments.is_array() {
                             if let Some(block_id) = self.streaming_tool_call_blocks.remove(&tool_call_id) {
-                                if let Some(block) = self.blocks.iter_mut().find(|b| b.id == block_id) {
+                                if let Some(block) = self.workspace.find_block_mut(&block_id) {
                                     // Transition to a regular Info block
                                     block.content = BlockContent::Info {
                                         title: format!("AI Tool Call: {}", tool_call.function.name),
@@ -722,14 +997,14 @@ impl Application for NeoTerm {
                             "AI Tool Result".to_string(),
                             result
                         );
-                        self.blocks.push(block);
+                        self.workspace.focused_pane_mut().blocks.push(block);
                     }
                     AgentMessage::SystemMessage(content) => {
                         let block = Block::new_info("System Message".to_string(), content);
-                        self.blocks.push(block);
+                        self.workspace.focused_pane_mut().blocks.push(block);
                     }
                     AgentMessage::Done => {
-                        if let Some(last_block) = self.blocks.last_mut() {
+                        if let Some(last_block) = self.workspace.focused_pane_mut().blocks.last_mut() {
                             if let BlockContent::AgentMessage { .. } = last_block.content {
                                 last_block.set_status("Completed".to_string());
                             }
@@ -740,12 +1015,12 @@ impl Application for NeoTerm {
                     }
                     AgentMessage::WorkflowSuggested(workflow) => {
                         let block = Block::new_workflow_suggestion(workflow);
-                        self.blocks.push(block);
+                        self.workspace.focused_pane_mut().blocks.push(block);
                         self.agent_streaming_rx = None; // Workflow suggestion ends the current AI stream
                     }
                     AgentMessage::AgentPromptRequest { prompt_id, message } => {
                         // Find the existing agent prompt block if it exists, or create a new one
-                        if let Some(block) = self.blocks.iter_mut().find(|b| {
+                        if let Some(block) = self.workspace.focused_pane_mut().blocks.iter_mut().find(|b| {
                             if let BlockContent::AgentPrompt { prompt_id: existing_prompt_id, .. } = &b.content {
                                 existing_prompt_id == &prompt_id
                             } else {
@@ -757,9 +1032,12 @@ impl Application for NeoTerm {
                             }
                         } else {
                             let block = Block::new_agent_prompt(prompt_id, message);
-                            self.blocks.push(block);
+                            self.workspace.focused_pane_mut().blocks.push(block);
                         }
                     }
+                    AgentMessage::CommandConfirmationRequest { prompt_id, command, findings } => {
+                        self.pending_command = Some(PendingCommand { command, findings, agent_prompt_id: Some(prompt_id) });
+                    }
                     AgentMessage::AgentPromptResponse { .. } => {
                         // This message is handled internally by AgentMode, not displayed directly
                         Command::none()
@@ -774,37 +1052,59 @@ impl Application for NeoTerm {
             }
             Message::AgentError(error) => {
                 let block = Block::new_error(format!("Agent error: {}", error));
-                self.blocks.push(block);
+                self.workspace.focused_pane_mut().blocks.push(block);
                 self.agent_streaming_rx = None;
                 self.streaming_tool_call_blocks.clear(); // Ensure cleanup
                 Command::none()
             }
             Message::CommandGenerated(generated_command) => {
                 // Auto-fill the input bar with the generated command
-                self.input_bar.update(InputMessage::InputChanged(generated_command.clone()));
+                self.input_bar.update(InputMessage::SetText(generated_command.clone()));
                 // Optionally, add an info block that the command was generated
                 let info_block = Block::new_info(
                     "AI Generated Command".to_string(),
                     format!("The command has been auto-filled into the input bar: `{}`. Press Enter to execute.", generated_command)
                 );
-                self.blocks.push(info_block);
+                self.workspace.focused_pane_mut().blocks.push(info_block);
                 Command::none()
             }
             Message::SuggestedFix(suggested_command) => {
                 // Auto-fill the input bar with the suggested command
-                self.input_bar.update(InputMessage::InputChanged(suggested_command.clone()));
+                self.input_bar.update(InputMessage::SetText(suggested_command.clone()));
                 let info_block = Block::new_info(
                     "AI Suggested Fix".to_string(),
                     format!("AI suggested a fix for the last failed command. It has been auto-filled into the input bar: `{}`. Press Enter to execute.", suggested_command)
                 );
-                self.blocks.push(info_block);
+                self.workspace.focused_pane_mut().blocks.push(info_block);
                 Command::none()
             }
             Message::UsageQuotaUpdated(quota_info) => {
                 let info_block = Block::new_info("AI Usage Quota".to_string(), quota_info);
-                self.blocks.push(info_block);
+                self.workspace.focused_pane_mut().blocks.push(info_block);
                 Command::none()
             }
+            Message::SwitchEnvProfile(name) => self.switch_tab_profile(name),
+            Message::ToggleCommandPalette => {
+                if self.command_palette.is_open() {
+                    self.command_palette.close();
+                } else {
+                    self.command_palette.open();
+                }
+                Command::none()
+            }
+            Message::CommandPalette(palette_message) => {
+                if let CommandPaletteMessage::Close = palette_message {
+                    self.command_palette.close();
+                    return Command::none();
+                }
+                match self.command_palette.update(palette_message) {
+                    Some(action) => {
+                        self.command_palette.close();
+                        self.update(action)
+                    }
+                    None => Command::none(),
+                }
+            }
             Message::ToggleSettings => {
                 self.settings_open = !self.settings_open;
                 Command::none()
@@ -813,17 +1113,119 @@ impl Application for NeoTerm {
                 self.handle_block_action(block_id, action)
             }
             Message::Tick => {
+                if self.preferences.general.restore_session
+                    && self.pending_session.is_none()
+                    && self.last_session_save.elapsed() >= SESSION_AUTOSAVE_INTERVAL
+                {
+                    self.save_session();
+                }
+                Command::none()
+            }
+            Message::OpenSearch => {
+                self.search_bar.open();
+                text_input::focus(text_input::Id::new(search_bar::INPUT_ID))
+            }
+            Message::Search(search_message) => {
+                match self.search_bar.update(search_message) {
+                    Some(action) => self.apply_search_action(action),
+                    None => Command::none(),
+                }
+            }
+            Message::OpenHistorySearch => {
+                self.history_search.open(self.focused_cwd());
+                text_input::focus(text_input::Id::new(history_search::INPUT_ID))
+            }
+            Message::HistorySearch(history_message) => {
+                match self.history_search.update(history_message) {
+                    Some(HistorySearchAction::Insert(command)) => {
+                        self.input_bar.update(InputMessage::SetText(command));
+                        text_input::focus(text_input::Id::new(input::INPUT_ID))
+                    }
+                    None => Command::none(),
+                }
+            }
+            Message::PlaybackTick(now) => {
+                for pane in self.workspace.panes_mut() {
+                    for player in pane.blocks.iter().filter_map(Block::player) {
+                        player.lock().unwrap().tick(now);
+                    }
+                }
                 Command::none()
             }
+            Message::RestoreSession(restore) => {
+                match self.pending_session.take() {
+                    Some(session) if restore => self.restore_session(session),
+                    _ => {
+                        if let Err(e) = session::Session::discard() {
+                            log::warn!("Failed to discard previous session: {}", e);
+                        }
+                        Command::none()
+                    }
+                }
+            }
+            Message::WindowCloseRequested(id) => {
+                // Don't overwrite a session the user hasn't decided about yet.
+                if self.preferences.general.restore_session && self.pending_session.is_none() {
+                    self.save_session();
+                }
+                iced::window::close(id)
+            }
+            Message::Workspace(workspace_message) => {
+                self.update_workspace(workspace_message)
+            }
             Message::KeyboardEvent(event) => {
+                if let keyboard::Event::ModifiersChanged(modifiers) = event {
+                    self.input_bar.set_modifiers(modifiers);
+                } else if self.input_bar_has_keys() && self.input_bar.handle_key(&event, &self.preferences.keybindings) {
+                    return self.input_bar.sync_cursor();
+                }
                 match event {
                     keyboard::Event::KeyPressed { key_code, modifiers, .. } => {
-                        match key_code {
-                            KeyCode::Up => {
-                                self.input_bar.update(InputMessage::HistoryNavigated(HistoryDirection::Up));
+                        if let Some(workspace_message) = workspace::message_for_key(&self.preferences.keybindings, key_code, modifiers) {
+                            return self.update_workspace(workspace_message);
+                        }
+                        if self.preferences.keybindings.binding("command_palette")
+                            .is_some_and(|binding| workspace::binding_matches(binding, key_code, modifiers))
+                        {
+                            return self.update(Message::ToggleCommandPalette);
+                        }
+                        let search_bindings = [
+                            ("search", Message::OpenSearch),
+                            ("search_next", Message::Search(SearchBarMessage::Next)),
+                            ("search_previous", Message::Search(SearchBarMessage::Previous)),
+                            ("history_search", Message::OpenHistorySearch),
+                            ("edit_command_in_editor", Message::Input(InputMessage::OpenInEditor)),
+                        ];
+                        for (action, message) in search_bindings {
+                            if self.preferences.keybindings.binding(action)
+                                .is_some_and(|binding| workspace::binding_matches(binding, key_code, modifiers))
+                            {
+                                return self.update(message);
                             }
-                            KeyCode::Down => {
-                                self.input_bar.update(InputMessage::HistoryNavigated(HistoryDirection::Down));
+                        }
+                        if self.history_search.is_open() {
+                            let history_message = match key_code {
+                                KeyCode::Escape => Some(HistorySearchMessage::Close),
+                                KeyCode::Up => Some(HistorySearchMessage::Step(false)),
+                                KeyCode::Down => Some(HistorySearchMessage::Step(true)),
+                                _ => None,
+                            };
+                            return match history_message {
+                                Some(history_message) => self.update(Message::HistorySearch(history_message)),
+                                None => Command::none(),
+                            };
+                        }
+                        if key_code == KeyCode::Escape && self.search_bar.is_open() {
+                            return self.update(Message::Search(SearchBarMessage::Close));
+                        }
+                        match key_code {
+                            KeyCode::Up | KeyCode::Down => {
+                                let direction = if key_code == KeyCode::Up { HistoryDirection::Up } else { HistoryDirection::Down };
+                                self.input_bar.update(InputMessage::HistoryNavigated(direction));
+                                if self.input_bar.is_multiline() {
+                                    // The arrows moved between the command's lines.
+                                    return self.input_bar.sync_cursor();
+                                }
                             }
                             KeyCode::Tab => {
                                 self.input_bar.update(InputMessage::NavigateSuggestions(Direction::Down));
@@ -839,12 +1241,61 @@ impl Application for NeoTerm {
                 }
                 Command::none()
             }
+            Message::UncapturedKey(key_code, modifiers) => {
+                // Widgets such as the focused input bar copy and paste by themselves.
+                let bound = |action| self.preferences.keybindings.binding(action)
+                    .is_some_and(|binding| workspace::binding_matches(binding, key_code, modifiers));
+                if bound("copy") {
+                    self.copy_selection();
+                } else if bound("paste") {
+                    return self.paste_from_clipboard();
+                }
+                Command::none()
+            }
+            Message::ConfirmPaste(confirmed) => {
+                match self.pending_paste.take() {
+                    Some(pending) if confirmed => {
+                        let risky_input = pending.block_id.is_none() && !pending.warnings.is_empty();
+                        let command = self.finish_paste(pending);
+                        self.confirmed_paste = risky_input.then(|| self.input_bar.script());
+                        command
+                    }
+                    _ => Command::none(),
+                }
+            }
+            Message::ConfirmCommand(confirmed) => {
+                match self.pending_command.take() {
+                    Some(PendingCommand { agent_prompt_id: Some(prompt_id), .. }) => {
+                        let response = if confirmed { agent_mode_eval::CONFIRM_COMMAND } else { "abort" };
+                        self.update(Message::UserResponseToAgentPrompt(prompt_id, response.to_string()))
+                    }
+                    Some(pending) if confirmed => self.execute_command(pending.command),
+                    _ => Command::none(),
+                }
+            }
+            Message::ShellCompletions(line, completions) => {
+                self.input_bar.add_completions(&line, completions);
+                Command::none()
+            }
+            Message::ScriptEdited(result) => {
+                let saved = result.unwrap_or_else(|e| {
+                    self.workspace.focused_pane_mut().blocks.push(Block::new_error(e));
+                    false
+                });
+                self.finish_editing_script(saved);
+                Command::none()
+            }
+            Message::ImportShellHistory(shell) => {
+                self.import_shell_history(shell);
+                Command::none()
+            }
             Message::ConfigLoaded(_) => Command::none(),
             Message::ConfigSaved => Command::none(),
             Message::SettingsMessage(msg) => {
                 let mut settings_view = settings::SettingsView::new(self.config.clone());
                 settings_view.update(msg);
                 self.config = settings_view.config;
+                self.input_bar.set_editing_mode(self.config.preferences.ui.input_editing_mode);
                 Command::none()
             }
             Message::RunBenchmarks => {
@@ -860,39 +1311,39 @@ impl Application for NeoTerm {
             Message::BenchmarkResults(suite) => {
                 let summary = suite.get_performance_summary();
                 let block = Block::new_info("Performance Benchmark Results".to_string(), summary);
-                self.blocks.push(block);
+                self.workspace.focused_pane_mut().blocks.push(block);
                 Command::none()
             }
             Message::WorkflowExecutionEvent(event) => {
                 match event {
                     WorkflowExecutionEvent::Started { workflow_id, name } => {
                         let block = Block::new_info("Workflow Started".to_string(), format!("Workflow '{}' (ID: {}) started.", name, workflow_id));
-                        self.blocks.push(block);
+                        self.workspace.focused_pane_mut().blocks.push(block);
                     }
                     WorkflowExecutionEvent::StepStarted { workflow_id: _, step_id: _, name } => {
                         let block = Block::new_info("Workflow Step Started".to_string(), format!("Step '{}' started.", name));
-                        self.blocks.push(block);
+                        self.workspace.focused_pane_mut().blocks.push(block);
                     }
                     WorkflowExecutionEvent::StepCompleted { workflow_id: _, step_id: _, name, output } => {
                         let block = Block::new_info("Workflow Step Completed".to_string(), format!("Step '{}' completed. Output:\n{}", name, output));
-                        self.blocks.push(block);
+                        self.workspace.focused_pane_mut().blocks.push(block);
                     }
                     WorkflowExecutionEvent::StepFailed { workflow_id: _, step_id: _, name, error } => {
                         let block = Block::new_error(format!("Workflow Step '{}' failed: {}", name, error));
-                        self.blocks.push(block);
+                        self.workspace.focused_pane_mut().blocks.push(block);
                     }
                     WorkflowExecutionEvent::Completed { workflow_id, name, success } => {
                         let status = if success { "successfully" } else { "with errors" };
                         let block = Block::new_info("Workflow Completed".to_string(), format!("Workflow '{}' (ID: {}) completed {}.", name, workflow_id, status));
-                        self.blocks.push(block);
+                        self.workspace.focused_pane_mut().blocks.push(block);
                     }
                     WorkflowExecutionEvent::Error { workflow_id, message } => {
                         let block = Block::new_error(format!("Workflow execution error for ID {}: {}", workflow_id, message));
-                        self.blocks.push(block);
+                        self.workspace.focused_pane_mut().blocks.push(block);
                     }
                     WorkflowExecutionEvent::AgentPromptRequest { workflow_id: _, step_id: _, prompt_id, message } => {
                         // Find the existing agent prompt block if it exists, or create a new one
-                        if let Some(block) = self.blocks.iter_mut().find(|b| {
+                        if let Some(block) = self.workspace.focused_pane_mut().blocks.iter_mut().find(|b| {
                             if let BlockContent::AgentPrompt { prompt_id: existing_prompt_id, .. } = &b.content {
                                 existing_prompt_id == &prompt_id
                             } else {
@@ -904,18 +1355,17 @@ impl Application for NeoTerm {
                             }
                         } else {
                             let block = Block::new_agent_prompt(prompt_id, message);
-                            self.blocks.push(block);
+                            self.workspace.focused_pane_mut().blocks.push(block);
                         }
                     }
                 }
                 Command::none()
             }
             Message::UserResponseToAgentPrompt(prompt_id, response) => {
-                let agent_mode_arc_clone = self.agent_mode.clone();
+                let agent_prompts = self.agent_prompts.clone();
                 Command::perform(
                     async move {
-                        let mut agent_mode = agent_mode_arc_clone.write().await;
-                        match agent_mode.handle_agent_prompt_response(prompt_id, response).await {
+                        match agent_mode_eval::respond_to_prompt(&agent_prompts, prompt_id, response).await {
                             Ok(_) => Message::Tick, // Just a dummy message to trigger update
                             Err(e) => Message::AgentError(format!("Failed to send agent prompt response: {}", e)),
                         }
@@ -940,16 +1390,8 @@ impl Application for NeoTerm {
             return settings_view.view().map(Message::SettingsMessage);
         }
 
-        let blocks_view = scrollable(
-            column(
-                self.blocks
-                    .iter()
-                    .map(|block| block.view().map(|msg| Message::BlockAction(block.id.clone(), msg)))
-                    .collect::<Vec<_>>()
-            )
-            .spacing(8)
-        )
-        .height(iced::Length::Fill);
+        let tab_bar = self.create_tab_bar();
+        let panes_view = self.create_pane_grid();
 
         let prompt_indicator = if self.agent_enabled {
             "🤖 "
@@ -967,7 +1409,40 @@ impl Application for NeoTerm {
 
         let toolbar = self.create_toolbar();
 
-        column![toolbar, blocks_view, input_view]
+        let mut layout = column![toolbar, tab_bar];
+        if self.command_palette.is_open() {
+            layout = layout.push(self.command_palette.view().map(Message::CommandPalette));
+        }
+        if self.search_bar.is_open() {
+            layout = layout.push(self.search_bar.view().map(Message::Search));
+        }
+        if self.history_search.is_open() {
+            layout = layout.push(self.history_search.view().map(Message::HistorySearch));
+        }
+        if let Some(pending) = &self.pending_paste {
+            layout = layout.push(self.paste_confirmation(pending));
+        }
+        if let Some(pending) = &self.pending_command {
+            layout = layout.push(self.command_confirmation(pending));
+        }
+        if let Some(session) = &self.pending_session {
+            layout = layout.push(
+                row![
+                    text(format!(
+                        "Restore previous session from {} ({} blocks)?",
+                        session.saved_at.format("%Y-%m-%d %H:%M"),
+                        session.block_count()
+                    )).size(14),
+                    button(text("Restore")).on_press(Message::RestoreSession(true)),
+                    button(text("Start fresh")).on_press(Message::RestoreSession(false)),
+                ]
+                .spacing(8)
+                .align_items(iced::Alignment::Center)
+            );
+        }
+
+        layout.push(panes_view)
+            .push(input_view)
             .spacing(8)
             .padding(16)
             .into()
@@ -997,8 +1472,30 @@ impl Application for NeoTerm {
             iced::Subscription::none()
         };
 
+        let close_requests = iced::event::listen_with(|event, _status| match event {
+            iced::Event::Window(id, iced::window::Event::CloseRequested) => Some(Message::WindowCloseRequested(id)),
+            _ => None,
+        });
+
+        // Playback only needs frequent ticks while a recording is playing.
+        let playback_ticks = if self.is_playing_back() {
+            iced::time::every(std::time::Duration::from_millis(50)).map(Message::PlaybackTick)
+        } else {
+            iced::Subscription::none()
+        };
+
+        let uncaptured_keys = iced::event::listen_with(|event, status| match (event, status) {
+            (iced::Event::Keyboard(keyboard::Event::KeyPressed { key_code, modifiers, .. }), iced::event::Status::Ignored) => {
+                Some(Message::UncapturedKey(key_code, modifiers))
+            }
+            _ => None,
+        });
+
         iced::Subscription::batch(vec![
             iced::time::every(std::time::Duration::from_millis(100)).map(|_| Message::Tick),
+            playback_ticks,
+            close_requests,
+            uncaptured_keys,
             self.pty_manager_subscription(),
             keyboard::Event::all().map(Message::KeyboardEvent),
             agent_stream_sub,
@@ -1008,6 +1505,389 @@ impl Application for NeoTerm {
 }
 
 impl NeoTerm {
+    /// Returns the variables of the active environment profile.
+    fn active_env_vars(&self) -> HashMap<String, String> {
+        self.config.env_profiles.active_variables()
+    }
+
+    /// Switches every pane of the active tab to the environment profile `name`.
+    /// The panes' shells are restarted in place so new commands see the profile's variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of a profile in `config.env_profiles`.
+    ///
+    /// # Returns
+    ///
+    /// An `iced::Command` that terminates the replaced shells.
+    fn switch_tab_profile(&mut self, name: String) -> Command<Message> {
+        if !self.config.env_profiles.profiles.contains_key(&name) {
+            self.workspace.focused_pane_mut().blocks.push(Block::new_error(format!("Unknown environment profile: {}", name)));
+            return Command::none();
+        }
+        let env = self.config.env_profiles.variables(Some(&name));
+        let replaced = self.workspace.active_tab_mut().set_profile(Some(name.clone()), env);
+        info!("Switched tab to environment profile '{}'", name);
+        self.workspace.focused_pane_mut().blocks.push(Block::new_info(
+            "Environment profile".to_string(),
+            format!("This tab now uses the '{}' profile. Its shells restart in the same directory on the next command.", name),
+        ));
+        Command::batch(replaced.into_iter().map(Self::terminate_shell))
+    }
+
+    /// Starts or stops recording the panes of the active tab to asciicast files.
+    ///
+    /// Commands run outside the shell are added to the focused pane's recording. When
+    /// recording stops, an info block lists the files with a hint on how to replay them.
+    fn toggle_tab_recording(&mut self) {
+        let tab = self.workspace.active_tab_mut();
+        if tab.is_recording() {
+            let paths = tab.stop_recording();
+            self.command_manager.set_recorder(None);
+            let list = paths.iter().map(|path| format!("/play {}", path.display())).join("\n");
+            self.workspace.focused_pane_mut().blocks.push(Block::new_info(
+                "Recording saved".to_string(),
+                format!("Replay a recording with:\n{}", list),
+            ));
+            return;
+        }
+        let dir = config::DATA_DIR.join("recordings");
+        match tab.start_recording(&dir) {
+            Ok(paths) => {
+                self.command_manager.set_recorder(self.workspace.focused_pane().shell.recorder());
+                info!("Recording tab to {:?}", paths);
+            }
+            Err(e) => {
+                self.workspace.focused_pane_mut().blocks.push(Block::new_error(format!("Failed to start recording: {}", e)));
+            }
+        }
+    }
+
+    /// Adds a playback block for the asciicast recording at `path` to the focused pane.
+    fn open_recording(&mut self, path: &str) {
+        let block = match Block::new_playback(path.to_string()) {
+            Ok(block) => block,
+            Err(e) => Block::new_error(format!("Failed to open recording {}: {}", path, e)),
+        };
+        self.workspace.focused_pane_mut().blocks.push(block);
+    }
+
+    /// Carries out what the search bar asked for after a message.
+    ///
+    /// Searches run on a blocking thread over a snapshot of the blocks, so typing stays
+    /// responsive with a lot of output; the search bar ignores results of outdated queries.
+    ///
+    /// # Returns
+    ///
+    /// An `iced::Command` that runs the search or scrolls to the current match.
+    fn apply_search_action(&mut self, action: SearchAction) -> Command<Message> {
+        self.apply_search_highlights();
+        match action {
+            SearchAction::Search { generation, regex } => {
+                let blocks: Vec<Block> = self.workspace.panes().flat_map(|pane| pane.blocks.iter().cloned()).collect();
+                Command::perform(
+                    async move {
+                        tokio::task::spawn_blocking(move || {
+                            let mut results = search::SearchResults::default();
+                            for block in &blocks {
+                                if !block.search(&regex, &mut results) {
+                                    break;
+                                }
+                            }
+                            results
+                        })
+                        .await
+                        .unwrap_or_default()
+                    },
+                    move |results| Message::Search(SearchBarMessage::ResultsReady(generation, results)),
+                )
+            }
+            SearchAction::Jump(found) => self.jump_to_match(&found),
+            SearchAction::Refresh => Command::none(),
+        }
+    }
+
+    /// Hands the search bar's current highlights to the blocks of all panes.
+    fn apply_search_highlights(&mut self) {
+        let mut highlights = self.search_bar.highlights();
+        for pane in self.workspace.panes_mut() {
+            for block in &mut pane.blocks {
+                block.highlights = highlights.remove(&block.id).unwrap_or_default();
+            }
+        }
+    }
+
+    /// Brings a search match into view: switches to its tab and pane, expands its block,
+    /// pages back to spilled output if needed and scrolls the pane to the block.
+    fn jump_to_match(&mut self, found: &search::SearchMatch) -> Command<Message> {
+        if !self.workspace.focus_block(&found.block_id) {
+            return Command::none();
+        }
+        let pane = self.workspace.active_tab().focus;
+        let blocks = &mut self.workspace.focused_pane_mut().blocks;
+        let Some(index) = blocks.iter().position(|block| block.id == found.block_id) else {
+            return Command::none();
+        };
+        let block = &mut blocks[index];
+        block.collapsed = false;
+        if let search::MatchField::Output(line) = found.field {
+            block.show_output_line(line);
+        }
+        let offset = if blocks.len() > 1 { index as f32 / (blocks.len() - 1) as f32 } else { 0.0 };
+        scrollable::snap_to(pane_scroll_id(pane), scrollable::RelativeOffset { x: 0.0, y: offset })
+    }
+
+    /// Returns true if any playback block is playing.
+    fn is_playing_back(&self) -> bool {
+        self.workspace
+            .panes()
+            .flat_map(|pane| pane.blocks.iter())
+            .filter_map(Block::player)
+            .any(|player| player.lock().unwrap().is_playing())
+    }
+
+    /// Writes the open tabs and panes, input history and agent conversation to disk.
+    fn save_session(&mut self) {
+        self.last_session_save = std::time::Instant::now();
+        let agent_conversation = match self.ai_assistant.try_read() {
+            Ok(assistant) => assistant.conversation_history.clone(),
+            Err(_) => {
+                log::warn!("AI assistant is busy; saving the session without the agent conversation.");
+                Vec::new()
+            }
+        };
+        let session = session::Session::capture(
+            &self.workspace,
+            self.input_bar.history(),
+            agent_conversation,
+            self.preferences.terminal.scrollback_lines as usize,
+        );
+        if let Err(e) = session.save() {
+            error!("Failed to save session: {}", e);
+        }
+    }
+
+    /// Replaces the current state with a session saved by a previous run.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - The session to restore.
+    ///
+    /// # Returns
+    ///
+    /// An `iced::Command` that restores the agent conversation and stops the shells of
+    /// the panes that were replaced. Restored shells start on their pane's first command.
+    fn restore_session(&mut self, session: session::Session) -> Command<Message> {
+        info!("Restoring session saved at {}", session.saved_at);
+        let mut commands = Vec::new();
+        if let Some(workspace) = session.restore_workspace(
+            self.preferences.terminal.scrollback_lines as usize,
+            self.preferences.general.restore_shell_directories,
+            &self.config.env_profiles,
+        ) {
+            // Shells started before the user chose to restore belong to panes that go away.
+            let replaced = std::mem::replace(&mut self.workspace, workspace);
+            commands.extend(replaced.panes().map(Self::terminate_pane_shell));
+        }
+        if self.preferences.privacy.command_history_retention_days > 0 {
+            self.input_bar.set_history(session.input_history.clone());
+        }
+
+        let ai_assistant = self.ai_assistant.clone();
+        let conversation = session.agent_conversation.clone();
+        commands.push(Command::perform(
+            async move {
+                ai_assistant.write().await.conversation_history = conversation;
+            },
+            |_| Message::Tick,
+        ));
+        Command::batch(commands)
+    }
+
+    /// Handles tab and pane actions.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The `WorkspaceMessage` to process.
+    ///
+    /// # Returns
+    ///
+    /// An `iced::Command` that terminates the shells of closed panes.
+    fn update_workspace(&mut self, message: workspace::WorkspaceMessage) -> Command<Message> {
+        use workspace::WorkspaceMessage;
+
+        match message {
+            WorkspaceMessage::NewTab => {
+                let pane = workspace::PaneState::new(
+                    self.workspace.focused_pane().working_directory(),
+                    self.config.env_profiles.active_profile.clone(),
+                    self.active_env_vars(),
+                );
+                self.workspace.new_tab(pane);
+            }
+            WorkspaceMessage::SelectTab(index) => self.workspace.select_tab(index),
+            WorkspaceMessage::NextTab => self.workspace.next_tab(),
+            WorkspaceMessage::PreviousTab => self.workspace.previous_tab(),
+            WorkspaceMessage::CloseTab(index) => {
+                if let Some(tab) = self.workspace.close_tab(index) {
+                    return Command::batch(tab.panes.iter().map(|(_, pane)| Self::terminate_pane_shell(pane)));
+                }
+            }
+            WorkspaceMessage::Split(axis) => {
+                self.workspace.active_tab_mut().split(axis);
+            }
+            WorkspaceMessage::ClosePane(pane) => {
+                if let Some(closed) = self.workspace.active_tab_mut().close(pane) {
+                    return Self::terminate_pane_shell(&closed);
+                }
+            }
+            WorkspaceMessage::CloseFocused => {
+                let tab = self.workspace.active_tab_mut();
+                if tab.panes.len() > 1 {
+                    let focus = tab.focus;
+                    return self.update_workspace(WorkspaceMessage::ClosePane(focus));
+                }
+                let index = self.workspace.active_index();
+                return self.update_workspace(WorkspaceMessage::CloseTab(index));
+            }
+            WorkspaceMessage::FocusPane(pane) => self.workspace.active_tab_mut().focus = pane,
+            WorkspaceMessage::FocusAdjacent(direction) => {
+                self.workspace.active_tab_mut().focus_adjacent(direction);
+            }
+            WorkspaceMessage::Resized(pane_grid::ResizeEvent { split, ratio }) => {
+                self.workspace.active_tab_mut().panes.resize(split, ratio);
+            }
+            WorkspaceMessage::ToggleRecording => self.toggle_tab_recording(),
+        }
+        Command::none()
+    }
+
+    /// Returns a command that stops the shell of a closed pane.
+    fn terminate_pane_shell(pane: &workspace::PaneState) -> Command<Message> {
+        Self::terminate_shell(pane.shell.clone())
+    }
+
+    /// Terminates a shell that is no longer used by any pane.
+    fn terminate_shell(shell: Arc<ShellManager>) -> Command<Message> {
+        Command::perform(
+            async move {
+                if let Err(e) = shell.terminate_shell().await {
+                    error!("Failed to terminate shell: {}", e);
+                }
+            },
+            |_| Message::Tick,
+        )
+    }
+
+    /// Creates the tab bar: one button per tab, each with a close button, and a button
+    /// that opens a new tab.
+    ///
+    /// # Returns
+    ///
+    /// An `iced::Element` representing the tab bar.
+    fn create_tab_bar(&self) -> Element<Message> {
+        use workspace::WorkspaceMessage;
+
+        let mut tabs = row![].spacing(4).align_items(iced::Alignment::Center);
+        for (index, tab) in self.workspace.tabs().iter().enumerate() {
+            let is_active = index == self.workspace.active_index();
+            let title = if tab.is_recording() { format!("⏺ {}", tab.title()) } else { tab.title() };
+            let label = button(text(title).size(14))
+                .on_press(Message::Workspace(WorkspaceMessage::SelectTab(index)))
+                .style(if is_active { iced::theme::Button::Primary } else { iced::theme::Button::Secondary });
+            let mut tab_row = row![label].spacing(2);
+            if self.workspace.tabs().len() > 1 {
+                tab_row = tab_row.push(
+                    button(text("×").size(14))
+                        .on_press(Message::Workspace(WorkspaceMessage::CloseTab(index)))
+                        .style(iced::widget::button::text::Style::Text),
+                );
+            }
+            tabs = tabs.push(tab_row);
+        }
+        let recording = self.workspace.active_tab().is_recording();
+        tabs.push(
+            button(text("+").size(14))
+                .on_press(Message::Workspace(WorkspaceMessage::NewTab))
+                .style(iced::widget::button::text::Style::Text),
+        )
+        .push(
+            button(text(if recording { "⏹ Stop recording" } else { "⏺ Record" }).size(14))
+                .on_press(Message::Workspace(WorkspaceMessage::ToggleRecording))
+                .style(iced::widget::button::text::Style::Text),
+        )
+        .into()
+    }
+
+    /// Creates the split panes of the active tab, each showing its own blocks under a
+    /// title bar with the pane's working directory.
+    ///
+    /// # Returns
+    ///
+    /// An `iced::Element` representing the panes.
+    fn create_pane_grid(&self) -> Element<Message> {
+        use workspace::WorkspaceMessage;
+
+        let tab = self.workspace.active_tab();
+        let has_splits = tab.panes.len() > 1;
+        let dim_inactive = self.preferences.ui.dim_inactive_panes;
+
+        PaneGrid::new(&tab.panes, |pane, state, _is_maximized| {
+            let is_focused = pane == tab.focus;
+            let blocks_view = scrollable(
+                column(
+                    state.blocks
+                        .iter()
+                        .map(|block| block.view().map(|msg| Message::BlockAction(block.id.clone(), msg)))
+                        .collect::<Vec<_>>()
+                )
+                .spacing(8)
+            )
+            .id(pane_scroll_id(pane))
+            .height(iced::Length::Fill);
+
+            let mut title_bar = row![
+                text(state.working_directory().unwrap_or_else(|| "~".to_string())).size(12),
+            ]
+            .spacing(8)
+            .align_items(iced::Alignment::Center);
+            if has_splits {
+                title_bar = title_bar.push(
+                    button(text("×").size(12))
+                        .on_press(Message::Workspace(WorkspaceMessage::ClosePane(pane)))
+                        .style(iced::widget::button::text::Style::Text),
+                );
+            }
+
+            let content = pane_grid::Content::new(container(blocks_view).padding(4));
+            if !has_splits {
+                return content;
+            }
+            content
+                .title_bar(pane_grid::TitleBar::new(title_bar).padding(4))
+                .style(move |theme: &Theme| {
+                    let palette = theme.palette();
+                    container::Appearance {
+                        background: (dim_inactive && !is_focused)
+                            .then(|| palette.background.scale_alpha(0.6).into()),
+                        text_color: (dim_inactive && !is_focused)
+                            .then(|| palette.text.scale_alpha(0.6)),
+                        border: iced::Border {
+                            color: if is_focused { palette.primary } else { palette.text.scale_alpha(0.2) },
+                            width: 1.0,
+                            radius: 4.0.into(),
+                        },
+                        ..Default::default()
+                    }
+                })
+        })
+        .spacing(8)
+        .height(iced::Length::Fill)
+        .on_click(|pane| Message::Workspace(WorkspaceMessage::FocusPane(pane)))
+        .on_resize(10, |event| Message::Workspace(WorkspaceMessage::Resized(event)))
+        .into()
+    }
+
     /// Creates the application toolbar with various action buttons.
     ///
     /// # Returns
@@ -1025,7 +1905,7 @@ impl NeoTerm {
         let usage_button = button(text("📊 Usage"))
             .on_press(Message::BlockAction("".to_string(), BlockMessage::FetchUsageQuota));
 
-        let active_profile_name = self.config.env_profiles.active_profile.as_deref().unwrap_or("None");
+        let active_profile_name = self.workspace.active_tab().profile().unwrap_or("None");
         let env_profile_indicator = text(format!("Env: {}", active_profile_name)).size(14);
 
         row![agent_button, settings_button, usage_button, env_profile_indicator]
@@ -1050,16 +1930,18 @@ impl NeoTerm {
         let prompt_content = command.trim_start_matches('#').trim_start_matches("/ai").trim().to_string();
         
         let user_block = Block::new_user_message(command.clone());
-        self.blocks.push(user_block);
+        self.workspace.focused_pane_mut().blocks.push(user_block);
         
         let mut context_blocks = Vec::new();
         if let Some(id) = context_block_id {
-            if let Some(block) = self.blocks.iter().find(|b| b.id == id) {
+            if let Some(block) = self.workspace.find_block(&id) {
                 context_blocks.push(block.clone());
             }
         }
 
         let agent_mode_arc_clone = self.agent_mode.clone();
+        let working_directory = self.focused_cwd().map(PathBuf::from);
+        let profile_env = self.workspace.focused_pane().env.clone();
         let (tx, rx) = mpsc::channel(100);
         self.agent_streaming_rx = Some(rx);
 
@@ -1091,8 +1973,9 @@ impl NeoTerm {
         } else {
             Command::perform(
                 async move {
-                    let agent_mode = agent_mode_arc_clone.read().await; // Read lock for initial call
-                    match agent_mode.send_message(prompt_content, context_blocks).await {
+                    // The read lock is released once the conversation started.
+                    let stream = agent_mode_arc_clone.read().await.send_message(prompt_content, context_blocks, working_directory, profile_env).await;
+                    match stream {
                         Ok(mut stream_rx) => {
                             while let Some(msg) = stream_rx.recv().await {
                                 if tx.send(msg).await.is_err() {
@@ -1124,45 +2007,44 @@ impl NeoTerm {
     ///
     /// An `iced::Command` to update the UI or trigger backend operations.
     fn handle_block_action(&mut self, block_id: String, action: BlockMessage) -> Command<Message> {
-        if let Some(block_index) = self.blocks.iter().position(|b| b.id == block_id) {
-            let block = &mut self.blocks[block_index];
+        if let Some(block) = self.workspace.find_block_mut(&block_id) {
             match action {
                 BlockMessage::Rerun => {
                     if let BlockContent::Command { input, working_directory, .. } = &block.content {
                         let command = input.clone();
                         let wd = working_directory.clone();
                         // Re-execute the command, passing the original working directory
-                        self.execute_command_with_wd(command, wd)
+                        self.execute_command_with_wd(command, wd).1
                     } else {
                         Command::none()
                     }
                 }
                 BlockMessage::Delete => {
-                    self.blocks.retain(|b| b.id != block_id);
+                    self.workspace.remove_block(&block_id);
                     Command::none()
                 }
-                BlockMessage::Copy => {
-                    // Mock implementation for copy to clipboard
-                    let content_to_copy = match &block.content {
-                        BlockContent::Command { input, output, .. } => {
-                            format!("Command: {}\nOutput:\n{}", input, output.iter().map(|(s, _)| s.clone()).join("\n"))
-                        },
-                        BlockContent::AgentMessage { content, .. } => content.clone(),
-                        BlockContent::Info { message, .. } => message.clone(),
-                        BlockContent::Error { message, .. } => message.clone(),
-                        BlockContent::WorkflowSuggestion { workflow } => format!("{:#?}", workflow),
-                        BlockContent::AgentPrompt { message, .. } => message.clone(),
-                        BlockContent::StreamingToolCall { name, arguments, .. } => format!("Tool Call: {}\nArguments: {}", name, arguments),
-                    };
-                    log::info!("Mock Copy: Copied content to clipboard (not actually implemented): {}", content_to_copy);
-                    // In a real app, you'd use a platform-specific clipboard API
+                BlockMessage::Copy(scope) => {
+                    if let Some(text) = block.copy_text(scope) {
+                        self.copy_to_clipboard(&text);
+                    }
+                    Command::none()
+                }
+                BlockMessage::SelectOutputLine(line) => {
+                    block.select_output_line(line);
                     Command::none()
                 }
+                BlockMessage::AnswerClipboardQuery(allow) => {
+                    match block.clipboard_query.take() {
+                        Some(selection) if allow => self.answer_clipboard_query(block_id, selection),
+                        _ => Command::none(),
+                    }
+                }
                 BlockMessage::Export => {
                     // Mock implementation for export functionality
                     let export_content = match &block.content {
-                        BlockContent::Command { input, output, .. } => {
-                            format!("Command: {}\nOutput:\n{}", input, output.iter().map(|(s, _)| s.clone()).join("\n"))
+                        BlockContent::Command { input, output, stats, .. } => {
+                            let stats_text = stats.as_ref().map(|s| format!("\n{}", s.report())).unwrap_or_default();
+                            format!("Command: {}{}\nOutput:\n{}", input, stats_text, output.text())
                         },
                         BlockContent::AgentMessage { content, .. } => content.clone(),
                         BlockContent::Info { message, .. } => message.clone(),
@@ -1170,6 +2052,7 @@ impl NeoTerm {
                         BlockContent::WorkflowSuggestion { workflow } => format!("{:#?}", workflow),
                         BlockContent::AgentPrompt { message, .. } => message.clone(),
                         BlockContent::StreamingToolCall { name, arguments, .. } => format!("Tool Call: {}\nArguments: {}", name, arguments),
+                        BlockContent::Playback { path, .. } => path.clone(),
                     };
                     log::info!("Mock Export: Exported content (not actually implemented):\n{}", export_content);
                     // In a real app, you'd open a save dialog or write to a file
@@ -1179,23 +2062,113 @@ impl NeoTerm {
                     block.toggle_collapse();
                     Command::none()
                 }
-                BlockMessage::SendToAI => {
-                    let block_to_send = block.clone();
-                    let user_prompt_for_ai = "Please analyze the provided context."; // A generic prompt
-                    
-                    // Add a user message block to the UI to indicate AI interaction
-                    let user_block = Block::new_user_message(format!("AI: Analyze block #{}", &block_to_send.id[0..8]));
-                    self.blocks.push(user_block);
-
+                BlockMessage::ShowEarlierOutput => {
+                    block.show_earlier_output();
+                    Command::none()
+                }
+                BlockMessage::ShowLatestOutput => {
+                    block.show_latest_output();
+                    Command::none()
+                }
+                BlockMessage::SendSignal(_) | BlockMessage::ShowProcesses | BlockMessage::ProcessesLoaded(_)
+                | BlockMessage::Foreground | BlockMessage::Background => {
+                    self.handle_job_action(block_id, action)
+                }
+                BlockMessage::PlaybackToggle | BlockMessage::PlaybackSeek(_) | BlockMessage::PlaybackSpeed(_) => {
+                    if let Some(player) = block.player() {
+                        let mut player = player.lock().unwrap();
+                        match action {
+                            BlockMessage::PlaybackToggle => player.toggle(std::time::Instant::now()),
+                            BlockMessage::PlaybackSeek(position) => player.seek(position),
+                            BlockMessage::PlaybackSpeed(speed) => player.set_speed(speed),
+                            _ => {}
+                        }
+                        block.status = Some(if player.is_playing() { "Playing" } else { "Paused" }.to_string());
+                    }
+                    Command::none()
+                }
+                BlockMessage::ToggleFilter => {
+                    block.toggle_filter(self.preferences.output_filters.names());
+                    Command::none()
+                }
+                BlockMessage::FilterChanged(filter) => {
+                    block.set_filter(filter);
+                    Command::none()
+                }
+                BlockMessage::FilterSaveNameChanged(name) => {
+                    if let Some(filter) = &mut block.filter {
+                        filter.save_name = name;
+                    }
+                    Command::none()
+                }
+                BlockMessage::ApplySavedFilter(name) => {
+                    if let Some(filter) = self.preferences.output_filters.saved.get(&name) {
+                        block.set_filter(filter.clone());
+                    }
+                    Command::none()
+                }
+                BlockMessage::OpenLink(LinkTarget::Url(url)) => {
+                    if let Err(e) = links::open_url(&url) {
+                        error!("{}", e);
+                    }
+                    Command::none()
+                }
+                BlockMessage::OpenLink(LinkTarget::File { path, line, column }) => {
+                    // Blocks don't pass keys on to the programs they run, so files open in a GUI editor.
+                    let path = links::resolve_path(&path, block.working_directory());
+                    let opened = links::gui_editor(self.preferences.links.editor.as_deref(), false)
+                        .and_then(|editor| links::spawn_editor(&links::editor_command(&editor, &path, line, column)));
+                    if let Err(e) = opened {
+                        self.workspace.focused_pane_mut().blocks.push(Block::new_error(e.to_string()));
+                    }
+                    Command::none()
+                }
+                BlockMessage::SaveFilter => {
+                    let Some(block_filter) = &block.filter else {
+                        return Command::none();
+                    };
+                    let name = block_filter.save_name.trim().to_string();
+                    if name.is_empty() {
+                        return Command::none();
+                    }
+                    self.preferences.output_filters.saved.insert(name.clone(), block_filter.filter.clone());
+                    let saved_names = self.preferences.output_filters.names();
+                    for pane in self.workspace.panes_mut() {
+                        for block in pane.blocks.iter_mut().filter_map(|block| block.filter.as_mut()) {
+                            block.saved_names = saved_names.clone();
+                        }
+                    }
+                    info!("Saved output filter '{}'", name);
+
+                    let config_manager = self.config_manager.clone();
+                    let preferences = self.preferences.clone();
+                    Command::perform(
+                        async move { config_manager.update_preferences(preferences).await },
+                        |result| match result {
+                            Ok(()) => Message::Tick,
+                            Err(e) => Message::AgentError(format!("Failed to save output filter: {}", e)),
+                        },
+                    )
+                }
+                BlockMessage::SendToAI => {
+                    let block_to_send = block.clone();
+                    let user_prompt_for_ai = "Please analyze the provided context."; // A generic prompt
+                    
+                    // Add a user message block to the UI to indicate AI interaction
+                    let user_block = Block::new_user_message(format!("AI: Analyze block #{}", &block_to_send.id[0..8]));
+                    self.workspace.focused_pane_mut().blocks.push(user_block);
+
                     let agent_mode_arc_clone = self.agent_mode.clone();
+                    let working_directory = self.focused_cwd().map(PathBuf::from);
+                    let profile_env = self.workspace.focused_pane().env.clone();
                     let (tx, rx) = mpsc::channel(100);
                     self.agent_streaming_rx = Some(rx);
 
                     // Send the generic prompt and the specific block as context
                     return Command::perform(
                         async move {
-                            let agent_mode = agent_mode_arc_clone.read().await;
-                            match agent_mode.send_message(user_prompt_for_ai.to_string(), vec![block_to_send]).await {
+                            let stream = agent_mode_arc_clone.read().await.send_message(user_prompt_for_ai.to_string(), vec![block_to_send], working_directory, profile_env).await;
+                            match stream {
                                 Ok(mut stream_rx) => {
                                     while let Some(msg) = stream_rx.recv().await {
                                         if tx.send(msg).await.is_err() {
@@ -1216,7 +2189,7 @@ impl NeoTerm {
                         let original_command = input.clone();
                         let error_output = output.iter()
                             .filter(|(_, is_stdout)| !is_stdout)
-                            .map(|(s, _)| s.clone())
+                            .map(|(s, _)| s)
                             .join("\n");
                         let error_msg = if *error {
                             if error_output.is_empty() {
@@ -1246,8 +2219,8 @@ impl NeoTerm {
                     let agent_mode_arc_clone = self.agent_mode.clone();
                     let (command_input, output_content, error_message) = match &block.content {
                         BlockContent::Command { input, output, error, .. } => {
-                            let full_output = output.iter().map(|(s, _)| s.clone()).join("\n");
-                            let stderr_output = output.iter().filter(|(_, is_stdout)| !is_stdout).map(|(s, _)| s.clone()).join("\n");
+                            let full_output = output.text();
+                            let stderr_output = output.iter().filter(|(_, is_stdout)| !is_stdout).map(|(s, _)| s).join("\n");
                             let err_msg = if *error && !stderr_output.is_empty() {
                                 Some(stderr_output)
                             } else if *error {
@@ -1283,7 +2256,7 @@ impl NeoTerm {
                         let workflow_manager_clone = self.workflow_manager.clone();
                         let block_id_clone = block_id.clone();
 
-                        self.blocks.retain(|b| b.id != block_id_clone);
+                        self.workspace.remove_block(&block_id_clone);
 
                         return Command::perform(
                             async move {
@@ -1305,7 +2278,7 @@ impl NeoTerm {
                     Command::none()
                 }
                 BlockMessage::RejectWorkflow => {
-                    self.blocks.retain(|b| b.id != block_id);
+                    self.workspace.remove_block(&block_id);
                     Command::none()
                 }
                 BlockMessage::AgentPromptInputChanged(new_value) => {
@@ -1319,7 +2292,7 @@ impl NeoTerm {
                         let prompt_id_clone = prompt_id.clone();
                         let response_clone = input_value.clone();
                         
-                        self.blocks.retain(|b| b.id != block_id);
+                        self.workspace.remove_block(&block_id);
 
                         return Command::perform(
                             async move {
@@ -1348,10 +2321,10 @@ impl NeoTerm {
         }
     }
 
-    /// Executes a shell command using the command manager.
+    /// Executes a shell command in the persistent shell session.
     ///
     /// This function creates a new command block, adds it to the UI,
-    /// and then dispatches the command for execution via a PTY session.
+    /// and then runs the command in the shell's current working directory.
     ///
     /// # Arguments
     ///
@@ -1361,130 +2334,533 @@ impl NeoTerm {
     ///
     /// An `iced::Command` to initiate command execution.
     fn execute_command(&mut self, command: String) -> Command<Message> {
-        // Get current working directory
-        let current_dir = std::env::current_dir()
-            .ok()
-            .and_then(|p| p.to_str().map(|s| s.to_string()));
+        let (block_id, run) = self.execute_command_with_wd(command, None);
+        self.record_command(&block_id);
+        run
+    }
+
+    /// Executes a command from the input bar, unless the linter finds something risky in
+    /// it; the user is asked to confirm it first then. A command pasted with risky text the
+    /// user already confirmed runs right away if it wasn't changed since.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command string to execute.
+    ///
+    /// # Returns
+    ///
+    /// An `iced::Command` to initiate command execution, if the command runs right away.
+    fn lint_and_execute(&mut self, command: String) -> Command<Message> {
+        if self.confirmed_paste.take().is_some_and(|confirmed| confirmed == command) {
+            return self.execute_command(command);
+        }
+        let context = LintContext::new(self.focused_cwd().map(PathBuf::from));
+        let findings = Linter::default().check(&command, &context);
+        if findings.is_empty() {
+            return self.execute_command(command);
+        }
+        self.pending_command = Some(PendingCommand { command, findings, agent_prompt_id: None });
+        Command::none()
+    }
+
+    /// Whether keys go to the input bar's vi or Emacs editing, rather than to an open
+    /// search, palette, dialog or the settings.
+    fn input_bar_has_keys(&self) -> bool {
+        !self.settings_open
+            && !self.command_palette.is_open()
+            && !self.search_bar.is_open()
+            && !self.history_search.is_open()
+            && self.pending_paste.is_none()
+            && self.pending_command.is_none()
+    }
+
+    /// Opens the input bar's command in the editor files open in, by default `$VISUAL` or
+    /// `$EDITOR`. The input bar takes the edited command once the editor exits successfully.
+    /// Blocks don't pass keys on to the programs they run, so the editor must be a GUI
+    /// editor; it is started with its wait flag, e.g. `code -w`.
+    fn edit_script_in_editor(&mut self) -> Command<Message> {
+        if self.script_in_editor.is_some() {
+            return Command::none();
+        }
+        let editor = match links::gui_editor(self.preferences.links.editor.as_deref(), true) {
+            Ok(editor) => editor,
+            Err(e) => {
+                self.workspace.focused_pane_mut().blocks.push(Block::new_error(e.to_string()));
+                return Command::none();
+            }
+        };
+        let file = tempfile::Builder::new()
+            .prefix("neoterm-command-")
+            .suffix(".sh")
+            .tempfile()
+            .and_then(|mut file| {
+                use std::io::Write;
+                writeln!(file, "{}", self.input_bar.script())?;
+                file.into_temp_path().keep().map_err(|e| e.error)
+            });
+        let path = match file {
+            Ok(path) => path,
+            Err(e) => {
+                error!("Failed to write the command to a file for the editor: {}", e);
+                return Command::none();
+            }
+        };
+        let command = links::editor_command(&editor, &path, None, None);
+        self.script_in_editor = Some(path);
+        Command::perform(links::run_editor(command), |result| Message::ScriptEdited(result.map_err(|e| e.to_string())))
+    }
+
+    /// Puts the command edited in the editor back into the input bar, unless the editor
+    /// failed, and removes its file.
+    fn finish_editing_script(&mut self, saved: bool) {
+        let Some(path) = self.script_in_editor.take() else {
+            return;
+        };
+        if saved {
+            match std::fs::read_to_string(&path) {
+                Ok(script) => self.input_bar.update(InputMessage::SetText(script.trim_end_matches('\n').to_string())),
+                Err(e) => error!("Failed to read the command edited in {:?}: {}", path, e),
+            }
+        }
+        if let Err(e) = std::fs::remove_file(&path) {
+            log::warn!("Failed to remove {:?}: {}", path, e);
+        }
+    }
+
+    /// Returns the working directory of the focused pane's shell, or where it will start.
+    fn focused_cwd(&self) -> Option<String> {
+        let pane = self.workspace.focused_pane();
+        pane.shell.current_dir().or_else(|| pane.initial_dir.clone())
+    }
+
+    /// Asks the user's shell in the background for completions of the input bar's text.
+    /// Commands and AI queries are completed locally only.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The input bar's text.
+    /// * `cwd` - The directory of the focused pane.
+    ///
+    /// # Returns
+    ///
+    /// An `iced::Command` reporting the completions with `Message::ShellCompletions`.
+    fn complete_from_shell(&self, line: String, cwd: Option<String>) -> Command<Message> {
+        if self.agent_enabled || !input::is_shell_command(&line) || completion::LineContext::parse(&line).is_command_position() {
+            return Command::none();
+        }
+        let shell = self.preferences.terminal.shell.clone();
+        Command::perform(
+            completion::shell_completions(line.clone(), cwd.map(PathBuf::from), shell),
+            move |completions| Message::ShellCompletions(line, completions),
+        )
+    }
+
+    /// Opens the command history and prunes it according to the privacy preferences.
+    /// If the history file can't be read, commands are only kept until the application exits.
+    ///
+    /// # Arguments
+    ///
+    /// * `retention_days` - How long commands are kept. With 0, none are.
+    fn open_history(retention_days: u16) -> HistoryStore {
+        let path = history::default_path();
+        let mut store = HistoryStore::open(&path).unwrap_or_else(|e| {
+            error!("Failed to open command history: {:#}", e);
+            HistoryStore::in_memory()
+        });
+        match store.compact(retention_days, Local::now()) {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {} entries from the command history", removed),
+            Err(e) => error!("Failed to prune command history: {:#}", e),
+        }
+        store
+    }
+
+    /// Adds the command of block `block_id` to the command history, unless the privacy
+    /// preferences keep no history.
+    fn record_command(&mut self, block_id: &str) {
+        if self.preferences.privacy.command_history_retention_days == 0 {
+            return;
+        }
+        let tab_id = self.workspace.active_tab().id.clone();
+        let Some(block) = self.workspace.find_block(block_id) else {
+            return;
+        };
+        let BlockContent::Command { input, working_directory, .. } = &block.content else {
+            return;
+        };
+        let entry = HistoryEntry {
+            session_id: Some(self.session_id.clone()),
+            tab_id: Some(tab_id),
+            ..HistoryEntry::new(block.id.clone(), input.clone(), working_directory.clone())
+        };
+        if let Err(e) = self.history.lock().unwrap().add(entry) {
+            error!("Failed to record command in history: {:#}", e);
+        }
+    }
+
+    /// Imports a shell's history file into the command history and reports the result in
+    /// an info block.
+    fn import_shell_history(&mut self, shell: ShellHistory) {
+        if self.preferences.privacy.command_history_retention_days == 0 {
+            self.workspace.focused_pane_mut().blocks.push(Block::new_error(
+                "The command history is turned off: its retention is 0 days in the privacy preferences.".to_string(),
+            ));
+            return;
+        }
+        let Some(path) = shell.default_path() else {
+            self.workspace.focused_pane_mut().blocks.push(Block::new_error(format!("No {} history file was found.", shell.name())));
+            return;
+        };
+        let result = self.history.lock().unwrap().import(shell, &path);
+        let block = match result {
+            Ok(count) => Block::new_info(
+                "History imported".to_string(),
+                format!("Imported {} commands from {}.", count, path.display()),
+            ),
+            Err(e) => Block::new_error(format!("Failed to import {} history: {:#}", shell.name(), e)),
+        };
+        self.workspace.focused_pane_mut().blocks.push(block);
+    }
+
+    /// Puts text copied from a block on the clipboard, with ANSI escape sequences
+    /// stripped unless the clipboard preferences keep them.
+    fn copy_to_clipboard(&self, text: &str) {
+        let text = if self.preferences.clipboard.copy_ansi { text.to_string() } else { clipboard::strip_ansi(text) };
+        match clipboard::set_text(&text) {
+            Ok(()) => info!("Copied {} bytes to the clipboard", text.len()),
+            Err(e) => error!("{}", e),
+        }
+    }
+
+    /// Copies the output lines selected in the focused pane, most recent block first.
+    fn copy_selection(&self) {
+        let text = self.workspace.focused_pane().blocks.iter().rev()
+            .find(|block| block.selection.is_some())
+            .and_then(|block| block.copy_text(CopyScope::Selection));
+        if let Some(text) = text {
+            self.copy_to_clipboard(&text);
+        }
+    }
+
+    /// Pastes the text on the clipboard, see `paste`.
+    fn paste_from_clipboard(&mut self) -> Command<Message> {
+        match clipboard::get_text() {
+            Ok(text) => self.paste(text),
+            Err(e) => {
+                error!("{}", e);
+                Command::none()
+            }
+        }
+    }
+
+    /// Pastes `text` into the program running in the focused pane, or into the input bar
+    /// when no command is running. Text with risky commands, or with several lines that
+    /// would run one by one, is shown for confirmation first.
+    fn paste(&mut self, text: String) -> Command<Message> {
+        let pane = self.workspace.focused_pane();
+        let block_id = pane.blocks.iter().rev().find(|block| block.is_running()).map(|block| block.id.clone());
+        let bracketed = block_id.is_some() && pane.shell.bracketed_paste();
+        let warnings = PasteChecker::new(&self.preferences.paste.warning_rules).check(&text);
+        let confirm_lines = self.preferences.paste.confirm_multiline && !bracketed && paste::is_multiline(&text);
+
+        let pending = PendingPaste { text, block_id, warnings };
+        if pending.warnings.is_empty() && !confirm_lines {
+            return self.finish_paste(pending);
+        }
+        self.pending_paste = Some(pending);
+        Command::none()
+    }
+
+    /// Sends confirmed pasted text to where it was pasted.
+    fn finish_paste(&mut self, pending: PendingPaste) -> Command<Message> {
+        let Some(block_id) = pending.block_id else {
+            let value = format!("{}{}", self.input_bar.value(), pending.text);
+            self.input_bar.update(InputMessage::InputChanged(value));
+            return Command::none();
+        };
+        let Some(shell) = self.workspace.pane_of_block(&block_id).map(|pane| pane.shell.clone()) else {
+            return Command::none();
+        };
+        Command::perform(
+            async move { shell.paste(&pending.text).await },
+            move |result| {
+                if let Err(e) = result {
+                    error!("Failed to paste into block {}: {}", block_id, e);
+                }
+                Message::Tick
+            }
+        )
+    }
+
+    /// Renders the confirmation for pasted text, with a preview and the risky commands found in it.
+    fn paste_confirmation(&self, pending: &PendingPaste) -> Element<Message> {
+        const PREVIEW_LINES: usize = 8;
+        let line_count = pending.text.lines().count();
+        let target = if pending.block_id.is_some() { "the running command" } else { "the input bar" };
+        let mut dialog = column![
+            text(format!("Paste {} line{} into {}?", line_count, if line_count == 1 { "" } else { "s" }, target)).size(14),
+        ].spacing(5);
+        for warning in &pending.warnings {
+            dialog = dialog.push(
+                text(format!("⚠ {}: {}", warning.message, warning.command)).size(14).color(iced::Color::from_rgb(1.0, 0.4, 0.4))
+            );
+        }
+        let mut preview = pending.text.lines().take(PREVIEW_LINES).collect::<Vec<_>>().join("\n");
+        if line_count > PREVIEW_LINES {
+            preview.push_str(&format!("\n⋯ {} more lines", line_count - PREVIEW_LINES));
+        }
+        dialog
+            .push(container(text(preview).size(12).font(iced::Font::MONOSPACE)).padding(5))
+            .push(
+                row![
+                    button(text("Paste")).on_press(Message::ConfirmPaste(true)),
+                    button(text("Cancel")).on_press(Message::ConfirmPaste(false)),
+                ].spacing(8)
+            )
+            .into()
+    }
+
+    /// Renders the confirmation for a risky command, with what the linter found in it.
+    fn command_confirmation(&self, pending: &PendingCommand) -> Element<Message> {
+        let asker = if pending.agent_prompt_id.is_some() { "The agent wants to run" } else { "Run" };
+        let mut dialog = column![
+            text(format!("{} this command?", asker)).size(14),
+            container(text(&pending.command).size(12).font(iced::Font::MONOSPACE)).padding(5),
+        ].spacing(5);
+        for finding in &pending.findings {
+            let color = match finding.severity {
+                lint::Severity::Critical => iced::Color::from_rgb(1.0, 0.4, 0.4),
+                lint::Severity::Warning => iced::Color::from_rgb(1.0, 0.7, 0.3),
+            };
+            dialog = dialog.push(
+                text(format!("⚠ {}: {} ({})", finding.severity, finding.message, finding.snippet)).size(14).color(color)
+            );
+        }
+        dialog
+            .push(
+                row![
+                    button(text("Run anyway")).on_press(Message::ConfirmCommand(true)),
+                    button(text("Abort")).on_press(Message::ConfirmCommand(false)),
+                ].spacing(8)
+            )
+            .into()
+    }
+
+    /// Handles a command's OSC 52 request to set or read the clipboard, as allowed by the
+    /// clipboard preferences.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_id` - The ID of the command block whose program sent the request.
+    /// * `request` - The OSC 52 request.
+    ///
+    /// # Returns
+    ///
+    /// An `iced::Command` that sends the clipboard's contents to the program, if a read is allowed.
+    fn handle_clipboard_request(&mut self, block_id: String, request: Osc52) -> Command<Message> {
+        match request {
+            Osc52::Set { text, .. } => {
+                if !self.preferences.clipboard.osc52_write {
+                    info!("Ignoring OSC 52 clipboard write from block {}", block_id);
+                } else if let Err(e) = clipboard::set_text(&text) {
+                    error!("{}", e);
+                }
+                Command::none()
+            }
+            Osc52::Query { selection } => match self.preferences.clipboard.osc52_read {
+                Osc52Read::Allow => self.answer_clipboard_query(block_id, selection),
+                Osc52Read::Deny => {
+                    info!("Denied OSC 52 clipboard read from block {}", block_id);
+                    Command::none()
+                }
+                Osc52Read::Ask => {
+                    if let Some(block) = self.workspace.find_block_mut(&block_id) {
+                        block.clipboard_query = Some(selection);
+                    }
+                    Command::none()
+                }
+            },
+        }
+    }
 
-        self.execute_command_with_wd(command, current_dir)
+    /// Sends the clipboard's contents to the program running in a command block, answering
+    /// its OSC 52 query. Nothing is sent once the command has finished, as the reply would
+    /// end up at the shell prompt.
+    fn answer_clipboard_query(&mut self, block_id: String, selection: String) -> Command<Message> {
+        if !self.workspace.find_block_mut(&block_id).is_some_and(|block| block.is_running()) {
+            return Command::none();
+        }
+        let Some(shell) = self.workspace.pane_of_block(&block_id).map(|pane| pane.shell.clone()) else {
+            return Command::none();
+        };
+        let text = clipboard::get_text().unwrap_or_else(|e| {
+            error!("{}", e);
+            String::new()
+        });
+        let reply = Osc52::reply(&selection, &text);
+        Command::perform(
+            async move { shell.send_response(&reply).await },
+            move |result| {
+                if let Err(e) = result {
+                    error!("Failed to answer the clipboard query of block {}: {}", block_id, e);
+                }
+                Message::Tick
+            }
+        )
+    }
+
+    /// Handles job control actions of a command block. Commands run in the pane's
+    /// shell, so signals go to the shell's foreground job.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_id` - The ID of the command block.
+    /// * `action` - One of the job control `BlockMessage`s.
+    ///
+    /// # Returns
+    ///
+    /// An `iced::Command` that reads the process tree or resumes the job, if needed.
+    fn handle_job_action(&mut self, block_id: String, action: BlockMessage) -> Command<Message> {
+        let Some(shell) = self.workspace.pane_of_block(&block_id).map(|pane| pane.shell.clone()) else {
+            return Command::none();
+        };
+        match action {
+            BlockMessage::SendSignal(signal) => {
+                if let Err(e) = shell.signal_foreground(signal) {
+                    error!("Failed to send {} to block {}: {}", signal.name(), block_id, e);
+                    if let Some(block) = self.workspace.find_block_mut(&block_id) {
+                        block.add_output_line(format!("Failed to send {}: {}", signal.name(), e), false);
+                    }
+                }
+                Command::none()
+            }
+            BlockMessage::ShowProcesses => {
+                if let Some(block) = self.workspace.find_block_mut(&block_id) {
+                    if block.process_tree.take().is_some() {
+                        return Command::none();
+                    }
+                }
+                Command::perform(
+                    async move {
+                        match tokio::task::spawn_blocking(move || shell.process_tree()).await {
+                            Ok(Ok(tree)) => tree.render(),
+                            Ok(Err(e)) => format!("Failed to list processes: {}", e),
+                            Err(e) => format!("Failed to list processes: {}", e),
+                        }
+                    },
+                    move |tree| Message::BlockAction(block_id, BlockMessage::ProcessesLoaded(tree))
+                )
+            }
+            BlockMessage::ProcessesLoaded(tree) => {
+                if let Some(block) = self.workspace.find_block_mut(&block_id) {
+                    block.process_tree = Some(tree);
+                }
+                Command::none()
+            }
+            BlockMessage::Foreground | BlockMessage::Background => {
+                let Some(block) = self.workspace.find_block_mut(&block_id).filter(|block| block.is_stopped()) else {
+                    return Command::none();
+                };
+                let BlockContent::Command { start_time, .. } = block.content else {
+                    return Command::none();
+                };
+                // zsh's stop notice has no job number; the shell's current job is then the
+                // one stopped last.
+                let job = block.stopped_job().map_or_else(|| "%+".to_string(), |number| format!("%{}", number));
+                let in_foreground = matches!(action, BlockMessage::Foreground);
+                block.resume(in_foreground);
+                let pty_tx = self.pty_tx.clone();
+                // The job belongs to the shell of the block's pane, so it is resumed there.
+                Command::perform(
+                    async move {
+                        if in_foreground {
+                            let result = shell.run_command(&format!("fg {}", job), None).await;
+                            forward_shell_events(result, block_id, start_time, &pty_tx).await;
+                            return Message::Tick;
+                        }
+                        // A job in the background writes to the shell's terminal; its output
+                        // and exit are not followed.
+                        match shell.run_command(&format!("bg {}", job), None).await {
+                            Ok(mut events) => while events.recv().await.is_some() {},
+                            Err(e) => {
+                                let _ = pty_tx.send(PtyMessage::Failed {
+                                    block_id,
+                                    error: format!("Failed to resume {} in the background: {}", job, e),
+                                    duration: Local::now().signed_duration_since(start_time),
+                                }).await;
+                            }
+                        }
+                        Message::Tick
+                    },
+                    |msg| msg
+                )
+            }
+            _ => Command::none(),
+        }
     }
 
     /// Executes a shell command with a specified working directory.
     ///
+    /// Commands run inside one long-lived shell, so `cd`, `export` and shell functions
+    /// carry over from one block to the next. The shell is spawned on first use.
+    ///
     /// # Arguments
     ///
     /// * `command` - The command string to execute.
-    /// * `working_directory` - The working directory for the command.
+    /// * `working_directory` - If set, the shell changes to this directory before running the command.
     ///
     /// # Returns
     ///
-    /// An `iced::Command` to initiate command execution.
-    fn execute_command_with_wd(&mut self, command: String, working_directory: Option<String>) -> Command<Message> {
-        let command_block = Block::new_command(command.clone(), working_directory.clone());
+    /// The id of the command's new block, and an `iced::Command` to initiate command execution.
+    fn execute_command_with_wd(&mut self, command: String, working_directory: Option<String>) -> (String, Command<Message>) {
+        let scrollback_lines = self.preferences.terminal.scrollback_lines as usize;
+        let image_memory_limit = self.preferences.terminal.image_memory_limit_mb as usize * 1024 * 1024;
+        let pane = self.workspace.focused_pane_mut();
+        // A shell that isn't running yet starts where the pane was opened or restored.
+        let current_dir = pane.initial_dir.clone().or_else(|| {
+            std::env::current_dir()
+                .ok()
+                .and_then(|p| p.to_str().map(|s| s.to_string()))
+        });
+        let display_dir = working_directory.clone()
+            .or_else(|| pane.shell.current_dir())
+            .or_else(|| current_dir.clone());
+        let mut command_block = Block::new_command(command.clone(), display_dir);
+        command_block.set_scrollback_limit(scrollback_lines);
+        command_block.set_image_memory_limit(image_memory_limit);
+        command_block.set_profile(pane.profile.clone());
         let block_id = command_block.id.clone();
-        self.blocks.push(command_block);
-        
-        let env_vars = self.config.env_profiles.active_profile
-            .as_ref()
-            .and_then(|name| self.config.env_profiles.profiles.get(name))
-            .map(|profile| profile.variables.clone())
-            .unwrap_or_default(); // Provide a default empty HashMap
+        let new_block_id = block_id.clone();
+        pane.blocks.push(command_block);
+
+        let env_vars = pane.env.clone();
+        let shell_manager_clone = pane.shell.clone();
 
         let pty_tx = self.pty_tx.clone();
-        let command_manager_clone = self.command_manager.clone();
+        let shell_path = self.preferences.terminal.shell.clone();
 
-        Command::perform(
+        let run = Command::perform(
             async move {
-                let parts: Vec<&str> = command.split_whitespace().collect();
-                if parts.is_empty() {
+                if command.trim().is_empty() {
                     return Message::PtyOutput(PtyMessage::Failed {
                         block_id: block_id.clone(),
                         error: "No command provided.".to_string(),
                         duration: Duration::zero(),
                     });
                 }
-                let cmd_executable = parts[0].to_string();
-                let cmd_args = parts[1..].iter().map(|s| s.to_string()).collect();
-
-                let cmd_obj = command::Command {
-                    id: block_id.clone(),
-                    name: cmd_executable.clone(),
-                    description: format!("Executed: {}", command),
-                    executable: cmd_executable,
-                    args: cmd_args,
-                    env: env_vars,
-                    working_dir: working_directory.map(PathBuf::from), // Use the provided working directory
-                    output_format: command::CommandOutputFormat::PlainText,
-                };
 
                 let start_time = Local::now();
 
-                match command_manager_clone.execute_command_with_output_channel(cmd_obj, pty_tx.clone()).await {
-                    Ok(mut output_receiver) => {
-                        while let Some(output) = output_receiver.recv().await {
-                            match output.status {
-                                CommandStatus::Running => {
-                                    if !output.stdout.is_empty() {
-                                        let _ = pty_tx.send(PtyMessage::OutputChunk {
-                                            block_id: block_id.clone(),
-                                            content: output.stdout,
-                                            is_stdout: true,
-                                        }).await;
-                                    }
-                                    if !output.stderr.is_empty() {
-                                        let _ = pty_tx.send(PtyMessage::OutputChunk {
-                                            block_id: block_id.clone(),
-                                            content: output.stderr,
-                                            is_stdout: false, // Corrected from is_stderr: false
-                                        }).await;
-                                    }
-                                }
-                                CommandStatus::Completed(exit_code) => {
-                                    let end_time = Local::now();
-                                    let duration = end_time.signed_duration_since(start_time);
-                                    let _ = pty_tx.send(PtyMessage::Completed {
-                                        block_id: block_id.clone(),
-                                        exit_code,
-                                        duration,
-                                    }).await;
-                                    break;
-                                }
-                                CommandStatus::Failed(error) => {
-                                    let end_time = Local::now();
-                                    let duration = end_time.signed_duration_since(start_time);
-                                    let _ = pty_tx.send(PtyMessage::Failed {
-                                        block_id: block_id.clone(),
-                                        error,
-                                        duration,
-                                    }).await;
-                                    break;
-                                }
-                                CommandStatus::Killed => {
-                                    let end_time = Local::now();
-                                    let duration = end_time.signed_duration_since(start_time);
-                                    let _ = pty_tx.send(PtyMessage::Killed {
-                                        block_id: block_id.clone(),
-                                        duration,
-                                    }).await;
-                                    break;
-                                }
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        let end_time = Local::now();
-                        let duration = end_time.signed_duration_since(start_time);
-                        let _ = pty_tx.send(PtyMessage::Failed {
-                            block_id: block_id.clone(),
-                            error: format!("Failed to execute command: {}", e),
-                            duration,
-                        }).await;
-                    }
-                }
+                let result = match shell_manager_clone.ensure_shell(&shell_path, current_dir.as_deref(), &env_vars).await {
+                    Ok(()) => shell_manager_clone.run_command(&command, working_directory.as_deref()).await,
+                    Err(e) => Err(e),
+                };
+                forward_shell_events(result, block_id, start_time, &pty_tx).await;
                 Message::Tick // Dummy message to trigger UI update after command finishes
             },
             |msg| msg
-        )
+        );
+        (new_block_id, run)
     }
 
     /// Adds initial sample blocks to the UI for demonstration purposes.
@@ -1511,12 +2887,14 @@ impl NeoTerm {
                     ("warning: 1 warning emitted".to_string(), true),
                     ("".to_string(), true),
                     ("Finished dev [unoptimized + debuginfo] target(s) in 7.27s".to_string(), true),
-                ],
+                ].into(),
                 status: "Completed with exit code: 0".to_string(),
                 error: false,
                 start_time: Local::now() - Duration::milliseconds(7270),
                 end_time: Some(Local::now()),
                 working_directory: Some("~/User/zachlloyd/Projects/warp".to_string()),
+                stats: None,
+                profile: None,
             },
             Color::from_rgb(0.0, 0.2, 0.25), // Teal-like background
         );
@@ -1550,10 +2928,10 @@ impl NeoTerm {
         docker_build_block.add_output_line("Sending build context to Docker daemon 296.4kB".to_string(), true);
         docker_build_block.set_status("Running...".to_string()); // Still running
 
-        self.blocks.push(welcome_block);
-        self.blocks.push(cargo_check_block);
-        self.blocks.push(git_push_block);
-        self.blocks.push(docker_build_block);
+        self.workspace.focused_pane_mut().blocks.push(welcome_block);
+        self.workspace.focused_pane_mut().blocks.push(cargo_check_block);
+        self.workspace.focused_pane_mut().blocks.push(git_push_block);
+        self.workspace.focused_pane_mut().blocks.push(docker_build_block);
     }
 
     /// Creates a subscription for PTY manager events.
@@ -1600,6 +2978,10 @@ impl PtyMessage {
     fn get_block_id(&self) -> &str {
         match self {
             PtyMessage::OutputChunk { block_id, .. } => block_id,
+            PtyMessage::Image { block_id, .. } => block_id,
+            PtyMessage::Clipboard { block_id, .. } => block_id,
+            PtyMessage::Screen { block_id, .. } => block_id,
+            PtyMessage::Redrawn { block_id, .. } => block_id,
             PtyMessage::Completed { block_id, .. } => block_id,
             PtyMessage::Failed { block_id, .. } => block_id,
             PtyMessage::Killed { block_id, .. } => block_id,
@@ -1607,6 +2989,129 @@ impl PtyMessage {
     }
 }
 
+/// Forwards the events of a command running in a pane's shell to its block as
+/// `PtyMessage`s, ending with its completion or failure.
+///
+/// # Arguments
+///
+/// * `result` - The command's events, as returned by `ShellManager::run_command`.
+/// * `block_id` - The block the output and final status are added to.
+/// * `start_time` - When the block's command started, used for its duration.
+/// * `pty_tx` - The channel the messages are sent on.
+async fn forward_shell_events(
+    result: Result<mpsc::Receiver<ShellCommandEvent>>,
+    block_id: String,
+    start_time: DateTime<Local>,
+    pty_tx: &mpsc::Sender<PtyMessage>,
+) {
+    match result {
+        Ok(mut event_receiver) => {
+            while let Some(event) = event_receiver.recv().await {
+                match event {
+                    ShellCommandEvent::Output { line, hyperlinks, is_stderr } => {
+                        let _ = pty_tx.send(PtyMessage::OutputChunk {
+                            block_id: block_id.clone(),
+                            content: line,
+                            is_stdout: !is_stderr,
+                            hyperlinks,
+                        }).await;
+                    }
+                    ShellCommandEvent::Image(image) => {
+                        let _ = pty_tx.send(PtyMessage::Image {
+                            block_id: block_id.clone(),
+                            image,
+                        }).await;
+                    }
+                    ShellCommandEvent::Clipboard(request) => {
+                        let _ = pty_tx.send(PtyMessage::Clipboard {
+                            block_id: block_id.clone(),
+                            request,
+                        }).await;
+                    }
+                    ShellCommandEvent::Screen { view, replaces } => {
+                        let _ = pty_tx.send(PtyMessage::Screen {
+                            block_id: block_id.clone(),
+                            view,
+                            replaces,
+                        }).await;
+                    }
+                    ShellCommandEvent::Redrawn { lines, replaces } => {
+                        let _ = pty_tx.send(PtyMessage::Redrawn {
+                            block_id: block_id.clone(),
+                            lines,
+                            replaces,
+                        }).await;
+                    }
+                    ShellCommandEvent::Finished { exit_code, stats, .. } => {
+                        let end_time = Local::now();
+                        let duration = end_time.signed_duration_since(start_time);
+                        let _ = pty_tx.send(PtyMessage::Completed {
+                            block_id: block_id.clone(),
+                            exit_code,
+                            duration,
+                            stats: Some(stats),
+                        }).await;
+                        break;
+                    }
+                }
+            }
+        },
+        Err(e) => {
+            let end_time = Local::now();
+            let duration = end_time.signed_duration_since(start_time);
+            let _ = pty_tx.send(PtyMessage::Failed {
+                block_id,
+                error: format!("Failed to execute command: {}", e),
+                duration,
+            }).await;
+        }
+    }
+}
+
+/// Returns the id of the scrollable holding the blocks of `pane`.
+fn pane_scroll_id(pane: pane_grid::Pane) -> scrollable::Id {
+    scrollable::Id::new(format!("pane-blocks-{:?}", pane))
+}
+
+/// Options the GUI is started with from the command line.
+#[derive(Debug, Default)]
+pub struct LaunchOptions {
+    /// Record the first tab to asciicast files in this directory.
+    record_dir: Option<PathBuf>,
+}
+
+/// Returns the settings the GUI is run with.
+///
+/// Closing the window is handled by the application (see `Message::WindowCloseRequested`)
+/// so the session can be saved before exiting.
+fn app_settings(flags: LaunchOptions) -> Settings<LaunchOptions> {
+    Settings {
+        window: iced::window::Settings {
+            exit_on_close_request: false,
+            ..Default::default()
+        },
+        ..Settings::with_flags(flags)
+    }
+}
+
+/// Replays the recording at `path` to stdout with its original timing, scaled by `speed`.
+async fn play_recording(path: &std::path::Path, speed: f64) -> Result<()> {
+    use std::io::Write;
+
+    let cast = asciicast::Cast::load(path)?;
+    let speed = speed.clamp(0.1, 16.0);
+    let mut stdout = std::io::stdout();
+    let mut elapsed = 0.0;
+    for event in cast.events.iter().filter(|event| event.kind == asciicast::EventKind::Output) {
+        let delay = (event.time - elapsed).max(0.0) / speed;
+        elapsed = event.time;
+        tokio::time::sleep(std::time::Duration::from_secs_f64(delay)).await;
+        stdout.write_all(event.data.as_bytes())?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
 /// The main entry point for the NeoTerm application.
 ///
 /// This function initializes logging, Sentry for error reporting,
@@ -1636,20 +3141,36 @@ async fn main() -> Result<()> {
 
     // Initialize core modules
     config::init();
+    asciicast::init();
     asset_macro::init();
     block::init();
     cli::init();
+    clipboard::init();
     command::pty::init();
+    command_highlight::init();
+    command_info::init();
+    completion::init();
     fuzzy_match::init();
     graphql::init();
+    history::init();
     input::init();
     languages::init();
+    line_editor::init();
+    links::init();
+    lint::init();
     lpc::init();
     markdown_parser::init();
+    multiline::init();
+    output_filter::init();
+    paste::init();
+    shell_syntax::init();
     string_offset::init();
     sum_tree::init();
     syntax_tree::init();
+    terminal::init();
     ui::init();
+    ui::search_bar::init();
+    ui::history_search::init();
     virtual_fs::init();
     watcher::init();
     websocket::init();
@@ -1663,24 +3184,34 @@ async fn main() -> Result<()> {
     plugins::plugin_manager::init();
     plugins::wasm_runtime::init();
     resources::init();
+    scrollback::init();
+    search::init();
     serve_wasm::init();
+    session::init();
     settings::init();
     settings::keybinding_editor::init();
     settings::theme_editor::init();
     settings::yaml_theme_ui::init();
     settings::appearance_settings::init();
     shell::init();
+    workspace::init();
     ai::init();
     agent_mode_eval::init(); // Initialize agent_mode_eval
 
     // Run the Iced GUI application
     match cli.command {
-        Some(cli::Commands::Gui { path }) => {
+        Some(cli::Commands::Gui { path, record }) => {
             if let Some(p) = path {
                 log::info!("Starting GUI with initial path: {}", p.display());
                 // TODO: Pass initial path to shell manager or virtual FS
             }
-            NeoTerm::run(Settings::default()).await?;
+            let options = LaunchOptions {
+                record_dir: record.map(|dir| dir.unwrap_or_else(|| config::DATA_DIR.join("recordings"))),
+            };
+            NeoTerm::run(app_settings(options)).await?;
+        }
+        Some(cli::Commands::Play { file, speed }) => {
+            play_recording(&file, speed).await?;
         }
         Some(cli::Commands::Run { command, args }) => {
             log::info!("Running command in headless mode: {} {:?}", command, args);
@@ -1694,9 +3225,10 @@ async fn main() -> Result<()> {
                 description: format!("CLI run: {} {}", command, args.join(" ")),
                 executable: command,
                 args,
-                env: std::collections::HashMap::new(),
+                env: UserPreferences::load().await.map(|prefs| prefs.env_profiles.active_variables()).unwrap_or_default(),
                 working_dir: None,
                 output_format: command::CommandOutputFormat::PlainText,
+                execution_mode: command::ExecutionMode::Auto,
             };
             command_manager.execute_command(cmd).await?;
 
@@ -1705,9 +3237,9 @@ async fn main() -> Result<()> {
                     CommandEvent::Output { data, .. } => {
                         print!("{}", String::from_utf8_lossy(&data));
                     }
-                    CommandEvent::Completed { id, exit_code } => {
+                    CommandEvent::Completed { id, exit_code, stats } => {
                         if id == cmd_id {
-                            log::info!("Headless command completed with exit code {:?}", exit_code);
+                            log::info!("Headless command completed with exit code {:?} ({})", exit_code, stats.summary());
                             break;
                         }
                     }
@@ -2028,7 +3560,7 @@ async fn main() -> Result<()> {
         }
         None => {
             // No subcommand, run GUI by default
-            NeoTerm::run(Settings::default()).await?;
+            NeoTerm::run(app_settings(LaunchOptions::default())).await?;
         }
     }
 
@@ -0,0 +1,394 @@
+//! Multi-line commands.
+//!
+//! The input bar grows to more lines while the shell would wait for more of a command: an
+//! open quote, a `\` at the end of a line, a heredoc without its end line, a compound
+//! command without its closing keyword, or an operator without a command after it.
+//! tree-sitter-bash decides whether the command is incomplete, as its parse then has an
+//! error reaching to the end of the text; a scan of the text tells what it waits for, and
+//! how far the next line is indented.
+
+use log::{error, info};
+use std::fmt;
+use tree_sitter::{Node, Parser};
+
+/// Keywords that end a line after which the next one is indented.
+const OPENERS: &[&str] = &["do", "then", "else", "{", "(", "in"];
+
+/// Keywords that start a line indented one level less than the lines before.
+const CLOSERS: &[&str] = &["done", "fi", "esac", "}", ")", "else", "elif"];
+
+/// What an incomplete command waits for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Continuation {
+    /// The last line ends with `\`.
+    Backslash,
+    /// A quote isn't closed.
+    Quote(char),
+    /// A heredoc has no line with its delimiter yet.
+    Heredoc(String),
+    /// A compound command, subshell or command substitution isn't closed by this.
+    Keyword(&'static str),
+    /// A pipe or list operator has no command after it.
+    Operator(&'static str),
+}
+
+impl fmt::Display for Continuation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Continuation::Backslash => write!(f, "The line continues after `\\`"),
+            Continuation::Quote(quote) => write!(f, "Waiting for the closing {}", quote),
+            Continuation::Heredoc(delimiter) => write!(f, "Heredoc waits for `{}`", delimiter),
+            Continuation::Keyword(keyword) => write!(f, "Waiting for `{}`", keyword),
+            Continuation::Operator(operator) => write!(f, "Waiting for a command after `{}`", operator),
+        }
+    }
+}
+
+/// Tells whether the shell would wait for more of `script`, and for what.
+///
+/// # Arguments
+///
+/// * `script` - The input, possibly of several lines.
+///
+/// # Returns
+///
+/// What the script waits for, or `None` if it is complete. Syntax errors that more
+/// lines can't fix, like a stray `)`, leave the script complete, for the shell to report.
+pub fn continuation(script: &str) -> Option<Continuation> {
+    if script.trim().is_empty() {
+        return None;
+    }
+    let mut parser = Parser::new();
+    if let Err(e) = parser.set_language(&tree_sitter_bash::language()) {
+        error!("Failed to load the bash grammar: {}", e);
+        return None;
+    }
+    let tree = parser.parse(script, None)?;
+    let trimmed = script.trim_end();
+    if !tree.root_node().has_error() || !error_reaches(tree.root_node(), trimmed.len()) {
+        return None;
+    }
+    let scan = Scan::new(script);
+    if let Some(delimiter) = scan.heredoc {
+        return Some(Continuation::Heredoc(delimiter));
+    }
+    if let Some(quote) = scan.quote {
+        return Some(Continuation::Quote(quote));
+    }
+    if trimmed.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1 {
+        return Some(Continuation::Backslash);
+    }
+    if let Some(operator) = ["&&", "||", "|"].into_iter().find(|operator| trimmed.ends_with(operator)) {
+        return Some(Continuation::Operator(operator));
+    }
+    scan.open.last().map(|keyword| Continuation::Keyword(keyword))
+}
+
+/// Whether `node` has an error or missing node that ends at or after `end`.
+fn error_reaches(node: Node, end: usize) -> bool {
+    if (node.is_error() || node.is_missing()) && node.end_byte() >= end {
+        return true;
+    }
+    let mut cursor = node.walk();
+    let reaches = node.children(&mut cursor).any(|child| error_reaches(child, end));
+    reaches
+}
+
+/// Returns the indentation of the line after the last line of `script`: that of the
+/// command the line belongs to, one level more after a line continued with `\` or
+/// ending in a keyword like `do`, and none in a heredoc's text.
+///
+/// # Arguments
+///
+/// * `script` - The lines before the new one.
+/// * `width` - Spaces per level of indentation.
+pub fn indentation(script: &str, width: usize) -> String {
+    if matches!(continuation(script), Some(Continuation::Heredoc(_))) {
+        return String::new();
+    }
+    let lines: Vec<&str> = script.split('\n').collect();
+    // The first line of the command the last line continues.
+    let mut first = lines.len() - 1;
+    while first > 0 && ends_with_backslash(lines[first - 1]) {
+        first -= 1;
+    }
+    let base = leading_whitespace(lines[first]);
+    let last = lines[lines.len() - 1].trim_end();
+    let opens = ends_with_backslash(last)
+        || last.ends_with('|')
+        || last.ends_with("&&")
+        || OPENERS.iter().any(|opener| ends_with_word(last, opener))
+        // A pattern of a `case`.
+        || (last.ends_with(')') && !last.contains('(') && matches!(continuation(script), Some(Continuation::Keyword("esac"))));
+    if first < lines.len() - 1 && ends_with_backslash(last) {
+        // Further lines of a continued command line up with the second.
+        leading_whitespace(lines[lines.len() - 1]).to_string()
+    } else if opens {
+        format!("{}{}", base, " ".repeat(width))
+    } else {
+        base.to_string()
+    }
+}
+
+/// Takes a level of indentation off a line that starts with a closing keyword like
+/// `done`, as it belongs with the line that opened the block.
+///
+/// # Arguments
+///
+/// * `line` - A line as it was typed, indented like the lines before it.
+/// * `width` - Spaces per level of indentation.
+pub fn dedent_closer(line: &str, width: usize) -> String {
+    let indent = leading_whitespace(line);
+    let rest = &line[indent.len()..];
+    let closes = CLOSERS.iter().any(|closer| {
+        rest.strip_prefix(closer).is_some_and(|after| after.is_empty() || after.starts_with(|c: char| c.is_whitespace() || c == ';'))
+    });
+    if closes && indent.len() >= width && indent.chars().all(|c| c == ' ') {
+        line[width..].to_string()
+    } else {
+        line.to_string()
+    }
+}
+
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+fn ends_with_backslash(line: &str) -> bool {
+    line.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1
+}
+
+/// Whether `line` ends with `word` as a word of its own.
+fn ends_with_word(line: &str, word: &str) -> bool {
+    line.strip_suffix(word)
+        .is_some_and(|before| before.is_empty() || before.ends_with(|c: char| c.is_whitespace() || c == ';' || c == '&' || c == '|'))
+}
+
+/// What a lexical scan of a script finds open at its end.
+#[derive(Debug, Default)]
+struct Scan {
+    /// The quote of a string that isn't closed.
+    quote: Option<char>,
+    /// The delimiter of a heredoc that has no end line.
+    heredoc: Option<String>,
+    /// Closing keywords of blocks that aren't closed, innermost last.
+    open: Vec<&'static str>,
+}
+
+impl Scan {
+    fn new(script: &str) -> Self {
+        let chars: Vec<char> = script.chars().collect();
+        let mut scan = Scan::default();
+        // Heredocs whose text starts on the next line, and whether `<<-` strips tabs.
+        let mut heredocs: Vec<(String, bool)> = Vec::new();
+        let mut word = String::new();
+        let mut quoted = false;
+        let mut command_position = true;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let ends_word = c.is_whitespace() || matches!(c, ';' | '&' | '|' | '(' | ')' | '<' | '>');
+            if ends_word && (!word.is_empty() || quoted) {
+                command_position = scan.word(&word, quoted, command_position);
+                word.clear();
+                quoted = false;
+            }
+            match c {
+                '\\' => {
+                    if chars.get(i + 1) != Some(&'\n') {
+                        word.push(chars.get(i + 1).copied().unwrap_or(c));
+                    }
+                    i += 2;
+                    continue;
+                }
+                '\'' | '"' | '`' => {
+                    let escapes = c != '\'' || (word.ends_with('$') && !quoted);
+                    match closing_quote(&chars, i + 1, c, escapes) {
+                        Some(close) => {
+                            quoted = true;
+                            i = close + 1;
+                        }
+                        None => {
+                            scan.quote = Some(c);
+                            return scan;
+                        }
+                    }
+                    continue;
+                }
+                '#' if word.is_empty() && !quoted => {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                    continue;
+                }
+                '$' if chars.get(i + 1) == Some(&'(') => {
+                    scan.open.push(")");
+                    command_position = true;
+                    i += 2;
+                    continue;
+                }
+                '$' if chars.get(i + 1) == Some(&'{') => match chars[i..].iter().position(|c| *c == '}') {
+                    Some(close) => {
+                        word.push('$');
+                        i += close + 1;
+                        continue;
+                    }
+                    None => {
+                        scan.open.push("}");
+                        return scan;
+                    }
+                },
+                '\n' => {
+                    for (delimiter, strip_tabs) in heredocs.drain(..) {
+                        loop {
+                            let start = i + 1;
+                            if start > chars.len() {
+                                scan.heredoc = Some(delimiter);
+                                return scan;
+                            }
+                            let end = chars[start..].iter().position(|c| *c == '\n').map_or(chars.len(), |n| start + n);
+                            let line: String = chars[start..end].iter().collect();
+                            i = end;
+                            let line = if strip_tabs { line.trim_start_matches('\t') } else { line.as_str() };
+                            if line == delimiter {
+                                break;
+                            }
+                        }
+                    }
+                    command_position = true;
+                }
+                ';' | '&' | '|' => command_position = true,
+                '(' => {
+                    scan.open.push(")");
+                    command_position = true;
+                }
+                ')' => {
+                    // Otherwise it ends a pattern of a `case`.
+                    if scan.open.last() == Some(&")") {
+                        scan.open.pop();
+                    }
+                    command_position = true;
+                }
+                '<' if chars.get(i + 1) == Some(&'<') && chars.get(i + 2) != Some(&'<') => {
+                    i += 2;
+                    let strip_tabs = chars.get(i) == Some(&'-');
+                    if strip_tabs {
+                        i += 1;
+                    }
+                    while chars.get(i).is_some_and(|c| *c == ' ' || *c == '\t') {
+                        i += 1;
+                    }
+                    let mut delimiter = String::new();
+                    while let Some(&c) = chars.get(i) {
+                        if c.is_whitespace() || matches!(c, ';' | '&' | '|' | '<' | '>' | '(' | ')') {
+                            break;
+                        }
+                        if !matches!(c, '\'' | '"' | '\\') {
+                            delimiter.push(c);
+                        }
+                        i += 1;
+                    }
+                    if !delimiter.is_empty() {
+                        heredocs.push((delimiter, strip_tabs));
+                    }
+                    command_position = false;
+                    continue;
+                }
+                '<' | '>' => command_position = false,
+                c if c.is_whitespace() => {}
+                c => word.push(c),
+            }
+            i += 1;
+        }
+        if let Some((delimiter, _)) = heredocs.pop() {
+            scan.heredoc = Some(delimiter);
+        }
+        scan
+    }
+
+    /// Opens or closes a block for a keyword in command position.
+    ///
+    /// # Returns
+    ///
+    /// Whether the next word is in command position.
+    fn word(&mut self, word: &str, quoted: bool, command_position: bool) -> bool {
+        if quoted || !command_position {
+            return false;
+        }
+        match word {
+            "if" => self.open.push("fi"),
+            "while" | "until" => self.open.push("done"),
+            "for" | "select" => {
+                self.open.push("done");
+                return false;
+            }
+            "case" => {
+                self.open.push("esac");
+                return false;
+            }
+            "{" => self.open.push("}"),
+            "done" | "fi" | "esac" | "}" => {
+                if self.open.last() == Some(&word) {
+                    self.open.pop();
+                }
+                return false;
+            }
+            "then" | "do" | "else" | "elif" | "!" | "time" => {}
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Returns the index of the quote closing a string whose text starts at `start`.
+fn closing_quote(chars: &[char], start: usize, quote: char, escapes: bool) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        if chars[i] == '\\' && escapes {
+            i += 2;
+            continue;
+        }
+        if chars[i] == quote {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Initializes the multiline module.
+pub fn init() {
+    info!("multiline module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_continuation() {
+        assert_eq!(continuation("echo \"abc"), Some(Continuation::Quote('"')));
+        assert_eq!(continuation("docker run \\"), Some(Continuation::Backslash));
+        assert_eq!(continuation("cat <<EOF\nhello"), Some(Continuation::Heredoc("EOF".to_string())));
+        assert_eq!(continuation("for i in a b; do\n  echo $i"), Some(Continuation::Keyword("done")));
+        assert_eq!(continuation("if true; then\n  echo 'it is'"), Some(Continuation::Keyword("fi")));
+        assert_eq!(continuation("ls |"), Some(Continuation::Operator("|")));
+        assert_eq!(continuation("make &&"), Some(Continuation::Operator("&&")));
+        assert_eq!(continuation("cat <<EOF\nhello\nEOF"), None);
+        assert_eq!(continuation("for i in a b; do echo \"$i\"; done"), None);
+        assert_eq!(continuation("echo )"), None);
+        assert_eq!(continuation("ls -la"), None);
+    }
+
+    #[test]
+    fn test_indentation() {
+        assert_eq!(indentation("for i in a b; do", 4), "    ");
+        assert_eq!(indentation("for i in a b; do\n    echo $i", 4), "    ");
+        assert_eq!(indentation("docker run \\", 4), "    ");
+        assert_eq!(indentation("docker run \\\n    -it \\", 4), "    ");
+        assert_eq!(indentation("docker run \\\n    -it \\\n    image", 4), "");
+        assert_eq!(indentation("  cat <<EOF", 2), "");
+        assert_eq!(dedent_closer("    done", 4), "done");
+        assert_eq!(dedent_closer("    donel", 4), "    donel");
+    }
+}
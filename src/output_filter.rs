@@ -0,0 +1,316 @@
+//! Live, grep-like filtering of a command block's output.
+//!
+//! A filter never touches the block's `Scrollback`; it only decides which lines are shown.
+//! The filtered view is built once over the whole output, spilled lines included, and is
+//! then kept up to date one line at a time while the command streams, so changing or
+//! clearing the filter always starts again from the complete output.
+
+use crate::scrollback::{OutputLine, Scrollback};
+use crate::search::{self, SearchMode};
+use anyhow::Result;
+use log::info;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The filtered view keeps at most this many lines, dropping the oldest ones beyond it.
+pub const MAX_SHOWN_LINES: usize = 10_000;
+
+/// Numbers of context lines offered in the filter bar.
+pub const CONTEXT_CHOICES: [usize; 6] = [0, 1, 2, 3, 5, 10];
+
+/// Whether lines matching the pattern are kept or dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FilterAction {
+    #[default]
+    Include,
+    Exclude,
+}
+
+impl FilterAction {
+    pub const ALL: [FilterAction; 2] = [FilterAction::Include, FilterAction::Exclude];
+}
+
+impl fmt::Display for FilterAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FilterAction::Include => "Include",
+            FilterAction::Exclude => "Exclude",
+        })
+    }
+}
+
+/// The settings of an output filter. Saved filters are stored in the preferences by name.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct OutputFilter {
+    /// Text or regex to match; an empty pattern matches every line.
+    #[serde(default)]
+    pub pattern: String,
+    #[serde(default)]
+    pub action: FilterAction,
+    #[serde(default)]
+    pub mode: SearchMode,
+    /// Lines to show before and after each matching line, like `grep -C`.
+    #[serde(default)]
+    pub context: usize,
+    /// Only stderr lines can match.
+    #[serde(default)]
+    pub only_stderr: bool,
+}
+
+impl OutputFilter {
+    /// Returns true if the filter hides anything.
+    pub fn is_active(&self) -> bool {
+        !self.pattern.is_empty() || self.only_stderr
+    }
+}
+
+/// Decides whether a single line matches a filter.
+#[derive(Debug, Clone)]
+struct LineMatcher {
+    regex: Option<Regex>,
+    action: FilterAction,
+    only_stderr: bool,
+}
+
+impl LineMatcher {
+    fn new(filter: &OutputFilter) -> Result<Self> {
+        let regex = if filter.pattern.is_empty() { None } else { Some(search::compile(&filter.pattern, filter.mode)?) };
+        Ok(Self { regex, action: filter.action, only_stderr: filter.only_stderr })
+    }
+
+    fn matches(&self, line: &str, is_stdout: bool) -> bool {
+        if self.only_stderr && is_stdout {
+            return false;
+        }
+        match &self.regex {
+            None => true,
+            Some(regex) => regex.is_match(line) == (self.action == FilterAction::Include),
+        }
+    }
+}
+
+/// A line of the filtered view.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilteredLine {
+    /// An output line, with its index in the block's output and whether it matched
+    /// or is only shown as context.
+    Line { index: usize, line: OutputLine, matched: bool },
+    /// Marks skipped lines between two groups, like `--` in `grep -C` output.
+    Gap,
+}
+
+/// The lines of a block's output that pass a filter, updated as output arrives.
+#[derive(Debug, Clone)]
+pub struct FilterView {
+    matcher: LineMatcher,
+    context: usize,
+    lines: VecDeque<FilteredLine>,
+    /// The last lines that didn't match, kept as leading context for the next match.
+    before: VecDeque<(usize, OutputLine)>,
+    /// Lines still to show as trailing context of the last match.
+    after: usize,
+    last_shown: Option<usize>,
+    next_index: usize,
+    matched: usize,
+    /// Lines dropped from the front of the view to stay within `MAX_SHOWN_LINES`.
+    trimmed: usize,
+}
+
+impl FilterView {
+    /// Builds the view of `filter` over all of `output`, reading spilled lines back from disk.
+    ///
+    /// # Returns
+    ///
+    /// The view, or an error if the pattern is not a valid regex.
+    pub fn build(filter: &OutputFilter, output: &Scrollback) -> Result<Self> {
+        let mut view = Self {
+            matcher: LineMatcher::new(filter)?,
+            context: filter.context,
+            lines: VecDeque::new(),
+            before: VecDeque::new(),
+            after: 0,
+            last_shown: None,
+            next_index: 0,
+            matched: 0,
+            trimmed: 0,
+        };
+        for (line, is_stdout) in output.iter() {
+            view.push(line, is_stdout);
+        }
+        Ok(view)
+    }
+
+    /// Adds the next line of output to the view.
+    pub fn push(&mut self, line: String, is_stdout: bool) {
+        let index = self.next_index;
+        self.next_index += 1;
+
+        if self.matcher.matches(&line, is_stdout) {
+            self.matched += 1;
+            let leading: Vec<(usize, OutputLine)> = self.before.drain(..).collect();
+            for (before_index, before_line) in leading {
+                self.show(before_index, before_line, false);
+            }
+            self.show(index, (line, is_stdout), true);
+            self.after = self.context;
+        } else if self.after > 0 {
+            self.after -= 1;
+            self.show(index, (line, is_stdout), false);
+        } else if self.context > 0 {
+            self.before.push_back((index, (line, is_stdout)));
+            if self.before.len() > self.context {
+                self.before.pop_front();
+            }
+        }
+    }
+
+    fn show(&mut self, index: usize, line: OutputLine, matched: bool) {
+        if self.last_shown.is_some_and(|last| index > last + 1) {
+            self.lines.push_back(FilteredLine::Gap);
+        }
+        self.lines.push_back(FilteredLine::Line { index, line, matched });
+        self.last_shown = Some(index);
+        while self.lines.len() > MAX_SHOWN_LINES {
+            if let Some(FilteredLine::Line { .. }) = self.lines.pop_front() {
+                self.trimmed += 1;
+            }
+        }
+    }
+
+    /// Returns the lines to show, oldest first.
+    pub fn lines(&self) -> impl Iterator<Item = &FilteredLine> {
+        self.lines.iter()
+    }
+
+    /// Returns the number of lines that matched so far.
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+
+    /// Returns the number of output lines seen so far.
+    pub fn total(&self) -> usize {
+        self.next_index
+    }
+
+    /// Returns the number of shown lines dropped to keep the view bounded.
+    pub fn trimmed(&self) -> usize {
+        self.trimmed
+    }
+}
+
+/// The filter bar of a command block: the settings being edited and the resulting view.
+#[derive(Debug, Clone, Default)]
+pub struct BlockFilter {
+    pub filter: OutputFilter,
+    view: Option<FilterView>,
+    error: Option<String>,
+    /// Name typed in to save the filter under.
+    pub save_name: String,
+    /// Names of the saved filters, offered for reuse.
+    pub saved_names: Vec<String>,
+}
+
+impl BlockFilter {
+    pub fn new(saved_names: Vec<String>) -> Self {
+        Self { saved_names, ..Self::default() }
+    }
+
+    /// Replaces the filter settings and rebuilds the view over `output`.
+    pub fn apply(&mut self, filter: OutputFilter, output: &Scrollback) {
+        self.error = None;
+        self.view = None;
+        if filter.is_active() {
+            match FilterView::build(&filter, output) {
+                Ok(view) => self.view = Some(view),
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+        self.filter = filter;
+    }
+
+    /// Passes a new line of output on to the view.
+    pub fn push(&mut self, line: &str, is_stdout: bool) {
+        if let Some(view) = &mut self.view {
+            view.push(line.to_string(), is_stdout);
+        }
+    }
+
+    /// Returns the filtered view, or none while the filter is empty or invalid.
+    pub fn view(&self) -> Option<&FilterView> {
+        self.view.as_ref()
+    }
+
+    /// Returns why the pattern could not be used.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+/// Initializes the output filter module.
+pub fn init() {
+    info!("output filter module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(lines: &[(&str, bool)]) -> Scrollback {
+        Scrollback::from(lines.iter().map(|(line, is_stdout)| (line.to_string(), *is_stdout)).collect::<Vec<_>>())
+    }
+
+    fn filter(pattern: &str, action: FilterAction, mode: SearchMode, only_stderr: bool) -> OutputFilter {
+        OutputFilter { pattern: pattern.to_string(), action, mode, only_stderr, ..OutputFilter::default() }
+    }
+
+    /// Shows matches as their index, context lines in parentheses and gaps as `--`.
+    fn shown(view: &FilterView) -> Vec<String> {
+        view.lines()
+            .map(|line| match line {
+                FilteredLine::Line { index, matched: true, .. } => index.to_string(),
+                FilteredLine::Line { index, matched: false, .. } => format!("({})", index),
+                FilteredLine::Gap => "--".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_include_exclude_and_stderr() {
+        let output = output(&[("compiling a", true), ("warning: unused", false), ("compiling b", true), ("error: failed", false)]);
+
+        let view = FilterView::build(&filter("COMPILING", FilterAction::Include, SearchMode::IgnoreCase, false), &output).unwrap();
+        assert_eq!(shown(&view), vec!["0", "--", "2"]);
+        let view = FilterView::build(&filter("compiling", FilterAction::Exclude, SearchMode::MatchCase, false), &output).unwrap();
+        assert_eq!(shown(&view), vec!["1", "--", "3"]);
+        let view = FilterView::build(&filter("", FilterAction::Include, SearchMode::IgnoreCase, true), &output).unwrap();
+        assert_eq!(view.matched(), 2);
+        let view = FilterView::build(&filter("^error", FilterAction::Include, SearchMode::Regex, true), &output).unwrap();
+        assert_eq!(shown(&view), vec!["3"]);
+        assert!(FilterView::build(&filter("(", FilterAction::Include, SearchMode::Regex, false), &output).is_err());
+    }
+
+    #[test]
+    fn test_context_updates_live() {
+        let mut block_filter = BlockFilter::new(Vec::new());
+        let mut output = output(&[("a", true), ("b", true), ("hit", true)]);
+        block_filter.apply(OutputFilter { context: 1, ..filter("hit", FilterAction::Include, SearchMode::IgnoreCase, false) }, &output);
+
+        for line in ["c", "d", "e", "hit", "hit", "f", "g"] {
+            output.push(line.to_string(), true);
+            block_filter.push(line, true);
+        }
+        let view = block_filter.view().unwrap();
+        assert_eq!(shown(view), vec!["(1)", "2", "(3)", "--", "(5)", "6", "7", "(8)"]);
+        assert_eq!((view.matched(), view.total()), (3, 10));
+        // The view is built from the output alone, so rebuilding gives the same result.
+        let rebuilt = FilterView::build(&block_filter.filter, &output).unwrap();
+        assert_eq!(shown(&rebuilt), shown(view));
+
+        // Clearing the filter leaves the output untouched.
+        block_filter.apply(OutputFilter::default(), &output);
+        assert!(block_filter.view().is_none());
+        assert_eq!(output.len(), 10);
+    }
+}
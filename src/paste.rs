@@ -0,0 +1,292 @@
+//! Paste safety.
+//!
+//! Pasted text is checked before it can run: text for the input bar is parsed with
+//! tree-sitter-bash and matched against `PasteRule`s, so `rm -rf /` is caught however it
+//! is quoted or wrapped in `sudo`, while a mere mention of it in an `echo` is not. Text
+//! for a running program is sent with bracketed paste when the program enabled it.
+
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tree_sitter::{Node, Parser};
+use crate::shell_syntax::{real_command, text_of, unquote, visit_pipelines};
+
+/// Marks the start of bracketed paste (mode 2004).
+const PASTE_START: &str = "\x1b[200~";
+/// Marks the end of bracketed paste.
+const PASTE_END: &str = "\x1b[201~";
+
+/// A risky command to warn about when it is pasted.
+///
+/// All patterns are regexes matched against whole, unquoted words. A command matches
+/// when its name matches `command`, every pattern in `arguments` matches at least one of
+/// its arguments and, if `piped_to` is set, its output is piped into a matching command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasteRule {
+    pub name: String,
+    /// Shown to the user when the rule matches.
+    pub message: String,
+    pub command: String,
+    #[serde(default)]
+    pub arguments: Vec<String>,
+    #[serde(default)]
+    pub piped_to: Option<String>,
+}
+
+impl PasteRule {
+    fn new(name: &str, message: &str, command: &str, arguments: &[&str], piped_to: Option<&str>) -> Self {
+        Self {
+            name: name.to_string(),
+            message: message.to_string(),
+            command: command.to_string(),
+            arguments: arguments.iter().map(|argument| argument.to_string()).collect(),
+            piped_to: piped_to.map(str::to_string),
+        }
+    }
+}
+
+/// Rules for deleting or opening up the whole file system, overwriting disks and running
+/// scripts straight from the network.
+pub fn default_rules() -> Vec<PasteRule> {
+    vec![
+        PasteRule::new(
+            "rm-root",
+            "Recursively deletes the root or home directory",
+            "^rm$",
+            &["^(-[a-zA-Z]*[rR][a-zA-Z]*|--recursive)$", r"^(/|/\*|~/?|~/\*|\$HOME/?|\$HOME/\*)$"],
+            None,
+        ),
+        PasteRule::new(
+            "pipe-to-shell",
+            "Runs a script downloaded from the network without showing it first",
+            "^(curl|wget)$",
+            &[],
+            Some("^(sh|bash|zsh|dash|ksh|fish|python[0-9.]*|perl|ruby)$"),
+        ),
+        PasteRule::new(
+            "dd-disk",
+            "Overwrites a disk device",
+            "^dd$",
+            &["^of=/dev/(sd|hd|vd|xvd|nvme|mmcblk|disk|rdisk)"],
+            None,
+        ),
+        PasteRule::new(
+            "chmod-root",
+            "Makes every file on the system writable by everyone",
+            "^chmod$",
+            &["^(-[a-zA-Z]*R[a-zA-Z]*|--recursive)$", "^0?777$", "^/$"],
+            None,
+        ),
+    ]
+}
+
+/// A rule that matched pasted text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasteWarning {
+    pub rule: String,
+    pub message: String,
+    /// The command that matched, as pasted.
+    pub command: String,
+}
+
+/// A `PasteRule` with its patterns compiled.
+#[derive(Debug)]
+struct CompiledRule {
+    rule: PasteRule,
+    command: Regex,
+    arguments: Vec<Regex>,
+    piped_to: Option<Regex>,
+}
+
+impl CompiledRule {
+    fn new(rule: &PasteRule) -> Result<Self> {
+        let compile = |pattern: &str| Regex::new(pattern).map_err(|e| anyhow!("invalid pattern '{}': {}", pattern, e));
+        Ok(Self {
+            command: compile(&rule.command)?,
+            arguments: rule.arguments.iter().map(|pattern| compile(pattern)).collect::<Result<_>>()?,
+            piped_to: rule.piped_to.as_deref().map(compile).transpose()?,
+            rule: rule.clone(),
+        })
+    }
+
+    /// Returns true if `command` matches the rule, with `piped_to` being the commands its
+    /// output is piped into.
+    fn matches(&self, command: &SimpleCommand, piped_to: &[SimpleCommand]) -> bool {
+        self.command.is_match(&command.name)
+            && self.arguments.iter().all(|pattern| command.arguments.iter().any(|argument| pattern.is_match(argument)))
+            && self.piped_to.as_ref().map_or(true, |pattern| piped_to.iter().any(|next| pattern.is_match(&next.name)))
+    }
+}
+
+/// A command found in pasted text, with wrappers such as `sudo` removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SimpleCommand {
+    /// The command name without its directory, e.g. `rm` for `/bin/rm`.
+    name: String,
+    arguments: Vec<String>,
+    /// The command as written.
+    text: String,
+}
+
+impl SimpleCommand {
+    /// Reads a tree-sitter `command` node.
+    fn from_node(node: Node, source: &str) -> Option<Self> {
+        let command = real_command(node, source)?;
+        Some(Self {
+            name: command.name,
+            arguments: command.arguments.iter().map(|argument| unquote(text_of(*argument, source))).collect(),
+            text: text_of(node, source).to_string(),
+        })
+    }
+}
+
+/// Checks pasted text against `PasteRule`s.
+#[derive(Debug)]
+pub struct PasteChecker {
+    rules: Vec<CompiledRule>,
+}
+
+impl PasteChecker {
+    /// Compiles `rules`. Rules with an invalid pattern are logged and skipped.
+    pub fn new(rules: &[PasteRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| match CompiledRule::new(rule) {
+                Ok(rule) => Some(rule),
+                Err(e) => {
+                    error!("Ignoring paste warning rule '{}': {}", rule.name, e);
+                    None
+                }
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Returns a warning for each command in `text` that matches a rule.
+    pub fn check(&self, text: &str) -> Vec<PasteWarning> {
+        let mut parser = Parser::new();
+        if let Err(e) = parser.set_language(&tree_sitter_bash::language()) {
+            error!("Failed to load the bash grammar: {}", e);
+            return Vec::new();
+        }
+        let Some(tree) = parser.parse(text, None) else {
+            return Vec::new();
+        };
+
+        let mut warnings = Vec::new();
+        visit_pipelines(tree.root_node(), &mut |commands| {
+            let pipeline: Vec<SimpleCommand> = commands.iter().filter_map(|command| SimpleCommand::from_node(*command, text)).collect();
+            for (i, command) in pipeline.iter().enumerate() {
+                for rule in &self.rules {
+                    if rule.matches(command, &pipeline[i + 1..]) {
+                        warnings.push(PasteWarning {
+                            rule: rule.rule.name.clone(),
+                            message: rule.rule.message.clone(),
+                            command: command.text.clone(),
+                        });
+                    }
+                }
+            }
+        });
+        warnings
+    }
+}
+
+/// Pasted text waiting for the user to confirm it.
+#[derive(Debug, Clone)]
+pub struct PendingPaste {
+    pub text: String,
+    /// The command block whose running program gets the text; the input bar when `None`.
+    pub block_id: Option<String>,
+    pub warnings: Vec<PasteWarning>,
+}
+
+/// Encodes pasted text as input for a program in a terminal.
+///
+/// # Arguments
+///
+/// * `text` - The pasted text.
+/// * `bracketed` - Whether the program enabled bracketed paste (mode 2004). The text is
+///   then wrapped in `ESC [200~` and `ESC [201~`, with any end marker inside it removed
+///   so the paste can't break out early.
+///
+/// # Returns
+///
+/// The bytes to write to the PTY. Line breaks are sent as carriage returns, like the Enter key.
+pub fn encode(text: &str, bracketed: bool) -> Vec<u8> {
+    let text = text.replace("\r\n", "\r").replace('\n', "\r");
+    if bracketed {
+        format!("{}{}{}", PASTE_START, text.replace(PASTE_END, ""), PASTE_END).into_bytes()
+    } else {
+        text.into_bytes()
+    }
+}
+
+/// Returns true if `text` would run more than one line when pasted. A single trailing
+/// line break, as copied along with a line, doesn't count.
+pub fn is_multiline(text: &str) -> bool {
+    text.trim_end_matches(['\r', '\n']).contains(['\r', '\n'])
+}
+
+/// Initializes the paste module.
+pub fn init() {
+    info!("paste module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules_matched(text: &str) -> Vec<String> {
+        PasteChecker::new(&default_rules()).check(text).into_iter().map(|warning| warning.rule).collect()
+    }
+
+    #[test]
+    fn test_dangerous_commands() {
+        assert_eq!(rules_matched("rm -rf /"), vec!["rm-root"]);
+        assert_eq!(rules_matched("sudo rm -r --no-preserve-root \"/\""), vec!["rm-root"]);
+        assert_eq!(rules_matched("cd /tmp && /bin/rm -fR ~"), vec!["rm-root"]);
+        assert_eq!(rules_matched("curl -fsSL https://get.example.sh | sudo bash"), vec!["pipe-to-shell"]);
+        assert_eq!(rules_matched("wget -qO- x.io/i.sh | sh 2>&1"), vec!["pipe-to-shell"]);
+        assert_eq!(rules_matched("sudo dd if=disk.img of=/dev/sdb bs=4M"), vec!["dd-disk"]);
+        assert_eq!(rules_matched("chmod -R 777 /"), vec!["chmod-root"]);
+        assert_eq!(rules_matched("echo start\nfor f in a b; do rm -rf /; done"), vec!["rm-root"]);
+        assert_eq!(rules_matched("echo $(curl -s x.io | sh)"), vec!["pipe-to-shell"]);
+    }
+
+    #[test]
+    fn test_harmless_commands() {
+        assert!(rules_matched("rm -rf ./build /tmp/cache").is_empty());
+        assert!(rules_matched("echo 'rm -rf /'").is_empty());
+        assert!(rules_matched("curl -o install.sh https://x.io/i.sh").is_empty());
+        assert!(rules_matched("curl https://x.io | jq .").is_empty());
+        assert!(rules_matched("dd if=/dev/zero of=disk.img count=1").is_empty());
+        assert!(rules_matched("chmod -R 755 ./public").is_empty());
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        let rule = PasteRule::new("force-push", "Rewrites remote history", "^git$", &["^push$", "^(-f|--force)$"], None);
+        let invalid = PasteRule::new("invalid", "", "(", &[], None);
+        let checker = PasteChecker::new(&[rule, invalid]);
+        let warnings = checker.check("git push --force origin main");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].command, "git push --force origin main");
+        assert!(checker.check("git push origin main").is_empty());
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("ls\nls -l\n", false), b"ls\rls -l\r".to_vec());
+        assert_eq!(encode("a\r\nb", true), b"\x1b[200~a\rb\x1b[201~".to_vec());
+        assert_eq!(encode("x\x1b[201~; rm -rf ~", true), b"\x1b[200~x; rm -rf ~\x1b[201~".to_vec());
+    }
+
+    #[test]
+    fn test_is_multiline() {
+        assert!(!is_multiline("ls -l\n"));
+        assert!(is_multiline("cd /\nls"));
+        assert!(!is_multiline("plain"));
+    }
+}
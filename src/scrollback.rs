@@ -0,0 +1,419 @@
+//! Bounded storage for command output.
+//!
+//! A `Scrollback` keeps the most recent lines of a block in memory. Once more lines arrive
+//! than the configured limit, the oldest ones are written in chunks to gzip-compressed
+//! temporary files under the cache directory. Spilled lines stay addressable by index, so
+//! paging back, searching and exporting work over the whole output.
+
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{error, info, warn};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+use tempfile::NamedTempFile;
+
+/// Number of lines kept in memory when no limit is configured.
+pub const DEFAULT_SCROLLBACK_LINES: usize = 10_000;
+
+/// Spill at least this many lines at a time so small limits don't create a file per line.
+const MIN_CHUNK_LINES: usize = 256;
+
+/// A line of output and whether it came from stdout.
+pub type OutputLine = (String, bool);
+
+/// A run of lines written to a compressed temporary file.
+/// The file is removed once the last `Scrollback` referring to it is dropped.
+#[derive(Debug, Clone)]
+struct SpilledChunk {
+    file: Arc<NamedTempFile>,
+    /// Index of the chunk's first line within the scrollback.
+    first_line: usize,
+    lines: usize,
+}
+
+/// Output lines of a block, bounded in memory with older lines spilled to disk.
+#[derive(Debug, Clone)]
+pub struct Scrollback {
+    limit: usize,
+    recent: VecDeque<OutputLine>,
+    spilled: Vec<SpilledChunk>,
+    spilled_lines: usize,
+    /// Lines that could not be written to disk and were discarded.
+    dropped_lines: usize,
+    /// Lines that came from stderr, including spilled and dropped ones.
+    stderr_lines: usize,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self::new(DEFAULT_SCROLLBACK_LINES)
+    }
+}
+
+impl From<Vec<OutputLine>> for Scrollback {
+    fn from(lines: Vec<OutputLine>) -> Self {
+        let mut scrollback = Scrollback::default();
+        for (line, is_stdout) in lines {
+            scrollback.push(line, is_stdout);
+        }
+        scrollback
+    }
+}
+
+/// Serializes as the list of all lines, including spilled ones.
+impl Serialize for Scrollback {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/// Deserializes a list of lines with the default limit, spilling as needed.
+impl<'de> Deserialize<'de> for Scrollback {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Vec::<OutputLine>::deserialize(deserializer).map(Scrollback::from)
+    }
+}
+
+impl Scrollback {
+    /// Creates an empty scrollback that keeps up to `limit` lines in memory.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            recent: VecDeque::new(),
+            spilled: Vec::new(),
+            spilled_lines: 0,
+            dropped_lines: 0,
+            stderr_lines: 0,
+        }
+    }
+
+    /// Changes the number of lines kept in memory, spilling immediately if needed.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.max(1);
+        self.spill_if_needed();
+    }
+
+    /// Appends a line of output.
+    pub fn push(&mut self, line: String, is_stdout: bool) {
+        if !is_stdout {
+            self.stderr_lines += 1;
+        }
+        self.recent.push_back((line, is_stdout));
+        self.spill_if_needed();
+    }
+
+    /// Removes the lines after the first `len`. Spilled lines are kept.
+    pub fn truncate(&mut self, len: usize) {
+        let keep = len.saturating_sub(self.spilled_lines);
+        while self.recent.len() > keep {
+            if let Some((_, false)) = self.recent.pop_back() {
+                self.stderr_lines -= 1;
+            }
+        }
+    }
+
+    /// Returns the number of stored lines, in memory and on disk.
+    pub fn len(&self) -> usize {
+        self.spilled_lines + self.recent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of lines that live on disk.
+    pub fn spilled_len(&self) -> usize {
+        self.spilled_lines
+    }
+
+    /// Returns the number of lines that came from stderr.
+    pub fn stderr_len(&self) -> usize {
+        self.stderr_lines
+    }
+
+    /// Returns the number of lines lost because they could not be spilled.
+    pub fn dropped_lines(&self) -> usize {
+        self.dropped_lines
+    }
+
+    /// Returns the lines held in memory, which are always the most recent ones.
+    pub fn recent(&self) -> impl Iterator<Item = &OutputLine> {
+        self.recent.iter()
+    }
+
+    /// Iterates over all lines, reading spilled chunks from disk one at a time.
+    pub fn iter(&self) -> Lines<'_> {
+        Lines {
+            scrollback: self,
+            next_chunk: 0,
+            chunk: Vec::new().into_iter(),
+            recent: None,
+        }
+    }
+
+    /// Returns up to `count` lines starting at line `start`.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - Index of the first line, where 0 is the oldest stored line.
+    /// * `count` - Maximum number of lines to return.
+    ///
+    /// # Returns
+    ///
+    /// The lines, or an error if a spilled chunk could not be read back.
+    pub fn lines(&self, start: usize, count: usize) -> Result<Vec<OutputLine>> {
+        let end = start.saturating_add(count).min(self.len());
+        let start = start.min(end);
+        let mut lines = Vec::with_capacity(end - start);
+        for chunk in &self.spilled {
+            let chunk_end = chunk.first_line + chunk.lines;
+            if chunk_end <= start || chunk.first_line >= end {
+                continue;
+            }
+            let from = start.saturating_sub(chunk.first_line);
+            let to = end.min(chunk_end) - chunk.first_line;
+            lines.extend(read_chunk(chunk)?.drain(from..to));
+        }
+        let recent_start = start.max(self.spilled_lines) - self.spilled_lines;
+        let recent_end = end.max(self.spilled_lines) - self.spilled_lines;
+        lines.extend(self.recent.range(recent_start..recent_end).cloned());
+        Ok(lines)
+    }
+
+    /// Returns the last `count` lines.
+    pub fn tail(&self, count: usize) -> Result<Vec<OutputLine>> {
+        self.lines(self.len().saturating_sub(count), count)
+    }
+
+    /// Returns the indices of all lines containing `needle`, including spilled lines.
+    pub fn search(&self, needle: &str) -> Result<Vec<usize>> {
+        let mut matches = Vec::new();
+        for chunk in &self.spilled {
+            for (i, (line, _)) in read_chunk(chunk)?.iter().enumerate() {
+                if line.contains(needle) {
+                    matches.push(chunk.first_line + i);
+                }
+            }
+        }
+        for (i, (line, _)) in self.recent.iter().enumerate() {
+            if line.contains(needle) {
+                matches.push(self.spilled_lines + i);
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Joins all lines with newlines, e.g. for exporting the block.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for (i, (line, _)) in self.iter().enumerate() {
+            if i > 0 {
+                text.push('\n');
+            }
+            text.push_str(&line);
+        }
+        text
+    }
+
+    fn spill_if_needed(&mut self) {
+        if self.recent.len() <= self.limit {
+            return;
+        }
+        let chunk_lines = (self.recent.len() - self.limit).max((self.limit / 4).max(MIN_CHUNK_LINES)).min(self.recent.len());
+        let lines: Vec<OutputLine> = self.recent.drain(..chunk_lines).collect();
+        match write_chunk(&lines) {
+            Ok(file) => {
+                self.spilled.push(SpilledChunk {
+                    file: Arc::new(file),
+                    first_line: self.spilled_lines,
+                    lines: lines.len(),
+                });
+                self.spilled_lines += lines.len();
+            }
+            Err(e) => {
+                // Keeping the lines would defeat the memory bound, so they are discarded.
+                error!("Failed to spill {} scrollback lines to disk: {}", lines.len(), e);
+                self.dropped_lines += lines.len();
+            }
+        }
+    }
+}
+
+/// Iterator over all lines of a `Scrollback`, created by `Scrollback::iter`.
+///
+/// A spilled chunk that can no longer be read yields a single placeholder line
+/// instead of its contents.
+pub struct Lines<'a> {
+    scrollback: &'a Scrollback,
+    next_chunk: usize,
+    chunk: std::vec::IntoIter<OutputLine>,
+    recent: Option<std::collections::vec_deque::Iter<'a, OutputLine>>,
+}
+
+impl Iterator for Lines<'_> {
+    type Item = OutputLine;
+
+    fn next(&mut self) -> Option<OutputLine> {
+        loop {
+            if let Some(line) = self.chunk.next() {
+                return Some(line);
+            }
+            if let Some(recent) = self.recent.as_mut() {
+                return recent.next().cloned();
+            }
+            match self.scrollback.spilled.get(self.next_chunk) {
+                Some(chunk) => {
+                    self.next_chunk += 1;
+                    self.chunk = match read_chunk(chunk) {
+                        Ok(lines) => lines.into_iter(),
+                        Err(e) => {
+                            warn!("Failed to read spilled scrollback: {}", e);
+                            vec![(format!("[{} lines of output could not be read back: {}]", chunk.lines, e), false)].into_iter()
+                        }
+                    };
+                }
+                None => self.recent = Some(self.scrollback.recent.iter()),
+            }
+        }
+    }
+}
+
+/// Writes `lines` to a new compressed temporary file.
+///
+/// Each line is stored as a stream flag byte, a little-endian `u32` length and the UTF-8 text.
+fn write_chunk(lines: &[OutputLine]) -> Result<NamedTempFile> {
+    let dir = crate::config::CACHE_DIR.join("scrollback");
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let file = tempfile::Builder::new()
+        .prefix("scrollback-")
+        .suffix(".gz")
+        .tempfile_in(&dir)
+        .with_context(|| format!("Failed to create a temporary file in {:?}", dir))?;
+
+    let mut encoder = GzEncoder::new(BufWriter::new(file.as_file()), Compression::fast());
+    for (line, is_stdout) in lines {
+        encoder.write_all(&[*is_stdout as u8])?;
+        encoder.write_all(&(line.len() as u32).to_le_bytes())?;
+        encoder.write_all(line.as_bytes())?;
+    }
+    encoder.finish()?.flush()?;
+    Ok(file)
+}
+
+/// Reads back all lines of a spilled chunk.
+fn read_chunk(chunk: &SpilledChunk) -> Result<Vec<OutputLine>> {
+    let file = File::open(chunk.file.path())
+        .with_context(|| format!("Failed to open {:?}", chunk.file.path()))?;
+    let mut decoder = GzDecoder::new(BufReader::new(file));
+    let mut lines = Vec::with_capacity(chunk.lines);
+    for _ in 0..chunk.lines {
+        let mut header = [0u8; 5];
+        decoder.read_exact(&mut header)?;
+        let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        let mut text = vec![0u8; len];
+        decoder.read_exact(&mut text)?;
+        let text = String::from_utf8(text).map_err(|e| anyhow!("Corrupt scrollback chunk: {}", e))?;
+        lines.push((text, header[0] != 0));
+    }
+    Ok(lines)
+}
+
+pub fn init() {
+    info!("scrollback module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(limit: usize, count: usize) -> Scrollback {
+        let mut scrollback = Scrollback::new(limit);
+        for i in 0..count {
+            scrollback.push(format!("line {}", i), i % 3 != 0);
+        }
+        scrollback
+    }
+
+    #[test]
+    fn test_memory_is_bounded() {
+        let scrollback = filled(300, 2000);
+        assert_eq!(scrollback.len(), 2000);
+        assert!(scrollback.recent().count() <= 300);
+        assert_eq!(scrollback.spilled_len() + scrollback.recent().count(), 2000);
+        assert_eq!(scrollback.dropped_lines(), 0);
+        assert_eq!(scrollback.stderr_len(), 667);
+        assert_eq!(scrollback.recent().last().unwrap().0, "line 1999");
+    }
+
+    #[test]
+    fn test_iter_reads_spilled_lines_in_order() {
+        let scrollback = filled(300, 2000);
+        let lines: Vec<OutputLine> = scrollback.iter().collect();
+        assert_eq!(lines.len(), 2000);
+        for (i, (line, is_stdout)) in lines.iter().enumerate() {
+            assert_eq!(line, &format!("line {}", i));
+            assert_eq!(*is_stdout, i % 3 != 0);
+        }
+    }
+
+    #[test]
+    fn test_lines_across_chunk_boundaries() {
+        let scrollback = filled(300, 2000);
+        let page = scrollback.lines(250, 1700).unwrap();
+        assert_eq!(page.len(), 1700);
+        assert_eq!(page[0].0, "line 250");
+        assert_eq!(page[1699].0, "line 1949");
+        let tail = scrollback.tail(3).unwrap();
+        assert_eq!(tail.iter().map(|(l, _)| l.as_str()).collect::<Vec<_>>(), vec!["line 1997", "line 1998", "line 1999"]);
+        assert!(scrollback.lines(5000, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search_and_text_cover_spilled_data() {
+        let mut scrollback = filled(300, 2000);
+        scrollback.push("multi-byte ✓ line".to_string(), true);
+        assert_eq!(scrollback.search("line 12").unwrap()[..3], [12, 120, 121]);
+        assert_eq!(scrollback.search("✓").unwrap(), vec![2000]);
+        let text = scrollback.text();
+        assert!(text.starts_with("line 0\nline 1\n"));
+        assert!(text.ends_with("line 1999\nmulti-byte ✓ line"));
+    }
+
+    #[test]
+    fn test_serde_round_trip_includes_spilled_lines() {
+        let scrollback = filled(300, 1000);
+        let json = serde_json::to_string(&scrollback).unwrap();
+        let restored: Scrollback = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.len(), 1000);
+        assert_eq!(restored.iter().collect::<Vec<_>>(), scrollback.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_truncate_keeps_spilled_lines() {
+        let mut scrollback = filled(300, 2000);
+        scrollback.truncate(1998);
+        assert_eq!(scrollback.len(), 1998);
+        assert_eq!(scrollback.stderr_len(), 666);
+        assert_eq!(scrollback.recent().last().unwrap().0, "line 1997");
+
+        scrollback.truncate(0);
+        assert_eq!(scrollback.len(), scrollback.spilled_len());
+    }
+
+    #[test]
+    fn test_lowering_limit_spills_and_clones_share_chunks() {
+        let mut scrollback = filled(10_000, 1000);
+        assert_eq!(scrollback.spilled_len(), 0);
+        scrollback.set_limit(100);
+        assert!(scrollback.recent().count() <= 100);
+
+        let clone = scrollback.clone();
+        drop(scrollback);
+        assert_eq!(clone.lines(0, 1).unwrap()[0].0, "line 0");
+    }
+}
@@ -0,0 +1,267 @@
+//! Full-text search over blocks.
+//!
+//! A query is compiled to a regex for every mode, so plain and case-insensitive searches
+//! share the matching code with regex searches. Matches are addressed by block id, field
+//! and byte range, which lets the UI highlight them and jump to them later. Output lines
+//! are numbered like `Scrollback` lines, so matches in spilled output can be paged back to.
+
+use anyhow::{anyhow, Result};
+use log::info;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+/// Searches stop after this many matches so a short query over huge output stays fast.
+pub const MAX_MATCHES: usize = 10_000;
+
+/// How the query text is matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SearchMode {
+    /// Literal text, ignoring case.
+    #[default]
+    IgnoreCase,
+    /// Literal text, matching case.
+    MatchCase,
+    /// A regular expression.
+    Regex,
+}
+
+impl SearchMode {
+    pub const ALL: [SearchMode; 3] = [SearchMode::IgnoreCase, SearchMode::MatchCase, SearchMode::Regex];
+}
+
+impl fmt::Display for SearchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SearchMode::IgnoreCase => "Ignore case",
+            SearchMode::MatchCase => "Match case",
+            SearchMode::Regex => "Regex",
+        })
+    }
+}
+
+/// Compiles `query` for `mode`.
+///
+/// # Returns
+///
+/// The regex to search with, or an error for an empty query or an invalid regex.
+pub fn compile(query: &str, mode: SearchMode) -> Result<Regex> {
+    if query.is_empty() {
+        return Err(anyhow!("The search query is empty."));
+    }
+    let pattern = match mode {
+        SearchMode::IgnoreCase | SearchMode::MatchCase => regex::escape(query),
+        SearchMode::Regex => query.to_string(),
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(mode == SearchMode::IgnoreCase)
+        .build()
+        .map_err(|e| anyhow!("Invalid regex: {}", e))
+}
+
+/// The part of a block a match is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatchField {
+    /// A command's input line, or the title of other blocks.
+    Input,
+    /// A line of a command's output, numbered like `Scrollback` lines.
+    Output(usize),
+    /// A line of the text of a message, info or error block.
+    Text(usize),
+}
+
+/// A single match: where it is and which bytes of the line it covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub block_id: String,
+    pub field: MatchField,
+    pub range: Range<usize>,
+}
+
+/// Matches of a search, in block order.
+#[derive(Debug, Clone, Default)]
+pub struct SearchResults {
+    pub matches: Vec<SearchMatch>,
+    /// Set if the search stopped at `MAX_MATCHES`.
+    pub truncated: bool,
+}
+
+impl SearchResults {
+    /// Returns true once no more matches are accepted.
+    pub fn is_full(&self) -> bool {
+        self.truncated
+    }
+
+    /// Adds the matches of `regex` in `line`.
+    ///
+    /// # Returns
+    ///
+    /// `false` once the results are full and the search should stop.
+    pub fn find_in_line(&mut self, regex: &Regex, block_id: &str, field: MatchField, line: &str) -> bool {
+        for found in regex.find_iter(line) {
+            // An empty match (e.g. `^`) can't be highlighted or told apart from its neighbours.
+            if found.range().is_empty() {
+                continue;
+            }
+            if self.matches.len() >= MAX_MATCHES {
+                self.truncated = true;
+                return false;
+            }
+            self.matches.push(SearchMatch { block_id: block_id.to_string(), field, range: found.range() });
+        }
+        true
+    }
+
+    /// Adds the matches of `regex` in each of `lines`, numbering them with `field`.
+    ///
+    /// # Returns
+    ///
+    /// `false` once the results are full and the search should stop.
+    pub fn find_in_lines<S: AsRef<str>>(
+        &mut self,
+        regex: &Regex,
+        block_id: &str,
+        field: fn(usize) -> MatchField,
+        lines: impl IntoIterator<Item = S>,
+    ) -> bool {
+        lines
+            .into_iter()
+            .enumerate()
+            .all(|(index, line)| self.find_in_line(regex, block_id, field(index), line.as_ref()))
+    }
+}
+
+/// A match to draw in a block, and whether it is the one navigated to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Highlight {
+    pub field: MatchField,
+    pub range: Range<usize>,
+    pub current: bool,
+}
+
+/// Groups the matches of `results` by block, marking match number `current`.
+pub fn group_highlights(results: &SearchResults, current: Option<usize>) -> HashMap<String, Vec<Highlight>> {
+    let mut highlights: HashMap<String, Vec<Highlight>> = HashMap::new();
+    for (index, found) in results.matches.iter().enumerate() {
+        highlights.entry(found.block_id.clone()).or_default().push(Highlight {
+            field: found.field,
+            range: found.range.clone(),
+            current: current == Some(index),
+        });
+    }
+    highlights
+}
+
+/// Splits `line` into runs of plain and highlighted text for drawing.
+///
+/// # Arguments
+///
+/// * `line` - The text of a line.
+/// * `highlights` - The highlights of the block; those for other fields are ignored.
+/// * `field` - The field `line` belongs to.
+///
+/// # Returns
+///
+/// The runs in order, each with `None` for plain text or `Some(current)` for a match.
+pub fn segments<'a>(line: &'a str, highlights: &[Highlight], field: MatchField) -> Vec<(&'a str, Option<bool>)> {
+    let mut ranges: Vec<(Range<usize>, bool)> = highlights
+        .iter()
+        .filter(|highlight| highlight.field == field)
+        .map(|highlight| (highlight.range.clone(), highlight.current))
+        .collect();
+    ranges.sort_by_key(|(range, _)| range.start);
+
+    let mut segments = Vec::new();
+    let mut position = 0;
+    for (range, current) in ranges {
+        // Skip ranges that overlap an earlier one or no longer fit the line.
+        if range.start < position || range.end > line.len() || !line.is_char_boundary(range.start) || !line.is_char_boundary(range.end) {
+            continue;
+        }
+        if range.start > position {
+            segments.push((&line[position..range.start], None));
+        }
+        segments.push((&line[range.clone()], Some(current)));
+        position = range.end;
+    }
+    if position < line.len() || segments.is_empty() {
+        segments.push((&line[position..], None));
+    }
+    segments
+}
+
+/// Initializes the search module.
+pub fn init() {
+    info!("search module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrollback::Scrollback;
+
+    #[test]
+    fn test_modes() {
+        let line = "Error: disk FULL (error 28)";
+        let count = |regex: Regex| regex.find_iter(line).count();
+        assert_eq!(count(compile("error", SearchMode::IgnoreCase).unwrap()), 2);
+        assert_eq!(count(compile("error", SearchMode::MatchCase).unwrap()), 1);
+        assert_eq!(count(compile("(", SearchMode::MatchCase).unwrap()), 1);
+        assert_eq!(count(compile(r"\d+", SearchMode::Regex).unwrap()), 1);
+        assert!(compile("(", SearchMode::Regex).is_err());
+        assert!(compile("", SearchMode::IgnoreCase).is_err());
+    }
+
+    #[test]
+    fn test_search_spilled_output() {
+        let mut output = Scrollback::new(1_000);
+        for i in 0..100_000 {
+            output.push(if i % 25_000 == 0 { format!("needle {}", i) } else { format!("line {}", i) }, true);
+        }
+        assert!(output.spilled_len() > 0);
+
+        let regex = compile("needle", SearchMode::IgnoreCase).unwrap();
+        let mut results = SearchResults::default();
+        assert!(results.find_in_lines(&regex, "block", MatchField::Output, output.iter().map(|(line, _)| line)));
+        let lines: Vec<MatchField> = results.matches.iter().map(|found| found.field).collect();
+        assert_eq!(lines, vec![MatchField::Output(0), MatchField::Output(25_000), MatchField::Output(50_000), MatchField::Output(75_000)]);
+        assert_eq!(results.matches[0].range, 0..6);
+    }
+
+    #[test]
+    fn test_results_are_capped() {
+        let regex = compile("x", SearchMode::MatchCase).unwrap();
+        let mut results = SearchResults::default();
+        let line = "x".repeat(MAX_MATCHES + 1);
+        assert!(!results.find_in_line(&regex, "block", MatchField::Input, &line));
+        assert!(results.is_full());
+        assert_eq!(results.matches.len(), MAX_MATCHES);
+
+        // Empty matches are skipped.
+        let mut results = SearchResults::default();
+        results.find_in_line(&compile("^", SearchMode::Regex).unwrap(), "block", MatchField::Input, "abc");
+        assert!(results.matches.is_empty());
+    }
+
+    #[test]
+    fn test_highlight_segments() {
+        let mut results = SearchResults::default();
+        let regex = compile("ab", SearchMode::IgnoreCase).unwrap();
+        results.find_in_line(&regex, "one", MatchField::Input, "xAbyab");
+        results.find_in_line(&regex, "two", MatchField::Text(3), "ab");
+        let highlights = group_highlights(&results, Some(1));
+        assert_eq!(highlights.len(), 2);
+
+        let one = &highlights["one"];
+        assert_eq!(
+            segments("xAbyab", one, MatchField::Input),
+            vec![("x", None), ("Ab", Some(false)), ("y", None), ("ab", Some(true))]
+        );
+        // Highlights of other fields and stale ranges are ignored.
+        assert_eq!(segments("xAbyab", one, MatchField::Output(0)), vec![("xAbyab", None)]);
+        assert_eq!(segments("xA", one, MatchField::Input), vec![("xA", None)]);
+        assert_eq!(segments("", &highlights["two"], MatchField::Text(3)), vec![("", None)]);
+    }
+}
@@ -0,0 +1,428 @@
+//! Session persistence across restarts.
+//!
+//! On exit (and periodically while running) NeoTerm writes its tabs, their pane layouts
+//! with each pane's blocks and shell state, the input history and the agent conversation
+//! to `session.json` in the data directory. On the next start the user is offered to
+//! restore it.
+
+use crate::ai::ChatMessage;
+use crate::block::{Block, BlockContent};
+use crate::config::preferences::EnvironmentProfiles;
+use crate::scrollback::Scrollback;
+use crate::workspace::{PaneState, Tab, Workspace};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use iced::widget::pane_grid::{self, Axis};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Version of the session file format. Files with another version are ignored.
+const SESSION_VERSION: u32 = 2;
+
+/// State of one pane.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PaneSession {
+    /// The shell's working directory as last reported through OSC 7.
+    pub working_directory: Option<String>,
+    /// Environment profile the pane's variables come from. Only the name is saved, as
+    /// profiles often hold secrets; the variables are looked up again on restore.
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub blocks: Vec<Block>,
+}
+
+impl PaneSession {
+    /// Captures a pane, keeping the last `max_output_lines` lines of each command block.
+    pub fn capture(pane: &PaneState, max_output_lines: usize) -> Self {
+        let blocks = pane
+            .blocks
+            .iter()
+            .map(|block| {
+                let mut block = block.clone();
+                block.output_page = None;
+                if let BlockContent::Command { output, .. } = &mut block.content {
+                    *output = truncated_output(output, max_output_lines);
+                }
+                block
+            })
+            .collect();
+
+        Self {
+            working_directory: pane.working_directory(),
+            profile: pane.profile.clone(),
+            blocks,
+        }
+    }
+
+    /// Prepares the saved blocks for display: commands that were still running when
+    /// the session was saved are marked as interrupted.
+    pub fn restored_blocks(&self, scrollback_lines: usize) -> Vec<Block> {
+        self.blocks
+            .iter()
+            .cloned()
+            .map(|mut block| {
+                block.set_scrollback_limit(scrollback_lines);
+                if let BlockContent::Command { end_time: None, .. } = block.content {
+                    block.set_status("Interrupted (restored from previous session)".to_string());
+                    block.set_error(true);
+                }
+                block.reload_player();
+                block
+            })
+            .collect()
+    }
+
+    /// Rebuilds the pane. Its shell starts in the saved directory on the first command
+    /// if `restore_directory` is set, with the current variables of its profile in `profiles`.
+    pub fn restore(&self, scrollback_lines: usize, restore_directory: bool, profiles: &EnvironmentProfiles) -> PaneState {
+        let initial_dir = self.working_directory.clone().filter(|_| restore_directory);
+        if let Some(name) = self.profile.as_deref().filter(|name| !profiles.profiles.contains_key(*name)) {
+            warn!("Environment profile '{}' of a restored pane no longer exists", name);
+        }
+        let env = profiles.variables(self.profile.as_deref());
+        let mut pane = PaneState::new(initial_dir, self.profile.clone(), env);
+        pane.blocks = self.restored_blocks(scrollback_lines);
+        pane
+    }
+}
+
+/// Direction of a saved split, mirroring `pane_grid::Axis`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitAxis {
+    /// Panes above each other.
+    Horizontal,
+    /// Panes side by side.
+    Vertical,
+}
+
+/// Pane layout of one tab.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SavedLayout {
+    Pane(PaneSession),
+    Split {
+        axis: SplitAxis,
+        ratio: f32,
+        a: Box<SavedLayout>,
+        b: Box<SavedLayout>,
+    },
+}
+
+impl SavedLayout {
+    /// Captures the layout of a tab and the state of each of its panes.
+    pub fn capture(tab: &Tab, max_output_lines: usize) -> Self {
+        Self::capture_node(tab, tab.panes.layout(), max_output_lines)
+    }
+
+    fn capture_node(tab: &Tab, node: &pane_grid::Node, max_output_lines: usize) -> Self {
+        match node {
+            pane_grid::Node::Pane(pane) => Self::Pane(
+                tab.panes
+                    .get(*pane)
+                    .map(|pane| PaneSession::capture(pane, max_output_lines))
+                    .unwrap_or_default(),
+            ),
+            pane_grid::Node::Split { axis, ratio, a, b, .. } => Self::Split {
+                axis: match axis {
+                    Axis::Horizontal => SplitAxis::Horizontal,
+                    Axis::Vertical => SplitAxis::Vertical,
+                },
+                ratio: *ratio,
+                a: Box::new(Self::capture_node(tab, a, max_output_lines)),
+                b: Box::new(Self::capture_node(tab, b, max_output_lines)),
+            },
+        }
+    }
+
+    /// Returns the saved panes in layout order.
+    pub fn panes(&self) -> Vec<&PaneSession> {
+        match self {
+            Self::Pane(pane) => vec![pane],
+            Self::Split { a, b, .. } => {
+                let mut panes = a.panes();
+                panes.extend(b.panes());
+                panes
+            }
+        }
+    }
+
+    /// Rebuilds the tab with the saved splits and ratios.
+    pub fn restore(&self, scrollback_lines: usize, restore_directories: bool, profiles: &EnvironmentProfiles) -> Tab {
+        Tab::from_configuration(self.configuration(scrollback_lines, restore_directories, profiles))
+    }
+
+    fn configuration(&self, scrollback_lines: usize, restore_directories: bool, profiles: &EnvironmentProfiles) -> pane_grid::Configuration<PaneState> {
+        match self {
+            Self::Pane(pane) => pane_grid::Configuration::Pane(pane.restore(scrollback_lines, restore_directories, profiles)),
+            Self::Split { axis, ratio, a, b } => pane_grid::Configuration::Split {
+                axis: match axis {
+                    SplitAxis::Horizontal => Axis::Horizontal,
+                    SplitAxis::Vertical => Axis::Vertical,
+                },
+                ratio: *ratio,
+                a: Box::new(a.configuration(scrollback_lines, restore_directories, profiles)),
+                b: Box::new(b.configuration(scrollback_lines, restore_directories, profiles)),
+            },
+        }
+    }
+}
+
+/// Everything needed to bring a previous session back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    pub saved_at: DateTime<Local>,
+    pub tabs: Vec<SavedLayout>,
+    /// Index of the tab that was active.
+    #[serde(default)]
+    pub active_tab: usize,
+    /// Input history, most recent first.
+    #[serde(default)]
+    pub input_history: Vec<String>,
+    #[serde(default)]
+    pub agent_conversation: Vec<ChatMessage>,
+}
+
+impl Session {
+    /// Captures a session from the application state.
+    ///
+    /// Only the last `max_output_lines` lines of each command block are kept; spilled
+    /// scrollback is not copied into the session file.
+    ///
+    /// # Arguments
+    ///
+    /// * `workspace` - The tabs and panes currently open.
+    /// * `input_history` - Input history, most recent first.
+    /// * `agent_conversation` - The AI assistant's conversation history.
+    /// * `max_output_lines` - Maximum number of output lines saved per command block.
+    pub fn capture(
+        workspace: &Workspace,
+        input_history: Vec<String>,
+        agent_conversation: Vec<ChatMessage>,
+        max_output_lines: usize,
+    ) -> Self {
+        Self {
+            version: SESSION_VERSION,
+            saved_at: Local::now(),
+            tabs: workspace
+                .tabs()
+                .iter()
+                .map(|tab| SavedLayout::capture(tab, max_output_lines))
+                .collect(),
+            active_tab: workspace.active_index(),
+            input_history,
+            agent_conversation,
+        }
+    }
+
+    /// Returns the number of blocks saved across all panes.
+    pub fn block_count(&self) -> usize {
+        self.tabs
+            .iter()
+            .flat_map(|tab| tab.panes())
+            .map(|pane| pane.blocks.len())
+            .sum()
+    }
+
+    /// Rebuilds the saved tabs and panes. Returns `None` if the session has no tabs.
+    ///
+    /// # Arguments
+    ///
+    /// * `scrollback_lines` - Scrollback limit for the restored blocks.
+    /// * `restore_directories` - Whether shells start in their saved working directories.
+    /// * `profiles` - The environment profiles the panes' variables are looked up in.
+    pub fn restore_workspace(&self, scrollback_lines: usize, restore_directories: bool, profiles: &EnvironmentProfiles) -> Option<Workspace> {
+        if self.tabs.is_empty() {
+            return None;
+        }
+        let tabs = self
+            .tabs
+            .iter()
+            .map(|tab| tab.restore(scrollback_lines, restore_directories, profiles))
+            .collect();
+        Some(Workspace::from_tabs(tabs, self.active_tab))
+    }
+
+    /// Returns the path of the session file.
+    pub fn path() -> PathBuf {
+        crate::config::DATA_DIR.join("session.json")
+    }
+
+    /// Loads the saved session, if there is one.
+    pub fn load() -> Result<Option<Session>> {
+        Self::load_from(&Self::path())
+    }
+
+    /// Loads a session from `path`. Returns `None` if the file doesn't exist or was
+    /// written by an incompatible version.
+    pub fn load_from(path: &Path) -> Result<Option<Session>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read session file {:?}", path))?;
+        let version = serde_json::from_str::<serde_json::Value>(&content)
+            .with_context(|| format!("Failed to parse session file {:?}", path))?
+            .get("version")
+            .and_then(|version| version.as_u64());
+        if version != Some(SESSION_VERSION as u64) {
+            warn!("Ignoring session file with unsupported version {:?}", version);
+            return Ok(None);
+        }
+        let session: Session = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse session file {:?}", path))?;
+        Ok(Some(session))
+    }
+
+    /// Saves the session to the data directory.
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&Self::path())
+    }
+
+    /// Saves the session to `path`, replacing the file atomically.
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create directory {:?}", dir))?;
+        }
+        let content = serde_json::to_string(self).context("Failed to serialize session")?;
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, content)
+            .with_context(|| format!("Failed to write session file {:?}", tmp_path))?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to replace session file {:?}", path))?;
+        Ok(())
+    }
+
+    /// Deletes the saved session, e.g. when the user chooses to start fresh.
+    pub fn discard() -> Result<()> {
+        let path = Self::path();
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove session file {:?}", path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies the last `max_lines` lines of `output`, noting how many earlier lines were left out.
+fn truncated_output(output: &Scrollback, max_lines: usize) -> Scrollback {
+    let omitted = output.len().saturating_sub(max_lines);
+    let mut lines = Vec::with_capacity(max_lines.min(output.len()) + 1);
+    if omitted > 0 {
+        lines.push((format!("[{} earlier lines were not saved]", omitted), true));
+    }
+    match output.tail(max_lines) {
+        Ok(tail) => lines.extend(tail),
+        Err(e) => warn!("Failed to read output for session: {}", e),
+    }
+    Scrollback::from(lines)
+}
+
+pub fn init() {
+    info!("session module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::preferences::EnvironmentProfile;
+    use std::collections::HashMap;
+
+    fn command_block(input: &str, lines: usize) -> Block {
+        let mut block = Block::new_command(input.to_string(), Some("/tmp".to_string()));
+        for i in 0..lines {
+            block.add_output_line(format!("line {}", i), true);
+        }
+        block
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("neoterm-session-{}.json", uuid::Uuid::new_v4()));
+        let mut finished = command_block("ls", 3);
+        finished.set_status("Completed with exit code: 0".to_string());
+        finished.toggle_collapse();
+        let env = HashMap::from([("RUST_LOG".to_string(), "debug".to_string())]);
+        let mut first = PaneState::new(Some("/srv/app".to_string()), Some("debug".to_string()), env);
+        first.blocks.push(finished);
+        let mut workspace = Workspace::new(first);
+        workspace.active_tab_mut().split(Axis::Vertical);
+        workspace.new_tab(PaneState::new(Some("/var/log".to_string()), None, HashMap::new()));
+
+        let conversation = vec![ChatMessage { role: "user".to_string(), content: Some("hi".to_string()), tool_calls: None, tool_call_id: None }];
+        let session = Session::capture(&workspace, vec!["ls".to_string(), "pwd".to_string()], conversation, 100);
+        session.save_to(&path).unwrap();
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("RUST_LOG"), "profile variables were saved: {}", saved);
+        let loaded = Session::load_from(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.input_history, vec!["ls", "pwd"]);
+        assert_eq!(loaded.agent_conversation[0].content.as_deref(), Some("hi"));
+        assert_eq!(loaded.block_count(), 1);
+        assert_eq!(loaded.active_tab, 1);
+
+        // The profile changed since the session was saved.
+        let mut profiles = EnvironmentProfiles::default();
+        let variables = HashMap::from([("RUST_LOG".to_string(), "trace".to_string())]);
+        profiles.profiles.insert("debug".to_string(), EnvironmentProfile { variables: variables.clone() });
+        let restored = loaded.restore_workspace(1000, true, &profiles).unwrap();
+        assert_eq!(restored.tabs().len(), 2);
+        assert_eq!(restored.active_index(), 1);
+        let first_tab = &restored.tabs()[0];
+        assert_eq!(first_tab.panes.len(), 2);
+        // Both panes of the split were saved with the directory they'd start in.
+        for pane in loaded.tabs[0].panes() {
+            assert_eq!(pane.working_directory.as_deref(), Some("/srv/app"));
+            assert_eq!(pane.profile.as_deref(), Some("debug"));
+        }
+        for (_, pane) in first_tab.panes.iter() {
+            assert_eq!(pane.env, variables);
+        }
+        let pane = first_tab.panes.iter().map(|(_, pane)| pane).find(|pane| !pane.blocks.is_empty()).unwrap();
+        let block = &pane.blocks[0];
+        assert!(block.collapsed);
+        match &block.content {
+            BlockContent::Command { input, output, working_directory, .. } => {
+                assert_eq!(input, "ls");
+                assert_eq!(output.text(), "line 0\nline 1\nline 2");
+                assert_eq!(working_directory.as_deref(), Some("/tmp"));
+            }
+            other => panic!("unexpected block content: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_output_is_truncated_and_running_commands_are_interrupted() {
+        let mut pane = PaneState::new(None, None, HashMap::new());
+        pane.blocks.push(command_block("yes", 50));
+        let blocks = PaneSession::capture(&pane, 10).restored_blocks(1000);
+        match &blocks[0].content {
+            BlockContent::Command { output, status, error, .. } => {
+                let lines: Vec<String> = output.iter().map(|(line, _)| line).collect();
+                assert_eq!(lines.len(), 11);
+                assert_eq!(lines[0], "[40 earlier lines were not saved]");
+                assert_eq!(lines[10], "line 49");
+                assert!(status.starts_with("Interrupted"));
+                assert!(*error);
+            }
+            other => panic!("unexpected block content: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_file_and_old_versions_are_ignored() {
+        let path = std::env::temp_dir().join("neoterm-session-does-not-exist.json");
+        assert!(Session::load_from(&path).unwrap().is_none());
+
+        let old = std::env::temp_dir().join(format!("neoterm-session-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&old, r#"{"version":1,"saved_at":"2024-01-01T00:00:00+00:00","blocks":[]}"#).unwrap();
+        let loaded = Session::load_from(&old).unwrap();
+        std::fs::remove_file(&old).unwrap();
+        assert!(loaded.is_none());
+    }
+}
@@ -2,7 +2,7 @@ use iced::{
     widget::{column, row, text, checkbox, slider, pick_list, text_input},
     Element, Length, Color, alignment,
 };
-use crate::config::preferences::{UiPreferences, InputType, InputPosition};
+use crate::config::preferences::{UiPreferences, InputType, InputPosition, EditingMode};
 use log::info;
 
 #[derive(Debug, Clone)]
@@ -14,6 +14,7 @@ pub enum AppearanceSettingsMessage {
     WindowBlurRadiusChanged(f32),
     InputTypeChanged(InputType),
     InputPositionChanged(InputPosition),
+    InputEditingModeChanged(EditingMode),
     DimInactivePanesToggled(bool),
     FocusFollowsMouseToggled(bool),
     // Add messages for other UI preferences
@@ -59,6 +60,10 @@ impl AppearanceSettings {
                 self.preferences.input_position = value;
                 info!("Input position: {:?}", value);
             }
+            AppearanceSettingsMessage::InputEditingModeChanged(value) => {
+                self.preferences.input_editing_mode = value;
+                info!("Input editing mode: {}", value);
+            }
             AppearanceSettingsMessage::DimInactivePanesToggled(value) => {
                 self.preferences.dim_inactive_panes = value;
                 info!("Dim inactive panes: {}", value);
@@ -126,6 +131,16 @@ impl AppearanceSettings {
             .width(Length::Fill)
         ].spacing(10).align_items(alignment::Horizontal::Center);
 
+        let input_editing_mode_picker = row![
+            text("Input Editing Mode:").width(Length::Fixed(150.0)),
+            pick_list(
+                &[EditingMode::Standard, EditingMode::Emacs, EditingMode::Vi][..],
+                Some(self.preferences.input_editing_mode),
+                AppearanceSettingsMessage::InputEditingModeChanged,
+            )
+            .width(Length::Fill)
+        ].spacing(10).align_items(alignment::Horizontal::Center);
+
         let dim_inactive_panes_toggle = checkbox(
             "Dim Inactive Panes",
             self.preferences.dim_inactive_panes,
@@ -147,6 +162,7 @@ impl AppearanceSettings {
             blur_slider,
             input_type_picker,
             input_position_picker,
+            input_editing_mode_picker,
             dim_inactive_panes_toggle,
             focus_follows_mouse_toggle,
         ]
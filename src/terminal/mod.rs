@@ -0,0 +1,1198 @@
+//! Grid-based terminal screen model.
+//!
+//! `TerminalScreen` consumes the output of a PTY through a `vte::Parser` and keeps
+//! the resulting screen state: cells with SGR attributes, the cursor, scroll regions,
+//! the alternate screen, line wrapping and DEC private modes. Full-screen programs
+//! such as `htop`, `vim` or `less`, and progress bars that redraw a single line, can
+//! therefore be rendered faithfully instead of as a stream of raw escape codes.
+//! Inline images (sixel, kitty graphics, iTerm2) are decoded by `images`.
+
+pub mod grid;
+pub mod images;
+
+use std::collections::VecDeque;
+use log::{debug, info};
+use unicode_width::UnicodeWidthChar;
+use vte::{Params, Parser, Perform};
+
+use crate::clipboard::Osc52;
+
+pub use grid::{Cell, CellAttributes, Grid, Row, TermColor};
+pub use images::{ApcScanner, ImageDecoder, InlineImage};
+
+/// Default number of lines kept in the primary screen's scrollback.
+const DEFAULT_SCROLLBACK_LIMIT: usize = 10_000;
+/// Width of a hardware tab stop.
+const TAB_WIDTH: usize = 8;
+
+/// Position and pen state of the cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cursor {
+    pub line: usize,
+    pub col: usize,
+    /// Attributes applied to newly printed cells.
+    pub attrs: CellAttributes,
+}
+
+/// Cursor state saved by DECSC / `CSI s` / mode 1048.
+#[derive(Debug, Clone, Copy, Default)]
+struct SavedCursor {
+    cursor: Cursor,
+    origin: bool,
+    autowrap: bool,
+}
+
+/// Terminal modes toggled by SM/RM and DECSET/DECRST.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalModes {
+    /// DECCKM: cursor keys send application sequences.
+    pub application_cursor: bool,
+    /// DECKPAM: keypad sends application sequences.
+    pub application_keypad: bool,
+    /// DECOM: cursor addressing is relative to the scroll region.
+    pub origin: bool,
+    /// DECAWM: printing past the last column wraps to the next line.
+    pub autowrap: bool,
+    /// DECTCEM: the cursor is visible.
+    pub cursor_visible: bool,
+    /// IRM: printed characters shift the rest of the line right.
+    pub insert: bool,
+    /// LNM: line feed also performs a carriage return.
+    pub linefeed_newline: bool,
+    /// Mode 2004: pasted text should be wrapped in `ESC[200~` / `ESC[201~`.
+    pub bracketed_paste: bool,
+    /// Modes 1000/1002/1003: the application wants mouse events.
+    pub mouse_tracking: bool,
+    /// Mode 1006: mouse events use the SGR encoding.
+    pub sgr_mouse: bool,
+}
+
+impl Default for TerminalModes {
+    fn default() -> Self {
+        Self {
+            application_cursor: false,
+            application_keypad: false,
+            origin: false,
+            autowrap: true,
+            cursor_visible: true,
+            insert: false,
+            linefeed_newline: false,
+            bracketed_paste: false,
+            mouse_tracking: false,
+            sgr_mouse: false,
+        }
+    }
+}
+
+/// Styled rows of a screen, as shown while a program draws on it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ScreenView {
+    /// The runs of identical attributes of each row, see `Row::styled_runs`.
+    pub rows: Vec<Vec<(String, CellAttributes)>>,
+    /// True when the rows are those of the alternate screen.
+    pub alternate: bool,
+}
+
+/// The complete state of an emulated terminal screen.
+#[derive(Debug, Clone)]
+pub struct TerminalScreen {
+    primary: Grid,
+    alternate: Grid,
+    alternate_active: bool,
+    cursor: Cursor,
+    saved_cursor: SavedCursor,
+    saved_cursor_alternate: SavedCursor,
+    /// Set when a character was printed into the last column and the next print must wrap first.
+    wrap_pending: bool,
+    scroll_top: usize,
+    scroll_bottom: usize,
+    modes: TerminalModes,
+    scrollback: VecDeque<Row>,
+    scrollback_limit: usize,
+    /// Rows that ever scrolled off the top of the primary screen, including those dropped
+    /// from scrollback. Added to a row's line it gives an index that doesn't change on scrolling.
+    scrolled_lines: usize,
+    title: Option<String>,
+    last_printed: Option<char>,
+    bell_count: usize,
+    /// Bytes the terminal must send back to the application (e.g. cursor position reports).
+    pending_responses: Vec<u8>,
+    /// URIs of the OSC 8 hyperlinks seen so far, indexed by the link ids stored in cells.
+    hyperlinks: Vec<String>,
+    /// The hyperlink applied to newly printed cells.
+    current_link: Option<u32>,
+    /// Decoder for inline images, holding those not taken yet.
+    images: ImageDecoder,
+    /// APC strings cut out of the output passed to `advance`.
+    apc: ApcScanner,
+    /// OSC 52 clipboard requests not taken yet.
+    clipboard_requests: Vec<Osc52>,
+}
+
+impl TerminalScreen {
+    /// Creates a blank screen of the given size.
+    pub fn new(lines: usize, cols: usize) -> Self {
+        let lines = lines.max(1);
+        let cols = cols.max(1);
+        Self {
+            primary: Grid::new(lines, cols),
+            alternate: Grid::new(lines, cols),
+            alternate_active: false,
+            cursor: Cursor::default(),
+            saved_cursor: SavedCursor::default(),
+            saved_cursor_alternate: SavedCursor::default(),
+            wrap_pending: false,
+            scroll_top: 0,
+            scroll_bottom: lines - 1,
+            modes: TerminalModes::default(),
+            scrollback: VecDeque::new(),
+            scrollback_limit: DEFAULT_SCROLLBACK_LIMIT,
+            scrolled_lines: 0,
+            title: None,
+            last_printed: None,
+            bell_count: 0,
+            pending_responses: Vec::new(),
+            hyperlinks: Vec::new(),
+            current_link: None,
+            images: ImageDecoder::default(),
+            apc: ApcScanner::default(),
+            clipboard_requests: Vec::new(),
+        }
+    }
+
+    /// Sets the maximum number of lines kept in scrollback, dropping the oldest if needed.
+    pub fn set_scrollback_limit(&mut self, limit: usize) {
+        self.scrollback_limit = limit;
+        while self.scrollback.len() > limit {
+            self.scrollback.pop_front();
+        }
+    }
+
+    /// Feeds raw PTY output through `parser` into this screen.
+    pub fn advance(&mut self, parser: &mut Parser, bytes: &[u8]) {
+        let mut scanner = std::mem::take(&mut self.apc);
+        advance(self, &mut scanner, parser, bytes);
+        self.apc = scanner;
+    }
+
+    /// Number of visible lines.
+    pub fn lines(&self) -> usize {
+        self.grid().lines()
+    }
+
+    /// Number of visible columns.
+    pub fn cols(&self) -> usize {
+        self.grid().cols()
+    }
+
+    /// The grid currently shown (primary or alternate).
+    pub fn grid(&self) -> &Grid {
+        if self.alternate_active { &self.alternate } else { &self.primary }
+    }
+
+    fn grid_mut(&mut self) -> &mut Grid {
+        if self.alternate_active { &mut self.alternate } else { &mut self.primary }
+    }
+
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+
+    pub fn modes(&self) -> &TerminalModes {
+        &self.modes
+    }
+
+    pub fn is_alternate_screen(&self) -> bool {
+        self.alternate_active
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Rows that have scrolled off the top of the primary screen, oldest first.
+    pub fn scrollback(&self) -> &VecDeque<Row> {
+        &self.scrollback
+    }
+
+    /// Returns the URI of the hyperlink with the given id.
+    pub fn hyperlink(&self, id: u32) -> Option<&str> {
+        self.hyperlinks.get(id as usize).map(String::as_str)
+    }
+
+    /// Returns the URI of the hyperlink currently being printed, if any.
+    pub fn current_hyperlink(&self) -> Option<&str> {
+        self.current_link.and_then(|id| self.hyperlink(id))
+    }
+
+    /// Returns the URI of the hyperlink under the given visible position.
+    pub fn link_at(&self, line: usize, col: usize) -> Option<&str> {
+        self.cell(line, col).link.and_then(|id| self.hyperlink(id))
+    }
+
+    /// Returns and resets the number of BEL characters received.
+    pub fn take_bell_count(&mut self) -> usize {
+        std::mem::take(&mut self.bell_count)
+    }
+
+    /// Returns and clears the inline images decoded since the last call.
+    pub fn take_images(&mut self) -> Vec<InlineImage> {
+        self.images.take_images()
+    }
+
+    /// Returns and clears the OSC 52 clipboard requests received since the last call.
+    /// Whether they are carried out is up to the application.
+    pub fn take_clipboard_requests(&mut self) -> Vec<Osc52> {
+        std::mem::take(&mut self.clipboard_requests)
+    }
+
+    /// Handles an APC string (`ESC _ ... ESC \`), as cut out by `advance`.
+    pub fn apc_dispatch(&mut self, data: &[u8]) {
+        if let Some(reply) = self.images.kitty(data) {
+            self.pending_responses.extend_from_slice(&reply);
+        }
+    }
+
+    /// Returns bytes that should be written back to the PTY, such as DSR replies.
+    pub fn take_pending_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending_responses)
+    }
+
+    /// Returns the cell at the given visible position.
+    pub fn cell(&self, line: usize, col: usize) -> &Cell {
+        self.grid().cell(line, col)
+    }
+
+    /// Returns the text of each visible row, with trailing blanks trimmed.
+    pub fn visible_lines(&self) -> Vec<String> {
+        self.grid().rows().iter().map(Row::text).collect()
+    }
+
+    /// Returns the styled runs of each visible row for rendering.
+    pub fn styled_lines(&self) -> Vec<Vec<(String, CellAttributes)>> {
+        self.grid().rows().iter().map(Row::styled_runs).collect()
+    }
+
+    /// Returns scrollback plus the visible screen as logical lines: rows joined by
+    /// autowrap are merged back together and trailing empty rows are dropped.
+    pub fn contents(&self) -> Vec<String> {
+        let mut lines = join_rows(self.scrollback.iter().chain(self.grid().rows().iter()));
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        lines
+    }
+
+    /// Returns the absolute line of the cursor on the primary screen: the number of rows
+    /// scrolled off its top so far plus the cursor's line. Scrolling doesn't change which
+    /// row an absolute line refers to. Only meaningful while the primary screen is shown.
+    pub fn absolute_line(&self) -> usize {
+        self.scrolled_lines + self.cursor.line
+    }
+
+    /// Returns the rows of the primary screen from absolute line `start` through `end`
+    /// that are still in scrollback or on the screen.
+    fn primary_rows(&self, start: usize, end: usize) -> impl Iterator<Item = &Row> {
+        let first = self.scrolled_lines - self.scrollback.len();
+        let skip = start.saturating_sub(first);
+        let take = (end + 1).saturating_sub(start.max(first));
+        self.scrollback.iter().chain(self.primary.rows().iter()).skip(skip).take(take)
+    }
+
+    /// Returns the primary screen's absolute lines `start` through `end` as logical lines,
+    /// with rows joined by autowrap merged. Empty lines are kept.
+    pub fn logical_lines(&self, start: usize, end: usize) -> Vec<String> {
+        join_rows(self.primary_rows(start, end))
+    }
+
+    /// Returns the rows to render for a program drawing on the screen: the whole alternate
+    /// screen while it is shown, otherwise the primary screen's absolute lines `start`
+    /// through `end`, or the last screenful of them.
+    pub fn view(&self, start: usize, end: usize) -> ScreenView {
+        if self.alternate_active {
+            return ScreenView { rows: self.styled_lines(), alternate: true };
+        }
+        let start = start.max((end + 1).saturating_sub(self.lines()));
+        ScreenView {
+            rows: self.primary_rows(start, end).map(Row::styled_runs).collect(),
+            alternate: false,
+        }
+    }
+
+    /// Resizes both screens. The scroll region is reset and the cursor clamped.
+    pub fn resize(&mut self, lines: usize, cols: usize) {
+        let lines = lines.max(1);
+        let cols = cols.max(1);
+        info!("Resizing terminal screen to {}x{}", cols, lines);
+        self.primary.resize(lines, cols);
+        self.alternate.resize(lines, cols);
+        self.scroll_top = 0;
+        self.scroll_bottom = lines - 1;
+        self.cursor.line = self.cursor.line.min(lines - 1);
+        self.cursor.col = self.cursor.col.min(cols - 1);
+        self.wrap_pending = false;
+    }
+
+    /// Resets the terminal to its initial state (RIS). Scrollback, and the hyperlinks
+    /// its cells refer to, are preserved.
+    pub fn reset(&mut self) {
+        let (lines, cols) = (self.lines(), self.cols());
+        let scrollback = std::mem::take(&mut self.scrollback);
+        let hyperlinks = std::mem::take(&mut self.hyperlinks);
+        let (limit, scrolled_lines) = (self.scrollback_limit, self.scrolled_lines);
+        *self = Self::new(lines, cols);
+        self.scrollback = scrollback;
+        self.hyperlinks = hyperlinks;
+        self.scrollback_limit = limit;
+        self.scrolled_lines = scrolled_lines;
+    }
+
+    /// Starts or, with an empty URI, ends an OSC 8 hyperlink (`OSC 8 ; params ; URI ST`).
+    fn set_hyperlink(&mut self, uri: &str) {
+        if uri.is_empty() {
+            self.current_link = None;
+            return;
+        }
+        // Programs usually print the same link several times in a row; reuse its id.
+        let id = match self.hyperlinks.last() {
+            Some(last) if last == uri => self.hyperlinks.len() - 1,
+            _ => {
+                self.hyperlinks.push(uri.to_string());
+                self.hyperlinks.len() - 1
+            }
+        };
+        self.current_link = Some(id as u32);
+    }
+
+    // --- Cursor movement ---
+
+    fn goto(&mut self, line: usize, col: usize) {
+        let (min_line, max_line) = if self.modes.origin {
+            (self.scroll_top, self.scroll_bottom)
+        } else {
+            (0, self.lines() - 1)
+        };
+        let line = if self.modes.origin { line + self.scroll_top } else { line };
+        self.cursor.line = line.clamp(min_line, max_line);
+        self.cursor.col = col.min(self.cols() - 1);
+        self.wrap_pending = false;
+    }
+
+    fn move_up(&mut self, count: usize) {
+        let top = if self.cursor.line >= self.scroll_top { self.scroll_top } else { 0 };
+        self.cursor.line = self.cursor.line.saturating_sub(count).max(top);
+        self.wrap_pending = false;
+    }
+
+    fn move_down(&mut self, count: usize) {
+        let bottom = if self.cursor.line <= self.scroll_bottom { self.scroll_bottom } else { self.lines() - 1 };
+        self.cursor.line = (self.cursor.line + count).min(bottom);
+        self.wrap_pending = false;
+    }
+
+    fn move_forward(&mut self, count: usize) {
+        self.cursor.col = (self.cursor.col + count).min(self.cols() - 1);
+        self.wrap_pending = false;
+    }
+
+    fn move_backward(&mut self, count: usize) {
+        self.cursor.col = self.cursor.col.saturating_sub(count);
+        self.wrap_pending = false;
+    }
+
+    fn carriage_return(&mut self) {
+        self.cursor.col = 0;
+        self.wrap_pending = false;
+    }
+
+    fn tab(&mut self) {
+        let next = (self.cursor.col / TAB_WIDTH + 1) * TAB_WIDTH;
+        self.cursor.col = next.min(self.cols() - 1);
+    }
+
+    /// Moves the cursor down one line, scrolling the region if it is at the bottom margin.
+    fn linefeed(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.line == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor.line < self.lines() - 1 {
+            self.cursor.line += 1;
+        }
+    }
+
+    /// Moves the cursor up one line, scrolling the region down if it is at the top margin.
+    fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.line == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.cursor.line > 0 {
+            self.cursor.line -= 1;
+        }
+    }
+
+    fn scroll_up(&mut self, count: usize) {
+        let (top, bottom, attrs) = (self.scroll_top, self.scroll_bottom, self.cursor.attrs);
+        let removed = self.grid_mut().scroll_up(top, bottom, count, &attrs);
+        // Only lines leaving the top of the full primary screen become scrollback.
+        if !self.alternate_active && top == 0 {
+            self.scrolled_lines += removed.len();
+            for row in removed {
+                self.scrollback.push_back(row);
+                if self.scrollback.len() > self.scrollback_limit {
+                    self.scrollback.pop_front();
+                }
+            }
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        let (top, bottom, attrs) = (self.scroll_top, self.scroll_bottom, self.cursor.attrs);
+        self.grid_mut().scroll_down(top, bottom, count, &attrs);
+    }
+
+    fn save_cursor(&mut self) {
+        let saved = SavedCursor {
+            cursor: self.cursor,
+            origin: self.modes.origin,
+            autowrap: self.modes.autowrap,
+        };
+        if self.alternate_active {
+            self.saved_cursor_alternate = saved;
+        } else {
+            self.saved_cursor = saved;
+        }
+    }
+
+    fn restore_cursor(&mut self) {
+        let saved = if self.alternate_active { self.saved_cursor_alternate } else { self.saved_cursor };
+        self.cursor = saved.cursor;
+        self.cursor.line = self.cursor.line.min(self.lines() - 1);
+        self.cursor.col = self.cursor.col.min(self.cols() - 1);
+        self.modes.origin = saved.origin;
+        self.modes.autowrap = saved.autowrap;
+        self.wrap_pending = false;
+    }
+
+    fn enter_alternate_screen(&mut self, clear: bool) {
+        if self.alternate_active {
+            return;
+        }
+        self.alternate_active = true;
+        if clear {
+            let attrs = self.cursor.attrs;
+            self.alternate.clear(&attrs);
+        }
+    }
+
+    fn exit_alternate_screen(&mut self) {
+        if !self.alternate_active {
+            return;
+        }
+        self.alternate_active = false;
+        self.wrap_pending = false;
+    }
+
+    // --- Erasing ---
+
+    fn erase_in_display(&mut self, mode: u16) {
+        let attrs = self.cursor.attrs;
+        let (line, col) = (self.cursor.line, self.cursor.col);
+        let lines = self.lines();
+        let cols = self.cols();
+        match mode {
+            0 => {
+                self.grid_mut().clear_cells(line, col, cols, &attrs);
+                for l in line + 1..lines {
+                    self.grid_mut().clear_row(l, &attrs);
+                }
+            }
+            1 => {
+                for l in 0..line {
+                    self.grid_mut().clear_row(l, &attrs);
+                }
+                self.grid_mut().clear_cells(line, 0, col + 1, &attrs);
+            }
+            2 => self.grid_mut().clear(&attrs),
+            3 => self.scrollback.clear(),
+            _ => debug!("Unhandled ED mode {}", mode),
+        }
+    }
+
+    fn erase_in_line(&mut self, mode: u16) {
+        let attrs = self.cursor.attrs;
+        let (line, col) = (self.cursor.line, self.cursor.col);
+        let cols = self.cols();
+        match mode {
+            0 => self.grid_mut().clear_cells(line, col, cols, &attrs),
+            1 => self.grid_mut().clear_cells(line, 0, col + 1, &attrs),
+            2 => self.grid_mut().clear_cells(line, 0, cols, &attrs),
+            _ => debug!("Unhandled EL mode {}", mode),
+        }
+        if mode != 1 {
+            self.grid_mut().row_mut(line).wrapped = false;
+        }
+    }
+
+    // --- Modes ---
+
+    fn set_private_mode(&mut self, mode: u16, enabled: bool) {
+        match mode {
+            1 => self.modes.application_cursor = enabled,
+            6 => {
+                self.modes.origin = enabled;
+                self.goto(0, 0);
+            }
+            7 => self.modes.autowrap = enabled,
+            12 => {} // Cursor blinking is a rendering concern.
+            25 => self.modes.cursor_visible = enabled,
+            47 | 1047 => {
+                if enabled {
+                    self.enter_alternate_screen(mode == 1047);
+                } else {
+                    if mode == 1047 {
+                        let attrs = self.cursor.attrs;
+                        self.alternate.clear(&attrs);
+                    }
+                    self.exit_alternate_screen();
+                }
+            }
+            1048 => {
+                if enabled { self.save_cursor() } else { self.restore_cursor() }
+            }
+            1049 => {
+                if enabled {
+                    self.save_cursor();
+                    self.enter_alternate_screen(true);
+                } else {
+                    self.exit_alternate_screen();
+                    self.restore_cursor();
+                }
+            }
+            1000 | 1002 | 1003 => self.modes.mouse_tracking = enabled,
+            1006 => self.modes.sgr_mouse = enabled,
+            2004 => self.modes.bracketed_paste = enabled,
+            _ => debug!("Unhandled DEC private mode {} ({})", mode, enabled),
+        }
+    }
+
+    fn set_ansi_mode(&mut self, mode: u16, enabled: bool) {
+        match mode {
+            4 => self.modes.insert = enabled,
+            20 => self.modes.linefeed_newline = enabled,
+            _ => debug!("Unhandled ANSI mode {} ({})", mode, enabled),
+        }
+    }
+
+    fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+        let lines = self.lines();
+        let top = top.saturating_sub(1);
+        let bottom = if bottom == 0 { lines } else { bottom.min(lines) } - 1;
+        if top < bottom {
+            self.scroll_top = top;
+            self.scroll_bottom = bottom;
+            self.goto(0, 0);
+        }
+    }
+
+    // --- SGR ---
+
+    fn set_graphics_rendition(&mut self, params: &Params) {
+        let groups: Vec<&[u16]> = params.iter().collect();
+        if groups.is_empty() {
+            self.cursor.attrs = CellAttributes::default();
+            return;
+        }
+
+        let attrs = &mut self.cursor.attrs;
+        let mut i = 0;
+        while i < groups.len() {
+            let group = groups[i];
+            match group[0] {
+                0 => *attrs = CellAttributes::default(),
+                1 => attrs.bold = true,
+                2 => attrs.dim = true,
+                3 => attrs.italic = true,
+                4 => attrs.underline = group.get(1).is_none_or(|style| *style != 0),
+                5 | 6 => attrs.blink = true,
+                7 => attrs.inverse = true,
+                8 => attrs.hidden = true,
+                9 => attrs.strikethrough = true,
+                21 => attrs.underline = true,
+                22 => {
+                    attrs.bold = false;
+                    attrs.dim = false;
+                }
+                23 => attrs.italic = false,
+                24 => attrs.underline = false,
+                25 => attrs.blink = false,
+                27 => attrs.inverse = false,
+                28 => attrs.hidden = false,
+                29 => attrs.strikethrough = false,
+                code @ 30..=37 => attrs.fg = TermColor::Indexed((code - 30) as u8),
+                38 => {
+                    let (color, consumed) = parse_extended_color(&groups[i..]);
+                    if let Some(color) = color {
+                        attrs.fg = color;
+                    }
+                    i += consumed;
+                }
+                39 => attrs.fg = TermColor::Default,
+                code @ 40..=47 => attrs.bg = TermColor::Indexed((code - 40) as u8),
+                48 => {
+                    let (color, consumed) = parse_extended_color(&groups[i..]);
+                    if let Some(color) = color {
+                        attrs.bg = color;
+                    }
+                    i += consumed;
+                }
+                49 => attrs.bg = TermColor::Default,
+                code @ 90..=97 => attrs.fg = TermColor::Indexed((code - 90 + 8) as u8),
+                code @ 100..=107 => attrs.bg = TermColor::Indexed((code - 100 + 8) as u8),
+                code => debug!("Unhandled SGR parameter {}", code),
+            }
+            i += 1;
+        }
+    }
+
+    fn write_char(&mut self, c: char) {
+        // Combining and other zero-width characters have no cell of their own.
+        let width = c.width().unwrap_or(0).min(self.cols());
+        if width == 0 {
+            return;
+        }
+
+        // A wide character that doesn't fit in the rest of the row wraps as a whole,
+        // leaving a spacer in the last column.
+        if self.wrap_pending || self.cursor.col + width > self.cols() {
+            if self.modes.autowrap {
+                let (line, col) = (self.cursor.line, self.cursor.col);
+                if !self.wrap_pending {
+                    self.grid_mut().cell_mut(line, col).spacer = true;
+                }
+                self.grid_mut().row_mut(line).wrapped = true;
+                self.carriage_return();
+                self.linefeed();
+            } else {
+                self.cursor.col = self.cols() - width;
+            }
+            self.wrap_pending = false;
+        }
+
+        let (line, col, attrs) = (self.cursor.line, self.cursor.col, self.cursor.attrs);
+        if self.modes.insert {
+            self.grid_mut().insert_cells(line, col, width, &attrs);
+        }
+        let link = self.current_link;
+        for offset in 0..width {
+            self.grid_mut().split_wide_char(line, col + offset);
+        }
+        *self.grid_mut().cell_mut(line, col) = Cell { c, attrs, link, spacer: false };
+        if width == 2 {
+            *self.grid_mut().cell_mut(line, col + 1) = Cell { c: ' ', attrs, link, spacer: true };
+        }
+
+        if col + width >= self.cols() {
+            self.cursor.col = self.cols() - 1;
+            self.wrap_pending = true;
+        } else {
+            self.cursor.col += width;
+        }
+        self.last_printed = Some(c);
+    }
+}
+
+/// Joins rows into logical lines, merging rows continued by autowrap.
+fn join_rows<'a>(rows: impl Iterator<Item = &'a Row>) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for row in rows {
+        if row.wrapped {
+            current.push_str(&row.full_text());
+        } else {
+            current.push_str(&row.text());
+            lines.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+/// Parses the color following an SGR 38/48 code, in both the `38;5;n` / `38;2;r;g;b`
+/// form and the colon-separated `38:5:n` / `38:2::r:g:b` form.
+/// Returns the color and the number of additional parameter groups consumed.
+fn parse_extended_color(groups: &[&[u16]]) -> (Option<TermColor>, usize) {
+    let first = groups[0];
+    if first.len() > 1 {
+        // Colon form: everything is in one group.
+        let color = match first[1] {
+            5 => first.get(2).map(|index| TermColor::Indexed(*index as u8)),
+            2 => {
+                // The color space id is optional: 38:2:r:g:b or 38:2:cs:r:g:b.
+                let rgb = if first.len() >= 6 { &first[3..6] } else { first.get(2..5).unwrap_or(&[]) };
+                if rgb.len() == 3 {
+                    Some(TermColor::Rgb(rgb[0] as u8, rgb[1] as u8, rgb[2] as u8))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        return (color, 0);
+    }
+
+    // A malformed color only skips its own parameters, not the rest of the sequence.
+    match groups.get(1).map(|group| group[0]) {
+        Some(5) => match groups.get(2) {
+            Some(group) => (Some(TermColor::Indexed(group[0] as u8)), 2),
+            None => (None, 1),
+        },
+        Some(2) if groups.len() >= 5 => (
+            Some(TermColor::Rgb(groups[2][0] as u8, groups[3][0] as u8, groups[4][0] as u8)),
+            4,
+        ),
+        Some(2) => (None, groups.len() - 1),
+        _ => (None, 0),
+    }
+}
+
+/// Returns the parameter at `index`, treating missing or zero values as `default`.
+fn param_or(params: &[u16], index: usize, default: u16) -> u16 {
+    match params.get(index) {
+        Some(0) | None => default,
+        Some(value) => *value,
+    }
+}
+
+impl Perform for TerminalScreen {
+    fn print(&mut self, c: char) {
+        self.write_char(c);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            0x07 => self.bell_count += 1,
+            0x08 => self.move_backward(1),
+            0x09 => self.tab(),
+            0x0A..=0x0C => {
+                self.linefeed();
+                if self.modes.linefeed_newline {
+                    self.carriage_return();
+                }
+            }
+            0x0D => self.carriage_return(),
+            _ => debug!("Unhandled C0 control 0x{:02x}", byte),
+        }
+    }
+
+    fn hook(&mut self, _params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        // DCS P1;P2;P3 q starts sixel data; the parameters only concern the background and aspect ratio.
+        if action == 'q' && intermediates.is_empty() && !ignore {
+            self.images.sixel_start();
+        }
+    }
+
+    fn put(&mut self, byte: u8) {
+        self.images.sixel_put(byte);
+    }
+
+    fn unhook(&mut self) {
+        self.images.sixel_end();
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        match params.first() {
+            Some(&b"0") | Some(&b"2") => {
+                if let Some(title) = params.get(1) {
+                    self.title = Some(String::from_utf8_lossy(title).to_string());
+                }
+            }
+            Some(&b"8") => {
+                // The URI may itself contain ';', which the parser splits on.
+                let uri = params.get(2..).unwrap_or_default().iter()
+                    .map(|part| String::from_utf8_lossy(part))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                self.set_hyperlink(&uri);
+            }
+            Some(&b"1337") => {
+                let text = params[1..].iter()
+                    .map(|part| String::from_utf8_lossy(part))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                let (cols, lines) = (self.cols(), self.lines());
+                self.images.iterm2(&text, cols, lines);
+            }
+            Some(&b"52") => {
+                if let Some(request) = Osc52::parse(params) {
+                    self.clipboard_requests.push(request);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+        let args: Vec<u16> = params.iter().map(|group| group[0]).collect();
+        let private = intermediates.first() == Some(&b'?');
+
+        match action {
+            '@' => {
+                let (line, col, attrs) = (self.cursor.line, self.cursor.col, self.cursor.attrs);
+                self.grid_mut().insert_cells(line, col, param_or(&args, 0, 1) as usize, &attrs);
+            }
+            'A' => self.move_up(param_or(&args, 0, 1) as usize),
+            'B' | 'e' => self.move_down(param_or(&args, 0, 1) as usize),
+            'C' | 'a' => self.move_forward(param_or(&args, 0, 1) as usize),
+            'D' => self.move_backward(param_or(&args, 0, 1) as usize),
+            'E' => {
+                self.move_down(param_or(&args, 0, 1) as usize);
+                self.carriage_return();
+            }
+            'F' => {
+                self.move_up(param_or(&args, 0, 1) as usize);
+                self.carriage_return();
+            }
+            'G' | '`' => {
+                self.cursor.col = (param_or(&args, 0, 1) as usize - 1).min(self.cols() - 1);
+                self.wrap_pending = false;
+            }
+            'H' | 'f' => {
+                let line = param_or(&args, 0, 1) as usize - 1;
+                let col = param_or(&args, 1, 1) as usize - 1;
+                self.goto(line, col);
+            }
+            'd' => {
+                let col = self.cursor.col;
+                self.goto(param_or(&args, 0, 1) as usize - 1, col);
+            }
+            'J' => self.erase_in_display(args.first().copied().unwrap_or(0)),
+            'K' => self.erase_in_line(args.first().copied().unwrap_or(0)),
+            'L' | 'M' => {
+                let line = self.cursor.line;
+                if line >= self.scroll_top && line <= self.scroll_bottom {
+                    let (bottom, attrs) = (self.scroll_bottom, self.cursor.attrs);
+                    let count = param_or(&args, 0, 1) as usize;
+                    if action == 'L' {
+                        self.grid_mut().scroll_down(line, bottom, count, &attrs);
+                    } else {
+                        self.grid_mut().scroll_up(line, bottom, count, &attrs);
+                    }
+                    self.carriage_return();
+                }
+            }
+            'P' => {
+                let (line, col, attrs) = (self.cursor.line, self.cursor.col, self.cursor.attrs);
+                self.grid_mut().delete_cells(line, col, param_or(&args, 0, 1) as usize, &attrs);
+            }
+            'X' => {
+                let (line, col, attrs) = (self.cursor.line, self.cursor.col, self.cursor.attrs);
+                let count = param_or(&args, 0, 1) as usize;
+                self.grid_mut().clear_cells(line, col, col + count, &attrs);
+            }
+            'S' => self.scroll_up(param_or(&args, 0, 1) as usize),
+            'T' => self.scroll_down(param_or(&args, 0, 1) as usize),
+            'b' => {
+                if let Some(c) = self.last_printed {
+                    for _ in 0..param_or(&args, 0, 1) {
+                        self.write_char(c);
+                    }
+                }
+            }
+            'r' if !private => {
+                let top = param_or(&args, 0, 1) as usize;
+                let bottom = args.get(1).copied().unwrap_or(0) as usize;
+                self.set_scroll_region(top, bottom);
+            }
+            'm' if !private => self.set_graphics_rendition(params),
+            'h' | 'l' => {
+                let enabled = action == 'h';
+                for mode in &args {
+                    if private {
+                        self.set_private_mode(*mode, enabled);
+                    } else {
+                        self.set_ansi_mode(*mode, enabled);
+                    }
+                }
+            }
+            's' if !private => self.save_cursor(),
+            'u' if !private => self.restore_cursor(),
+            'n' if !private => match args.first() {
+                Some(5) => self.pending_responses.extend_from_slice(b"\x1b[0n"),
+                Some(6) => {
+                    let line = if self.modes.origin { self.cursor.line - self.scroll_top } else { self.cursor.line };
+                    let report = format!("\x1b[{};{}R", line + 1, self.cursor.col + 1);
+                    self.pending_responses.extend_from_slice(report.as_bytes());
+                }
+                _ => {}
+            },
+            'c' if intermediates.is_empty() => {
+                // Primary device attributes: identify as a VT220 with sixel graphics.
+                self.pending_responses.extend_from_slice(b"\x1b[?62;4c");
+            }
+            _ => debug!("Unhandled CSI: params={:?}, intermediates={:?}, action={}", args, intermediates, action),
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        if !intermediates.is_empty() {
+            // Character set designation (ESC ( B etc.) is not emulated.
+            return;
+        }
+        match byte {
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'D' => self.linefeed(),
+            b'E' => {
+                self.linefeed();
+                self.carriage_return();
+            }
+            b'M' => self.reverse_index(),
+            b'c' => self.reset(),
+            b'=' => self.modes.application_keypad = true,
+            b'>' => self.modes.application_keypad = false,
+            _ => debug!("Unhandled ESC: byte={}", byte as char),
+        }
+    }
+}
+
+/// A `Perform` that also receives the APC strings `vte` drops.
+pub trait ApcPerform: Perform {
+    fn apc_dispatch(&mut self, data: &[u8]);
+}
+
+impl ApcPerform for TerminalScreen {
+    fn apc_dispatch(&mut self, data: &[u8]) {
+        TerminalScreen::apc_dispatch(self, data);
+    }
+}
+
+/// Feeds `bytes` through `parser` into `performer`. APC strings are cut out of the
+/// stream by `scanner` beforehand and passed to `ApcPerform::apc_dispatch`.
+pub fn advance<P: ApcPerform>(performer: &mut P, scanner: &mut ApcScanner, parser: &mut Parser, bytes: &[u8]) {
+    let mut pass = Vec::with_capacity(2);
+    for &byte in bytes {
+        let apc = scanner.scan(byte, &mut pass);
+        for byte in pass.drain(..) {
+            parser.advance(performer, byte);
+        }
+        if let Some(data) = apc {
+            performer.apc_dispatch(&data);
+        }
+    }
+}
+
+/// Initializes the terminal module.
+pub fn init() {
+    info!("terminal module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen_with(lines: usize, cols: usize, input: &str) -> TerminalScreen {
+        let mut screen = TerminalScreen::new(lines, cols);
+        let mut parser = Parser::new();
+        screen.advance(&mut parser, input.as_bytes());
+        screen
+    }
+
+    #[test]
+    fn test_plain_text_and_newlines() {
+        let screen = screen_with(3, 10, "hello\r\nworld");
+        assert_eq!(screen.visible_lines(), vec!["hello", "world", ""]);
+        assert_eq!(screen.cursor().line, 1);
+        assert_eq!(screen.cursor().col, 5);
+    }
+
+    #[test]
+    fn test_carriage_return_overwrites_progress_bar() {
+        let screen = screen_with(2, 20, "progress 10%\rprogress 100%");
+        assert_eq!(screen.visible_lines()[0], "progress 100%");
+    }
+
+    #[test]
+    fn test_cursor_movement_and_erase() {
+        // Draw two lines, move up, clear to end of line and overwrite.
+        let screen = screen_with(3, 10, "aaaa\r\nbbbb\x1b[1A\x1b[2G\x1b[Kx");
+        assert_eq!(screen.visible_lines(), vec!["ax", "bbbb", ""]);
+
+        let screen = screen_with(3, 10, "aaaa\r\nbbbb\x1b[2J\x1b[H!");
+        assert_eq!(screen.visible_lines(), vec!["!", "", ""]);
+    }
+
+    #[test]
+    fn test_sgr_attributes() {
+        let screen = screen_with(1, 20, "\x1b[1;4;31mA\x1b[0mB\x1b[38;5;208mC\x1b[48;2;1;2;3mD\x1b[38:2::10:20:30mE");
+        let a = screen.cell(0, 0).attrs;
+        assert!(a.bold && a.underline);
+        assert_eq!(a.fg, TermColor::Indexed(1));
+
+        assert_eq!(screen.cell(0, 1).attrs, CellAttributes::default());
+        assert_eq!(screen.cell(0, 2).attrs.fg, TermColor::Indexed(208));
+        assert_eq!(screen.cell(0, 3).attrs.bg, TermColor::Rgb(1, 2, 3));
+        assert_eq!(screen.cell(0, 4).attrs.fg, TermColor::Rgb(10, 20, 30));
+    }
+
+    #[test]
+    fn test_malformed_extended_color_keeps_the_rest_of_the_sgr() {
+        let screen = screen_with(1, 10, "\x1b[38;7;1mA\x1b[0;48;5m\x1b[4mB");
+        let a = screen.cell(0, 0).attrs;
+        assert_eq!(a.fg, TermColor::Default);
+        assert!(a.inverse && a.bold);
+        let b = screen.cell(0, 1).attrs;
+        assert_eq!(b.bg, TermColor::Default);
+        assert!(b.underline);
+    }
+
+    #[test]
+    fn test_wide_characters() {
+        let screen = screen_with(2, 6, "a中b");
+        assert_eq!(screen.visible_lines()[0], "a中b");
+        assert!(screen.cell(0, 2).spacer);
+        assert_eq!(screen.cursor().col, 4);
+
+        // A wide character doesn't fit in the last column and wraps whole.
+        let screen = screen_with(2, 6, "abcde😀");
+        assert_eq!(screen.visible_lines(), vec!["abcde", "😀"]);
+        assert_eq!(screen.contents(), vec!["abcde😀"]);
+
+        // Overwriting half of a wide character blanks the other half; combining marks take no cell.
+        let screen = screen_with(1, 6, "中文\x1b[2Gx\x1b[4Ge\u{301}!");
+        assert_eq!(screen.visible_lines()[0], " x e!");
+    }
+
+    #[test]
+    fn test_logical_lines_and_view_by_absolute_line() {
+        let mut screen = screen_with(3, 4, "one\r\n");
+        let start = screen.absolute_line();
+        screen.advance(&mut Parser::new(), b"abcdef\r\n10%\r\n\x1b[1A\x1b[2K\rdone\r\n");
+        assert_eq!(screen.absolute_line(), start + 3);
+        assert_eq!(screen.logical_lines(start, screen.absolute_line()), vec!["abcdef", "done", ""]);
+
+        // At most a screenful is shown.
+        let view = screen.view(start, screen.absolute_line());
+        assert!(!view.alternate);
+        assert_eq!(view.rows.len(), 3);
+        assert_eq!(view.rows[1][0].0, "done");
+
+        screen.advance(&mut Parser::new(), b"\x1b[?1049h\x1b[Hvim");
+        let view = screen.view(start, screen.absolute_line());
+        assert!(view.alternate);
+        assert_eq!(view.rows[0][0].0, "vim");
+    }
+
+    #[test]
+    fn test_line_wrapping() {
+        let screen = screen_with(3, 4, "abcdef");
+        assert_eq!(screen.visible_lines(), vec!["abcd", "ef", ""]);
+        assert!(screen.grid().row(0).wrapped);
+        assert_eq!(screen.contents(), vec!["abcdef"]);
+
+        let screen = screen_with(3, 4, "\x1b[?7labcdef");
+        assert_eq!(screen.visible_lines(), vec!["abcf", "", ""]);
+    }
+
+    #[test]
+    fn test_scrolling_into_scrollback() {
+        let screen = screen_with(2, 10, "one\r\ntwo\r\nthree");
+        assert_eq!(screen.visible_lines(), vec!["two", "three"]);
+        assert_eq!(screen.scrollback().len(), 1);
+        assert_eq!(screen.contents(), vec!["one", "two", "three"]);
+    }
+
+    #[test]
+    fn test_scroll_region() {
+        // Restrict scrolling to lines 2-3; line 1 acts as a fixed header.
+        let screen = screen_with(3, 10, "header\x1b[2;3r\x1b[2;1Ha\r\nb\r\nc");
+        assert_eq!(screen.visible_lines(), vec!["header", "b", "c"]);
+        assert!(screen.scrollback().is_empty());
+    }
+
+    #[test]
+    fn test_alternate_screen() {
+        let mut screen = TerminalScreen::new(2, 10);
+        let mut parser = Parser::new();
+        screen.advance(&mut parser, b"shell$ ");
+        screen.advance(&mut parser, b"\x1b[?1049h\x1b[Hvim buffer");
+        assert!(screen.is_alternate_screen());
+        assert_eq!(screen.visible_lines()[0], "vim buffer");
+
+        screen.advance(&mut parser, b"\x1b[?1049l");
+        assert!(!screen.is_alternate_screen());
+        assert_eq!(screen.visible_lines()[0], "shell$");
+        assert_eq!(screen.cursor().col, 7);
+    }
+
+    #[test]
+    fn test_private_modes() {
+        let screen = screen_with(2, 10, "\x1b[?1h\x1b[?25l\x1b[?2004h");
+        assert!(screen.modes().application_cursor);
+        assert!(!screen.modes().cursor_visible);
+        assert!(screen.modes().bracketed_paste);
+    }
+
+    #[test]
+    fn test_insert_and_delete_characters() {
+        let screen = screen_with(1, 10, "abcd\x1b[1G\x1b[2@");
+        assert_eq!(screen.visible_lines()[0], "  abcd");
+        let screen = screen_with(1, 10, "abcd\x1b[1G\x1b[2P");
+        assert_eq!(screen.visible_lines()[0], "cd");
+    }
+
+    #[test]
+    fn test_cursor_position_report() {
+        let mut screen = screen_with(5, 10, "\x1b[3;4H\x1b[6n");
+        assert_eq!(screen.take_pending_responses(), b"\x1b[3;4R".to_vec());
+        assert!(screen.take_pending_responses().is_empty());
+    }
+
+    #[test]
+    fn test_osc8_hyperlinks() {
+        let screen = screen_with(2, 30, "see \x1b]8;id=1;https://a.io/x;y\x1b\\docs\x1b]8;;\x1b\\ and \x1b]8;;file:///tmp\x07tmp\x1b]8;;\x07");
+        assert_eq!(screen.visible_lines()[0], "see docs and tmp");
+        assert_eq!(screen.link_at(0, 3), None);
+        assert_eq!(screen.link_at(0, 4), Some("https://a.io/x;y"));
+        assert_eq!(screen.link_at(0, 13), Some("file:///tmp"));
+        assert_eq!(screen.current_hyperlink(), None);
+
+        let row = screen.grid().row(0);
+        let links: Vec<_> = row.links().into_iter().map(|(range, id)| (range, screen.hyperlink(id).unwrap())).collect();
+        assert_eq!(links, vec![(4..8, "https://a.io/x;y"), (13..16, "file:///tmp")]);
+    }
+
+    #[test]
+    fn test_inline_images_from_captured_stream() {
+        // What `viu`, `kitty +kitten icat` and `imgcat` print, split across reads.
+        let stream = concat!(
+            "a\x1bPq\"1;1;2;6#1;2;100;100;100#1~~\x1b\\b\r\n",
+            "\x1b_Ga=T,i=3,f=24,s=1,v=1;/wAA\x1b\\",
+            "\x1b]1337;File=inline=1;width=auto:iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAYAAAD0In+KAAAADklEQVR4nGP4z8AAQv8BD/kD/YURmXYAAAAASUVORK5CYII=\x07c",
+        );
+        let mut screen = TerminalScreen::new(3, 10);
+        let mut parser = Parser::new();
+        let (first, rest) = stream.as_bytes().split_at(60);
+        screen.advance(&mut parser, first);
+        screen.advance(&mut parser, rest);
+
+        assert_eq!(screen.visible_lines(), vec!["ab", "c", ""]);
+        let sizes: Vec<_> = screen.take_images().iter().map(|image| (image.width, image.height)).collect();
+        assert_eq!(sizes, vec![(2, 6), (1, 1), (2, 1)]);
+        assert_eq!(screen.take_pending_responses(), b"\x1b_Gi=3;OK\x1b\\".to_vec());
+        assert!(screen.take_images().is_empty());
+    }
+
+    #[test]
+    fn test_title_and_resize() {
+        let mut screen = screen_with(5, 10, "\x1b]0;my title\x07");
+        assert_eq!(screen.title(), Some("my title"));
+        screen.resize(2, 4);
+        assert_eq!(screen.lines(), 2);
+        assert_eq!(screen.cols(), 4);
+    }
+}
@@ -13,6 +13,7 @@ use iced::{
 use iced::keyboard::{KeyCode, Modifiers};
 use crate::main::Message; // Assuming Message is in main.rs
 use crate::input::Message as InputMessage; // Assuming InputMessage is in input.rs
+use crate::history::ShellHistory;
 use log::info;
 
 #[derive(Debug, Clone)]
@@ -30,6 +31,9 @@ pub enum Direction {
     Down,
 }
 
+/// Prefix of the ids of the commands that switch the environment profile.
+const ENV_PROFILE_COMMAND_PREFIX: &str = "env_profile:";
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct CommandAction {
     pub id: String,
@@ -90,12 +94,33 @@ impl CommandPalette {
             },
             // Add more commands here
         ]
+        .into_iter()
+        .chain(ShellHistory::ALL.into_iter().map(|shell| CommandAction {
+            id: format!("import_{}_history", shell.name().to_lowercase()),
+            name: format!("Import {} History", shell.name()),
+            description: format!("Adds the commands of your {} history file to the command history.", shell.name()),
+            message: Message::ImportShellHistory(shell),
+        }))
+        .collect()
     }
 
     pub fn init(&self) {
         log::info!("Command palette initialized.");
     }
 
+    /// Replaces the commands that switch the active tab to an environment profile,
+    /// one per profile name.
+    pub fn set_env_profiles(&mut self, names: &[String]) {
+        self.commands.retain(|cmd| !cmd.id.starts_with(ENV_PROFILE_COMMAND_PREFIX));
+        self.commands.extend(names.iter().map(|name| CommandAction {
+            id: format!("{}{}", ENV_PROFILE_COMMAND_PREFIX, name),
+            name: format!("Switch Tab to Environment Profile: {}", name),
+            description: format!("Restarts this tab's shells with the variables of the '{}' profile.", name),
+            message: Message::SwitchEnvProfile(name.clone()),
+        }));
+        self.filter_commands();
+    }
+
     pub fn open(&mut self) {
         self.is_open = true;
         self.input_area.set_cursor_line_style(Style::default());
@@ -246,11 +271,9 @@ impl CommandPalette {
                 None
             }
             CommandPaletteMessage::Submit => {
-                if let Some(index) = self.selected_index {
-                    if let Some(command) = self.filtered_commands.get(index) {
-                        info!("Executing command palette action: {}", command.name);
-                        return Some(command.message.clone());
-                    }
+                if let Some(command) = self.filtered_commands.get(self.selected_index) {
+                    info!("Executing command palette action: {}", command.name);
+                    return Some(command.message.clone());
                 }
                 None
             }
@@ -0,0 +1,257 @@
+//! Interactive search of the command history, like a shell's Ctrl+R.
+//!
+//! The overlay fuzzily matches its query against the persistent history, restricted by
+//! its filters, and previews what is known about the selected command. Choosing a command
+//! puts it in the input bar without running it; `update` says so through a
+//! `HistorySearchAction`.
+
+use crate::fuzzy_match::FuzzyMatchManager;
+use crate::history::{HistoryFilter, HistoryMatch, HistoryStore, TimeRange};
+use chrono::Local;
+use iced::{
+    widget::{button, checkbox, column, container, mouse_area, pick_list, row, scrollable, text, text_input},
+    Alignment, Color, Element, Length,
+};
+use log::info;
+use std::sync::{Arc, Mutex};
+
+/// Id of the query input, so it can be focused when the search opens.
+pub const INPUT_ID: &str = "history-search-input";
+
+/// Most commands listed at once.
+const MAX_RESULTS: usize = 50;
+
+#[derive(Debug, Clone)]
+pub enum HistorySearchMessage {
+    QueryChanged(String),
+    CurrentDirectoryOnly(bool),
+    SuccessfulOnly(bool),
+    SessionOnly(bool),
+    TimeRangeSelected(TimeRange),
+    /// Select the command at an index of the results.
+    Select(usize),
+    /// Move the selection to the next (`true`) or previous result.
+    Step(bool),
+    /// Put the selected command in the input bar.
+    Insert,
+    Close,
+}
+
+/// What the application has to do after a `HistorySearchMessage`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistorySearchAction {
+    /// Replace the input bar's text with the command; the search closed.
+    Insert(String),
+}
+
+pub struct HistorySearch {
+    history: Arc<Mutex<HistoryStore>>,
+    matcher: FuzzyMatchManager,
+    is_open: bool,
+    query: String,
+    current_directory_only: bool,
+    successful_only: bool,
+    session_only: bool,
+    time_range: TimeRange,
+    /// Directory of the focused pane when the search opened.
+    cwd: Option<String>,
+    /// Identifies this run of the application in the history.
+    session_id: String,
+    results: Vec<HistoryMatch>,
+    selected: usize,
+}
+
+impl HistorySearch {
+    pub fn new(history: Arc<Mutex<HistoryStore>>, session_id: String) -> Self {
+        Self {
+            history,
+            matcher: FuzzyMatchManager::new(),
+            is_open: false,
+            query: String::new(),
+            current_directory_only: false,
+            successful_only: false,
+            session_only: false,
+            time_range: TimeRange::default(),
+            cwd: None,
+            session_id,
+            results: Vec::new(),
+            selected: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.is_open
+    }
+
+    /// Opens the search with an empty query. The filters keep their last settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - The directory the "This directory" filter restricts to.
+    pub fn open(&mut self, cwd: Option<String>) {
+        self.is_open = true;
+        self.cwd = cwd;
+        self.query.clear();
+        self.refresh();
+    }
+
+    pub fn update(&mut self, message: HistorySearchMessage) -> Option<HistorySearchAction> {
+        match message {
+            HistorySearchMessage::QueryChanged(query) => {
+                self.query = query;
+                self.refresh();
+            }
+            HistorySearchMessage::CurrentDirectoryOnly(only) => {
+                self.current_directory_only = only;
+                self.refresh();
+            }
+            HistorySearchMessage::SuccessfulOnly(only) => {
+                self.successful_only = only;
+                self.refresh();
+            }
+            HistorySearchMessage::SessionOnly(only) => {
+                self.session_only = only;
+                self.refresh();
+            }
+            HistorySearchMessage::TimeRangeSelected(range) => {
+                self.time_range = range;
+                self.refresh();
+            }
+            HistorySearchMessage::Select(index) => {
+                if index < self.results.len() {
+                    self.selected = index;
+                }
+            }
+            HistorySearchMessage::Step(forward) => {
+                let count = self.results.len();
+                if count > 0 {
+                    self.selected = if forward { (self.selected + 1) % count } else { (self.selected + count - 1) % count };
+                }
+            }
+            HistorySearchMessage::Insert => {
+                let command = self.results.get(self.selected)?.entry.command.clone();
+                self.close();
+                return Some(HistorySearchAction::Insert(command));
+            }
+            HistorySearchMessage::Close => self.close(),
+        }
+        None
+    }
+
+    fn close(&mut self) {
+        self.is_open = false;
+        self.results.clear();
+        self.selected = 0;
+    }
+
+    fn filter(&self) -> HistoryFilter {
+        HistoryFilter {
+            cwd: self.cwd.clone().filter(|_| self.current_directory_only),
+            successful_only: self.successful_only,
+            session_id: self.session_only.then(|| self.session_id.clone()),
+            since: self.time_range.since(Local::now()),
+        }
+    }
+
+    /// Runs the search again with the current query and filters.
+    fn refresh(&mut self) {
+        let filter = self.filter();
+        self.results = self.history.lock().unwrap().fuzzy_search(&self.matcher, &self.query, &filter, MAX_RESULTS);
+        self.selected = 0;
+    }
+
+    pub fn view(&self) -> Element<HistorySearchMessage> {
+        let dim = Color::from_rgb(0.7, 0.7, 0.7);
+        let search = row![
+            text_input("Search history…", &self.query)
+                .id(text_input::Id::new(INPUT_ID))
+                .on_input(HistorySearchMessage::QueryChanged)
+                .on_submit(HistorySearchMessage::Insert)
+                .padding(6)
+                .size(14)
+                .width(Length::Fill),
+            pick_list(&TimeRange::ALL[..], Some(self.time_range), HistorySearchMessage::TimeRangeSelected).text_size(12),
+            button(text("×").size(14)).on_press(HistorySearchMessage::Close).style(iced::widget::button::text::Style::Text),
+        ]
+        .spacing(6)
+        .align_items(Alignment::Center);
+
+        let filters = row![
+            checkbox("This directory", self.current_directory_only, HistorySearchMessage::CurrentDirectoryOnly).size(14).text_size(12),
+            checkbox("Only successful", self.successful_only, HistorySearchMessage::SuccessfulOnly).size(14).text_size(12),
+            checkbox("This session", self.session_only, HistorySearchMessage::SessionOnly).size(14).text_size(12),
+            text(format!("{} commands", self.results.len())).size(12).color(dim),
+        ]
+        .spacing(12)
+        .align_items(Alignment::Center);
+
+        let results = self.results.iter().enumerate().fold(column![], |results, (index, found)| {
+            let line = container(highlighted_command(found))
+                .width(Length::Fill)
+                .padding([2, 5])
+                .style(iced::widget::container::Appearance {
+                    background: (index == self.selected).then_some(iced::Background::Color(Color::from_rgb(0.2, 0.3, 0.5))),
+                    ..Default::default()
+                });
+            results.push(mouse_area(line).on_press(HistorySearchMessage::Select(index)))
+        });
+
+        let mut overlay = column![search, filters, scrollable(results).height(Length::Fixed(200.0))].spacing(6);
+        if let Some(found) = self.results.get(self.selected) {
+            overlay = overlay.push(self.preview(found));
+        }
+        overlay.push(text("↑↓ select · Enter insert · Esc close").size(11).color(dim)).into()
+    }
+
+    /// Renders the metadata of a command's most recent run.
+    fn preview(&self, found: &HistoryMatch) -> Element<HistorySearchMessage> {
+        let entry = &found.entry;
+        let outcome = match (entry.exit_code, entry.duration_ms) {
+            (Some(code), Some(ms)) => format!("Exit code {} after {:.1}s", code, ms as f64 / 1000.0),
+            (Some(code), None) => format!("Exit code {}", code),
+            (None, _) if entry.imported_from.is_some() => "Exit code unknown".to_string(),
+            (None, _) => "Not finished".to_string(),
+        };
+        let origin = match (&entry.imported_from, &entry.session_id) {
+            (Some(file), _) => format!("imported from {}", file),
+            (None, Some(session)) if *session == self.session_id => "this session".to_string(),
+            (None, _) => "an earlier session".to_string(),
+        };
+        let details = [
+            format!("Directory: {}", entry.cwd.as_deref().unwrap_or("unknown")),
+            outcome,
+            format!(
+                "Run {} on {} in {}",
+                entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                entry.hostname.as_deref().unwrap_or("an unknown host"),
+                origin,
+            ),
+        ];
+        container(details.into_iter().fold(column![], |preview, line| preview.push(text(line).size(12))))
+            .padding(5)
+            .into()
+    }
+}
+
+/// Renders a found command with the characters matching the query highlighted.
+fn highlighted_command(found: &HistoryMatch) -> Element<'static, HistorySearchMessage> {
+    let mut segments: Vec<(String, bool)> = Vec::new();
+    for (index, c) in found.entry.command.chars().enumerate() {
+        let matched = found.indices.contains(&index);
+        match segments.last_mut() {
+            Some((segment, last_matched)) if *last_matched == matched => segment.push(c),
+            _ => segments.push((c.to_string(), matched)),
+        }
+    }
+    segments
+        .into_iter()
+        .fold(row![], |row, (segment, matched)| {
+            let segment = text(segment).size(14).font(iced::Font::MONOSPACE);
+            row.push(if matched { segment.color(Color::from_rgb(1.0, 0.6, 0.0)) } else { segment })
+        })
+        .into()
+}
+
+pub fn init() {
+    info!("History search module loaded");
+}
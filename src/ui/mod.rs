@@ -1,4 +1,6 @@
 pub mod command_palette;
+pub mod search_bar;
+pub mod history_search;
 pub mod ai_sidebar;
 pub mod collapsible_block;
 pub mod ratatui_block; // This module is kept for completeness but not used in the Iced GUI.
@@ -0,0 +1,188 @@
+//! Search bar for full-text search across all blocks.
+//!
+//! The bar keeps the query, mode and results. Running the search and moving to a match
+//! are left to the application, which owns the blocks; `update` says what to do through
+//! a `SearchAction`.
+
+use crate::search::{self, Highlight, SearchMatch, SearchMode, SearchResults};
+use iced::{
+    widget::{button, pick_list, row, text, text_input},
+    Alignment, Color, Element, Length,
+};
+use log::info;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Id of the query input, so it can be focused when the bar opens.
+pub const INPUT_ID: &str = "search-bar-input";
+
+#[derive(Debug, Clone)]
+pub enum SearchBarMessage {
+    QueryChanged(String),
+    ModeSelected(SearchMode),
+    Next,
+    Previous,
+    Close,
+    /// Results of the search with the given generation.
+    ResultsReady(u64, SearchResults),
+}
+
+/// What the application has to do after a `SearchBarMessage`.
+#[derive(Debug, Clone)]
+pub enum SearchAction {
+    /// Search all blocks with `regex` and report back with `ResultsReady(generation, ..)`.
+    Search { generation: u64, regex: Regex },
+    /// Update the highlights and bring the current match into view.
+    Jump(SearchMatch),
+    /// Update the highlights; there is no match to move to.
+    Refresh,
+}
+
+#[derive(Debug, Default)]
+pub struct SearchBar {
+    is_open: bool,
+    query: String,
+    mode: SearchMode,
+    results: SearchResults,
+    /// Index of the match navigated to.
+    current: Option<usize>,
+    /// Incremented for every search so results of an outdated one are ignored.
+    generation: u64,
+    searching: bool,
+    error: Option<String>,
+}
+
+impl SearchBar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.is_open
+    }
+
+    pub fn open(&mut self) {
+        self.is_open = true;
+    }
+
+    /// Returns the highlights to draw, grouped by block id. Empty while the bar is closed.
+    pub fn highlights(&self) -> HashMap<String, Vec<Highlight>> {
+        if !self.is_open {
+            return HashMap::new();
+        }
+        search::group_highlights(&self.results, self.current)
+    }
+
+    pub fn update(&mut self, message: SearchBarMessage) -> Option<SearchAction> {
+        match message {
+            SearchBarMessage::QueryChanged(query) => {
+                self.query = query;
+                Some(self.start_search())
+            }
+            SearchBarMessage::ModeSelected(mode) => {
+                self.mode = mode;
+                Some(self.start_search())
+            }
+            SearchBarMessage::ResultsReady(generation, results) => {
+                if generation != self.generation {
+                    return None;
+                }
+                info!("Search for '{}' found {} matches", self.query, results.matches.len());
+                self.searching = false;
+                self.results = results;
+                self.current = None;
+                self.step(true)
+            }
+            SearchBarMessage::Next => self.step(true),
+            SearchBarMessage::Previous => self.step(false),
+            SearchBarMessage::Close => {
+                self.is_open = false;
+                self.generation += 1;
+                self.searching = false;
+                self.results = SearchResults::default();
+                self.current = None;
+                Some(SearchAction::Refresh)
+            }
+        }
+    }
+
+    fn start_search(&mut self) -> SearchAction {
+        self.generation += 1;
+        self.results = SearchResults::default();
+        self.current = None;
+        self.error = None;
+        self.searching = false;
+        if self.query.is_empty() {
+            return SearchAction::Refresh;
+        }
+        match search::compile(&self.query, self.mode) {
+            Ok(regex) => {
+                self.searching = true;
+                SearchAction::Search { generation: self.generation, regex }
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                SearchAction::Refresh
+            }
+        }
+    }
+
+    /// Moves to the next or previous match, wrapping around at the ends.
+    fn step(&mut self, forward: bool) -> Option<SearchAction> {
+        let count = self.results.matches.len();
+        if count == 0 {
+            return Some(SearchAction::Refresh);
+        }
+        let index = match (self.current, forward) {
+            (None, true) => 0,
+            (None, false) => count - 1,
+            (Some(index), true) => (index + 1) % count,
+            (Some(index), false) => (index + count - 1) % count,
+        };
+        self.current = Some(index);
+        Some(SearchAction::Jump(self.results.matches[index].clone()))
+    }
+
+    fn status(&self) -> String {
+        if let Some(error) = &self.error {
+            return error.clone();
+        }
+        if self.searching {
+            return "Searching…".to_string();
+        }
+        if self.query.is_empty() {
+            return String::new();
+        }
+        let total = self.results.matches.len();
+        let more = if self.results.truncated { "+" } else { "" };
+        match self.current {
+            Some(index) => format!("{} of {}{}", index + 1, total, more),
+            None => "No matches".to_string(),
+        }
+    }
+
+    pub fn view(&self) -> Element<SearchBarMessage> {
+        let status_color = if self.error.is_some() { Color::from_rgb(1.0, 0.4, 0.4) } else { Color::from_rgb(0.7, 0.7, 0.7) };
+        row![
+            text_input("Search blocks…", &self.query)
+                .id(text_input::Id::new(INPUT_ID))
+                .on_input(SearchBarMessage::QueryChanged)
+                .on_submit(SearchBarMessage::Next)
+                .padding(6)
+                .size(14)
+                .width(Length::Fill),
+            pick_list(&SearchMode::ALL[..], Some(self.mode), SearchBarMessage::ModeSelected).text_size(12),
+            text(self.status()).size(12).color(status_color),
+            button(text("↑").size(14)).on_press(SearchBarMessage::Previous).style(iced::widget::button::text::Style::Text),
+            button(text("↓").size(14)).on_press(SearchBarMessage::Next).style(iced::widget::button::text::Style::Text),
+            button(text("×").size(14)).on_press(SearchBarMessage::Close).style(iced::widget::button::text::Style::Text),
+        ]
+        .spacing(6)
+        .align_items(Alignment::Center)
+        .into()
+    }
+}
+
+pub fn init() {
+    info!("Search bar module loaded");
+}
@@ -71,6 +71,8 @@ pub struct WorkflowExecutor {
     collaboration_manager: Arc<SessionSharingManager>,
     sync_manager: Arc<SyncManager>,
     wasm_server: Arc<WasmServer>,
+    /// Variables of the active environment profile, applied under each step's own environment.
+    profile_env: std::sync::RwLock<HashMap<String, String>>,
 }
 
 impl WorkflowExecutor {
@@ -124,9 +126,15 @@ impl WorkflowExecutor {
             collaboration_manager,
             sync_manager,
             wasm_server,
+            profile_env: std::sync::RwLock::new(HashMap::new()),
         }
     }
 
+    /// Sets the environment profile variables that command steps run with.
+    pub fn set_profile_env(&self, env: HashMap<String, String>) {
+        *self.profile_env.write().unwrap() = env;
+    }
+
     pub fn set_event_sender(&mut self, sender: mpsc::Sender<WorkflowExecutionEvent>) {
         self.event_sender = sender;
     }
@@ -152,7 +160,11 @@ impl WorkflowExecutor {
         }
 
 
-        for step in workflow.steps {
+        for mut step in workflow.steps {
+            // Step variables override the workflow's.
+            for (key, value) in &workflow.environment {
+                step.environment.entry(key.clone()).or_insert_with(|| value.clone());
+            }
             let step_id = step.id.clone();
             let step_name = step.name.clone();
             log::info!("Executing step: {} (ID: {})", step_name, step_id);
@@ -214,7 +226,11 @@ impl WorkflowExecutor {
                     .map(|dir| self.resolve_placeholders(dir, context))
                     .transpose()?;
                 
-                self.execute_command_step(&resolved_command, &resolved_args, resolved_working_dir.as_deref()).await?
+                let mut env = self.profile_env.read().unwrap().clone();
+                for (key, value) in &step.environment {
+                    env.insert(key.clone(), self.resolve_placeholders(value, context)?);
+                }
+                self.execute_command_step(&resolved_command, &resolved_args, resolved_working_dir.as_deref(), env).await?
             },
             WorkflowStepType::AgentPrompt { message, input_variable } => {
                 let resolved_message = self.resolve_placeholders(message, context)?;
@@ -297,7 +313,7 @@ impl WorkflowExecutor {
         Ok(output)
     }
 
-    async fn execute_command_step(&self, command: &str, args: &[String], working_dir: Option<&str>) -> Result<String> {
+    async fn execute_command_step(&self, command: &str, args: &[String], working_dir: Option<&str>, env: HashMap<String, String>) -> Result<String> {
         let cmd_id = Uuid::new_v4().to_string();
         let cmd = Command {
             id: cmd_id.clone(),
@@ -305,9 +321,10 @@ impl WorkflowExecutor {
             description: format!("Workflow command: {}", command),
             executable: command.to_string(),
             args: args.to_vec(),
-            env: HashMap::new(), // Environment variables from step will be handled by PTY
+            env,
             working_dir: working_dir.map(|s| s.to_string()),
             output_format: crate::command::CommandOutputFormat::PlainText, // Always plain text for raw output
+            execution_mode: crate::command::ExecutionMode::Auto,
         };
 
         // Execute the command and capture its output
@@ -0,0 +1,588 @@
+//! Tabs and split panes.
+//!
+//! The workspace holds a list of tabs. Each tab is a `pane_grid` of panes, and every pane
+//! has its own shell session, block list and working directory.
+
+use crate::asciicast;
+use crate::block::Block;
+use crate::config::preferences::KeybindingPreferences;
+use crate::shell::ShellManager;
+use iced::keyboard::{KeyCode, Modifiers};
+use iced::widget::pane_grid::{self, Axis, Direction, Pane};
+use anyhow::Result;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// A single pane: one shell session with the blocks of the commands run in it.
+#[derive(Clone)]
+pub struct PaneState {
+    pub blocks: Vec<Block>,
+    pub shell: Arc<ShellManager>,
+    /// Directory the shell is started in if it isn't running yet.
+    pub initial_dir: Option<String>,
+    /// Name of the environment profile `env` comes from, if any.
+    pub profile: Option<String>,
+    /// Environment the shell was (or will be) started with.
+    pub env: HashMap<String, String>,
+}
+
+impl PaneState {
+    /// Creates an empty pane whose shell starts in `initial_dir` on the first command,
+    /// with the variables of the environment profile `profile`.
+    pub fn new(initial_dir: Option<String>, profile: Option<String>, env: HashMap<String, String>) -> Self {
+        Self {
+            blocks: Vec::new(),
+            shell: Arc::new(ShellManager::new()),
+            initial_dir,
+            profile,
+            env,
+        }
+    }
+
+    /// Returns the pane's working directory: the shell's, or the one it will start in.
+    pub fn working_directory(&self) -> Option<String> {
+        self.shell.current_dir().or_else(|| self.initial_dir.clone())
+    }
+
+    /// Returns a short title: the last component of the working directory.
+    pub fn title(&self) -> String {
+        match self.working_directory() {
+            Some(dir) => Path::new(&dir)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(str::to_string)
+                .unwrap_or(dir),
+            None => "~".to_string(),
+        }
+    }
+}
+
+impl fmt::Debug for PaneState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PaneState")
+            .field("blocks", &self.blocks.len())
+            .field("initial_dir", &self.initial_dir)
+            .field("profile", &self.profile)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A tab: panes laid out in a grid of splits, one of them focused.
+#[derive(Debug, Clone)]
+pub struct Tab {
+    /// Identifies the tab in the command history.
+    pub id: String,
+    pub panes: pane_grid::State<PaneState>,
+    pub focus: Pane,
+    /// Directory the tab's panes are recorded to while recording is on.
+    recording_dir: Option<PathBuf>,
+}
+
+impl Tab {
+    /// Creates a tab with a single pane.
+    pub fn new(pane: PaneState) -> Self {
+        let (panes, focus) = pane_grid::State::new(pane);
+        Self { id: Uuid::new_v4().to_string(), panes, focus, recording_dir: None }
+    }
+
+    /// Creates a tab from a saved layout. The first pane of the layout is focused.
+    pub fn from_configuration(configuration: pane_grid::Configuration<PaneState>) -> Self {
+        let panes = pane_grid::State::with_configuration(configuration);
+        let focus = first_pane(panes.layout());
+        Self { id: Uuid::new_v4().to_string(), panes, focus, recording_dir: None }
+    }
+
+    pub fn title(&self) -> String {
+        self.focused().title()
+    }
+
+    pub fn focused(&self) -> &PaneState {
+        self.panes.get(self.focus).expect("focused pane exists")
+    }
+
+    pub fn focused_mut(&mut self) -> &mut PaneState {
+        self.panes.get_mut(self.focus).expect("focused pane exists")
+    }
+
+    /// Splits the focused pane along `axis` and focuses the new pane, which starts
+    /// in the focused pane's directory with the same environment.
+    pub fn split(&mut self, axis: Axis) -> Option<Pane> {
+        let focused = self.focused();
+        let pane = PaneState::new(focused.working_directory(), focused.profile.clone(), focused.env.clone());
+        let (new_pane, _) = self.panes.split(axis, self.focus, pane)?;
+        self.focus = new_pane;
+        if let Some(dir) = &self.recording_dir {
+            if let Err(e) = record_pane(self.focused(), dir) {
+                warn!("Failed to record new pane: {}", e);
+            }
+        }
+        Some(new_pane)
+    }
+
+    /// Returns the environment profile of the tab's focused pane.
+    pub fn profile(&self) -> Option<&str> {
+        self.focused().profile.as_deref()
+    }
+
+    /// Switches every pane of the tab to the environment profile `profile` with variables `env`.
+    ///
+    /// A running shell can't change its environment, so each pane gets a new shell that
+    /// starts in the old one's directory on the next command.
+    ///
+    /// # Returns
+    ///
+    /// The replaced shells, so the caller can terminate them.
+    /// While recording, the new shells are recorded to new files.
+    pub fn set_profile(&mut self, profile: Option<String>, env: HashMap<String, String>) -> Vec<Arc<ShellManager>> {
+        let recording_dir = self.recording_dir.clone();
+        self.panes
+            .iter_mut()
+            .map(|(_, pane)| {
+                pane.initial_dir = pane.working_directory();
+                pane.profile = profile.clone();
+                pane.env = env.clone();
+                let old_shell = std::mem::replace(&mut pane.shell, Arc::new(ShellManager::new()));
+                if let Some(dir) = &recording_dir {
+                    old_shell.stop_recording();
+                    if let Err(e) = record_pane(pane, dir) {
+                        warn!("Failed to record pane: {}", e);
+                    }
+                }
+                old_shell
+            })
+            .collect()
+    }
+
+    /// Returns true if the tab's panes are being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.recording_dir.is_some()
+    }
+
+    /// Starts recording every pane of the tab to its own asciicast file in `dir`.
+    /// Panes split off later are recorded too.
+    ///
+    /// # Returns
+    ///
+    /// The paths of the new recordings.
+    pub fn start_recording(&mut self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let paths = self
+            .panes
+            .iter()
+            .map(|(_, pane)| record_pane(pane, dir))
+            .collect::<Result<Vec<_>>>();
+        match paths {
+            Ok(paths) => {
+                self.recording_dir = Some(dir.to_path_buf());
+                Ok(paths)
+            }
+            Err(e) => {
+                self.stop_recording();
+                Err(e)
+            }
+        }
+    }
+
+    /// Stops recording the tab's panes.
+    ///
+    /// # Returns
+    ///
+    /// The paths of the finished recordings.
+    pub fn stop_recording(&mut self) -> Vec<PathBuf> {
+        self.recording_dir = None;
+        self.panes.iter().filter_map(|(_, pane)| pane.shell.stop_recording()).collect()
+    }
+
+    /// Closes `pane` and focuses its sibling. The last pane of a tab can't be closed.
+    ///
+    /// # Returns
+    ///
+    /// The closed pane, so the caller can terminate its shell.
+    pub fn close(&mut self, pane: Pane) -> Option<PaneState> {
+        let (closed, sibling) = self.panes.close(pane)?;
+        if self.focus == pane {
+            self.focus = sibling;
+        }
+        Some(closed)
+    }
+
+    /// Moves the focus to the neighbouring pane in `direction`, if there is one.
+    pub fn focus_adjacent(&mut self, direction: Direction) -> bool {
+        match self.panes.adjacent(self.focus, direction) {
+            Some(pane) => {
+                self.focus = pane;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Starts recording `pane`'s shell to a new file in `dir`, named after its directory.
+fn record_pane(pane: &PaneState, dir: &Path) -> Result<PathBuf> {
+    let path = asciicast::recording_path(dir, &pane.title());
+    pane.shell.start_recording(&path)?;
+    Ok(path)
+}
+
+/// Returns the first pane of a layout, in reading order.
+fn first_pane(node: &pane_grid::Node) -> Pane {
+    match node {
+        pane_grid::Node::Pane(pane) => *pane,
+        pane_grid::Node::Split { a, .. } => first_pane(a),
+    }
+}
+
+/// All tabs of the window.
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    tabs: Vec<Tab>,
+    active: usize,
+}
+
+impl Workspace {
+    /// Creates a workspace with a single tab holding `pane`.
+    pub fn new(pane: PaneState) -> Self {
+        Self::from_tabs(vec![Tab::new(pane)], 0)
+    }
+
+    /// Creates a workspace from existing tabs, e.g. restored from a session.
+    pub fn from_tabs(tabs: Vec<Tab>, active: usize) -> Self {
+        assert!(!tabs.is_empty(), "a workspace needs at least one tab");
+        let active = active.min(tabs.len() - 1);
+        Self { tabs, active }
+    }
+
+    pub fn tabs(&self) -> &[Tab] {
+        &self.tabs
+    }
+
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    pub fn active_tab(&self) -> &Tab {
+        &self.tabs[self.active]
+    }
+
+    pub fn active_tab_mut(&mut self) -> &mut Tab {
+        &mut self.tabs[self.active]
+    }
+
+    /// Returns the focused pane of the active tab, which receives new commands and blocks.
+    pub fn focused_pane(&self) -> &PaneState {
+        self.active_tab().focused()
+    }
+
+    pub fn focused_pane_mut(&mut self) -> &mut PaneState {
+        self.active_tab_mut().focused_mut()
+    }
+
+    /// Opens a new tab with `pane` and makes it active.
+    pub fn new_tab(&mut self, pane: PaneState) -> usize {
+        self.tabs.push(Tab::new(pane));
+        self.active = self.tabs.len() - 1;
+        self.active
+    }
+
+    /// Closes the tab at `index`. The last tab can't be closed.
+    ///
+    /// # Returns
+    ///
+    /// The closed tab, so the caller can terminate its shells.
+    pub fn close_tab(&mut self, index: usize) -> Option<Tab> {
+        if self.tabs.len() <= 1 || index >= self.tabs.len() {
+            return None;
+        }
+        let tab = self.tabs.remove(index);
+        if self.active > index || self.active == self.tabs.len() {
+            self.active -= 1;
+        }
+        Some(tab)
+    }
+
+    pub fn select_tab(&mut self, index: usize) {
+        if index < self.tabs.len() {
+            self.active = index;
+        }
+    }
+
+    pub fn next_tab(&mut self) {
+        self.active = (self.active + 1) % self.tabs.len();
+    }
+
+    pub fn previous_tab(&mut self) {
+        self.active = (self.active + self.tabs.len() - 1) % self.tabs.len();
+    }
+
+    /// Iterates over every pane of every tab.
+    pub fn panes(&self) -> impl Iterator<Item = &PaneState> {
+        self.tabs.iter().flat_map(|tab| tab.panes.iter().map(|(_, pane)| pane))
+    }
+
+    pub fn panes_mut(&mut self) -> impl Iterator<Item = &mut PaneState> {
+        self.tabs.iter_mut().flat_map(|tab| tab.panes.iter_mut().map(|(_, pane)| pane))
+    }
+
+    /// Finds a block in any pane.
+    pub fn find_block(&self, block_id: &str) -> Option<&Block> {
+        self.panes().flat_map(|pane| pane.blocks.iter()).find(|block| block.id == block_id)
+    }
+
+    /// Finds a block in any pane. Output of a command keeps going to its block even
+    /// after the focus moved to another pane or tab.
+    pub fn find_block_mut(&mut self, block_id: &str) -> Option<&mut Block> {
+        self.panes_mut().flat_map(|pane| pane.blocks.iter_mut()).find(|block| block.id == block_id)
+    }
+
+    /// Returns the pane holding a block.
+    pub fn pane_of_block(&self, block_id: &str) -> Option<&PaneState> {
+        self.panes().find(|pane| pane.blocks.iter().any(|block| block.id == block_id))
+    }
+
+    /// Selects the tab and focuses the pane holding a block.
+    ///
+    /// # Returns
+    ///
+    /// `false` if no pane holds the block.
+    pub fn focus_block(&mut self, block_id: &str) -> bool {
+        for (index, tab) in self.tabs.iter_mut().enumerate() {
+            let found = tab
+                .panes
+                .iter()
+                .find(|(_, pane)| pane.blocks.iter().any(|block| block.id == block_id))
+                .map(|(pane, _)| *pane);
+            if let Some(pane) = found {
+                tab.focus = pane;
+                self.active = index;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Removes a block from whichever pane holds it.
+    pub fn remove_block(&mut self, block_id: &str) {
+        for pane in self.panes_mut() {
+            pane.blocks.retain(|block| block.id != block_id);
+        }
+    }
+}
+
+/// Workspace actions triggered from the tab bar, pane title bars or key bindings.
+#[derive(Debug, Clone)]
+pub enum WorkspaceMessage {
+    NewTab,
+    SelectTab(usize),
+    CloseTab(usize),
+    NextTab,
+    PreviousTab,
+    /// Split the focused pane. `Axis::Vertical` puts the new pane to the right,
+    /// `Axis::Horizontal` below.
+    Split(Axis),
+    ClosePane(Pane),
+    /// Close the focused pane, or the tab if it is the tab's last pane.
+    CloseFocused,
+    FocusPane(Pane),
+    FocusAdjacent(Direction),
+    Resized(pane_grid::ResizeEvent),
+    /// Start or stop recording the active tab.
+    ToggleRecording,
+}
+
+/// Key binding actions handled by the workspace and the message each one sends.
+fn actions() -> [(&'static str, WorkspaceMessage); 11] {
+    [
+        ("new_tab", WorkspaceMessage::NewTab),
+        ("close_pane", WorkspaceMessage::CloseFocused),
+        ("next_tab", WorkspaceMessage::NextTab),
+        ("previous_tab", WorkspaceMessage::PreviousTab),
+        ("split_right", WorkspaceMessage::Split(Axis::Vertical)),
+        ("split_down", WorkspaceMessage::Split(Axis::Horizontal)),
+        ("focus_pane_left", WorkspaceMessage::FocusAdjacent(Direction::Left)),
+        ("focus_pane_right", WorkspaceMessage::FocusAdjacent(Direction::Right)),
+        ("focus_pane_up", WorkspaceMessage::FocusAdjacent(Direction::Up)),
+        ("focus_pane_down", WorkspaceMessage::FocusAdjacent(Direction::Down)),
+        ("toggle_recording", WorkspaceMessage::ToggleRecording),
+    ]
+}
+
+/// Maps a key press to a workspace action using the configured key bindings.
+///
+/// # Arguments
+///
+/// * `keybindings` - The user's key bindings.
+/// * `key_code` - The pressed key.
+/// * `modifiers` - The modifiers held while pressing it.
+///
+/// # Returns
+///
+/// The message for the bound action, if any.
+pub fn message_for_key(keybindings: &KeybindingPreferences, key_code: KeyCode, modifiers: Modifiers) -> Option<WorkspaceMessage> {
+    actions().into_iter().find_map(|(action, message)| {
+        let binding = keybindings.binding(action)?;
+        binding_matches(binding, key_code, modifiers).then_some(message)
+    })
+}
+
+/// Returns true if a key combination such as `Cmd+Shift+D` matches the key press.
+/// Modifiers must match exactly, so `Cmd+D` doesn't fire for `Cmd+Shift+D`.
+pub fn binding_matches(binding: &str, key_code: KeyCode, modifiers: Modifiers) -> bool {
+    let mut expected = Modifiers::empty();
+    let mut key = None;
+    for part in binding.split('+').map(str::trim) {
+        match part.to_ascii_lowercase().as_str() {
+            "cmd" | "command" => expected |= Modifiers::COMMAND,
+            "ctrl" | "control" => expected |= Modifiers::CTRL,
+            "alt" | "option" => expected |= Modifiers::ALT,
+            "shift" => expected |= Modifiers::SHIFT,
+            "super" | "meta" | "logo" => expected |= Modifiers::LOGO,
+            _ => key = parse_key_code(part),
+        }
+    }
+    key == Some(key_code) && modifiers == expected
+}
+
+fn parse_key_code(name: &str) -> Option<KeyCode> {
+    let key = match name.to_ascii_uppercase().as_str() {
+        "A" => KeyCode::A, "B" => KeyCode::B, "C" => KeyCode::C, "D" => KeyCode::D,
+        "E" => KeyCode::E, "F" => KeyCode::F, "G" => KeyCode::G, "H" => KeyCode::H,
+        "I" => KeyCode::I, "J" => KeyCode::J, "K" => KeyCode::K, "L" => KeyCode::L,
+        "M" => KeyCode::M, "N" => KeyCode::N, "O" => KeyCode::O, "P" => KeyCode::P,
+        "Q" => KeyCode::Q, "R" => KeyCode::R, "S" => KeyCode::S, "T" => KeyCode::T,
+        "U" => KeyCode::U, "V" => KeyCode::V, "W" => KeyCode::W, "X" => KeyCode::X,
+        "Y" => KeyCode::Y, "Z" => KeyCode::Z,
+        "0" => KeyCode::Key0, "1" => KeyCode::Key1, "2" => KeyCode::Key2, "3" => KeyCode::Key3,
+        "4" => KeyCode::Key4, "5" => KeyCode::Key5, "6" => KeyCode::Key6, "7" => KeyCode::Key7,
+        "8" => KeyCode::Key8, "9" => KeyCode::Key9,
+        "TAB" => KeyCode::Tab,
+        "ENTER" | "RETURN" => KeyCode::Enter,
+        "ESC" | "ESCAPE" => KeyCode::Escape,
+        "SPACE" => KeyCode::Space,
+        "LEFT" => KeyCode::Left,
+        "RIGHT" => KeyCode::Right,
+        "UP" => KeyCode::Up,
+        "DOWN" => KeyCode::Down,
+        "[" => KeyCode::LBracket,
+        "]" => KeyCode::RBracket,
+        _ => return None,
+    };
+    Some(key)
+}
+
+pub fn init() {
+    info!("workspace module loaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pane(dir: &str) -> PaneState {
+        PaneState::new(Some(dir.to_string()), None, HashMap::new())
+    }
+
+    #[test]
+    fn test_split_focus_and_close() {
+        let mut tab = Tab::new(pane("/srv/app"));
+        let first = tab.focus;
+        let right = tab.split(Axis::Vertical).unwrap();
+        assert_eq!(tab.focus, right);
+        // The new pane starts where the focused one was.
+        assert_eq!(tab.focused().title(), "app");
+
+        assert!(tab.focus_adjacent(Direction::Left));
+        assert_eq!(tab.focus, first);
+        assert!(!tab.focus_adjacent(Direction::Left));
+
+        assert!(tab.close(first).is_some());
+        assert_eq!(tab.focus, right);
+        // The last pane stays.
+        assert!(tab.close(right).is_none());
+    }
+
+    #[test]
+    fn test_set_profile() {
+        let mut tab = Tab::new(pane("/srv/app"));
+        tab.split(Axis::Horizontal);
+        let old_shell = tab.focused().shell.clone();
+        let mut env = HashMap::new();
+        env.insert("API_URL".to_string(), "https://staging.example.com".to_string());
+
+        let replaced = tab.set_profile(Some("staging".to_string()), env.clone());
+        assert_eq!(replaced.len(), 2);
+        assert!(replaced.iter().any(|shell| Arc::ptr_eq(shell, &old_shell)));
+        assert_eq!(tab.profile(), Some("staging"));
+        for (_, pane) in tab.panes.iter() {
+            assert_eq!(pane.env, env);
+            assert!(!Arc::ptr_eq(&pane.shell, &old_shell));
+            // New shells start where the old ones were.
+            assert_eq!(pane.initial_dir.as_deref(), Some("/srv/app"));
+        }
+    }
+
+    #[test]
+    fn test_recording() {
+        let dir = std::env::temp_dir().join(format!("neoterm-recordings-{}", uuid::Uuid::new_v4()));
+        let mut tab = Tab::new(pane("/srv/app"));
+        assert_eq!(tab.start_recording(&dir).unwrap().len(), 1);
+        assert!(tab.is_recording());
+        // Panes split off while recording are recorded as well.
+        tab.split(Axis::Vertical);
+        assert!(tab.focused().shell.is_recording());
+
+        let paths = tab.stop_recording();
+        assert_eq!(paths.len(), 2);
+        assert!(!tab.is_recording());
+        assert!(paths.iter().all(|path| path.starts_with(&dir) && path.exists()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tabs_and_blocks() {
+        let mut workspace = Workspace::new(pane("/a"));
+        let block = Block::new_command("ls".to_string(), None);
+        let block_id = block.id.clone();
+        workspace.focused_pane_mut().blocks.push(block);
+
+        assert_eq!(workspace.new_tab(pane("/b")), 1);
+        assert_eq!(workspace.active_tab().title(), "b");
+        // Output still reaches a block in a background tab.
+        assert!(workspace.find_block_mut(&block_id).is_some());
+        assert_eq!(workspace.pane_of_block(&block_id).unwrap().title(), "a");
+        assert!(workspace.focus_block(&block_id));
+        assert_eq!(workspace.active_index(), 0);
+        workspace.select_tab(1);
+
+        workspace.next_tab();
+        assert_eq!(workspace.active_index(), 0);
+        workspace.previous_tab();
+        assert_eq!(workspace.active_index(), 1);
+
+        assert!(workspace.close_tab(1).is_some());
+        assert_eq!(workspace.active_index(), 0);
+        assert!(workspace.close_tab(0).is_none());
+
+        workspace.remove_block(&block_id);
+        assert!(workspace.find_block(&block_id).is_none());
+    }
+
+    #[test]
+    fn test_key_bindings() {
+        let mut keybindings = KeybindingPreferences::default();
+        keybindings.bindings.insert("new_tab".to_string(), "Ctrl+Shift+T".to_string());
+        assert!(matches!(
+            message_for_key(&keybindings, KeyCode::T, Modifiers::CTRL | Modifiers::SHIFT),
+            Some(WorkspaceMessage::NewTab)
+        ));
+        assert!(message_for_key(&keybindings, KeyCode::T, Modifiers::CTRL).is_none());
+        assert!(matches!(
+            message_for_key(&keybindings, KeyCode::D, Modifiers::COMMAND | Modifiers::SHIFT),
+            Some(WorkspaceMessage::Split(Axis::Horizontal))
+        ));
+        assert!(binding_matches("Cmd+]", KeyCode::RBracket, Modifiers::COMMAND));
+    }
+}